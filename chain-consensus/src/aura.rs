@@ -0,0 +1,346 @@
+//! Aura consensus: strict round-robin block authoring over the authority set
+
+use crate::clock::{Clock, SystemClock};
+use crate::poa::PoAConfig;
+use crate::slots::{self, SlotSchedule};
+use crate::traits::{AuthoritySet, Engine, StepContext, StepResult};
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::{Address, BlockHeader};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Aura consensus engine
+///
+/// The proposer of slot `n` is validator `n % len` of the authority set.
+/// Headers are stamped with the start of their slot and signed by their
+/// author. Aura reuses [`PoAConfig`] for its slot duration and authorities;
+/// the VRF seed is ignored.
+pub struct AuraEngine {
+    /// Configuration
+    config: PoAConfig,
+    /// Current authority set
+    authority_set: AuthoritySet,
+    /// Current slot
+    current_slot: u64,
+    /// Last slot in which this node proposed a block
+    last_proposed_slot: Option<u64>,
+    /// Local validator index (if this node is a validator)
+    local_validator_index: Option<usize>,
+    /// Private key signing our headers
+    signing_key: Option<Vec<u8>>,
+    /// Slot timing from genesis, rebased when the slot duration changes
+    slot_schedule: SlotSchedule,
+    /// Time source
+    clock: Arc<dyn Clock>,
+}
+
+impl AuraEngine {
+    /// Create a new Aura engine
+    pub fn new(
+        config: PoAConfig,
+        local_validator_address: Option<Address>,
+        genesis_timestamp: u64,
+    ) -> ConsensusResult<Self> {
+        config.validate()?;
+
+        let authority_set = config.to_authority_set(0)?;
+        let local_validator_index =
+            local_validator_address.and_then(|addr| authority_set.get_validator_index(&addr));
+
+        if local_validator_index.is_some() {
+            info!("Local node is Aura authority #{:?}", local_validator_index);
+        } else {
+            info!("Local node is not an Aura authority");
+        }
+
+        Ok(Self {
+            slot_schedule: SlotSchedule::new(genesis_timestamp, config.slot_duration),
+            config,
+            authority_set,
+            current_slot: 0,
+            last_proposed_slot: None,
+            local_validator_index,
+            signing_key: None,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Sign authored headers with the local validator's private key
+    pub fn with_signing_key(mut self, private_key: &[u8]) -> Self {
+        self.signing_key = Some(private_key.to_vec());
+        self
+    }
+
+    /// Use a custom time source instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get the slot schedule
+    pub fn slot_schedule(&self) -> SlotSchedule {
+        self.slot_schedule.clone()
+    }
+
+    /// Get the current authority set
+    pub fn authority_set(&self) -> &AuthoritySet {
+        &self.authority_set
+    }
+
+    /// Get the proposer for a slot
+    pub fn proposer_for_slot(&self, slot: u64) -> Option<usize> {
+        if self.authority_set.is_empty() {
+            return None;
+        }
+        Some((slot % self.authority_set.len() as u64) as usize)
+    }
+
+    /// Update authority set (hot-swappable)
+    pub fn update_authorities(&mut self, new_config: PoAConfig) -> ConsensusResult<()> {
+        new_config.validate()?;
+
+        let new_authority_set = new_config.to_authority_set(self.authority_set.epoch + 1)?;
+        info!(
+            "Updating Aura authorities to epoch {}",
+            new_authority_set.epoch
+        );
+
+        let local_address = self
+            .local_validator_index
+            .and_then(|index| self.authority_set.get_validator(index))
+            .map(|v| v.address);
+        self.local_validator_index =
+            local_address.and_then(|addr| new_authority_set.get_validator_index(&addr));
+
+        // A new slot duration applies from the next slot
+        if new_config.slot_duration != self.config.slot_duration {
+            self.slot_schedule = self
+                .slot_schedule
+                .rebase(self.current_slot + 1, new_config.slot_duration);
+        }
+
+        self.authority_set = new_authority_set;
+        self.config = new_config;
+
+        Ok(())
+    }
+}
+
+impl Engine for AuraEngine {
+    fn step(&mut self, ctx: StepContext) -> ConsensusResult<StepResult> {
+        let now = self.clock.now();
+        let schedule = self.slot_schedule();
+        let current_slot = schedule.slot_at(now);
+
+        if current_slot > self.current_slot {
+            self.current_slot = current_slot;
+            debug!("Advanced to Aura slot {}", current_slot);
+        }
+
+        if self.should_propose(&ctx) {
+            self.last_proposed_slot = Some(current_slot);
+
+            return Ok(StepResult::Propose {
+                header: slots::build_header(&ctx, schedule.slot_start(current_slot), current_slot),
+                timeout: self.config.slot_duration_as_duration(),
+            });
+        }
+
+        Ok(StepResult::Continue {
+            timeout: schedule.time_until_next_slot(now),
+        })
+    }
+
+    fn verify_block(&self, header: &BlockHeader) -> ConsensusResult<()> {
+        debug!("Verifying block #{}", header.number);
+
+        let slot = self
+            .slot_schedule()
+            .verify_timestamp(header.timestamp, self.clock.now())?;
+
+        // Only the proposer of the slot may author its block
+        let proposer = self
+            .proposer_for_slot(slot)
+            .and_then(|index| self.authority_set.get_validator(index))
+            .ok_or_else(|| ConsensusError::AuthoritySet("Empty authority set".to_string()))?;
+        if slots::header_author(header)? != proposer.address {
+            return Err(ConsensusError::NotAuthorized { slot });
+        }
+
+        debug!(
+            "Block #{} verified successfully (proposer: {:?})",
+            header.number, proposer.address
+        );
+        Ok(())
+    }
+
+    fn current_round(&self) -> u64 {
+        self.current_slot
+    }
+
+    fn should_propose(&self, _ctx: &StepContext) -> bool {
+        let Some(local_index) = self.local_validator_index else {
+            return false;
+        };
+
+        let current_slot = self.slot_schedule().slot_at(self.clock.now());
+        self.proposer_for_slot(current_slot) == Some(local_index)
+            && self.last_proposed_slot != Some(current_slot)
+    }
+
+    fn expected_proposer(&self, slot: u64) -> Option<usize> {
+        self.proposer_for_slot(slot)
+    }
+
+    fn seal(&self, header: BlockHeader) -> ConsensusResult<BlockHeader> {
+        let key = self.signing_key.as_ref().ok_or_else(|| {
+            ConsensusError::Config("Aura authoring needs a signing key".to_string())
+        })?;
+        slots::sign_header(header, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::poa::config::{default_test_authorities, AuthorityConfig};
+    use chain_core::{Hash, Signature};
+
+    const GENESIS_TIME: u64 = 1_700_000_000_000;
+
+    fn create_test_engine(local: Option<&str>) -> (AuraEngine, MockClock) {
+        let config = PoAConfig::new(2000, default_test_authorities());
+        let local = local.map(|hex| Address::from_hex(hex).unwrap());
+        let clock = MockClock::new(GENESIS_TIME);
+        let engine = AuraEngine::new(config, local, GENESIS_TIME)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+
+        (engine, clock)
+    }
+
+    fn key_address(key: &[u8]) -> Address {
+        Signature::sign_hash(&Hash::zero(), key)
+            .unwrap()
+            .recover_address(&Hash::zero())
+            .unwrap()
+    }
+
+    /// Engine over authorities signing with keys `[1; 32]`, `[2; 32]`, `[3; 32]`
+    fn create_keyed_engine(local_key: [u8; 32]) -> (AuraEngine, MockClock) {
+        let authorities = (1..=3u8)
+            .map(|i| AuthorityConfig {
+                address: format!("0x{}", key_address(&[i; 32]).to_hex()),
+                weight: 1,
+            })
+            .collect();
+        let config = PoAConfig::new(2000, authorities);
+        let clock = MockClock::new(GENESIS_TIME);
+        let engine = AuraEngine::new(config, Some(key_address(&local_key)), GENESIS_TIME)
+            .unwrap()
+            .with_signing_key(&local_key)
+            .with_clock(Arc::new(clock.clone()));
+
+        (engine, clock)
+    }
+
+    fn test_context() -> StepContext {
+        StepContext {
+            block_number: 1,
+            parent_hash: Hash::zero(),
+            timestamp: 0,
+            validator_index: None,
+            pending_transactions: 0,
+        }
+    }
+
+    #[test]
+    fn test_round_robin_proposers() {
+        let (engine, _) = create_test_engine(None);
+
+        let proposers: Vec<_> = (0..6).filter_map(|s| engine.expected_proposer(s)).collect();
+        assert_eq!(proposers, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_proposes_once_in_own_slot() {
+        // Authority #1
+        let (mut engine, clock) =
+            create_test_engine(Some("2345678901234567890123456789012345678901"));
+
+        // Slot 0 belongs to authority #0
+        assert!(matches!(
+            engine.step(test_context()).unwrap(),
+            StepResult::Continue { .. }
+        ));
+
+        clock.set(engine.slot_schedule().slot_start(1));
+        match engine.step(test_context()).unwrap() {
+            StepResult::Propose { header, .. } => assert_eq!(header.nonce, 1),
+            other => panic!("Expected Propose, got {:?}", other),
+        }
+
+        // Only one proposal per slot
+        assert!(matches!(
+            engine.step(test_context()).unwrap(),
+            StepResult::Continue { .. }
+        ));
+    }
+
+    #[test]
+    fn test_late_step_stamps_slot_start() {
+        // Authority #1 steps late in its slot
+        let (mut engine, clock) = create_keyed_engine([2; 32]);
+        let slot_start = engine.slot_schedule().slot_start(1);
+        clock.set(slot_start + 1_500);
+
+        let header = match engine.step(test_context()).unwrap() {
+            StepResult::Propose { header, .. } => header,
+            other => panic!("Expected Propose, got {:?}", other),
+        };
+        assert_eq!(header.timestamp, slot_start);
+
+        let sealed = engine.seal(header).unwrap();
+        engine.verify_block(&sealed).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_block_from_wrong_author() {
+        let (engine, clock) = create_keyed_engine([2; 32]);
+        let slot_start = engine.slot_schedule().slot_start(1);
+        clock.set(slot_start);
+        let header = slots::build_header(&test_context(), slot_start, 1);
+
+        // Unsigned headers have no author
+        assert!(engine.verify_block(&header).is_err());
+
+        // Slot 1 belongs to authority #1, not #0
+        let signed = slots::sign_header(header.clone(), &[1; 32]).unwrap();
+        assert!(matches!(
+            engine.verify_block(&signed),
+            Err(ConsensusError::NotAuthorized { slot: 1 })
+        ));
+
+        let signed = slots::sign_header(header, &[2; 32]).unwrap();
+        engine.verify_block(&signed).unwrap();
+    }
+
+    #[test]
+    fn test_slot_duration_change_keeps_past_slots() {
+        let (mut engine, clock) = create_test_engine(None);
+        let schedule = engine.slot_schedule();
+        clock.set(schedule.slot_start(5));
+        engine.step(test_context()).unwrap();
+
+        let mut config = engine.config.clone();
+        config.slot_duration = 1000;
+        engine.update_authorities(config).unwrap();
+
+        // Slots up to the current one keep their timing, later ones are shorter
+        let rebased = engine.slot_schedule();
+        assert_eq!(rebased.slot_at(clock.now()), 5);
+        assert_eq!(rebased.slot_start(6), schedule.slot_start(6));
+        assert_eq!(rebased.slot_start(7), schedule.slot_start(6) + 1000);
+    }
+}
@@ -0,0 +1,87 @@
+//! Time sources for consensus engines
+//!
+//! All timestamps are milliseconds since the Unix epoch, matching
+//! `chain_core::Timestamp` and `BlockHeader::timestamp`.
+
+use chain_core::Timestamp;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time for consensus engines
+pub trait Clock: Debug + Send + Sync {
+    /// Current time in milliseconds since the Unix epoch
+    fn now(&self) -> Timestamp;
+}
+
+/// Clock backed by the system wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as Timestamp
+    }
+}
+
+/// Manually driven clock for tests and simulations
+///
+/// Clones share the same time, so several engines can be advanced together.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Create a mock clock starting at the given timestamp
+    pub fn new(start: Timestamp) -> Self {
+        Self {
+            now: Arc::new(AtomicU64::new(start)),
+        }
+    }
+
+    /// Set the current time
+    pub fn set(&self, timestamp: Timestamp) {
+        self.now.store(timestamp, Ordering::SeqCst);
+    }
+
+    /// Advance the current time
+    pub fn advance(&self, duration: Duration) {
+        self.now
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_clock_millis() {
+        let now = SystemClock.now();
+        // Later than 2020-01-01 in milliseconds
+        assert!(now > 1_577_836_800_000);
+    }
+
+    #[test]
+    fn test_mock_clock_shared() {
+        let clock = MockClock::new(1_000);
+        let other = clock.clone();
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(other.now(), 1_500);
+
+        other.set(10_000);
+        assert_eq!(clock.now(), 10_000);
+    }
+}
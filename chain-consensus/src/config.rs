@@ -0,0 +1,158 @@
+//! Consensus engine selection
+
+use crate::aura::AuraEngine;
+use crate::clock::Clock;
+use crate::instant_seal::{InstantSealConfig, InstantSealEngine};
+use crate::poa::{PoAConfig, PoAEngine, SLOT_DURATION_PARAM};
+use crate::pow::{PowConfig, PowEngine};
+use crate::traits::Engine;
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::{Address, BlockHeader, BlockNumber, ForkSchedule};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Consensus engine configuration, tagged by engine type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "engine", rename_all = "snake_case")]
+pub enum EngineConfig {
+    /// Proof of Authority with VRF proposer selection
+    Poa(PoAConfig),
+    /// Aura round-robin proposer selection
+    Aura(PoAConfig),
+    /// Seal blocks as soon as transactions arrive
+    InstantSeal(InstantSealConfig),
+    /// Permissionless Proof of Work
+    Pow(PowConfig),
+}
+
+impl EngineConfig {
+    /// Get the engine name
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineConfig::Poa(_) => "poa",
+            EngineConfig::Aura(_) => "aura",
+            EngineConfig::InstantSeal(_) => "instant_seal",
+            EngineConfig::Pow(_) => "pow",
+        }
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> ConsensusResult<()> {
+        match self {
+            EngineConfig::Poa(config) | EngineConfig::Aura(config) => config.validate(),
+            EngineConfig::InstantSeal(config) => config.validate(),
+            EngineConfig::Pow(config) => config.validate(),
+        }
+    }
+
+    /// Build the configured engine on top of the best block
+    ///
+    /// Authority engines schedule slots from the genesis timestamp, and the
+    /// PoW engine only imports headers descending from the genesis header.
+    /// `timestamp_at` returns the timestamp of the canonical block at a height
+    /// and is used to replay the forks activated up to `best_number`.
+    pub fn build(
+        &self,
+        local_validator_address: Option<Address>,
+        genesis: &BlockHeader,
+        forks: &ForkSchedule,
+        best_number: BlockNumber,
+        timestamp_at: impl Fn(BlockNumber) -> Option<u64>,
+        clock: Arc<dyn Clock>,
+    ) -> ConsensusResult<Box<dyn Engine>> {
+        let engine: Box<dyn Engine> = match self {
+            EngineConfig::Poa(config) => Box::new(
+                PoAEngine::new(config.clone(), local_validator_address, genesis.timestamp)?
+                    .with_clock(clock)
+                    .with_fork_schedule(forks.clone(), best_number, timestamp_at)?,
+            ),
+            EngineConfig::Aura(_)
+                if forks
+                    .forks()
+                    .iter()
+                    .any(|fork| fork.params.contains_key(SLOT_DURATION_PARAM)) =>
+            {
+                return Err(ConsensusError::Config(
+                    "Aura does not support slot duration forks".to_string(),
+                ))
+            }
+            EngineConfig::Aura(config) => Box::new(
+                AuraEngine::new(config.clone(), local_validator_address, genesis.timestamp)?
+                    .with_clock(clock),
+            ),
+            EngineConfig::InstantSeal(config) => {
+                Box::new(InstantSealEngine::new(config.clone())?.with_clock(clock))
+            }
+            EngineConfig::Pow(config) => {
+                Box::new(PowEngine::new(config.clone(), genesis.clone())?.with_clock(clock))
+            }
+        };
+
+        Ok(engine)
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig::Poa(PoAConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::poa::config::default_test_authorities;
+    use chain_core::Fork;
+
+    #[test]
+    fn test_engine_config_serialization() {
+        let config = EngineConfig::InstantSeal(InstantSealConfig::default());
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains("\"engine\":\"instant_seal\""));
+
+        let deserialized: EngineConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.name(), "instant_seal");
+    }
+
+    #[test]
+    fn test_build_engines() {
+        let clock = Arc::new(MockClock::new(0));
+        let genesis = BlockHeader::genesis();
+        let authority_config = PoAConfig::new(3000, default_test_authorities());
+
+        for config in [
+            EngineConfig::Poa(authority_config.clone()),
+            EngineConfig::Aura(authority_config),
+            EngineConfig::InstantSeal(InstantSealConfig::default()),
+            EngineConfig::Pow(PowConfig::default()),
+        ] {
+            let forks = ForkSchedule::default();
+            assert!(config
+                .build(None, &genesis, &forks, 0, |_| None, clock.clone())
+                .is_ok());
+        }
+
+        // Authority engines need authorities
+        let forks = ForkSchedule::default();
+        assert!(EngineConfig::default()
+            .build(None, &genesis, &forks, 0, |_| None, clock.clone())
+            .is_err());
+
+        // The PoA engine starts with the slot duration of the forks activated so far
+        let forks = ForkSchedule::new(vec![
+            Fork::new("faster_slots", 1).with_param(SLOT_DURATION_PARAM, 1000)
+        ])
+        .unwrap();
+        let config = PoAConfig::new(3000, default_test_authorities());
+        assert!(EngineConfig::Poa(config.clone())
+            .build(None, &genesis, &forks, 5, |_| Some(0), clock.clone())
+            .is_ok());
+        assert!(EngineConfig::Poa(config.clone())
+            .build(None, &genesis, &forks, 5, |_| None, clock.clone())
+            .is_err());
+        assert!(EngineConfig::Aura(config)
+            .build(None, &genesis, &forks, 0, |_| None, clock)
+            .is_err());
+    }
+}
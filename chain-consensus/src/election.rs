@@ -0,0 +1,37 @@
+//! Validator elections at epoch boundaries
+//!
+//! Authority engines ask an [`ElectionProvider`] for the next validator set
+//! once a block at an election height is imported. The provider is typically
+//! backed by the staking state after that block, which lives outside this
+//! crate, so every node elects the same set.
+
+use crate::traits::Validator;
+use crate::ConsensusResult;
+use chain_core::Hash;
+
+/// Source of the validator set for an epoch
+pub trait ElectionProvider: Send + Sync {
+    /// Elect the validators for `epoch` from the state after `block`,
+    /// weighted by their backing stake
+    fn elect(&self, epoch: u64, block: &Hash) -> ConsensusResult<Vec<Validator>>;
+}
+
+/// Election provider that always returns the same validators
+#[derive(Debug, Clone)]
+pub struct StaticElection {
+    /// Validators returned for every epoch
+    validators: Vec<Validator>,
+}
+
+impl StaticElection {
+    /// Create a provider returning a fixed validator set
+    pub fn new(validators: Vec<Validator>) -> Self {
+        Self { validators }
+    }
+}
+
+impl ElectionProvider for StaticElection {
+    fn elect(&self, _epoch: u64, _block: &Hash) -> ConsensusResult<Vec<Validator>> {
+        Ok(self.validators.clone())
+    }
+}
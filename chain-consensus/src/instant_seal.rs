@@ -0,0 +1,165 @@
+//! Instant-seal consensus for local development and integration tests
+//!
+//! A block is sealed as soon as transactions are pending. There is no
+//! authority set and no slot timing; every header is accepted as long as its
+//! timestamp is not in the future.
+
+use crate::clock::{Clock, SystemClock};
+use crate::slots;
+use crate::traits::{Engine, StepContext, StepResult};
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::BlockHeader;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+/// Instant-seal configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstantSealConfig {
+    /// Interval in milliseconds between checks for pending transactions
+    pub poll_interval: u64,
+    /// Seal a block on every poll even without pending transactions
+    pub seal_empty_blocks: bool,
+}
+
+impl Default for InstantSealConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: 100,
+            seal_empty_blocks: false,
+        }
+    }
+}
+
+impl InstantSealConfig {
+    /// Validate the configuration
+    pub fn validate(&self) -> ConsensusResult<()> {
+        if self.poll_interval == 0 {
+            return Err(ConsensusError::Config(
+                "Poll interval must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get poll interval as Duration
+    pub fn poll_interval_as_duration(&self) -> Duration {
+        Duration::from_millis(self.poll_interval)
+    }
+}
+
+/// Instant-seal consensus engine
+pub struct InstantSealEngine {
+    /// Configuration
+    config: InstantSealConfig,
+    /// Number of blocks sealed by this engine
+    sealed_blocks: u64,
+    /// Time source
+    clock: Arc<dyn Clock>,
+}
+
+impl InstantSealEngine {
+    /// Create a new instant-seal engine
+    pub fn new(config: InstantSealConfig) -> ConsensusResult<Self> {
+        config.validate()?;
+
+        Ok(Self {
+            config,
+            sealed_blocks: 0,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Use a custom time source instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Engine for InstantSealEngine {
+    fn step(&mut self, ctx: StepContext) -> ConsensusResult<StepResult> {
+        let timeout = self.config.poll_interval_as_duration();
+
+        if self.should_propose(&ctx) {
+            self.sealed_blocks += 1;
+            debug!(
+                "Sealing block #{} with {} pending transactions",
+                ctx.block_number, ctx.pending_transactions
+            );
+
+            return Ok(StepResult::Propose {
+                header: slots::build_header(&ctx, self.clock.now(), ctx.block_number),
+                timeout,
+            });
+        }
+
+        Ok(StepResult::Continue { timeout })
+    }
+
+    fn verify_block(&self, header: &BlockHeader) -> ConsensusResult<()> {
+        let now = self.clock.now();
+
+        if header.timestamp > now + self.config.poll_interval {
+            return Err(ConsensusError::InvalidTimestamp {
+                expected: now,
+                actual: header.timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn current_round(&self) -> u64 {
+        self.sealed_blocks
+    }
+
+    fn should_propose(&self, ctx: &StepContext) -> bool {
+        ctx.pending_transactions > 0 || self.config.seal_empty_blocks
+    }
+
+    fn expected_proposer(&self, _slot: u64) -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chain_core::Hash;
+
+    fn test_context(pending_transactions: usize) -> StepContext {
+        StepContext {
+            block_number: 7,
+            parent_hash: Hash::zero(),
+            timestamp: 0,
+            validator_index: None,
+            pending_transactions,
+        }
+    }
+
+    #[test]
+    fn test_seals_only_with_transactions() {
+        let clock = MockClock::new(5_000);
+        let mut engine = InstantSealEngine::new(InstantSealConfig::default())
+            .unwrap()
+            .with_clock(Arc::new(clock));
+
+        assert!(matches!(
+            engine.step(test_context(0)).unwrap(),
+            StepResult::Continue { .. }
+        ));
+
+        match engine.step(test_context(3)).unwrap() {
+            StepResult::Propose { header, .. } => {
+                assert_eq!(header.number, 7);
+                assert_eq!(header.timestamp, 5_000);
+                assert!(engine.verify_block(&header).is_ok());
+            }
+            other => panic!("Expected Propose, got {:?}", other),
+        }
+        assert_eq!(engine.current_round(), 1);
+    }
+}
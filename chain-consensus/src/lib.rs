@@ -1,16 +1,40 @@
 //! Blockchain consensus engine
 //!
 //! This crate provides consensus mechanisms for the blockchain,
-//! including Proof of Authority (PoA) with VRF for validator rotation.
+//! including Proof of Authority (PoA) with VRF for validator rotation,
+//! Aura round-robin authoring, permissionless Proof of Work (PoW) and an
+//! instant-seal engine for development. Authority sets can be re-elected
+//! from bonded stake at every epoch boundary, and a header-only light
+//! client follows PoA chains across authority set changes.
 
+pub mod aura;
+pub mod clock;
+pub mod config;
+pub mod election;
 pub mod error;
+pub mod instant_seal;
+pub mod light;
+pub mod liveness;
 pub mod poa;
+pub mod pow;
 pub mod slashing;
+pub mod slots;
 pub mod traits;
+pub mod worker;
 
+pub use aura::AuraEngine;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use config::EngineConfig;
+pub use election::{ElectionProvider, StaticElection};
 pub use error::{ConsensusError, ConsensusResult};
+pub use instant_seal::{InstantSealConfig, InstantSealEngine};
+pub use light::{AuthoritySetChange, Checkpoint, LightClient};
 pub use poa::{PoAConfig, PoAEngine};
+pub use pow::{PowConfig, PowEngine};
 pub use traits::{Engine, StepContext, StepResult};
+pub use worker::{
+    ChainBackend, ConsensusWorker, SharedEngine, TransactionPool, WorkerHandle, WorkerStats,
+};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,660 @@
+//! Header-only light client for PoA chains
+//!
+//! A [`LightClient`] follows the chain from genesis or a trusted
+//! [`Checkpoint`] without executing blocks or keeping state. Headers are
+//! checked with the same slot timing and proposer rules as
+//! [`PoAEngine::verify_block`](crate::PoAEngine), and must commit to the
+//! authority set that produced them. Once the next set is elected, headers of
+//! the outgoing set also commit to it, so a set change is only accepted if a
+//! header verified under the old set announced it. An [`AuthoritySetChange`]
+//! proof carrying the announced set and the first header it produced then
+//! moves the client over to it.
+
+use crate::clock::{Clock, SystemClock};
+use crate::poa::engine::{next_set_commitment, set_commitment, verify_header};
+use crate::poa::{PoAConfig, VrfSeed, VrfSelector, SLOT_DURATION_PARAM};
+use crate::slots::SlotSchedule;
+use crate::traits::AuthoritySet;
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::{BlockHeader, BlockNumber, ForkSchedule, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Default number of headers between checkpoints
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// Default number of checkpoints kept by a light client
+pub const DEFAULT_MAX_CHECKPOINTS: usize = 16;
+
+/// Trusted point from which a light client can resume
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Block number
+    pub number: BlockNumber,
+    /// Block hash
+    pub hash: Hash,
+    /// Slot the block was produced in
+    pub slot: u64,
+    /// Authority set expected to produce the following blocks
+    pub authority_set: AuthoritySet,
+    /// Commitment to the set announced to take over next, if any
+    #[serde(default)]
+    pub next_set: Option<Hash>,
+    /// Slot timing in effect after the block
+    pub slot_schedule: SlotSchedule,
+}
+
+/// Proof that the authority set changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthoritySetChange {
+    /// The new authority set
+    pub authority_set: AuthoritySet,
+    /// First header produced by the new set, committing to it
+    pub header: BlockHeader,
+}
+
+/// Follows a PoA chain using headers only
+pub struct LightClient {
+    /// VRF seed for proposer selection
+    vrf_seed: [u8; 32],
+    /// Epoch length in slots
+    epoch_length: u64,
+    /// Authority set producing the next headers
+    authority_set: AuthoritySet,
+    /// Proposer selection for the current authority set
+    vrf_selector: VrfSelector,
+    /// Commitment to the set announced to take over next
+    next_set: Option<Hash>,
+    /// Slot timing
+    slot_schedule: SlotSchedule,
+    /// Protocol upgrades by block number
+    fork_schedule: ForkSchedule,
+    /// Latest verified header
+    best: Checkpoint,
+    /// Recent checkpoints, oldest first
+    checkpoints: VecDeque<Checkpoint>,
+    /// Headers between checkpoints
+    checkpoint_interval: u64,
+    /// Maximum number of checkpoints kept
+    max_checkpoints: usize,
+    /// Time source
+    clock: Arc<dyn Clock>,
+}
+
+impl LightClient {
+    /// Create a light client starting at the genesis header
+    pub fn new(config: &PoAConfig, genesis: &BlockHeader) -> ConsensusResult<Self> {
+        config.validate()?;
+
+        let checkpoint = Checkpoint {
+            number: genesis.number,
+            hash: hash_header(genesis)?,
+            slot: 0,
+            authority_set: config.to_authority_set(0)?,
+            next_set: None,
+            slot_schedule: SlotSchedule::new(genesis.timestamp, config.slot_duration),
+        };
+
+        Self::from_checkpoint(config, checkpoint)
+    }
+
+    /// Resume a light client from a trusted checkpoint
+    pub fn from_checkpoint(config: &PoAConfig, checkpoint: Checkpoint) -> ConsensusResult<Self> {
+        config.validate()?;
+
+        if checkpoint.authority_set.is_empty() {
+            return Err(ConsensusError::AuthoritySet(
+                "Checkpoint authority set is empty".to_string(),
+            ));
+        }
+
+        info!(
+            "Light client starting at block #{} (set {})",
+            checkpoint.number, checkpoint.authority_set.set_id
+        );
+
+        Ok(Self {
+            vrf_seed: config.vrf_seed,
+            epoch_length: config.epoch_length,
+            vrf_selector: selector_for(config.vrf_seed, &checkpoint.authority_set),
+            authority_set: checkpoint.authority_set.clone(),
+            next_set: checkpoint.next_set,
+            slot_schedule: checkpoint.slot_schedule.clone(),
+            fork_schedule: ForkSchedule::default(),
+            checkpoints: VecDeque::from([checkpoint.clone()]),
+            best: checkpoint,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            max_checkpoints: DEFAULT_MAX_CHECKPOINTS,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Use a custom time source instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Follow a fork schedule, as configured on the engine
+    pub fn with_fork_schedule(mut self, fork_schedule: ForkSchedule) -> Self {
+        if self.best.number == 0 {
+            if let Some(slot_duration) = fork_schedule.param(SLOT_DURATION_PARAM, 0) {
+                self.slot_schedule =
+                    SlotSchedule::new(self.slot_schedule.genesis_timestamp(), slot_duration);
+                self.best.slot_schedule = self.slot_schedule.clone();
+            }
+        }
+        self.fork_schedule = fork_schedule;
+        self
+    }
+
+    /// Set how many headers pass between checkpoints
+    pub fn with_checkpoint_interval(mut self, interval: u64) -> Self {
+        self.checkpoint_interval = interval.max(1);
+        self
+    }
+
+    /// Set how many checkpoints are kept
+    pub fn with_max_checkpoints(mut self, max_checkpoints: usize) -> Self {
+        self.max_checkpoints = max_checkpoints.max(1);
+        self
+    }
+
+    /// Get the latest verified header as a checkpoint
+    pub fn best(&self) -> &Checkpoint {
+        &self.best
+    }
+
+    /// Get the authority set producing the next headers
+    pub fn authority_set(&self) -> &AuthoritySet {
+        &self.authority_set
+    }
+
+    /// Get the retained checkpoints, oldest first
+    pub fn checkpoints(&self) -> impl Iterator<Item = &Checkpoint> {
+        self.checkpoints.iter()
+    }
+
+    /// Get the most recent checkpoint
+    pub fn latest_checkpoint(&self) -> &Checkpoint {
+        self.checkpoints.back().unwrap_or(&self.best)
+    }
+
+    /// Verify and import the next header produced by the current authority set
+    pub fn import_header(&mut self, header: &BlockHeader) -> ConsensusResult<()> {
+        match set_commitment(header) {
+            Some(commitment) if commitment == self.authority_set.commitment() => {}
+            Some(_) => return Err(ConsensusError::AuthoritySet(format!(
+                "Block #{} was authored by an unknown authority set, a change proof is required",
+                header.number
+            ))),
+            None => {
+                return Err(ConsensusError::AuthoritySet(format!(
+                    "Block #{} does not commit to an authority set",
+                    header.number
+                )))
+            }
+        }
+
+        // Once announced, every header of the set must carry the next set
+        let next_set = next_set_commitment(header);
+        match (self.next_set, next_set) {
+            (Some(announced), Some(next)) if announced != next => {
+                return Err(ConsensusError::AuthoritySet(format!(
+                    "Block #{} announces a different next authority set",
+                    header.number
+                )));
+            }
+            (Some(_), None) => {
+                return Err(ConsensusError::AuthoritySet(format!(
+                    "Block #{} drops the announced next authority set",
+                    header.number
+                )));
+            }
+            _ => {}
+        }
+
+        let slot = self.verify(header, &self.authority_set, &self.vrf_selector)?;
+        if next_set.is_some() {
+            self.next_set = next_set;
+        }
+        self.accept(header, slot, false)
+    }
+
+    /// Verify an authority set change and import the first header of the new set
+    pub fn apply_set_change(&mut self, change: &AuthoritySetChange) -> ConsensusResult<()> {
+        let new_set = &change.authority_set;
+
+        if new_set.set_id != self.authority_set.set_id + 1 {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Expected authority set {}, got {}",
+                self.authority_set.set_id + 1,
+                new_set.set_id
+            )));
+        }
+
+        if new_set.is_empty() || new_set.validators.iter().any(|v| v.weight == 0) {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Authority set {} has no backed validators",
+                new_set.set_id
+            )));
+        }
+
+        if self.next_set != Some(new_set.commitment()) {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Authority set {} was not announced by set {}",
+                new_set.set_id, self.authority_set.set_id
+            )));
+        }
+
+        if set_commitment(&change.header) != Some(new_set.commitment()) {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Block #{} does not commit to authority set {}",
+                change.header.number, new_set.set_id
+            )));
+        }
+
+        let selector = selector_for(self.vrf_seed, new_set);
+        let slot = self.verify(&change.header, new_set, &selector)?;
+
+        // Sets only change at epoch boundaries
+        if new_set.epoch <= self.authority_set.epoch || slot / self.epoch_length < new_set.epoch {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Authority set {} for epoch {} cannot take over at slot {}",
+                new_set.set_id, new_set.epoch, slot
+            )));
+        }
+
+        info!(
+            "Light client switching to authority set {} at block #{}",
+            new_set.set_id, change.header.number
+        );
+        self.authority_set = new_set.clone();
+        self.vrf_selector = selector;
+        self.next_set = next_set_commitment(&change.header);
+        self.accept(&change.header, slot, true)
+    }
+
+    /// Check that a header extends the best header, fits its slot and is
+    /// sealed by the slot's proposer in `authority_set`
+    fn verify(
+        &self,
+        header: &BlockHeader,
+        authority_set: &AuthoritySet,
+        selector: &VrfSelector,
+    ) -> ConsensusResult<u64> {
+        if header.number != self.best.number + 1 || header.parent_hash != self.best.hash {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Block #{} does not extend best block #{}",
+                header.number, self.best.number
+            )));
+        }
+
+        let (slot, proposer) = verify_header(
+            &self.slot_schedule,
+            selector,
+            authority_set,
+            header,
+            self.clock.now(),
+        )?;
+
+        if slot <= self.best.slot && self.best.number > 0 {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Block #{} in slot {} does not follow slot {}",
+                header.number, slot, self.best.slot
+            )));
+        }
+
+        debug!(
+            "Light client verified block #{} (proposer: {})",
+            header.number, proposer
+        );
+        Ok(slot)
+    }
+
+    /// Make a verified header the best one, recording a checkpoint if due
+    fn accept(
+        &mut self,
+        header: &BlockHeader,
+        slot: u64,
+        force_checkpoint: bool,
+    ) -> ConsensusResult<()> {
+        // Parameters of forks activating at the next block apply from the next slot
+        if let Some(slot_duration) = self
+            .fork_schedule
+            .param_change_at(SLOT_DURATION_PARAM, header.number + 1)
+        {
+            self.slot_schedule = self.slot_schedule.rebase(slot + 1, slot_duration);
+        }
+
+        self.best = Checkpoint {
+            number: header.number,
+            hash: hash_header(header)?,
+            slot,
+            authority_set: self.authority_set.clone(),
+            next_set: self.next_set,
+            slot_schedule: self.slot_schedule.clone(),
+        };
+
+        if force_checkpoint || header.number.is_multiple_of(self.checkpoint_interval) {
+            self.checkpoints.push_back(self.best.clone());
+            while self.checkpoints.len() > self.max_checkpoints {
+                self.checkpoints.pop_front();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn selector_for(vrf_seed: [u8; 32], authority_set: &AuthoritySet) -> VrfSelector {
+    VrfSelector::new(VrfSeed::from_bytes(vrf_seed), authority_set.len())
+}
+
+fn hash_header(header: &BlockHeader) -> ConsensusResult<Hash> {
+    header
+        .hash()
+        .map_err(|e| ConsensusError::Other(format!("Failed to hash header: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::election::StaticElection;
+    use crate::poa::config::{keyed_test_authorities, test_authority_address, test_authority_key};
+    use crate::poa::engine::set_commitments;
+    use crate::slots;
+    use crate::traits::{Engine, StepContext, StepResult, Validator};
+    use crate::PoAEngine;
+
+    const GENESIS_TIME: u64 = 1_700_000_000_000;
+
+    fn test_config() -> PoAConfig {
+        PoAConfig {
+            slot_duration: 3000,
+            authorities: keyed_test_authorities(3),
+            vrf_seed: [1u8; 32],
+            epoch_length: 10,
+            rewards: Default::default(),
+        }
+    }
+
+    fn genesis() -> BlockHeader {
+        BlockHeader {
+            timestamp: GENESIS_TIME,
+            ..BlockHeader::genesis()
+        }
+    }
+
+    /// Seal a header for `slot` with the key of the slot's proposer in `set`
+    fn seal(header: BlockHeader, slot: u64, set: &AuthoritySet) -> BlockHeader {
+        let proposer = selector_for(test_config().vrf_seed, set).select_validator(slot);
+        let address = set.validators[proposer].address;
+        let index = (0..8)
+            .find(|index| test_authority_address(*index) == address)
+            .unwrap();
+        slots::sign_header(header, &test_authority_key(index)).unwrap()
+    }
+
+    /// Sealed header for `slot` on top of `parent`, committing to `set`
+    fn child(parent: &BlockHeader, slot: u64, set: &AuthoritySet) -> BlockHeader {
+        let header = BlockHeader {
+            parent_hash: parent.hash().unwrap(),
+            number: parent.number + 1,
+            timestamp: GENESIS_TIME + slot * 3000,
+            extra_data: set.commitment().as_bytes().to_vec(),
+            ..BlockHeader::genesis()
+        };
+        seal(header, slot, set)
+    }
+
+    /// Header like [`child`] that also announces `next` as the next set
+    fn announcing(
+        parent: &BlockHeader,
+        slot: u64,
+        set: &AuthoritySet,
+        next: &AuthoritySet,
+    ) -> BlockHeader {
+        let header = BlockHeader {
+            parent_hash: parent.hash().unwrap(),
+            number: parent.number + 1,
+            timestamp: GENESIS_TIME + slot * 3000,
+            extra_data: set_commitments(set, Some(next)),
+            ..BlockHeader::genesis()
+        };
+        seal(header, slot, set)
+    }
+
+    fn test_client(clock: &MockClock) -> LightClient {
+        LightClient::new(&test_config(), &genesis())
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()))
+            .with_checkpoint_interval(2)
+    }
+
+    #[test]
+    fn test_follows_engine_headers() {
+        let clock = MockClock::new(GENESIS_TIME);
+        let local = test_authority_address(0);
+        let mut engine = PoAEngine::new(test_config(), Some(local), GENESIS_TIME)
+            .unwrap()
+            .with_signing_key(&test_authority_key(0))
+            .with_clock(Arc::new(clock.clone()));
+        let mut client = test_client(&clock);
+
+        // Import every header the local validator authors, as a full node would
+        let mut parent = genesis();
+        for slot in 1..40 {
+            clock.set(GENESIS_TIME + slot * 3000);
+            let ctx = StepContext {
+                block_number: parent.number + 1,
+                parent_hash: parent.hash().unwrap(),
+                timestamp: clock.now(),
+                validator_index: None,
+                pending_transactions: 0,
+            };
+            if let StepResult::Propose { header, .. } = engine.step(ctx).unwrap() {
+                let header = engine.seal(header).unwrap();
+                engine.verify_block(&header).unwrap();
+                client.import_header(&header).unwrap();
+                parent = header;
+            }
+        }
+
+        assert!(parent.number > 0);
+        assert_eq!(client.best().number, parent.number);
+        assert_eq!(client.best().hash, parent.hash().unwrap());
+    }
+
+    #[test]
+    fn test_rejects_invalid_headers() {
+        let clock = MockClock::new(GENESIS_TIME + 10 * 3000);
+        let mut client = test_client(&clock);
+        let set = client.authority_set().clone();
+        let first = child(&genesis(), 1, &set);
+
+        // Wrong parent
+        let mut orphan = first.clone();
+        orphan.parent_hash = Hash::zero();
+        assert!(client.import_header(&orphan).is_err());
+
+        // Timestamp outside the first half of its slot
+        let mut late = first.clone();
+        late.timestamp += 2000;
+        assert!(client.import_header(&late).is_err());
+
+        // Sealed by an authority other than the slot's proposer
+        let unsealed = BlockHeader {
+            extra_data: first.extra_data[..32].to_vec(),
+            ..first.clone()
+        };
+        let proposer = client.vrf_selector.select_validator(1);
+        let other = test_authority_key((proposer + 1) % 3);
+        assert!(matches!(
+            client.import_header(&slots::sign_header(unsealed, &other).unwrap()),
+            Err(ConsensusError::NotAuthorized { slot: 1 })
+        ));
+
+        // Committing to another authority set
+        let mut other = set.clone();
+        other.set_id = 1;
+        assert!(client.import_header(&child(&genesis(), 1, &other)).is_err());
+
+        // Missing the set commitment
+        let mut uncommitted = first.clone();
+        uncommitted.extra_data = vec![];
+        assert!(client.import_header(&uncommitted).is_err());
+        uncommitted.extra_data = vec![0u8; 16];
+        assert!(client.import_header(&uncommitted).is_err());
+
+        client.import_header(&first).unwrap();
+
+        // Slots must increase
+        let mut same_slot = child(&first, 1, &set);
+        same_slot.timestamp = first.timestamp;
+        assert!(client.import_header(&same_slot).is_err());
+    }
+
+    #[test]
+    fn test_authority_set_change_and_checkpoints() {
+        let clock = MockClock::new(GENESIS_TIME + 20 * 3000);
+        let mut client = test_client(&clock);
+        let set = client.authority_set().clone();
+
+        // Epoch 1 starts at slot 10 with a new set
+        let mut new_set = AuthoritySet::new(
+            vec![Validator {
+                address: test_authority_address(3),
+                weight: 100,
+            }],
+            1,
+        );
+        new_set.set_id = 1;
+
+        let mut parent = genesis();
+        for slot in 1..=3 {
+            let header = child(&parent, slot, &set);
+            client.import_header(&header).unwrap();
+            parent = header;
+        }
+
+        // The new set cannot take over before the old set announced it
+        let unannounced = AuthoritySetChange {
+            authority_set: new_set.clone(),
+            header: child(&parent, 10, &new_set),
+        };
+        assert!(client.apply_set_change(&unannounced).is_err());
+
+        let header = announcing(&parent, 4, &set, &new_set);
+        client.import_header(&header).unwrap();
+        parent = header;
+
+        // Later headers of the old set cannot announce another set
+        let mut other_set = new_set.clone();
+        other_set.validators[0].weight = 200;
+        assert!(client
+            .import_header(&announcing(&parent, 5, &set, &other_set))
+            .is_err());
+
+        // Nor drop the announcement
+        assert!(client.import_header(&child(&parent, 5, &set)).is_err());
+
+        // The new set cannot take over before its epoch starts
+        let early = AuthoritySetChange {
+            authority_set: new_set.clone(),
+            header: child(&parent, 9, &new_set),
+        };
+        assert!(client.apply_set_change(&early).is_err());
+
+        // Headers from the new set need the change proof first
+        let header = child(&parent, 10, &new_set);
+        assert!(client.import_header(&header).is_err());
+
+        let change = AuthoritySetChange {
+            authority_set: new_set.clone(),
+            header: header.clone(),
+        };
+        client.apply_set_change(&change).unwrap();
+        assert_eq!(client.authority_set().set_id, 1);
+        assert!(client.apply_set_change(&change).is_err());
+
+        // Genesis, blocks #2 and #4 and the set change are checkpointed
+        let numbers: Vec<_> = client.checkpoints().map(|c| c.number).collect();
+        assert_eq!(numbers, vec![0, 2, 4, 5]);
+
+        // A client resumed from the latest checkpoint continues with the new set
+        let checkpoint = client.latest_checkpoint().clone();
+        let mut resumed = LightClient::from_checkpoint(&test_config(), checkpoint)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        resumed
+            .import_header(&child(&header, 11, &new_set))
+            .unwrap();
+        assert_eq!(resumed.best().number, 6);
+    }
+
+    #[test]
+    fn test_follows_engine_set_change() {
+        let clock = MockClock::new(GENESIS_TIME);
+        let local = test_authority_address(0);
+        let elected = vec![Validator {
+            address: local,
+            weight: 100,
+        }];
+        // A lone authority authors every slot, so each epoch has an election height
+        let config = PoAConfig {
+            authorities: keyed_test_authorities(1),
+            ..test_config()
+        };
+        let mut engine = PoAEngine::new(config.clone(), Some(local), GENESIS_TIME)
+            .unwrap()
+            .with_signing_key(&test_authority_key(0))
+            .with_clock(Arc::new(clock.clone()))
+            .with_election_provider(Arc::new(StaticElection::new(elected)));
+        let mut client = LightClient::new(&config, &genesis())
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+
+        let mut parent = genesis();
+        let mut changed = false;
+        for slot in 1..30 {
+            clock.set(GENESIS_TIME + slot * 3000);
+            let ctx = StepContext {
+                block_number: parent.number + 1,
+                parent_hash: parent.hash().unwrap(),
+                timestamp: clock.now(),
+                validator_index: None,
+                pending_transactions: 0,
+            };
+            let StepResult::Propose { header, .. } = engine.step(ctx.clone()).unwrap() else {
+                continue;
+            };
+            let header = engine.seal(header).unwrap();
+            engine.import_block(header.clone()).unwrap();
+            // Step again like the worker does, so the next slot is authored too
+            engine.step(ctx).unwrap();
+
+            let authority_set = engine.authority_set();
+            if authority_set.set_id == client.authority_set().set_id {
+                client.import_header(&header).unwrap();
+            } else {
+                client
+                    .apply_set_change(&AuthoritySetChange {
+                        authority_set,
+                        header: header.clone(),
+                    })
+                    .unwrap();
+                changed = true;
+            }
+            parent = header;
+        }
+
+        // The old set announced its successor before handing over
+        assert!(changed);
+        assert_eq!(client.authority_set().set_id, 1);
+        assert_eq!(client.best().hash, parent.hash().unwrap());
+    }
+}
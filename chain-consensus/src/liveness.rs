@@ -0,0 +1,250 @@
+//! Validator liveness tracking based on per-slot block production
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Outcome of a closed slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotOutcome {
+    /// A valid block was imported for the slot
+    Produced,
+    /// No valid block was imported for the slot
+    Missed,
+}
+
+/// Block production record for a closed slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotRecord {
+    /// Validator expected to propose in this slot
+    pub expected_proposer: usize,
+    /// Whether the slot produced a block
+    pub outcome: SlotOutcome,
+}
+
+/// Uptime of a single validator over a window of slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidatorUptime {
+    /// Validator index
+    pub validator_index: usize,
+    /// Slots assigned to the validator within the window
+    pub assigned_slots: u64,
+    /// Assigned slots in which a block was produced
+    pub produced_blocks: u64,
+}
+
+impl ValidatorUptime {
+    /// Number of assigned slots without a block
+    pub fn missed_slots(&self) -> u64 {
+        self.assigned_slots - self.produced_blocks
+    }
+
+    /// Fraction of assigned slots with a block (1.0 if nothing was assigned)
+    pub fn uptime(&self) -> f64 {
+        if self.assigned_slots == 0 {
+            return 1.0;
+        }
+        self.produced_blocks as f64 / self.assigned_slots as f64
+    }
+}
+
+/// Tracks which slots produced blocks and derives validator uptime
+#[derive(Debug)]
+pub struct LivenessTracker {
+    /// Open slots for which a valid block has been imported
+    produced: BTreeSet<u64>,
+    /// Closed slots and their outcome
+    closed: BTreeMap<u64, SlotRecord>,
+    /// Number of closed slots kept for uptime windows
+    history_slots: u64,
+}
+
+impl LivenessTracker {
+    /// Create a new liveness tracker keeping `history_slots` closed slots
+    pub fn new(history_slots: u64) -> Self {
+        Self {
+            produced: BTreeSet::new(),
+            closed: BTreeMap::new(),
+            history_slots: history_slots.max(1),
+        }
+    }
+
+    /// Record that a valid block was imported for a slot
+    ///
+    /// Returns whether the slot had already been closed as missed, so the
+    /// miss charged to its proposer can be taken back.
+    pub fn record_block(&mut self, slot: u64) -> bool {
+        // A late block still counts towards uptime of an already closed slot
+        if let Some(record) = self.closed.get_mut(&slot) {
+            let reopened = record.outcome == SlotOutcome::Missed;
+            record.outcome = SlotOutcome::Produced;
+            return reopened;
+        }
+        self.produced.insert(slot);
+        false
+    }
+
+    /// Close an elapsed slot and return whether it produced a block
+    pub fn close_slot(&mut self, slot: u64, expected_proposer: usize) -> SlotOutcome {
+        let outcome = if self.produced.remove(&slot) {
+            SlotOutcome::Produced
+        } else {
+            SlotOutcome::Missed
+        };
+
+        self.closed.insert(
+            slot,
+            SlotRecord {
+                expected_proposer,
+                outcome,
+            },
+        );
+        self.prune();
+
+        outcome
+    }
+
+    /// Get the record of a closed slot
+    pub fn slot_record(&self, slot: u64) -> Option<&SlotRecord> {
+        self.closed.get(&slot)
+    }
+
+    /// Get the most recently closed slot
+    pub fn last_closed_slot(&self) -> Option<u64> {
+        self.closed.keys().next_back().copied()
+    }
+
+    /// Get uptime of a validator over the last `window` closed slots
+    pub fn validator_uptime(&self, validator_index: usize, window: u64) -> ValidatorUptime {
+        let mut uptime = ValidatorUptime {
+            validator_index,
+            assigned_slots: 0,
+            produced_blocks: 0,
+        };
+
+        for record in self.window(window) {
+            if record.expected_proposer == validator_index {
+                uptime.assigned_slots += 1;
+                if record.outcome == SlotOutcome::Produced {
+                    uptime.produced_blocks += 1;
+                }
+            }
+        }
+
+        uptime
+    }
+
+    /// Get uptime of every validator assigned a slot in the last `window` closed slots
+    pub fn uptime_report(&self, window: u64) -> Vec<ValidatorUptime> {
+        let mut report: BTreeMap<usize, ValidatorUptime> = BTreeMap::new();
+
+        for record in self.window(window) {
+            let entry = report
+                .entry(record.expected_proposer)
+                .or_insert(ValidatorUptime {
+                    validator_index: record.expected_proposer,
+                    assigned_slots: 0,
+                    produced_blocks: 0,
+                });
+            entry.assigned_slots += 1;
+            if record.outcome == SlotOutcome::Produced {
+                entry.produced_blocks += 1;
+            }
+        }
+
+        report.into_values().collect()
+    }
+
+    /// Forget all recorded slots (e.g. after an authority set change)
+    pub fn clear(&mut self) {
+        self.produced.clear();
+        self.closed.clear();
+    }
+
+    /// Closed slot records within the last `window` slots
+    fn window(&self, window: u64) -> impl Iterator<Item = &SlotRecord> {
+        let start = self
+            .last_closed_slot()
+            .map(|last| (last + 1).saturating_sub(window))
+            .unwrap_or(0);
+        self.closed.range(start..).map(|(_, record)| record)
+    }
+
+    /// Drop records that fall outside the history window
+    fn prune(&mut self) {
+        if let Some(last) = self.last_closed_slot() {
+            let cutoff = (last + 1).saturating_sub(self.history_slots);
+            self.closed = self.closed.split_off(&cutoff);
+            self.produced = self.produced.split_off(&cutoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missed_only_without_block() {
+        let mut tracker = LivenessTracker::new(100);
+
+        assert!(!tracker.record_block(1));
+        assert_eq!(tracker.close_slot(1, 0), SlotOutcome::Produced);
+        assert_eq!(tracker.close_slot(2, 1), SlotOutcome::Missed);
+        assert_eq!(tracker.last_closed_slot(), Some(2));
+    }
+
+    #[test]
+    fn test_late_block_counts_towards_uptime() {
+        let mut tracker = LivenessTracker::new(100);
+
+        assert_eq!(tracker.close_slot(5, 2), SlotOutcome::Missed);
+        assert!(tracker.record_block(5));
+        // Only the first block reopens the slot
+        assert!(!tracker.record_block(5));
+
+        assert_eq!(
+            tracker.slot_record(5).map(|r| r.outcome),
+            Some(SlotOutcome::Produced)
+        );
+    }
+
+    #[test]
+    fn test_uptime_sliding_window() {
+        let mut tracker = LivenessTracker::new(100);
+
+        // Validator 0 produces in even slots, validator 1 never produces
+        for slot in 0..10 {
+            let proposer = (slot % 2) as usize;
+            if proposer == 0 {
+                tracker.record_block(slot);
+            }
+            tracker.close_slot(slot, proposer);
+        }
+
+        let uptime = tracker.validator_uptime(0, 10);
+        assert_eq!(uptime.assigned_slots, 5);
+        assert_eq!(uptime.produced_blocks, 5);
+        assert_eq!(uptime.uptime(), 1.0);
+
+        let uptime = tracker.validator_uptime(1, 4);
+        assert_eq!(uptime.assigned_slots, 2);
+        assert_eq!(uptime.missed_slots(), 2);
+        assert_eq!(uptime.uptime(), 0.0);
+
+        let report = tracker.uptime_report(10);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[1].validator_index, 1);
+    }
+
+    #[test]
+    fn test_history_pruning() {
+        let mut tracker = LivenessTracker::new(3);
+
+        for slot in 0..10 {
+            tracker.close_slot(slot, 0);
+        }
+
+        assert!(tracker.slot_record(6).is_none());
+        assert!(tracker.slot_record(7).is_some());
+        assert_eq!(tracker.validator_uptime(0, 100).assigned_slots, 3);
+    }
+}
@@ -2,16 +2,19 @@
 
 use crate::traits::{AuthoritySet, Validator};
 use crate::{ConsensusError, ConsensusResult};
-use chain_core::Address;
+use chain_core::{Address, Hash, RewardConfig, Signature};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
 use std::time::Duration;
 
+/// Fork parameter overriding the slot duration in milliseconds
+pub const SLOT_DURATION_PARAM: &str = "slot_duration";
+
 /// PoA consensus configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PoAConfig {
-    /// Slot duration in seconds
+    /// Slot duration in milliseconds
     pub slot_duration: u64,
     /// Authority set
     pub authorities: Vec<AuthorityConfig>,
@@ -19,6 +22,9 @@ pub struct PoAConfig {
     pub vrf_seed: [u8; 32],
     /// Epoch length in slots
     pub epoch_length: u64,
+    /// Block rewards, inflation and fee distribution
+    #[serde(default)]
+    pub rewards: RewardConfig,
 }
 
 /// Authority configuration
@@ -33,10 +39,11 @@ pub struct AuthorityConfig {
 impl Default for PoAConfig {
     fn default() -> Self {
         Self {
-            slot_duration: 3, // 3 seconds per slot
+            slot_duration: 3000, // 3 seconds per slot
             authorities: vec![],
             vrf_seed: [0u8; 32],
             epoch_length: 100, // 100 slots per epoch
+            rewards: RewardConfig::default(),
         }
     }
 }
@@ -49,6 +56,7 @@ impl PoAConfig {
             authorities,
             vrf_seed: rand::random(),
             epoch_length: 100,
+            rewards: RewardConfig::default(),
         }
     }
 
@@ -90,6 +98,10 @@ impl PoAConfig {
             ));
         }
 
+        self.rewards
+            .validate()
+            .map_err(|e| ConsensusError::Config(e.to_string()))?;
+
         // Validate authority addresses
         for (i, authority) in self.authorities.iter().enumerate() {
             if authority.address.len() != 42 || !authority.address.starts_with("0x") {
@@ -140,7 +152,7 @@ impl PoAConfig {
 
     /// Get slot duration as Duration
     pub fn slot_duration_as_duration(&self) -> Duration {
-        Duration::from_secs(self.slot_duration)
+        Duration::from_millis(self.slot_duration)
     }
 
     /// Set VRF seed
@@ -154,6 +166,12 @@ impl PoAConfig {
         self.epoch_length = length;
         self
     }
+
+    /// Set the block reward and fee distribution parameters
+    pub fn with_rewards(mut self, rewards: RewardConfig) -> Self {
+        self.rewards = rewards;
+        self
+    }
 }
 
 /// Default authorities configuration for testing
@@ -174,6 +192,28 @@ pub fn default_test_authorities() -> Vec<AuthorityConfig> {
     ]
 }
 
+/// Private key of keyed test authority `index`
+pub fn test_authority_key(index: usize) -> [u8; 32] {
+    [index as u8 + 1; 32]
+}
+
+/// Address of keyed test authority `index`
+pub fn test_authority_address(index: usize) -> Address {
+    Signature::sign_hash(&Hash::zero(), &test_authority_key(index))
+        .and_then(|signature| signature.recover_address(&Hash::zero()))
+        .expect("test authority keys are valid")
+}
+
+/// Test authorities signing with the keys of [`test_authority_key`]
+pub fn keyed_test_authorities(count: usize) -> Vec<AuthorityConfig> {
+    (0..count)
+        .map(|index| AuthorityConfig {
+            address: format!("0x{}", test_authority_address(index).to_hex()),
+            weight: 1,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,7 +222,7 @@ mod tests {
     #[test]
     fn test_default_config() {
         let config = PoAConfig::default();
-        assert_eq!(config.slot_duration, 3);
+        assert_eq!(config.slot_duration, 3000);
         assert!(config.authorities.is_empty());
     }
 
@@ -205,10 +245,11 @@ mod tests {
     #[test]
     fn test_config_serialization() {
         let config = PoAConfig {
-            slot_duration: 5,
+            slot_duration: 5000,
             authorities: default_test_authorities(),
             vrf_seed: [1u8; 32],
             epoch_length: 200,
+            rewards: RewardConfig::default(),
         };
 
         let json = serde_json::to_string(&config).unwrap();
@@ -226,10 +267,11 @@ mod tests {
         let file_path = dir.path().join("authorities.json");
 
         let config = PoAConfig {
-            slot_duration: 5,
+            slot_duration: 5000,
             authorities: default_test_authorities(),
             vrf_seed: [1u8; 32],
             epoch_length: 200,
+            rewards: RewardConfig::default(),
         };
 
         // Save to file
@@ -245,10 +287,11 @@ mod tests {
     #[test]
     fn test_to_authority_set() {
         let config = PoAConfig {
-            slot_duration: 3,
+            slot_duration: 3000,
             authorities: default_test_authorities(),
             vrf_seed: [0u8; 32],
             epoch_length: 100,
+            rewards: RewardConfig::default(),
         };
 
         let authority_set = config.to_authority_set(1).unwrap();
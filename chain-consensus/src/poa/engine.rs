@@ -1,12 +1,15 @@
 //! PoA consensus engine implementation
 
-use crate::poa::{PoAConfig, VrfSelector, VrfSeed};
+use crate::clock::{Clock, SystemClock};
+use crate::election::ElectionProvider;
+use crate::liveness::{LivenessTracker, SlotOutcome};
+use crate::poa::{PoAConfig, VrfSelector, VrfSeed, SLOT_DURATION_PARAM};
 use crate::slashing::{SlashingDetector, SlashingOffence};
+use crate::slots::{self, SlotSchedule};
 use crate::traits::{AuthoritySet, Engine, StepContext, StepResult};
 use crate::{ConsensusError, ConsensusResult};
-use chain_core::{BlockHeader, Hash};
+use chain_core::{BlockHeader, BlockNumber, ForkSchedule, Hash};
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 
@@ -35,10 +38,30 @@ pub struct PoAEngine {
     current_slot: u64,
     /// Slashing detector
     slashing_detector: SlashingDetector,
+    /// Per-slot block production tracker
+    liveness: LivenessTracker,
+    /// First slot observed by the step loop (earlier slots are never charged)
+    liveness_start: Option<u64>,
     /// Local validator index (if this node is a validator)
     local_validator_index: Option<usize>,
-    /// Genesis timestamp
+    /// Local validator address, kept to find our index in later authority sets
+    local_validator_address: Option<chain_core::Address>,
+    /// Private key signing our headers
+    signing_key: Option<Vec<u8>>,
+    /// Source of the next authority set at epoch boundaries
+    election_provider: Option<Arc<dyn ElectionProvider>>,
+    /// Set elected for the next epoch, announced in authored headers
+    next_authority_set: Option<AuthoritySet>,
+    /// Epoch the last election chose a set for
+    elected_epoch: Option<u64>,
+    /// Genesis timestamp in milliseconds
     genesis_timestamp: u64,
+    /// Slot timing, rebased whenever a fork changes the slot duration
+    slot_schedule: SlotSchedule,
+    /// Protocol upgrades by block number
+    fork_schedule: ForkSchedule,
+    /// Time source
+    clock: Arc<dyn Clock>,
     /// Event sender for notifications
     event_sender: Option<mpsc::UnboundedSender<ConsensusEvent>>,
 }
@@ -54,6 +77,8 @@ pub enum ConsensusEvent {
     BlockReceived { header: BlockHeader },
     /// Slashing offence detected
     SlashingDetected { offence: SlashingOffence },
+    /// A new authority set took effect
+    AuthoritySetChanged { epoch: u64, set_id: u64 },
 }
 
 impl PoAEngine {
@@ -85,49 +110,169 @@ impl PoAEngine {
         }
 
         Ok(Self {
+            slot_schedule: SlotSchedule::new(genesis_timestamp, config.slot_duration),
             config,
             authority_set,
             vrf_selector,
             state: PoAState::Waiting,
             current_slot: 0,
             slashing_detector: SlashingDetector::new(10), // Allow 10 missed slots
+            liveness: LivenessTracker::new(1000),         // Keep 1000 slots for uptime windows
+            liveness_start: None,
             local_validator_index,
+            local_validator_address,
+            signing_key: None,
+            election_provider: None,
+            next_authority_set: None,
+            elected_epoch: None,
             genesis_timestamp,
+            fork_schedule: ForkSchedule::default(),
+            clock: Arc::new(SystemClock),
             event_sender: None,
         })
     }
 
+    /// Sign authored headers with the local validator's private key
+    pub fn with_signing_key(mut self, private_key: &[u8]) -> Self {
+        self.signing_key = Some(private_key.to_vec());
+        self
+    }
+
+    /// Use a custom time source instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Elect the authority set at every epoch boundary instead of keeping the configured one
+    ///
+    /// Each set is elected one epoch ahead from the state after the block at
+    /// every multiple of the epoch length, so headers of the outgoing set can
+    /// announce it.
+    pub fn with_election_provider(mut self, provider: Arc<dyn ElectionProvider>) -> Self {
+        self.election_provider = Some(provider);
+        self
+    }
+
+    /// Follow a fork schedule from the current best block
+    ///
+    /// Parameters of the forks activated up to the block after `best_number`
+    /// apply right away. Slot duration changes are replayed using
+    /// `timestamp_at`, the timestamp of the canonical block at a height, so
+    /// earlier blocks keep verifying against the timing they were produced with.
+    pub fn with_fork_schedule(
+        mut self,
+        fork_schedule: ForkSchedule,
+        best_number: BlockNumber,
+        timestamp_at: impl Fn(BlockNumber) -> Option<u64>,
+    ) -> ConsensusResult<Self> {
+        if let Some(slot_duration) = fork_schedule.param(SLOT_DURATION_PARAM, 0) {
+            self.config.slot_duration = slot_duration;
+            self.slot_schedule = SlotSchedule::new(self.genesis_timestamp, slot_duration);
+        }
+
+        let activated: Vec<BlockNumber> = fork_schedule
+            .forks()
+            .iter()
+            .map(|fork| fork.block)
+            .filter(|&block| block > 0 && block <= best_number + 1)
+            .collect();
+        self.fork_schedule = fork_schedule;
+
+        for block in activated {
+            let parent_timestamp = timestamp_at(block - 1).ok_or_else(|| {
+                ConsensusError::Config(format!(
+                    "Block #{} is needed to replay the fork at block {}",
+                    block - 1,
+                    block
+                ))
+            })?;
+            let slot = self.current_slot_from_timestamp(parent_timestamp);
+            self.apply_forks(block, slot);
+        }
+
+        Ok(self)
+    }
+
     /// Set event sender for notifications
     pub fn set_event_sender(&mut self, sender: mpsc::UnboundedSender<ConsensusEvent>) {
         self.event_sender = Some(sender);
     }
 
-    /// Get current slot from timestamp
+    /// Get the current time in milliseconds from the engine clock
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Get the slot schedule for the current configuration
+    pub fn slot_schedule(&self) -> SlotSchedule {
+        self.slot_schedule.clone()
+    }
+
+    /// Get the fork schedule
+    pub fn fork_schedule(&self) -> &ForkSchedule {
+        &self.fork_schedule
+    }
+
+    /// Get current slot from timestamp (milliseconds)
     pub fn current_slot_from_timestamp(&self, timestamp: u64) -> u64 {
-        if timestamp < self.genesis_timestamp {
-            return 0;
-        }
-        (timestamp - self.genesis_timestamp) / self.config.slot_duration
+        self.slot_schedule.slot_at(timestamp)
     }
 
-    /// Get timestamp for a slot
+    /// Get timestamp (milliseconds) for the start of a slot
     pub fn slot_timestamp(&self, slot: u64) -> u64 {
-        self.genesis_timestamp + slot * self.config.slot_duration
+        self.slot_schedule.slot_start(slot)
     }
 
     /// Check if we are the proposer for a given slot
     pub fn is_proposer_for_slot(&self, slot: u64) -> bool {
-        if let Some(local_index) = self.local_validator_index {
-            let expected_proposer = self.vrf_selector.select_validator(slot);
-            expected_proposer == local_index
-        } else {
-            false
-        }
+        self.local_index_for_slot(slot)
+            .is_some_and(|local_index| self.get_proposer_for_slot(slot) == local_index)
+    }
+
+    /// Our index in the authority set of a slot's epoch, if we are in it
+    fn local_index_for_slot(&self, slot: u64) -> Option<usize> {
+        let (authority_set, _) = self.sets_for_epoch(slot / self.config.epoch_length);
+        self.local_validator_address
+            .and_then(|addr| authority_set.get_validator_index(&addr))
     }
 
     /// Get the expected proposer for a slot
     pub fn get_proposer_for_slot(&self, slot: u64) -> usize {
-        self.vrf_selector.select_validator(slot)
+        let epoch = slot / self.config.epoch_length;
+        if epoch <= self.authority_set.read().unwrap().epoch {
+            return self.vrf_selector.select_validator(slot);
+        }
+        let (authority_set, _) = self.sets_for_epoch(epoch);
+        self.selector_for(&authority_set).select_validator(slot)
+    }
+
+    /// Authority set in force for blocks of `epoch` and the successor their
+    /// headers announce
+    ///
+    /// Blocks past the current set's epoch belong to the announced successor,
+    /// which takes over once the first of them is imported. Without one, the
+    /// current set carries on.
+    fn sets_for_epoch(&self, epoch: u64) -> (AuthoritySet, Option<AuthoritySet>) {
+        let current = self.authority_set.read().unwrap();
+        if epoch <= current.epoch {
+            return (current.clone(), self.next_authority_set.clone());
+        }
+
+        let mut authority_set = self
+            .next_authority_set
+            .clone()
+            .unwrap_or_else(|| current.clone());
+        authority_set.epoch = epoch;
+        (authority_set, None)
+    }
+
+    /// Proposer selection over an authority set
+    fn selector_for(&self, authority_set: &AuthoritySet) -> VrfSelector {
+        VrfSelector::new(
+            VrfSeed::from_bytes(self.config.vrf_seed),
+            authority_set.len(),
+        )
     }
 
     /// Update authority set (hot-swappable)
@@ -147,25 +292,159 @@ impl PoAEngine {
         );
 
         // Update local validator index
-        if let Some(local_addr) = self.get_local_validator_address() {
+        if let Some(local_addr) = self.local_validator_address {
             self.local_validator_index = new_authority_set.get_validator_index(&local_addr);
         }
 
-        // Update authority set
+        // Validator indices change with the set, so old production records no longer apply
+        self.liveness.clear();
+
+        // A new slot duration applies from the next slot
+        if new_config.slot_duration != self.config.slot_duration {
+            self.slot_schedule = self
+                .slot_schedule
+                .rebase(self.current_slot + 1, new_config.slot_duration);
+        }
+
+        // Update authority set, electing its successor again
         *self.authority_set.write().unwrap() = new_authority_set;
         self.config = new_config;
+        self.next_authority_set = None;
+        self.elected_epoch = None;
 
         Ok(())
     }
 
-    /// Get local validator address if this node is a validator
-    fn get_local_validator_address(&self) -> Option<chain_core::Address> {
-        if let Some(index) = self.local_validator_index {
-            self.authority_set.read().unwrap()
-                .get_validator(index)
-                .map(|v| v.address)
-        } else {
-            None
+    /// Get the current authority set
+    pub fn authority_set(&self) -> AuthoritySet {
+        self.authority_set.read().unwrap().clone()
+    }
+
+    /// Get the authority set elected for the next epoch, if any
+    pub fn next_authority_set(&self) -> Option<AuthoritySet> {
+        self.next_authority_set.clone()
+    }
+
+    /// Elect the authority set for `epoch` from the state after `block`
+    ///
+    /// An empty election result elects nothing, so the current set is kept
+    /// and the chain cannot halt.
+    fn elect(&self, epoch: u64, block: &Hash) -> ConsensusResult<Option<AuthoritySet>> {
+        let Some(provider) = self.election_provider.clone() else {
+            return Ok(None);
+        };
+
+        let validators = provider.elect(epoch, block)?;
+        if validators.is_empty() {
+            warn!("Election for epoch {} returned no validators, keeping current set", epoch);
+            return Ok(None);
+        }
+
+        if validators.iter().any(|v| v.weight == 0) {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Elected validator for epoch {} has no backing stake",
+                epoch
+            )));
+        }
+
+        let mut authority_set = AuthoritySet::new(validators, epoch);
+        authority_set.set_id = self.authority_set.read().unwrap().set_id + 1;
+        Ok(Some(authority_set))
+    }
+
+    /// Elect the set following `epoch` after an election block, unless already done
+    ///
+    /// When blocks are missed, several election heights can fall in one epoch;
+    /// the first one elects.
+    fn elect_next_authorities(&mut self, epoch: u64, block: &Hash) -> ConsensusResult<()> {
+        if self.elected_epoch.is_some_and(|elected| elected > epoch) {
+            return Ok(());
+        }
+
+        self.next_authority_set = self.elect(epoch + 1, block)?;
+        self.elected_epoch = Some(epoch + 1);
+        if let Some(next) = &self.next_authority_set {
+            info!(
+                "Elected {} validators for epoch {} (set {})",
+                next.len(),
+                next.epoch,
+                next.set_id
+            );
+        }
+        Ok(())
+    }
+
+    /// Hand over to the set announced for `epoch` when importing its first block
+    ///
+    /// Without an announced set, because the chain has no election block in
+    /// the previous epoch, the current set carries on.
+    fn rotate_authorities(&mut self, epoch: u64) -> ConsensusResult<()> {
+        let Some(mut new_authority_set) = self.next_authority_set.take() else {
+            self.authority_set.write().unwrap().epoch = epoch;
+            return Ok(());
+        };
+        new_authority_set.epoch = epoch;
+
+        self.vrf_selector = self.selector_for(&new_authority_set);
+        self.local_validator_index = self
+            .local_validator_address
+            .and_then(|addr| new_authority_set.get_validator_index(&addr));
+
+        // Validator indices change with the set, so old production records no longer apply
+        self.liveness.clear();
+
+        let set_id = new_authority_set.set_id;
+        info!("Authority set {} takes over in epoch {}", set_id, epoch);
+        *self.authority_set.write().unwrap() = new_authority_set;
+
+        self.send_event(ConsensusEvent::AuthoritySetChanged { epoch, set_id });
+        Ok(())
+    }
+
+    /// Get the liveness tracker
+    pub fn liveness(&self) -> &LivenessTracker {
+        &self.liveness
+    }
+
+    /// Close elapsed slots and charge their proposers if no block was produced
+    fn close_elapsed_slots(&mut self, from_slot: u64, to_slot: u64) {
+        for slot in from_slot..to_slot {
+            let expected_proposer = self.get_proposer_for_slot(slot);
+
+            match self.liveness.close_slot(slot, expected_proposer) {
+                SlotOutcome::Produced => {
+                    self.slashing_detector.reset_missed_slots(expected_proposer);
+                }
+                SlotOutcome::Missed => {
+                    debug!("Validator {} missed slot {}", expected_proposer, slot);
+                    if let Some(offence) =
+                        self.slashing_detector.record_missed_slot(expected_proposer)
+                    {
+                        warn!("Validator {} missed too many slots", expected_proposer);
+                        self.send_event(ConsensusEvent::SlashingDetected { offence });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply consensus parameters of forks activating at `next_block`
+    ///
+    /// `slot` is the slot of the block before it; a new slot duration applies
+    /// from the following slot so the parent keeps its original timing.
+    fn apply_forks(&mut self, next_block: u64, slot: u64) {
+        if let Some(slot_duration) = self
+            .fork_schedule
+            .param_change_at(SLOT_DURATION_PARAM, next_block)
+        {
+            info!(
+                "Fork at block {} changes slot duration to {}ms from slot {}",
+                next_block,
+                slot_duration,
+                slot + 1
+            );
+            self.slot_schedule = self.slot_schedule.rebase(slot + 1, slot_duration);
+            self.config.slot_duration = slot_duration;
         }
     }
 
@@ -185,8 +464,14 @@ impl PoAEngine {
         // Verify the block
         self.verify_block(&header)?;
 
-        // Check for slashing
+        // The first block of a new epoch hands over to the set announced for it
         let proposer_slot = self.current_slot_from_timestamp(header.timestamp);
+        let epoch = proposer_slot / self.config.epoch_length;
+        if epoch > self.authority_set.read().unwrap().epoch {
+            self.rotate_authorities(epoch)?;
+        }
+
+        // Check for slashing
         let expected_proposer = self.get_proposer_for_slot(proposer_slot);
 
         if let Some(offence) = self.slashing_detector.record_signature(expected_proposer, header.clone())? {
@@ -194,41 +479,192 @@ impl PoAEngine {
             self.send_event(ConsensusEvent::SlashingDetected { offence });
         }
 
-        // Reset missed slots for the proposer
-        self.slashing_detector.reset_missed_slots(expected_proposer);
+        // Mark the slot as produced for liveness tracking, taking back the
+        // miss charged if the block arrived after its slot was closed
+        if self.liveness.record_block(proposer_slot) {
+            self.slashing_detector.undo_missed_slot(expected_proposer);
+        }
+
+        // Elect the next set from the state after an election block
+        if header.number.is_multiple_of(self.config.epoch_length) {
+            let hash = header.hash().map_err(|e| {
+                ConsensusError::InvalidBlock(format!("Failed to hash block: {}", e))
+            })?;
+            self.elect_next_authorities(epoch, &hash)?;
+        }
+
+        // Switch parameters before the next block is verified
+        self.apply_forks(header.number + 1, proposer_slot);
 
         self.send_event(ConsensusEvent::BlockReceived { header });
         Ok(())
     }
+
+    /// Take over the authority sets in force at a block whose ancestors were
+    /// not imported, such as a warp sync pivot
+    ///
+    /// Headers only carry commitments to the sets, so the election is run on
+    /// the pivot's state. Unless the pivot's set is still the configured one,
+    /// the winners must be the set it commits to, and the set it announces if
+    /// any. Otherwise the staking records changed since those elections and
+    /// the sets cannot be rebuilt from the pivot.
+    pub fn process_pivot(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        let slot = self.current_slot_from_timestamp(header.timestamp);
+        let epoch = slot / self.config.epoch_length;
+        let hash = header
+            .hash()
+            .map_err(|e| ConsensusError::InvalidBlock(format!("Failed to hash block: {}", e)))?;
+        let committed = set_commitment(&header).ok_or_else(|| {
+            ConsensusError::AuthoritySet(format!(
+                "Pivot #{} does not commit to an authority set",
+                header.number
+            ))
+        })?;
+        let elected = self.elect(epoch + 1, &hash)?;
+        let with_set_id = |set: &AuthoritySet, set_id| AuthoritySet {
+            set_id,
+            ..set.clone()
+        };
+
+        // Each election block up to the pivot may have elected a set
+        let configured = self.config.to_authority_set(epoch)?;
+        let current = if configured.commitment() == committed {
+            configured
+        } else {
+            elected
+                .as_ref()
+                .and_then(|elected| {
+                    (1..=header.number / self.config.epoch_length)
+                        .map(|set_id| with_set_id(elected, set_id))
+                        .find(|set| set.commitment() == committed)
+                })
+                .map(|set| AuthoritySet { epoch, ..set })
+                .ok_or_else(|| {
+                    ConsensusError::AuthoritySet(format!(
+                        "Pivot #{} commits to a set not elected by its state",
+                        header.number
+                    ))
+                })?
+        };
+        let next = match next_set_commitment(&header) {
+            Some(announced) => Some(
+                elected
+                    .map(|elected| with_set_id(&elected, current.set_id + 1))
+                    .filter(|set| set.commitment() == announced)
+                    .ok_or_else(|| {
+                        ConsensusError::AuthoritySet(format!(
+                            "Pivot #{} announces a set not elected by its state",
+                            header.number
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        verify_header(
+            &self.slot_schedule,
+            &self.selector_for(&current),
+            &current,
+            &header,
+            self.now(),
+        )?;
+
+        info!(
+            "Authority set {} in force at pivot #{} in epoch {}",
+            current.set_id, header.number, epoch
+        );
+        self.vrf_selector = self.selector_for(&current);
+        self.local_validator_index = self
+            .local_validator_address
+            .and_then(|addr| current.get_validator_index(&addr));
+        self.liveness.clear();
+        *self.authority_set.write().unwrap() = current;
+        self.elected_epoch = next.as_ref().map(|next| next.epoch);
+        self.next_authority_set = next;
+
+        // The pivot is imported like any other block from here on
+        if header.number.is_multiple_of(self.config.epoch_length) {
+            self.elect_next_authorities(epoch, &hash)?;
+        }
+        self.apply_forks(header.number + 1, slot);
+        Ok(())
+    }
+}
+
+/// Check a PoA header's slot timing and seal and return its slot and expected proposer
+///
+/// Shared by the engine and the light client so both accept the same headers.
+pub(crate) fn verify_header(
+    schedule: &SlotSchedule,
+    selector: &VrfSelector,
+    authority_set: &AuthoritySet,
+    header: &BlockHeader,
+    now: u64,
+) -> ConsensusResult<(u64, usize)> {
+    // Check the timestamp is reasonable and fits its slot
+    let slot = schedule.verify_timestamp(header.timestamp, now)?;
+
+    // Only the validator selected for the slot may author its block
+    let expected_proposer = selector.select_validator(slot);
+    let proposer = authority_set
+        .get_validator(expected_proposer)
+        .ok_or_else(|| {
+            ConsensusError::AuthoritySet(format!("No validator #{}", expected_proposer))
+        })?;
+    if slots::header_author(header)? != proposer.address {
+        return Err(ConsensusError::NotAuthorized { slot });
+    }
+
+    Ok((slot, expected_proposer))
+}
+
+/// Extra data committing to an authority set and, once elected, the set
+/// taking over from it
+pub(crate) fn set_commitments(current: &AuthoritySet, next: Option<&AuthoritySet>) -> Vec<u8> {
+    let mut extra_data = current.commitment().as_bytes().to_vec();
+    if let Some(next) = next {
+        extra_data.extend_from_slice(next.commitment().as_bytes());
+    }
+    extra_data
+}
+
+/// Extra data of a sealed header without the author's signature
+fn unsealed_extra_data(header: &BlockHeader) -> &[u8] {
+    let len = header.extra_data.len().saturating_sub(slots::SEAL_LENGTH);
+    &header.extra_data[..len]
+}
+
+/// Authority set commitment carried in a sealed header's extra data, if any
+pub(crate) fn set_commitment(header: &BlockHeader) -> Option<Hash> {
+    let extra_data = unsealed_extra_data(header);
+    matches!(extra_data.len(), 32 | 64).then(|| Hash::from_slice(&extra_data[..32]))
+}
+
+/// Commitment to the next authority set announced in a sealed header's extra data, if any
+pub(crate) fn next_set_commitment(header: &BlockHeader) -> Option<Hash> {
+    let extra_data = unsealed_extra_data(header);
+    (extra_data.len() == 64).then(|| Hash::from_slice(&extra_data[32..]))
 }
 
 impl Engine for PoAEngine {
     fn step(&mut self, ctx: StepContext) -> ConsensusResult<StepResult> {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let now = self.now();
 
         let current_slot = self.current_slot_from_timestamp(now);
+        let liveness_start = *self.liveness_start.get_or_insert(current_slot);
         
         // Update current slot
         if current_slot > self.current_slot {
+            let previous_slot = self.current_slot;
             self.current_slot = current_slot;
             debug!("Advanced to slot {}", current_slot);
 
-            // Check for missed slots by validators
-            for slot in (self.current_slot - 1)..current_slot {
-                let expected_proposer = self.get_proposer_for_slot(slot);
-                if let Some(offence) = self.slashing_detector.record_missed_slot(expected_proposer) {
-                    warn!("Validator {} missed too many slots", expected_proposer);
-                    self.send_event(ConsensusEvent::SlashingDetected { offence });
-                }
-            }
+            // Check elapsed slots for missing blocks
+            self.close_elapsed_slots(previous_slot.max(liveness_start), current_slot);
 
             self.send_event(ConsensusEvent::SlotStarted {
                 slot: current_slot,
                 validator: if self.is_proposer_for_slot(current_slot) {
-                    self.local_validator_index
+                    self.local_index_for_slot(current_slot)
                 } else {
                     None
                 },
@@ -240,20 +676,14 @@ impl Engine for PoAEngine {
             self.state = PoAState::Proposing;
             self.send_event(ConsensusEvent::ShouldPropose { slot: current_slot });
 
-            // Create block header
-            let header = BlockHeader {
-                parent_hash: ctx.parent_hash,
-                number: ctx.block_number,
-                state_root: Hash::zero(), // Will be filled by state execution
-                transactions_root: Hash::zero(),    // Will be filled by transaction processing
-                receipts_root: Hash::zero(), // Will be filled by receipt processing
-                gas_limit: 1000000, // Default gas limit
-                gas_used: 0, // Will be filled after execution
-                difficulty: 1, // PoA doesn't use difficulty
-                timestamp: now,
-                extra_data: vec![], // Could include VRF proof
-                nonce: current_slot, // Use slot as nonce
-            };
+            // Create block header stamped with the slot start, using the slot as
+            // nonce and committing to the slot's authority set and its elected
+            // successor
+            let timestamp = self.slot_schedule.slot_start(current_slot);
+            let mut header = slots::build_header(&ctx, timestamp, current_slot);
+            let (authority_set, next) =
+                self.sets_for_epoch(current_slot / self.config.epoch_length);
+            header.extra_data = set_commitments(&authority_set, next.as_ref());
 
             return Ok(StepResult::Propose {
                 header,
@@ -262,9 +692,7 @@ impl Engine for PoAEngine {
         }
 
         // Calculate time to next slot
-        let next_slot = current_slot + 1;
-        let next_slot_time = self.slot_timestamp(next_slot);
-        let timeout = Duration::from_secs(next_slot_time.saturating_sub(now).max(1));
+        let timeout = self.slot_schedule.time_until_next_slot(now);
 
         match self.state {
             PoAState::Waiting => Ok(StepResult::Continue { timeout }),
@@ -279,41 +707,25 @@ impl Engine for PoAEngine {
     fn verify_block(&self, header: &BlockHeader) -> ConsensusResult<()> {
         debug!("Verifying block #{}", header.number);
 
-        // Check timestamp is reasonable
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        if header.timestamp > now + self.config.slot_duration {
-            return Err(ConsensusError::InvalidTimestamp {
-                expected: now,
-                actual: header.timestamp,
-            });
-        }
-
-        if header.timestamp < self.genesis_timestamp {
-            return Err(ConsensusError::InvalidTimestamp {
-                expected: self.genesis_timestamp,
-                actual: header.timestamp,
-            });
-        }
-
-        // Verify the proposer
+        // The header's slot picks the set it is verified against
         let slot = self.current_slot_from_timestamp(header.timestamp);
-        let expected_proposer = self.get_proposer_for_slot(slot);
-        
-        // In a real implementation, we would verify the signature/VRF proof here
-        // For now, we just check the slot timing
-        let expected_timestamp = self.slot_timestamp(slot);
-        let tolerance = self.config.slot_duration / 2; // Allow some clock drift
-
-        if header.timestamp < expected_timestamp.saturating_sub(tolerance) ||
-           header.timestamp > expected_timestamp + tolerance {
-            return Err(ConsensusError::InvalidTimestamp {
-                expected: expected_timestamp,
-                actual: header.timestamp,
-            });
+        let (authority_set, next) = self.sets_for_epoch(slot / self.config.epoch_length);
+        let (_, expected_proposer) = verify_header(
+            &self.slot_schedule,
+            &self.selector_for(&authority_set),
+            &authority_set,
+            header,
+            self.now(),
+        )?;
+
+        // Headers must commit to their set and its elected successor, so
+        // light clients can follow set changes
+        let expected = set_commitments(&authority_set, next.as_ref());
+        if unsealed_extra_data(header) != expected.as_slice() {
+            return Err(ConsensusError::AuthoritySet(format!(
+                "Block #{} does not commit to the current and next authority sets",
+                header.number
+            )));
         }
 
         debug!("Block #{} verified successfully (proposer: {})", header.number, expected_proposer);
@@ -325,43 +737,108 @@ impl Engine for PoAEngine {
     }
 
     fn should_propose(&self, _ctx: &StepContext) -> bool {
-        if self.local_validator_index.is_none() {
-            return false;
-        }
-
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        let current_slot = self.current_slot_from_timestamp(now);
+        let current_slot = self.current_slot_from_timestamp(self.now());
         self.is_proposer_for_slot(current_slot) && self.state == PoAState::Waiting
     }
 
     fn expected_proposer(&self, slot: u64) -> Option<usize> {
         Some(self.get_proposer_for_slot(slot))
     }
+
+    fn seal(&self, header: BlockHeader) -> ConsensusResult<BlockHeader> {
+        let key = self.signing_key.as_ref().ok_or_else(|| {
+            ConsensusError::Config("PoA authoring needs a signing key".to_string())
+        })?;
+        slots::sign_header(header, key)
+    }
+
+    fn import_block(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        self.process_block(header)
+    }
+
+    fn import_pivot(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        self.process_pivot(header)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::poa::config::default_test_authorities;
-
-    fn create_test_engine() -> PoAEngine {
+    use crate::clock::MockClock;
+    use crate::election::StaticElection;
+    use crate::traits::Validator;
+    use crate::poa::config::{keyed_test_authorities, test_authority_address, test_authority_key};
+    use chain_core::{Fork, Hash};
+    use std::time::Duration;
+
+    const GENESIS_TIME: u64 = 1_700_000_000_000;
+
+    fn create_test_engine_with_clock(
+        local_validator: Option<chain_core::Address>,
+    ) -> (PoAEngine, MockClock) {
         let config = PoAConfig {
-            slot_duration: 3,
-            authorities: default_test_authorities(),
+            slot_duration: 3000,
+            authorities: keyed_test_authorities(3),
             vrf_seed: [1u8; 32],
             epoch_length: 100,
+            rewards: Default::default(),
         };
 
-        let genesis_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
+        let clock = MockClock::new(GENESIS_TIME);
+        let engine = PoAEngine::new(config, local_validator, GENESIS_TIME)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+
+        (engine, clock)
+    }
+
+    fn create_test_engine() -> PoAEngine {
+        create_test_engine_with_clock(None).0
+    }
+
+    /// Header for `slot` sealed by the slot's proposer, committing to the
+    /// engine's sets for the slot's epoch
+    fn sealed_header(engine: &PoAEngine, number: u64, slot: u64) -> BlockHeader {
+        let (authority_set, next) = engine.sets_for_epoch(slot / engine.config.epoch_length);
+        let header = BlockHeader {
+            number,
+            timestamp: engine.slot_timestamp(slot),
+            extra_data: set_commitments(&authority_set, next.as_ref()),
+            ..BlockHeader::genesis()
+        };
+        let proposer = authority_set
+            .get_validator(engine.get_proposer_for_slot(slot))
             .unwrap()
-            .as_secs();
+            .address;
+        let key = (0..8)
+            .find(|index| test_authority_address(*index) == proposer)
+            .map(test_authority_key)
+            .unwrap();
+        slots::sign_header(header, &key).unwrap()
+    }
 
-        PoAEngine::new(config, None, genesis_time).unwrap()
+    /// Validators with keys, outside the configured authority set
+    fn elected_validators() -> Vec<Validator> {
+        vec![
+            Validator {
+                address: test_authority_address(3),
+                weight: 500,
+            },
+            Validator {
+                address: test_authority_address(4),
+                weight: 300,
+            },
+        ]
+    }
+
+    fn test_context(block_number: u64) -> StepContext {
+        StepContext {
+            block_number,
+            parent_hash: Hash::zero(),
+            timestamp: 0,
+            validator_index: None,
+            pending_transactions: 0,
+        }
     }
 
     #[test]
@@ -378,8 +855,9 @@ mod tests {
         let genesis = engine.genesis_timestamp;
 
         assert_eq!(engine.current_slot_from_timestamp(genesis), 0);
-        assert_eq!(engine.current_slot_from_timestamp(genesis + 3), 1);
-        assert_eq!(engine.current_slot_from_timestamp(genesis + 6), 2);
+        assert_eq!(engine.current_slot_from_timestamp(genesis + 2999), 0);
+        assert_eq!(engine.current_slot_from_timestamp(genesis + 3000), 1);
+        assert_eq!(engine.current_slot_from_timestamp(genesis + 6000), 2);
     }
 
     #[test]
@@ -398,36 +876,141 @@ mod tests {
     #[test]
     fn test_block_verification() {
         let engine = create_test_engine();
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        let header = BlockHeader {
-            parent_hash: Hash::zero(),
-            number: 1,
-            state_root: Hash::zero(),            transactions_root: Hash::zero(),
-            receipts_root: Hash::zero(),
-            gas_limit: 1000000,
-            gas_used: 0,
-            difficulty: 1,
-            timestamp: now,
-            extra_data: vec![],
-            nonce: 0,
-        };
+        let header = sealed_header(&engine, 1, 0);
 
         // Should verify successfully
         assert!(engine.verify_block(&header).is_ok());
 
         // Future timestamp should fail
-        let mut future_header = header.clone();
-        future_header.timestamp = now + 3600; // 1 hour in future
+        let future_header = sealed_header(&engine, 1, 1_200); // 1 hour in future
         assert!(engine.verify_block(&future_header).is_err());
 
         // Past timestamp should fail
         let mut past_header = header.clone();
         past_header.timestamp = engine.genesis_timestamp - 1;
         assert!(engine.verify_block(&past_header).is_err());
+
+        // Headers must commit to the authority set
+        let unsealed = BlockHeader {
+            extra_data: Vec::new(),
+            ..sealed_header(&engine, 1, 0)
+        };
+        let uncommitted = slots::sign_header(
+            unsealed,
+            &test_authority_key(engine.get_proposer_for_slot(0)),
+        )
+        .unwrap();
+        assert!(matches!(
+            engine.verify_block(&uncommitted),
+            Err(ConsensusError::AuthoritySet(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_forged_headers() {
+        let engine = create_test_engine();
+        let proposer = engine.get_proposer_for_slot(0);
+        let header = BlockHeader {
+            number: 1,
+            timestamp: engine.slot_timestamp(0),
+            ..BlockHeader::genesis()
+        };
+
+        // Unsigned headers have no author
+        assert!(engine.verify_block(&header).is_err());
+
+        // Another authority cannot author the slot's block
+        let other = test_authority_key((proposer + 1) % 3);
+        let forged = slots::sign_header(header.clone(), &other).unwrap();
+        assert!(matches!(
+            engine.verify_block(&forged),
+            Err(ConsensusError::NotAuthorized { slot: 0 })
+        ));
+
+        // Nor can a key outside the authority set
+        let outsider = slots::sign_header(header, &test_authority_key(7)).unwrap();
+        assert!(matches!(
+            engine.verify_block(&outsider),
+            Err(ConsensusError::NotAuthorized { slot: 0 })
+        ));
+
+        // Changing a sealed header invalidates the seal
+        let mut tampered = sealed_header(&engine, 1, 0);
+        tampered.state_root = Hash::from_slice(&[1u8; 32]);
+        assert!(engine.verify_block(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_missed_slots_only_without_block() {
+        let mut engine = create_test_engine();
+        let genesis = engine.genesis_timestamp;
+
+        let header = sealed_header(&engine, 1, 0);
+        assert_eq!(header.timestamp, genesis);
+        engine.process_block(header).unwrap();
+
+        // Slot 0 produced a block, slot 1 did not
+        engine.close_elapsed_slots(0, 2);
+
+        let liveness = engine.liveness();
+        assert_eq!(liveness.slot_record(0).unwrap().outcome, SlotOutcome::Produced);
+        assert_eq!(liveness.slot_record(1).unwrap().outcome, SlotOutcome::Missed);
+
+        let missed_proposer = engine.get_proposer_for_slot(1);
+        assert_eq!(engine.slashing_detector.get_missed_slots(missed_proposer), 1);
+
+        // A late block for the closed slot takes the miss back
+        let late = sealed_header(&engine, 2, 1);
+        engine.process_block(late).unwrap();
+        assert_eq!(
+            engine.liveness().slot_record(1).unwrap().outcome,
+            SlotOutcome::Produced
+        );
+        assert_eq!(
+            engine.slashing_detector.get_missed_slots(missed_proposer),
+            0
+        );
+    }
+
+    #[test]
+    fn test_step_with_mock_clock() {
+        let (engine, clock) = create_test_engine_with_clock(Some(test_authority_address(0)));
+        let mut engine = engine.with_signing_key(&test_authority_key(0));
+        assert_eq!(engine.local_validator_index, Some(0));
+
+        // First slot after genesis in which we are the proposer
+        let slot = (1..100).find(|s| engine.get_proposer_for_slot(*s) == 0).unwrap();
+
+        // Start tracking at genesis
+        engine.step(test_context(1)).unwrap();
+
+        // Just before our slot we only wait until it starts
+        clock.set(engine.slot_timestamp(slot) - 1);
+        match engine.step(test_context(1)).unwrap() {
+            StepResult::Continue { timeout } | StepResult::Wait { timeout } => {
+                assert_eq!(timeout, Duration::from_millis(1))
+            }
+            other => panic!("Expected to wait, got {:?}", other),
+        }
+
+        // Stepping late into the slot still stamps the slot start
+        clock.advance(Duration::from_millis(1_001));
+        match engine.step(test_context(1)).unwrap() {
+            StepResult::Propose { header, .. } => {
+                assert_eq!(header.timestamp, engine.slot_timestamp(slot));
+                assert_eq!(header.nonce, slot);
+                let sealed = engine.seal(header).unwrap();
+                assert!(engine.verify_block(&sealed).is_ok());
+            }
+            other => panic!("Expected Propose, got {:?}", other),
+        }
+        assert_eq!(engine.current_round(), slot);
+
+        // No blocks were imported, so every elapsed slot is a miss
+        for elapsed in 0..slot {
+            let record = engine.liveness().slot_record(elapsed).unwrap();
+            assert_eq!(record.outcome, SlotOutcome::Missed);
+        }
     }
 
     #[test]
@@ -449,4 +1032,203 @@ mod tests {
         let new_count = engine.authority_set.read().unwrap().len();
         assert_eq!(new_count, original_count + 1);
     }
+
+    #[test]
+    fn test_elected_authorities_at_epoch_boundary() {
+        let local = test_authority_address(3);
+        let elected = elected_validators();
+
+        let (engine, clock) = create_test_engine_with_clock(Some(local));
+        let mut engine =
+            engine.with_election_provider(Arc::new(StaticElection::new(elected.clone())));
+        assert_eq!(engine.local_validator_index, None);
+
+        // Stepping through the epoch elects nothing; only blocks at election
+        // heights do
+        engine.step(test_context(1)).unwrap();
+        clock.set(engine.slot_timestamp(98));
+        engine.step(test_context(99)).unwrap();
+        let header = sealed_header(&engine, 99, 98);
+        engine.process_block(header).unwrap();
+        assert!(engine.next_authority_set().is_none());
+
+        // Block #100 elects the successor of the configured set from its state
+        clock.set(engine.slot_timestamp(99));
+        let header = sealed_header(&engine, 100, 99);
+        let uncommitted = header.clone();
+        engine.process_block(header).unwrap();
+        let next = engine.next_authority_set().unwrap();
+        assert_eq!((next.epoch, next.set_id), (1, 1));
+        assert_eq!(next.validators, elected);
+        assert_eq!(engine.authority_set().epoch, 0);
+
+        // From then on headers must announce the elected set
+        assert!(matches!(
+            engine.verify_block(&uncommitted),
+            Err(ConsensusError::AuthoritySet(_))
+        ));
+        let announcing = sealed_header(&engine, 101, 99);
+        assert!(engine.verify_block(&announcing).is_ok());
+
+        // Entering epoch 1 by the clock only changes who proposes
+        clock.set(engine.slot_timestamp(100));
+        engine.step(test_context(102)).unwrap();
+        assert_eq!(engine.authority_set().epoch, 0);
+        assert!(engine.get_proposer_for_slot(100) < 2);
+
+        // The first block of epoch 1 hands over to the elected validators
+        let header = sealed_header(&engine, 102, 100);
+        engine.process_block(header).unwrap();
+
+        let authority_set = engine.authority_set();
+        assert_eq!(authority_set.epoch, 1);
+        assert_eq!(authority_set.set_id, 1);
+        assert_eq!(authority_set.validators, elected);
+        assert_eq!(engine.local_validator_index, Some(0));
+    }
+
+    #[test]
+    fn test_imports_across_epochs_without_stepping() {
+        let config = PoAConfig {
+            slot_duration: 3000,
+            authorities: keyed_test_authorities(3),
+            vrf_seed: [1u8; 32],
+            epoch_length: 4,
+            rewards: Default::default(),
+        };
+        let clock = MockClock::new(GENESIS_TIME);
+        let mut engine = PoAEngine::new(config, None, GENESIS_TIME)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()))
+            .with_election_provider(Arc::new(StaticElection::new(elected_validators())));
+
+        // Block #4 opens epoch 1 with the configured set and elects for epoch 2
+        for number in 1..=8 {
+            clock.set(engine.slot_timestamp(number));
+            let header = sealed_header(&engine, number, number);
+            engine.import_block(header).unwrap();
+
+            let authority_set = engine.authority_set();
+            assert_eq!(authority_set.epoch, number / 4);
+            assert_eq!(authority_set.set_id, number / 8);
+        }
+
+        // Block #8 handed over to the elected set without the clock's help
+        assert_eq!(engine.authority_set().validators, elected_validators());
+        assert!(engine.verify_block(&sealed_header(&engine, 9, 9)).is_ok());
+
+        // Epoch 2 headers of the outgoing set are rejected
+        let old_set = PoAConfig {
+            epoch_length: 4,
+            ..create_test_engine().config
+        };
+        let outgoing = PoAEngine::new(old_set, None, GENESIS_TIME)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        let forged = sealed_header(&outgoing, 9, 9);
+        assert!(engine.verify_block(&forged).is_err());
+    }
+
+    #[test]
+    fn test_takes_over_sets_at_pivot() {
+        let config = PoAConfig {
+            slot_duration: 3000,
+            authorities: keyed_test_authorities(3),
+            vrf_seed: [1u8; 32],
+            epoch_length: 4,
+            rewards: Default::default(),
+        };
+        let clock = MockClock::new(GENESIS_TIME);
+        let engine_electing = |elected: Vec<Validator>| {
+            PoAEngine::new(config.clone(), None, GENESIS_TIME)
+                .unwrap()
+                .with_clock(Arc::new(clock.clone()))
+                .with_election_provider(Arc::new(StaticElection::new(elected)))
+        };
+        let mut engine = engine_electing(elected_validators());
+        let mut headers = Vec::new();
+        for number in 1..=9 {
+            clock.set(engine.slot_timestamp(number));
+            let header = sealed_header(&engine, number, number);
+            engine.import_block(header.clone()).unwrap();
+            headers.push(header);
+        }
+
+        // Block #9 is sealed by the set elected at block #4 and announces the
+        // one elected at block #8
+        let mut pivoted = engine_electing(elected_validators());
+        pivoted.import_pivot(headers[8].clone()).unwrap();
+        assert_eq!(pivoted.authority_set(), engine.authority_set());
+        assert_eq!(pivoted.next_authority_set(), engine.next_authority_set());
+        assert!(pivoted
+            .verify_block(&sealed_header(&engine, 10, 10))
+            .is_ok());
+
+        // Before the first handover the configured set is still in force
+        let mut pivoted = engine_electing(elected_validators());
+        pivoted.import_pivot(headers[4].clone()).unwrap();
+        assert_eq!(pivoted.authority_set().set_id, 0);
+        assert_eq!(pivoted.next_authority_set().unwrap().set_id, 1);
+
+        // A state electing other validators does not match the commitments
+        let others = vec![Validator {
+            address: test_authority_address(5),
+            weight: 100,
+        }];
+        let mut pivoted = engine_electing(others);
+        assert!(matches!(
+            pivoted.import_pivot(headers[8].clone()),
+            Err(ConsensusError::AuthoritySet(_))
+        ));
+    }
+
+    #[test]
+    fn test_fork_changes_slot_duration() {
+        let forks = ForkSchedule::new(vec![
+            Fork::new("genesis", 0).with_param(SLOT_DURATION_PARAM, 6000),
+            Fork::new("faster_slots", 2).with_param(SLOT_DURATION_PARAM, 1000),
+        ])
+        .unwrap();
+
+        let (engine, clock) = create_test_engine_with_clock(None);
+        let mut engine = engine.with_fork_schedule(forks, 0, |_| None).unwrap();
+        assert_eq!(engine.slot_schedule().slot_duration(), 6000);
+
+        // Block #1 in slot 5 is the last one with the genesis slot duration
+        clock.set(engine.slot_timestamp(5));
+        engine.process_block(sealed_header(&engine, 1, 5)).unwrap();
+
+        assert_eq!(engine.slot_schedule().slot_duration(), 1000);
+        assert_eq!(engine.slot_timestamp(6), GENESIS_TIME + 36_000);
+        assert_eq!(engine.slot_timestamp(7), GENESIS_TIME + 37_000);
+        assert_eq!(engine.current_slot_from_timestamp(GENESIS_TIME + 37_500), 7);
+    }
+
+    #[test]
+    fn test_fork_replayed_from_best_block() {
+        let forks = ForkSchedule::new(vec![
+            Fork::new("genesis", 0).with_param(SLOT_DURATION_PARAM, 6000),
+            Fork::new("faster_slots", 2).with_param(SLOT_DURATION_PARAM, 1000),
+        ])
+        .unwrap();
+
+        // Restarting at block #3 needs the block before the fork
+        let (engine, _clock) = create_test_engine_with_clock(None);
+        assert!(engine.with_fork_schedule(forks.clone(), 3, |_| None).is_err());
+
+        // Block #1 was produced in slot 5
+        let (engine, _clock) = create_test_engine_with_clock(None);
+        let engine = engine
+            .with_fork_schedule(forks, 3, |number| {
+                (number == 1).then_some(GENESIS_TIME + 30_000)
+            })
+            .unwrap();
+
+        assert_eq!(engine.slot_schedule().slot_duration(), 1000);
+        assert_eq!(engine.slot_timestamp(7), GENESIS_TIME + 37_000);
+
+        // Blocks before the fork keep their slots
+        assert_eq!(engine.slot_timestamp(5), GENESIS_TIME + 30_000);
+        assert_eq!(engine.current_slot_from_timestamp(GENESIS_TIME + 12_000), 2);
+    }
 }
@@ -4,6 +4,6 @@ pub mod config;
 pub mod engine;
 pub mod vrf;
 
-pub use config::PoAConfig;
+pub use config::{PoAConfig, SLOT_DURATION_PARAM};
 pub use engine::PoAEngine;
 pub use vrf::{VrfProof, VrfSeed, VrfSelector};
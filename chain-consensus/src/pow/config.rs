@@ -0,0 +1,91 @@
+//! PoW consensus configuration
+
+use crate::{ConsensusError, ConsensusResult};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// PoW consensus configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowConfig {
+    /// Target time between blocks in milliseconds
+    pub target_block_time: u64,
+    /// Difficulty used when no parent headers are known
+    pub initial_difficulty: u64,
+    /// Lower bound for retargeted difficulty
+    pub min_difficulty: u64,
+    /// Number of parent blocks used for difficulty retargeting
+    pub adjustment_window: u64,
+    /// Number of nonce search threads (0 disables mining)
+    pub mining_threads: usize,
+}
+
+impl Default for PowConfig {
+    fn default() -> Self {
+        Self {
+            target_block_time: 10_000, // 10 seconds per block
+            initial_difficulty: 1 << 16,
+            min_difficulty: 1,
+            adjustment_window: 10,
+            mining_threads: 0,
+        }
+    }
+}
+
+impl PowConfig {
+    /// Validate the configuration
+    pub fn validate(&self) -> ConsensusResult<()> {
+        if self.target_block_time == 0 {
+            return Err(ConsensusError::Config(
+                "Target block time must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.min_difficulty == 0 {
+            return Err(ConsensusError::Config(
+                "Minimum difficulty must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.initial_difficulty < self.min_difficulty {
+            return Err(ConsensusError::Config(
+                "Initial difficulty must not be below the minimum difficulty".to_string(),
+            ));
+        }
+
+        if self.adjustment_window == 0 {
+            return Err(ConsensusError::Config(
+                "Adjustment window must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Get target block time as Duration
+    pub fn target_block_time_as_duration(&self) -> Duration {
+        Duration::from_millis(self.target_block_time)
+    }
+
+    /// Set number of mining threads
+    pub fn with_mining_threads(mut self, threads: usize) -> Self {
+        self.mining_threads = threads;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_validation() {
+        let mut config = PowConfig::default();
+        assert!(config.validate().is_ok());
+
+        config.min_difficulty = 0;
+        assert!(config.validate().is_err());
+
+        config.min_difficulty = 1 << 20;
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! Difficulty retargeting from parent timestamps
+
+use crate::pow::PowConfig;
+use chain_core::BlockHeader;
+
+/// Maximum factor by which difficulty may change between two blocks
+pub const MAX_ADJUSTMENT_FACTOR: u64 = 4;
+
+/// Calculate the difficulty of the child of the last header in `parents`
+///
+/// `parents` holds consecutive ancestors in ascending order, ending with the
+/// parent. Only the last `adjustment_window + 1` headers are considered. The
+/// parent difficulty is scaled by the ratio of expected to actual timespan
+/// over the window and clamped to [`MAX_ADJUSTMENT_FACTOR`] in either
+/// direction.
+pub fn next_difficulty(config: &PowConfig, parents: &[BlockHeader]) -> u64 {
+    let Some(parent) = parents.last() else {
+        return config.initial_difficulty;
+    };
+
+    let window_len = (config.adjustment_window as usize + 1).min(parents.len());
+    let window = &parents[parents.len() - window_len..];
+    if window.len() < 2 {
+        return parent.difficulty.max(config.min_difficulty);
+    }
+
+    let first = &window[0];
+    let intervals = (window.len() - 1) as u128;
+    let expected_timespan = config.target_block_time as u128 * intervals;
+    let actual_timespan = (parent.timestamp.saturating_sub(first.timestamp) as u128).max(1);
+
+    let retargeted = parent.difficulty as u128 * expected_timespan / actual_timespan;
+
+    let lower = (parent.difficulty / MAX_ADJUSTMENT_FACTOR).max(1) as u128;
+    let upper = parent.difficulty.saturating_mul(MAX_ADJUSTMENT_FACTOR) as u128;
+
+    (retargeted.clamp(lower, upper) as u64).max(config.min_difficulty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with_interval(count: u64, interval: u64, difficulty: u64) -> Vec<BlockHeader> {
+        (0..count)
+            .map(|i| {
+                let mut header = BlockHeader::genesis();
+                header.number = i;
+                header.timestamp = i * interval;
+                header.difficulty = difficulty;
+                header
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_retarget_direction() {
+        let config = PowConfig::default();
+
+        // On target keeps difficulty
+        let parents = headers_with_interval(11, config.target_block_time, 1000);
+        assert_eq!(next_difficulty(&config, &parents), 1000);
+
+        // Twice as fast doubles difficulty
+        let parents = headers_with_interval(11, config.target_block_time / 2, 1000);
+        assert_eq!(next_difficulty(&config, &parents), 2000);
+
+        // Twice as slow halves difficulty
+        let parents = headers_with_interval(11, config.target_block_time * 2, 1000);
+        assert_eq!(next_difficulty(&config, &parents), 500);
+    }
+
+    #[test]
+    fn test_retarget_bounds() {
+        let config = PowConfig {
+            min_difficulty: 300,
+            ..PowConfig::default()
+        };
+
+        assert_eq!(next_difficulty(&config, &[]), config.initial_difficulty);
+
+        // Instant blocks are clamped to the maximum factor
+        let parents = headers_with_interval(11, 0, 1000);
+        assert_eq!(next_difficulty(&config, &parents), 4000);
+
+        // Very slow blocks are clamped by the factor and the minimum
+        let parents = headers_with_interval(11, config.target_block_time * 100, 1000);
+        assert_eq!(next_difficulty(&config, &parents), 300);
+    }
+}
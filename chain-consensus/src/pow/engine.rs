@@ -0,0 +1,537 @@
+//! PoW consensus engine implementation
+
+use crate::clock::{Clock, SystemClock};
+use crate::pow::difficulty::MAX_ADJUSTMENT_FACTOR;
+use crate::pow::{next_difficulty, Miner, PowConfig};
+use crate::slots;
+use crate::traits::{Engine, StepContext, StepResult};
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::{BlockHeader, Hash};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Maximum time in milliseconds a header timestamp may lie in the future
+pub const MAX_FUTURE_DRIFT: u64 = 15_000;
+
+/// Number of blocks below the best block that side chains may fork from
+pub const MAX_REORG_DEPTH: u64 = 64;
+
+/// An imported header with the work of the chain ending in it
+struct ImportedHeader {
+    /// The header
+    header: BlockHeader,
+    /// Sum of difficulties from the root header up to this one
+    total_difficulty: u128,
+}
+
+impl ImportedHeader {
+    /// A header trusted without its ancestors, such as the genesis header
+    fn root(header: BlockHeader) -> Self {
+        Self {
+            total_difficulty: header.difficulty as u128,
+            header,
+        }
+    }
+}
+
+/// PoW consensus engine
+///
+/// Block production is permissionless: any node with mining threads
+/// proposes on top of its best block and seals the header by nonce search.
+/// The best block is the tip of the chain with the most total difficulty.
+pub struct PowEngine {
+    /// Configuration
+    config: PowConfig,
+    /// Nonce search
+    miner: Miner,
+    /// Recent imported headers on the best chain and side chains, by hash
+    headers: HashMap<Hash, ImportedHeader>,
+    /// Hash of the best imported header
+    best: Hash,
+    /// Parent hash of the last proposal
+    last_proposed_parent: Option<Hash>,
+    /// Set to abort an in-progress nonce search
+    cancel_mining: Arc<AtomicBool>,
+    /// Time source
+    clock: Arc<dyn Clock>,
+}
+
+impl PowEngine {
+    /// Create a new PoW engine on top of the genesis header
+    ///
+    /// Every imported header must descend from the genesis header, or from a
+    /// pivot taken over with [`import_pivot`](Engine::import_pivot).
+    pub fn new(config: PowConfig, genesis: BlockHeader) -> ConsensusResult<Self> {
+        config.validate()?;
+        let hash = hash_header(&genesis)?;
+
+        if config.mining_threads > 0 {
+            info!("Mining enabled with {} threads", config.mining_threads);
+        } else {
+            info!("Mining disabled");
+        }
+
+        Ok(Self {
+            miner: Miner::new(config.mining_threads),
+            config,
+            headers: HashMap::from([(hash, ImportedHeader::root(genesis))]),
+            best: hash,
+            last_proposed_parent: None,
+            cancel_mining: Arc::new(AtomicBool::new(false)),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Use a custom time source instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get the best imported header
+    pub fn best_header(&self) -> &BlockHeader {
+        &self.headers[&self.best].header
+    }
+
+    /// Get the total difficulty of the chain ending in an imported header
+    pub fn total_difficulty(&self, hash: &Hash) -> Option<u128> {
+        self.headers
+            .get(hash)
+            .map(|imported| imported.total_difficulty)
+    }
+
+    /// Difficulty required for a child of the best imported header
+    pub fn next_difficulty(&self) -> u64 {
+        self.child_difficulty(&self.best)
+    }
+
+    /// Verify and import a header
+    ///
+    /// The header becomes the best block only if its chain has more total
+    /// difficulty than the current best chain. Otherwise it is kept as a
+    /// side-chain block. Returns whether the best block changed.
+    pub fn import_header(&mut self, header: BlockHeader) -> ConsensusResult<bool> {
+        self.verify_block(&header)?;
+
+        let hash = hash_header(&header)?;
+        if self.headers.contains_key(&hash) {
+            return Ok(false);
+        }
+
+        let parent_work = self
+            .total_difficulty(&header.parent_hash)
+            .unwrap_or_default();
+        let total_difficulty = parent_work + header.difficulty as u128;
+        let best_work = self.headers[&self.best].total_difficulty;
+        let is_best = total_difficulty > best_work;
+
+        if is_best && self.best != header.parent_hash {
+            info!(
+                "Reorganizing to block #{} with total difficulty {}",
+                header.number, total_difficulty
+            );
+        } else if !is_best {
+            debug!("Stored side-chain block #{}", header.number);
+        }
+
+        self.headers.insert(
+            hash,
+            ImportedHeader {
+                header,
+                total_difficulty,
+            },
+        );
+
+        if is_best {
+            self.best = hash;
+            self.prune();
+            // Work on the old parent is now stale
+            self.cancel_mining.store(true, Ordering::SeqCst);
+        }
+        Ok(is_best)
+    }
+
+    /// Difficulty required for a child of an imported header
+    fn child_difficulty(&self, parent_hash: &Hash) -> u64 {
+        next_difficulty(&self.config, &self.retarget_window(parent_hash))
+    }
+
+    /// Imported headers a child of `parent_hash` retargets on, oldest first
+    fn retarget_window(&self, parent_hash: &Hash) -> Vec<BlockHeader> {
+        let mut parents = Vec::new();
+        let mut next = Some(*parent_hash);
+        while let Some(imported) = next.and_then(|hash| self.headers.get(&hash)) {
+            if parents.len() as u64 > self.config.adjustment_window {
+                break;
+            }
+            parents.push(imported.header.clone());
+            next = Some(imported.header.parent_hash);
+        }
+        parents.reverse();
+        parents
+    }
+
+    /// Verify a header's difficulty against the headers it retargets on
+    ///
+    /// Right after a pivot the window reaches below the known headers, so
+    /// the difficulty is only held to the adjustment bounds of its parent.
+    fn verify_difficulty(&self, header: &BlockHeader) -> ConsensusResult<()> {
+        let parents = self.retarget_window(&header.parent_hash);
+        let complete = parents.len() as u64 > self.config.adjustment_window
+            || parents.first().is_some_and(|first| first.number == 0);
+        let parent = parents.last().map_or(0, |parent| parent.difficulty);
+        let expected = next_difficulty(&self.config, &parents);
+        let valid = if complete {
+            header.difficulty == expected
+        } else {
+            let lower = (parent / MAX_ADJUSTMENT_FACTOR).max(1);
+            let upper = parent.saturating_mul(MAX_ADJUSTMENT_FACTOR);
+            (lower..=upper).contains(&header.difficulty)
+        };
+        if !valid {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Invalid difficulty: expected {}, got {}",
+                expected, header.difficulty
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verify the checks a header passes without its parent: timestamp
+    /// drift, minimum difficulty and proof of work
+    fn verify_seal(&self, header: &BlockHeader) -> ConsensusResult<()> {
+        let now = self.clock.now();
+        if header.timestamp > now + MAX_FUTURE_DRIFT {
+            return Err(ConsensusError::InvalidTimestamp {
+                expected: now,
+                actual: header.timestamp,
+            });
+        }
+
+        if header.difficulty < self.config.min_difficulty {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Difficulty {} below minimum {}",
+                header.difficulty, self.config.min_difficulty
+            )));
+        }
+
+        let valid = header
+            .validate_pow()
+            .map_err(|e| ConsensusError::Other(format!("Failed to hash header: {}", e)))?;
+        if !valid {
+            return Err(ConsensusError::InvalidBlock(
+                "Proof of work does not meet difficulty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Drop headers too far below the best block to fork from or retarget on
+    fn prune(&mut self) {
+        let best_number = self.best_header().number;
+        let keep = MAX_REORG_DEPTH.max(self.config.adjustment_window + 1);
+        let lowest = best_number.saturating_sub(keep);
+        self.headers
+            .retain(|_, imported| imported.header.number >= lowest);
+    }
+}
+
+/// Hash a header, mapping failures to a consensus error
+fn hash_header(header: &BlockHeader) -> ConsensusResult<Hash> {
+    header
+        .hash()
+        .map_err(|e| ConsensusError::Other(format!("Failed to hash header: {}", e)))
+}
+
+impl Engine for PowEngine {
+    fn step(&mut self, ctx: StepContext) -> ConsensusResult<StepResult> {
+        let timeout = self.config.target_block_time_as_duration();
+
+        if self.should_propose(&ctx) {
+            self.last_proposed_parent = Some(ctx.parent_hash);
+            // Only imports after this proposal make its seal stale
+            self.cancel_mining.store(false, Ordering::SeqCst);
+
+            // Retarget against the parent we build on, which may not be our best block
+            let mut header = slots::build_header(&ctx, self.clock.now(), 0);
+            header.difficulty = self.child_difficulty(&ctx.parent_hash);
+
+            return Ok(StepResult::Propose { header, timeout });
+        }
+
+        Ok(StepResult::Continue { timeout })
+    }
+
+    fn verify_block(&self, header: &BlockHeader) -> ConsensusResult<()> {
+        debug!("Verifying block #{}", header.number);
+
+        let parent = self
+            .headers
+            .get(&header.parent_hash)
+            .map(|imported| &imported.header)
+            .ok_or_else(|| {
+                ConsensusError::InvalidBlock(format!(
+                    "Unknown parent {} of block #{}",
+                    header.parent_hash, header.number
+                ))
+            })?;
+
+        if header.timestamp <= parent.timestamp {
+            return Err(ConsensusError::InvalidTimestamp {
+                expected: parent.timestamp + 1,
+                actual: header.timestamp,
+            });
+        }
+        self.verify_difficulty(header)?;
+        self.verify_seal(header)?;
+
+        debug!("Block #{} verified successfully", header.number);
+        Ok(())
+    }
+
+    fn current_round(&self) -> u64 {
+        self.best_header().number + 1
+    }
+
+    fn should_propose(&self, ctx: &StepContext) -> bool {
+        self.config.mining_threads > 0 && self.last_proposed_parent != Some(ctx.parent_hash)
+    }
+
+    fn expected_proposer(&self, _slot: u64) -> Option<usize> {
+        None
+    }
+
+    fn seal(&self, header: BlockHeader) -> ConsensusResult<BlockHeader> {
+        self.miner
+            .mine(&header, &self.cancel_mining)?
+            .ok_or_else(|| ConsensusError::Other("Mining was cancelled".to_string()))
+    }
+
+    fn cancel_handle(&self) -> Option<Arc<AtomicBool>> {
+        Some(self.cancel_mining.clone())
+    }
+
+    fn import_block(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        self.import_header(header).map(|_| ())
+    }
+
+    fn import_pivot(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        self.verify_seal(&header)?;
+        let hash = hash_header(&header)?;
+        info!("Continuing from pivot block #{}", header.number);
+        self.headers = HashMap::from([(hash, ImportedHeader::root(header))]);
+        self.best = hash;
+        self.cancel_mining.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn test_config() -> PowConfig {
+        PowConfig {
+            target_block_time: 1000,
+            initial_difficulty: 64,
+            min_difficulty: 1,
+            adjustment_window: 4,
+            mining_threads: 2,
+        }
+    }
+
+    fn test_genesis() -> BlockHeader {
+        BlockHeader {
+            difficulty: 64,
+            timestamp: 1_000,
+            ..BlockHeader::genesis()
+        }
+    }
+
+    fn test_engine(clock: &MockClock) -> PowEngine {
+        PowEngine::new(test_config(), test_genesis())
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()))
+    }
+
+    fn test_context(parent_hash: Hash, block_number: u64) -> StepContext {
+        StepContext {
+            block_number,
+            parent_hash,
+            timestamp: 0,
+            validator_index: None,
+            pending_transactions: 0,
+        }
+    }
+
+    fn mine_next(engine: &mut PowEngine, parent_hash: Hash, number: u64) -> BlockHeader {
+        match engine.step(test_context(parent_hash, number)).unwrap() {
+            StepResult::Propose { header, .. } => engine.seal(header).unwrap(),
+            other => panic!("Expected Propose, got {:?}", other),
+        }
+    }
+
+    fn mine_on(engine: &PowEngine, parent: &BlockHeader, timestamp: u64) -> BlockHeader {
+        let parent_hash = parent.hash().unwrap();
+        let ctx = test_context(parent_hash, parent.number + 1);
+        let mut header = slots::build_header(&ctx, timestamp, 0);
+        header.difficulty = engine.child_difficulty(&parent_hash);
+        engine.cancel_mining.store(false, Ordering::SeqCst);
+        engine.seal(header).unwrap()
+    }
+
+    #[test]
+    fn test_mine_and_import_chain() {
+        let clock = MockClock::new(1_000);
+        let mut engine = test_engine(&clock);
+
+        let mut parent_hash = test_genesis().hash().unwrap();
+        for number in 1..=3 {
+            clock.advance(std::time::Duration::from_millis(1000));
+            let header = mine_next(&mut engine, parent_hash, number);
+            assert!(header.validate_pow().unwrap());
+
+            // Only one proposal per parent
+            assert!(!engine.should_propose(&test_context(parent_hash, number)));
+
+            parent_hash = header.hash().unwrap();
+            engine.import_header(header).unwrap();
+        }
+
+        assert_eq!(engine.current_round(), 4);
+        // Blocks arrived on target, so difficulty is unchanged
+        assert_eq!(engine.next_difficulty(), 64);
+    }
+
+    #[test]
+    fn test_reject_wrong_difficulty() {
+        let clock = MockClock::new(1_000);
+        let engine = test_engine(&clock);
+
+        let genesis_hash = test_genesis().hash().unwrap();
+        clock.advance(std::time::Duration::from_millis(1000));
+        let mut header = slots::build_header(&test_context(genesis_hash, 1), clock.now(), 0);
+        header.difficulty = 1;
+        engine.cancel_mining.store(false, Ordering::SeqCst);
+        let header = engine.seal(header).unwrap();
+
+        assert!(matches!(
+            engine.verify_block(&header),
+            Err(ConsensusError::InvalidBlock(_))
+        ));
+    }
+
+    #[test]
+    fn test_heavier_side_chain_reorgs() {
+        let clock = MockClock::new(10_000);
+        let mut engine = test_engine(&clock);
+
+        let genesis = test_genesis();
+        let a1 = mine_on(&engine, &genesis, 2_000);
+        assert!(engine.import_header(a1.clone()).unwrap());
+
+        // A sibling with equal work is stored without becoming best
+        let b1 = mine_on(&engine, &genesis, 1_250);
+        let b1_hash = b1.hash().unwrap();
+        assert!(!engine.import_header(b1.clone()).unwrap());
+        assert_eq!(engine.best_header(), &a1);
+        assert!(engine.total_difficulty(&b1_hash).is_some());
+
+        // Its child retargets from the side chain's own fast parent
+        let b2 = mine_on(&engine, &b1, 1_500);
+        assert_eq!(b2.difficulty, 64 * 4);
+        assert!(engine.import_header(b2.clone()).unwrap());
+        assert_eq!(engine.best_header(), &b2);
+        assert_eq!(
+            engine.next_difficulty(),
+            engine.child_difficulty(&b2.hash().unwrap())
+        );
+
+        // Proposals on the old chain retarget against their own parent
+        clock.set(3_000);
+        match engine.step(test_context(a1.hash().unwrap(), 2)).unwrap() {
+            StepResult::Propose { header, .. } => {
+                assert_eq!(
+                    header.difficulty,
+                    engine.child_difficulty(&a1.hash().unwrap())
+                );
+                assert_ne!(header.difficulty, engine.next_difficulty());
+            }
+            other => panic!("Expected Propose, got {:?}", other),
+        }
+
+        // The old chain can still be extended, but stays behind
+        let a2 = mine_on(&engine, &a1, 3_000);
+        assert!(!engine.import_header(a2).unwrap());
+        assert_eq!(engine.best_header(), &b2);
+    }
+
+    #[test]
+    fn test_reject_unknown_parent() {
+        let clock = MockClock::new(10_000);
+        let mut engine = test_engine(&clock);
+
+        // Headers not descending from genesis are rejected from the start
+        let orphan = mine_on(&engine, &BlockHeader::genesis(), 2_000);
+        assert!(matches!(
+            engine.import_header(orphan),
+            Err(ConsensusError::InvalidBlock(_))
+        ));
+        assert_eq!(engine.best_header(), &test_genesis());
+    }
+
+    #[test]
+    fn test_import_cancels_proposal_seal() {
+        let clock = MockClock::new(10_000);
+        let mut engine = test_engine(&clock);
+
+        let genesis = test_genesis();
+        let genesis_hash = genesis.hash().unwrap();
+
+        let proposal = match engine.step(test_context(genesis_hash, 1)).unwrap() {
+            StepResult::Propose { header, .. } => header,
+            other => panic!("Expected Propose, got {:?}", other),
+        };
+        assert!(!engine.cancel_handle().unwrap().load(Ordering::SeqCst));
+
+        // A block from the network makes the proposal stale
+        let block = mine_on(&engine, &genesis, 2_000);
+        engine.import_header(block).unwrap();
+        assert!(engine.seal(proposal).is_err());
+    }
+
+    #[test]
+    fn test_continues_from_pivot() {
+        let clock = MockClock::new(10_000);
+        let mut engine = test_engine(&clock);
+
+        // A pivot far from genesis, whose ancestors the engine never saw
+        let mut pivot = test_genesis();
+        pivot.number = 100;
+        pivot.parent_hash = Hash::new([7u8; 32]);
+        pivot.timestamp = 5_000;
+        let pivot = engine.seal(pivot).unwrap();
+        assert!(engine.verify_block(&pivot).is_err());
+        engine.import_pivot(pivot.clone()).unwrap();
+        assert_eq!(engine.best_header(), &pivot);
+
+        // Children retarget within bounds until the window is known again
+        let child = mine_on(&engine, &pivot, 6_000);
+        assert!(engine.import_header(child.clone()).unwrap());
+        let mut steep = slots::build_header(&test_context(child.hash().unwrap(), 102), 7_000, 0);
+        steep.difficulty = child.difficulty * (MAX_ADJUSTMENT_FACTOR + 1);
+        assert!(matches!(
+            engine.verify_block(&steep),
+            Err(ConsensusError::InvalidBlock(_))
+        ));
+
+        // A pivot without proof of work is refused
+        let mut unsealed = mine_on(&engine, &child, 7_000);
+        unsealed.difficulty = u64::MAX;
+        assert!(engine.import_pivot(unsealed).is_err());
+        assert_eq!(engine.best_header(), &child);
+    }
+}
@@ -0,0 +1,123 @@
+//! Multi-threaded nonce search
+
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::BlockHeader;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use tracing::debug;
+
+/// Searches for a nonce satisfying `BlockHeader::validate_pow`
+#[derive(Debug, Clone, Copy)]
+pub struct Miner {
+    /// Number of search threads
+    threads: usize,
+}
+
+impl Miner {
+    /// Create a new miner with the given number of threads
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    /// Get the number of search threads
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Search for a valid nonce
+    ///
+    /// Thread `i` tries nonces `i, i + threads, i + 2 * threads, ...`. Returns
+    /// `None` if `cancel` is set before a nonce is found.
+    pub fn mine(
+        &self,
+        header: &BlockHeader,
+        cancel: &AtomicBool,
+    ) -> ConsensusResult<Option<BlockHeader>> {
+        if header.difficulty == 0 {
+            return Err(ConsensusError::InvalidBlock(
+                "Difficulty must be greater than 0".to_string(),
+            ));
+        }
+
+        let found = AtomicBool::new(false);
+        let result: Mutex<Option<ConsensusResult<BlockHeader>>> = Mutex::new(None);
+        let step = self.threads as u64;
+
+        thread::scope(|scope| {
+            for start in 0..step {
+                let found = &found;
+                let result = &result;
+                let mut candidate = header.clone();
+
+                scope.spawn(move || {
+                    let mut nonce = start;
+                    while !found.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+                        candidate.nonce = nonce;
+                        let outcome = match candidate.validate_pow() {
+                            Ok(true) => Ok(candidate.clone()),
+                            Ok(false) => match nonce.checked_add(step) {
+                                Some(next) => {
+                                    nonce = next;
+                                    continue;
+                                }
+                                None => return,
+                            },
+                            Err(e) => Err(ConsensusError::Other(format!(
+                                "Failed to hash header: {}",
+                                e
+                            ))),
+                        };
+
+                        if !found.swap(true, Ordering::SeqCst) {
+                            *result.lock().unwrap() = Some(outcome);
+                        }
+                        return;
+                    }
+                });
+            }
+        });
+
+        match result.into_inner().unwrap() {
+            Some(Ok(sealed)) => {
+                debug!(
+                    "Found nonce {} for block #{} at difficulty {}",
+                    sealed.nonce, sealed.number, sealed.difficulty
+                );
+                Ok(Some(sealed))
+            }
+            Some(Err(e)) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mine_valid_nonce() {
+        let mut header = BlockHeader::genesis();
+        header.difficulty = 256;
+
+        let sealed = Miner::new(4)
+            .mine(&header, &AtomicBool::new(false))
+            .unwrap()
+            .unwrap();
+
+        assert!(sealed.validate_pow().unwrap());
+        assert_eq!(sealed.number, header.number);
+    }
+
+    #[test]
+    fn test_mine_cancelled() {
+        let mut header = BlockHeader::genesis();
+        header.difficulty = u64::MAX;
+
+        let result = Miner::new(2).mine(&header, &AtomicBool::new(true)).unwrap();
+        assert!(result.is_none());
+    }
+}
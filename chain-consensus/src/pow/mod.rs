@@ -0,0 +1,11 @@
+//! Proof of Work consensus implementation
+
+pub mod config;
+pub mod difficulty;
+pub mod engine;
+pub mod miner;
+
+pub use config::PowConfig;
+pub use difficulty::next_difficulty;
+pub use engine::PowEngine;
+pub use miner::Miner;
@@ -106,6 +106,16 @@ impl SlashingDetector {
         }
     }
 
+    /// Take back a missed slot charged to a validator whose block arrived late
+    pub fn undo_missed_slot(&mut self, validator_index: usize) {
+        if let Some(missed) = self.missed_slots.get_mut(&validator_index) {
+            *missed -= 1;
+            if *missed == 0 {
+                self.missed_slots.remove(&validator_index);
+            }
+        }
+    }
+
     /// Reset missed slots for a validator (called when they produce a block)
     pub fn reset_missed_slots(&mut self, validator_index: usize) {
         self.missed_slots.remove(&validator_index);
@@ -246,5 +256,13 @@ mod tests {
             }
             _ => panic!("Expected offline slashing"),
         }
+
+        // Misses taken back no longer count
+        detector.undo_missed_slot(0);
+        assert_eq!(detector.get_missed_slots(0), 2);
+        detector.undo_missed_slot(0);
+        detector.undo_missed_slot(0);
+        detector.undo_missed_slot(0);
+        assert_eq!(detector.get_missed_slots(0), 0);
     }
 }
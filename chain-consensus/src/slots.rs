@@ -0,0 +1,283 @@
+//! Slot timing and header construction shared by slot-based engines
+
+use crate::traits::StepContext;
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::{Address, BlockHeader, Hash, Signature};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Default gas limit for proposed block headers
+pub const DEFAULT_GAS_LIMIT: u64 = 1_000_000;
+
+/// Length of the author signature closing a signed header's `extra_data`
+pub const SEAL_LENGTH: usize = 65;
+
+/// Slot timing from one slot onwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SlotSegment {
+    /// First slot of the segment
+    start_slot: u64,
+    /// Start of `start_slot` in milliseconds
+    start_timestamp: u64,
+    /// Slot duration in milliseconds
+    slot_duration: u64,
+}
+
+/// Maps millisecond timestamps to fixed-length slots
+///
+/// A schedule starts at genesis with slot 0 and can be rebased onto a new
+/// slot duration from a later slot, keeping slot numbers continuous. Each
+/// rebase starts a new segment, so earlier timestamps keep the slots they
+/// had under the duration in effect at the time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotSchedule {
+    /// Segments in ascending order, the first starting at genesis with slot 0
+    segments: Vec<SlotSegment>,
+}
+
+impl SlotSchedule {
+    /// Create a new slot schedule
+    pub fn new(genesis_timestamp: u64, slot_duration: u64) -> Self {
+        Self {
+            segments: vec![SlotSegment {
+                start_slot: 0,
+                start_timestamp: genesis_timestamp,
+                slot_duration: slot_duration.max(1),
+            }],
+        }
+    }
+
+    /// Continue the schedule with a new slot duration from `slot` onwards
+    ///
+    /// Slots before `slot` keep their timing.
+    pub fn rebase(&self, slot: u64, slot_duration: u64) -> Self {
+        let start_timestamp = self.slot_start(slot);
+        let mut segments: Vec<SlotSegment> = self
+            .segments
+            .iter()
+            .filter(|segment| segment.start_slot < slot)
+            .copied()
+            .collect();
+        segments.push(SlotSegment {
+            start_slot: slot,
+            start_timestamp,
+            slot_duration: slot_duration.max(1),
+        });
+        Self { segments }
+    }
+
+    /// Get the genesis timestamp
+    pub fn genesis_timestamp(&self) -> u64 {
+        self.segments[0].start_timestamp
+    }
+
+    /// Get the latest slot duration in milliseconds
+    pub fn slot_duration(&self) -> u64 {
+        self.segments[self.segments.len() - 1].slot_duration
+    }
+
+    /// Get the slot containing a timestamp
+    pub fn slot_at(&self, timestamp: u64) -> u64 {
+        let segment = self.segment_at(timestamp);
+        segment.start_slot
+            + timestamp.saturating_sub(segment.start_timestamp) / segment.slot_duration
+    }
+
+    /// Get the start timestamp of a slot
+    pub fn slot_start(&self, slot: u64) -> u64 {
+        let segment = self.segment_of(slot);
+        segment.start_timestamp + (slot - segment.start_slot) * segment.slot_duration
+    }
+
+    /// Time remaining until the slot after the one containing `now` starts
+    pub fn time_until_next_slot(&self, now: u64) -> Duration {
+        let next_slot_time = self.slot_start(self.slot_at(now) + 1);
+        Duration::from_millis(next_slot_time.saturating_sub(now).max(1))
+    }
+
+    /// Segment in effect at a timestamp
+    fn segment_at(&self, timestamp: u64) -> &SlotSegment {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.start_timestamp <= timestamp)
+            .unwrap_or(&self.segments[0])
+    }
+
+    /// Segment containing a slot
+    fn segment_of(&self, slot: u64) -> &SlotSegment {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.start_slot <= slot)
+            .unwrap_or(&self.segments[0])
+    }
+
+    /// Check a header timestamp against the schedule and return its slot
+    ///
+    /// The timestamp must not lie in the future by more than one slot, must
+    /// not precede genesis, and must fall within the first half of its slot.
+    pub fn verify_timestamp(&self, timestamp: u64, now: u64) -> ConsensusResult<u64> {
+        if timestamp > now + self.slot_duration() {
+            return Err(ConsensusError::InvalidTimestamp {
+                expected: now,
+                actual: timestamp,
+            });
+        }
+
+        if timestamp < self.genesis_timestamp() {
+            return Err(ConsensusError::InvalidTimestamp {
+                expected: self.genesis_timestamp(),
+                actual: timestamp,
+            });
+        }
+
+        let slot = self.slot_at(timestamp);
+        let expected_timestamp = self.slot_start(slot);
+        let tolerance = self.segment_of(slot).slot_duration / 2; // Allow some clock drift
+
+        if timestamp < expected_timestamp.saturating_sub(tolerance)
+            || timestamp > expected_timestamp + tolerance
+        {
+            return Err(ConsensusError::InvalidTimestamp {
+                expected: expected_timestamp,
+                actual: timestamp,
+            });
+        }
+
+        Ok(slot)
+    }
+}
+
+/// Build an unsealed header on top of the step context
+///
+/// Roots and gas usage are filled in once the block has been executed.
+pub fn build_header(ctx: &StepContext, timestamp: u64, nonce: u64) -> BlockHeader {
+    BlockHeader {
+        parent_hash: ctx.parent_hash,
+        number: ctx.block_number,
+        state_root: Hash::zero(),        // Will be filled by state execution
+        transactions_root: Hash::zero(), // Will be filled by transaction processing
+        receipts_root: Hash::zero(),     // Will be filled by receipt processing
+        gas_limit: DEFAULT_GAS_LIMIT,
+        gas_used: 0,   // Will be filled after execution
+        difficulty: 1, // Authority engines don't use difficulty
+        timestamp,
+        extra_data: vec![],
+        nonce,
+    }
+}
+
+/// Hash of a header, as signed by its author before sealing
+fn header_hash(header: &BlockHeader) -> ConsensusResult<Hash> {
+    header
+        .hash()
+        .map_err(|e| ConsensusError::InvalidBlock(e.to_string()))
+}
+
+/// Sign a header, appending the author's signature to `extra_data`
+pub fn sign_header(mut header: BlockHeader, private_key: &[u8]) -> ConsensusResult<BlockHeader> {
+    let signature = Signature::sign_hash(&header_hash(&header)?, private_key)
+        .map_err(|e| ConsensusError::InvalidValidator(e.to_string()))?;
+    header.extra_data.extend_from_slice(&signature.to_bytes());
+    Ok(header)
+}
+
+/// Recover the author of a signed header
+pub fn header_author(header: &BlockHeader) -> ConsensusResult<Address> {
+    let Some(split) = header.extra_data.len().checked_sub(SEAL_LENGTH) else {
+        return Err(ConsensusError::InvalidBlock(format!(
+            "Block #{} is not signed",
+            header.number
+        )));
+    };
+    let signature = Signature::from_bytes(&header.extra_data[split..])
+        .map_err(|e| ConsensusError::InvalidBlock(e.to_string()))?;
+    let unsealed = BlockHeader {
+        extra_data: header.extra_data[..split].to_vec(),
+        ..header.clone()
+    };
+    signature
+        .recover_address(&header_hash(&unsealed)?)
+        .map_err(|e| ConsensusError::InvalidBlock(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_mapping() {
+        let schedule = SlotSchedule::new(10_000, 2_000);
+
+        assert_eq!(schedule.slot_at(9_000), 0);
+        assert_eq!(schedule.slot_at(11_999), 0);
+        assert_eq!(schedule.slot_at(12_000), 1);
+        assert_eq!(schedule.slot_start(3), 16_000);
+        assert_eq!(
+            schedule.time_until_next_slot(12_500),
+            Duration::from_millis(1_500)
+        );
+    }
+
+    #[test]
+    fn test_verify_timestamp() {
+        let schedule = SlotSchedule::new(10_000, 2_000);
+        let now = 20_000;
+
+        assert_eq!(schedule.verify_timestamp(14_000, now).unwrap(), 2);
+        assert_eq!(schedule.verify_timestamp(15_000, now).unwrap(), 2);
+
+        // Second half of a slot
+        assert!(schedule.verify_timestamp(15_001, now).is_err());
+        // Before genesis
+        assert!(schedule.verify_timestamp(9_999, now).is_err());
+        // Too far in the future
+        assert!(schedule.verify_timestamp(24_000, now).is_err());
+    }
+
+    #[test]
+    fn test_signed_header_author() {
+        let key = [7u8; 32];
+        let header = build_header(
+            &StepContext {
+                block_number: 1,
+                parent_hash: Hash::zero(),
+                timestamp: 0,
+                validator_index: None,
+                pending_transactions: 0,
+            },
+            10_000,
+            0,
+        );
+        assert!(header_author(&header).is_err());
+
+        let signed = sign_header(header, &key).unwrap();
+        let author = Signature::sign_hash(&Hash::zero(), &key)
+            .unwrap()
+            .recover_address(&Hash::zero())
+            .unwrap();
+        assert_eq!(header_author(&signed).unwrap(), author);
+
+        // Changing any signed field changes the recovered author
+        let mut tampered = signed;
+        tampered.timestamp += 1;
+        assert_ne!(header_author(&tampered).ok(), Some(author));
+    }
+
+    #[test]
+    fn test_rebase_keeps_slot_numbers() {
+        let schedule = SlotSchedule::new(10_000, 2_000).rebase(5, 1_000);
+
+        assert_eq!(schedule.slot_start(5), 20_000);
+        assert_eq!(schedule.slot_at(20_999), 5);
+        assert_eq!(schedule.slot_at(21_000), 6);
+        assert_eq!(schedule.slot_start(8), 23_000);
+        assert_eq!(schedule.slot_duration(), 1_000);
+
+        // Slots before the rebase keep their timing
+        assert_eq!(schedule.slot_start(3), 16_000);
+        assert_eq!(schedule.slot_at(16_500), 3);
+        assert_eq!(schedule.verify_timestamp(16_000, 30_000).unwrap(), 3);
+    }
+}
@@ -2,6 +2,9 @@
 
 use crate::ConsensusResult;
 use chain_core::{BlockHeader, Hash};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// Context for a consensus step
@@ -15,6 +18,8 @@ pub struct StepContext {
     pub timestamp: u64,
     /// Local node's validator index (if any)
     pub validator_index: Option<usize>,
+    /// Number of transactions waiting to be included
+    pub pending_transactions: usize,
 }
 
 /// Result of a consensus step
@@ -51,10 +56,42 @@ pub trait Engine: Send + Sync {
 
     /// Get the expected proposer for a given slot
     fn expected_proposer(&self, slot: u64) -> Option<usize>;
+
+    /// Seal a proposed header once the block has been executed
+    ///
+    /// Engines whose seal covers the final roots (e.g. PoW) override this;
+    /// by default the header is returned unchanged.
+    fn seal(&self, header: BlockHeader) -> ConsensusResult<BlockHeader> {
+        Ok(header)
+    }
+
+    /// Get a flag that aborts an in-progress [`seal`](Engine::seal) when set
+    ///
+    /// Only engines with long-running seals (e.g. PoW) return one.
+    fn cancel_handle(&self) -> Option<Arc<AtomicBool>> {
+        None
+    }
+
+    /// Verify a block header and record it as the new best block
+    ///
+    /// Engines that track chain state (difficulty, produced slots) override
+    /// this; by default the header is only verified.
+    fn import_block(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        self.verify_block(&header)
+    }
+
+    /// Continue from a block whose ancestors were not imported, such as a
+    /// warp sync pivot whose state is now the best state
+    ///
+    /// Engines that track chain state rebuild it from the pivot; by default
+    /// the header is only verified.
+    fn import_pivot(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        self.verify_block(&header)
+    }
 }
 
 /// Validator information
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Validator {
     /// Validator address/public key
     pub address: chain_core::Address,
@@ -63,7 +100,7 @@ pub struct Validator {
 }
 
 /// Authority set for consensus
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AuthoritySet {
     /// List of validators
     pub validators: Vec<Validator>,
@@ -109,4 +146,17 @@ impl AuthoritySet {
     pub fn total_weight(&self) -> u64 {
         self.validators.iter().map(|v| v.weight).sum()
     }
+
+    /// Hash of the set id and validators, committed to in authored headers
+    ///
+    /// The epoch is left out so re-confirming a set does not change it.
+    pub fn commitment(&self) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.set_id.to_be_bytes());
+        for validator in &self.validators {
+            hasher.update(validator.address.as_bytes());
+            hasher.update(&validator.weight.to_be_bytes());
+        }
+        Hash::new(*hasher.finalize().as_bytes())
+    }
 }
@@ -0,0 +1,633 @@
+//! Async driver for consensus engines
+//!
+//! [`ConsensusWorker`] owns an [`Engine`] and runs its step loop on tokio:
+//! it authors blocks from the transaction pool when the engine asks to
+//! propose, imports blocks handed over by the network and keeps statistics
+//! about both. Chain storage and execution are provided by the caller through
+//! [`ChainBackend`] and [`TransactionPool`], so the same worker runs in
+//! chain-node and in tests with in-memory implementations.
+
+use crate::clock::{Clock, SystemClock};
+use crate::poa::engine::ConsensusEvent;
+use crate::traits::{Engine, StepContext, StepResult};
+use crate::{ConsensusError, ConsensusResult};
+use chain_core::{Block, BlockHeader, Transaction};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Delay before stepping again when the engine returns no timeout
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Chain storage and execution used by the worker
+pub trait ChainBackend: Send + Sync {
+    /// Get the header of the best block
+    fn best_header(&self) -> ConsensusResult<BlockHeader>;
+
+    /// Execute transactions on top of the header's parent, usually the best block
+    ///
+    /// Returns the block with state root, transactions root and gas used filled in.
+    fn build_block(
+        &self,
+        header: BlockHeader,
+        transactions: Vec<Transaction>,
+    ) -> ConsensusResult<Block>;
+
+    /// Execute and store a verified block
+    ///
+    /// The block becomes the best block if its chain carries the most total
+    /// difficulty; otherwise it is kept on a side branch that may take over later.
+    fn import_block(&self, block: Block) -> ConsensusResult<()>;
+}
+
+/// Source of transactions for block authoring
+pub trait TransactionPool: Send + Sync {
+    /// Number of transactions waiting to be included
+    fn pending_count(&self) -> usize;
+
+    /// Transactions ready for inclusion, up to the given total gas limit
+    fn ready_transactions(&self, gas_limit: u64) -> Vec<Transaction>;
+
+    /// Drop transactions included in an imported block
+    fn remove_included(&self, transactions: &[Transaction]);
+}
+
+/// Worker statistics
+#[derive(Debug, Default, Clone)]
+pub struct WorkerStats {
+    /// Number of engine steps
+    pub steps: u64,
+    /// Number of blocks authored locally
+    pub blocks_authored: u64,
+    /// Number of blocks imported from the network
+    pub blocks_imported: u64,
+    /// Number of blocks that failed verification or import
+    pub import_failures: u64,
+    /// Number of failed engine steps or block proposals
+    pub step_errors: u64,
+    /// Number of slashing offences reported by the engine
+    pub slashing_reports: u64,
+    /// Most recent error
+    pub last_error: Option<String>,
+}
+
+impl WorkerStats {
+    /// Create new worker statistics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an engine step
+    pub fn record_step(&mut self) {
+        self.steps += 1;
+    }
+
+    /// Record a locally authored block
+    pub fn record_block_authored(&mut self) {
+        self.blocks_authored += 1;
+    }
+
+    /// Record an imported block
+    pub fn record_block_imported(&mut self) {
+        self.blocks_imported += 1;
+    }
+
+    /// Record a failed block import
+    pub fn record_import_failure(&mut self, error: &ConsensusError) {
+        self.import_failures += 1;
+        self.last_error = Some(error.to_string());
+    }
+
+    /// Record a failed step or proposal
+    pub fn record_step_error(&mut self, error: &ConsensusError) {
+        self.step_errors += 1;
+        self.last_error = Some(error.to_string());
+    }
+
+    /// Record a slashing offence
+    pub fn record_slashing_report(&mut self) {
+        self.slashing_reports += 1;
+    }
+}
+
+/// Commands sent to a running worker
+#[derive(Debug)]
+enum WorkerCommand {
+    /// Import a block received from the network
+    Import(Box<Block>),
+    /// Stop the worker
+    Shutdown,
+}
+
+/// Handle for interacting with a running worker
+#[derive(Debug, Clone)]
+pub struct WorkerHandle {
+    /// Command channel to the worker
+    commands: mpsc::UnboundedSender<WorkerCommand>,
+    /// Shared worker statistics
+    stats: Arc<RwLock<WorkerStats>>,
+}
+
+impl WorkerHandle {
+    /// Queue a block received from the network for import
+    pub fn import_block(&self, block: Block) -> ConsensusResult<()> {
+        self.send(WorkerCommand::Import(Box::new(block)))
+    }
+
+    /// Stop the worker after the current step
+    pub fn shutdown(&self) -> ConsensusResult<()> {
+        self.send(WorkerCommand::Shutdown)
+    }
+
+    /// Get a snapshot of the worker statistics
+    pub fn stats(&self) -> WorkerStats {
+        self.stats.read().unwrap().clone()
+    }
+
+    fn send(&self, command: WorkerCommand) -> ConsensusResult<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| ConsensusError::Other("Consensus worker stopped".to_string()))
+    }
+}
+
+/// Engine driven by a worker, readable by others while the worker runs
+pub type SharedEngine = Arc<RwLock<Box<dyn Engine>>>;
+
+/// Drives a consensus engine on tokio
+pub struct ConsensusWorker {
+    /// Consensus engine, shared with the blocking task sealing a proposal
+    engine: SharedEngine,
+    /// Chain storage and execution
+    backend: Arc<dyn ChainBackend>,
+    /// Transactions for authoring
+    pool: Arc<dyn TransactionPool>,
+    /// Time source for step contexts
+    clock: Arc<dyn Clock>,
+    /// Commands from worker handles
+    commands: mpsc::UnboundedReceiver<WorkerCommand>,
+    /// Commands received while sealing, handled once the seal is done
+    deferred: Vec<Option<WorkerCommand>>,
+    /// Notifications emitted by the engine
+    events: Option<mpsc::UnboundedReceiver<ConsensusEvent>>,
+    /// Receives every locally authored block, e.g. for broadcasting
+    block_sink: Option<mpsc::UnboundedSender<Block>>,
+    /// Shared worker statistics
+    stats: Arc<RwLock<WorkerStats>>,
+}
+
+impl ConsensusWorker {
+    /// Create a new worker and a handle to control it
+    pub fn new(
+        engine: Box<dyn Engine>,
+        backend: Arc<dyn ChainBackend>,
+        pool: Arc<dyn TransactionPool>,
+    ) -> (Self, WorkerHandle) {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let stats = Arc::new(RwLock::new(WorkerStats::new()));
+
+        let worker = Self {
+            engine: Arc::new(RwLock::new(engine)),
+            backend,
+            pool,
+            clock: Arc::new(SystemClock),
+            commands: command_rx,
+            deferred: Vec::new(),
+            events: None,
+            block_sink: None,
+            stats: stats.clone(),
+        };
+
+        let handle = WorkerHandle {
+            commands: command_tx,
+            stats,
+        };
+
+        (worker, handle)
+    }
+
+    /// Use a custom time source instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Consume the notifications of an engine's event sender
+    pub fn with_consensus_events(
+        mut self,
+        events: mpsc::UnboundedReceiver<ConsensusEvent>,
+    ) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Forward locally authored blocks to a channel
+    pub fn with_block_sink(mut self, sink: mpsc::UnboundedSender<Block>) -> Self {
+        self.block_sink = Some(sink);
+        self
+    }
+
+    /// The worker's engine, following every block the worker imports
+    ///
+    /// Checks against the chain's current rules, such as the authority set
+    /// of the epoch, should go through this engine rather than a copy.
+    pub fn engine(&self) -> SharedEngine {
+        self.engine.clone()
+    }
+
+    /// Run the step loop until shut down or all handles are dropped
+    pub async fn run(mut self) {
+        info!("Consensus worker started");
+
+        loop {
+            let timeout = self.step().await;
+
+            // Blocks that arrived while sealing are imported before waiting
+            let mut deferred = std::mem::take(&mut self.deferred).into_iter();
+            if !deferred.all(|command| self.handle_command(command)) {
+                info!("Consensus worker stopped");
+                return;
+            }
+
+            let sleep = tokio::time::sleep(timeout);
+            tokio::pin!(sleep);
+
+            // Handle imports and events until the engine is due for another step
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    command = self.commands.recv() => {
+                        if !self.handle_command(command) {
+                            info!("Consensus worker stopped");
+                            return;
+                        }
+                        break;
+                    }
+                    Some(event) = recv_event(&mut self.events) => self.handle_event(event),
+                }
+            }
+        }
+    }
+
+    /// Handle a command, returning whether the worker keeps running
+    fn handle_command(&mut self, command: Option<WorkerCommand>) -> bool {
+        match command {
+            Some(WorkerCommand::Import(block)) => {
+                self.import(*block);
+                true
+            }
+            Some(WorkerCommand::Shutdown) | None => false,
+        }
+    }
+
+    /// Step the engine once, authoring a block if asked to
+    async fn step(&mut self) -> Duration {
+        self.stats.write().unwrap().record_step();
+
+        let result = self
+            .build_context()
+            .and_then(|ctx| self.engine.write().unwrap().step(ctx));
+        match result {
+            Ok(StepResult::Propose { header, timeout }) => {
+                if let Err(e) = self.author_block(header).await {
+                    warn!("Failed to author block: {}", e);
+                    self.stats.write().unwrap().record_step_error(&e);
+                }
+                timeout
+            }
+            Ok(StepResult::Continue { timeout }) | Ok(StepResult::Wait { timeout }) => timeout,
+            Ok(StepResult::Complete) => IDLE_TIMEOUT,
+            Err(e) => {
+                warn!("Consensus step failed: {}", e);
+                self.stats.write().unwrap().record_step_error(&e);
+                IDLE_TIMEOUT
+            }
+        }
+    }
+
+    /// Build the step context from the best block and the pool
+    fn build_context(&self) -> ConsensusResult<StepContext> {
+        let best = self.backend.best_header()?;
+        let parent_hash = best
+            .hash()
+            .map_err(|e| ConsensusError::Other(format!("Failed to hash best header: {}", e)))?;
+
+        Ok(StepContext {
+            block_number: best.number + 1,
+            parent_hash,
+            timestamp: self.clock.now(),
+            validator_index: None,
+            pending_transactions: self.pool.pending_count(),
+        })
+    }
+
+    /// Fill, execute, seal and import a proposed block
+    async fn author_block(&mut self, header: BlockHeader) -> ConsensusResult<()> {
+        let transactions = self.pool.ready_transactions(header.gas_limit);
+        let mut block = self.backend.build_block(header, transactions)?;
+        block.header = match self.seal(block.header).await? {
+            Some(header) => header,
+            None => {
+                debug!("Sealing was cancelled by an incoming block");
+                return Ok(());
+            }
+        };
+
+        self.backend.import_block(block.clone())?;
+        self.engine
+            .write()
+            .unwrap()
+            .import_block(block.header.clone())?;
+        self.pool.remove_included(&block.transactions);
+
+        info!(
+            "Authored block #{} with {} transactions",
+            block.header.number,
+            block.transactions.len()
+        );
+        self.stats.write().unwrap().record_block_authored();
+
+        if let Some(sink) = &self.block_sink {
+            if sink.send(block).is_err() {
+                warn!("Block sink closed, authored block not forwarded");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Seal a header on a blocking thread
+    ///
+    /// Commands received meanwhile are deferred and cancel the seal if the
+    /// engine supports it. Returns `None` if sealing was cancelled.
+    async fn seal(&mut self, header: BlockHeader) -> ConsensusResult<Option<BlockHeader>> {
+        let engine = self.engine.clone();
+        let cancel = engine.read().unwrap().cancel_handle();
+        let mut sealing = tokio::task::spawn_blocking(move || engine.read().unwrap().seal(header));
+
+        let mut cancelled = false;
+        let mut closed = false;
+        let result = loop {
+            tokio::select! {
+                result = &mut sealing => break result,
+                command = self.commands.recv(), if !closed => {
+                    if let Some(cancel) = &cancel {
+                        cancel.store(true, Ordering::SeqCst);
+                        cancelled = true;
+                    }
+                    closed = !matches!(command, Some(WorkerCommand::Import(_)));
+                    self.deferred.push(command);
+                }
+            }
+        };
+
+        match result.map_err(|e| ConsensusError::Other(format!("Sealing task failed: {}", e)))? {
+            Ok(header) => Ok(Some(header)),
+            Err(_) if cancelled => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Verify and import a block received from the network
+    fn import(&mut self, block: Block) {
+        let number = block.header.number;
+        let header = block.header.clone();
+        let transactions = block.transactions.clone();
+
+        // Verify before executing, but only update the engine once the block is stored
+        let verified = self.engine.read().unwrap().verify_block(&header);
+        let result = verified
+            .and_then(|()| self.backend.import_block(block))
+            .and_then(|()| self.engine.write().unwrap().import_block(header))
+            .map(|()| self.pool.remove_included(&transactions));
+
+        match result {
+            Ok(()) => {
+                debug!("Imported block #{}", number);
+                self.stats.write().unwrap().record_block_imported();
+            }
+            Err(e) => {
+                warn!("Failed to import block #{}: {}", number, e);
+                self.stats.write().unwrap().record_import_failure(&e);
+            }
+        }
+    }
+
+    /// React to an engine notification
+    fn handle_event(&mut self, event: ConsensusEvent) {
+        match event {
+            ConsensusEvent::SlashingDetected { offence } => {
+                warn!("Slashing offence reported: {:?}", offence);
+                self.stats.write().unwrap().record_slashing_report();
+            }
+            ConsensusEvent::AuthoritySetChanged { epoch, set_id } => {
+                info!("Authority set {} active from epoch {}", set_id, epoch);
+            }
+            other => debug!("Consensus event: {:?}", other),
+        }
+    }
+}
+
+/// Receive from an optional channel, pending forever if there is none
+async fn recv_event(
+    events: &mut Option<mpsc::UnboundedReceiver<ConsensusEvent>>,
+) -> Option<ConsensusEvent> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instant_seal::{InstantSealConfig, InstantSealEngine};
+    use crate::pow::{PowConfig, PowEngine};
+    use std::sync::Mutex;
+
+    /// In-memory chain that only appends blocks
+    struct MemoryChain {
+        blocks: Mutex<Vec<Block>>,
+    }
+
+    impl MemoryChain {
+        fn new() -> Self {
+            Self {
+                blocks: Mutex::new(vec![Block::genesis()]),
+            }
+        }
+
+        fn height(&self) -> u64 {
+            self.blocks.lock().unwrap().last().unwrap().header.number
+        }
+    }
+
+    impl ChainBackend for MemoryChain {
+        fn best_header(&self) -> ConsensusResult<BlockHeader> {
+            Ok(self.blocks.lock().unwrap().last().unwrap().header.clone())
+        }
+
+        fn build_block(
+            &self,
+            header: BlockHeader,
+            transactions: Vec<Transaction>,
+        ) -> ConsensusResult<Block> {
+            Ok(Block::new(header, transactions))
+        }
+
+        fn import_block(&self, block: Block) -> ConsensusResult<()> {
+            self.blocks.lock().unwrap().push(block);
+            Ok(())
+        }
+    }
+
+    /// Pool holding transactions in submission order
+    struct MemoryPool {
+        transactions: Mutex<Vec<Transaction>>,
+    }
+
+    impl TransactionPool for MemoryPool {
+        fn pending_count(&self) -> usize {
+            self.transactions.lock().unwrap().len()
+        }
+
+        fn ready_transactions(&self, _gas_limit: u64) -> Vec<Transaction> {
+            self.transactions.lock().unwrap().clone()
+        }
+
+        fn remove_included(&self, transactions: &[Transaction]) {
+            self.transactions
+                .lock()
+                .unwrap()
+                .retain(|tx| !transactions.contains(tx));
+        }
+    }
+
+    fn test_worker(
+        pending: Vec<Transaction>,
+    ) -> (
+        ConsensusWorker,
+        WorkerHandle,
+        Arc<MemoryChain>,
+        Arc<MemoryPool>,
+    ) {
+        let engine = InstantSealEngine::new(InstantSealConfig {
+            poll_interval: 10,
+            seal_empty_blocks: false,
+        })
+        .unwrap();
+        let chain = Arc::new(MemoryChain::new());
+        let pool = Arc::new(MemoryPool {
+            transactions: Mutex::new(pending),
+        });
+
+        let (worker, handle) = ConsensusWorker::new(Box::new(engine), chain.clone(), pool.clone());
+        (worker, handle, chain, pool)
+    }
+
+    fn test_transaction(nonce: u64) -> Transaction {
+        Transaction::new(nonce, 1, 21_000, None, 0, vec![])
+    }
+
+    #[tokio::test]
+    async fn test_authors_block_from_pool() {
+        let (worker, handle, chain, pool) = test_worker(vec![test_transaction(0)]);
+        let (sink_tx, mut sink_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(worker.with_block_sink(sink_tx).run());
+
+        let block = tokio::time::timeout(Duration::from_secs(5), sink_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.header.number, 1);
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(chain.height(), 1);
+        assert_eq!(pool.pending_count(), 0);
+        assert_eq!(handle.stats().blocks_authored, 1);
+
+        handle.shutdown().unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_imports_network_blocks() {
+        let (worker, handle, chain, _pool) = test_worker(vec![]);
+        let task = tokio::spawn(worker.run());
+
+        let mut header = BlockHeader::genesis();
+        header.parent_hash = chain.best_header().unwrap().hash().unwrap();
+        header.number = 1;
+        handle
+            .import_block(Block::new(header.clone(), vec![]))
+            .unwrap();
+
+        // A block from the far future is rejected by the engine
+        header.timestamp = u64::MAX / 2;
+        handle.import_block(Block::new(header, vec![])).unwrap();
+
+        handle.shutdown().unwrap();
+        task.await.unwrap();
+
+        let stats = handle.stats();
+        assert_eq!(stats.blocks_imported, 1);
+        assert_eq!(stats.import_failures, 1);
+        assert!(stats.last_error.is_some());
+        assert_eq!(chain.height(), 1);
+
+        // The worker is gone
+        assert!(handle.import_block(Block::genesis()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_network_block_cancels_mining() {
+        // The engine starts from another genesis than the chain, so proposals
+        // on the chain's genesis retarget to the initial difficulty. A
+        // difficulty this high is never met, so only cancellation ends the seal
+        let root = BlockHeader {
+            extra_data: b"PoW root".to_vec(),
+            ..BlockHeader::genesis()
+        };
+        let engine = PowEngine::new(
+            PowConfig {
+                initial_difficulty: u64::MAX,
+                min_difficulty: 1,
+                mining_threads: 1,
+                ..PowConfig::default()
+            },
+            root.clone(),
+        )
+        .unwrap();
+        let chain = Arc::new(MemoryChain::new());
+        let pool = Arc::new(MemoryPool {
+            transactions: Mutex::new(vec![]),
+        });
+        let (worker, handle) = ConsensusWorker::new(Box::new(engine), chain.clone(), pool);
+        let task = tokio::spawn(worker.run());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut header = root.clone();
+        header.parent_hash = root.hash().unwrap();
+        header.number = 1;
+        header.timestamp = 1;
+        handle.import_block(Block::new(header, vec![])).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while handle.stats().blocks_imported == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(chain.height(), 1);
+        assert_eq!(handle.stats().blocks_authored, 0);
+
+        // Shutting down cancels the seal on the new best block too
+        handle.shutdown().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -26,6 +26,9 @@ pub enum CoreError {
     #[error("Trie error: {0}")]
     Trie(String),
 
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
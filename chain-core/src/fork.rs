@@ -0,0 +1,266 @@
+//! Protocol upgrades scheduled by block number
+//!
+//! A [`ForkSchedule`] lists named upgrades together with the block at which
+//! each one activates. Forks may override protocol parameters by name
+//! (for example `slot_duration` or `gas.tx_base`); each crate interprets the
+//! parameters it owns, so every node switches behaviour at the same block.
+
+use crate::error::{CoreError, CoreResult};
+use crate::types::BlockNumber;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// A named protocol upgrade
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fork {
+    /// Upgrade name, unique within a schedule
+    pub name: String,
+    /// First block the upgrade applies to
+    pub block: BlockNumber,
+    /// Protocol parameters changed by the upgrade
+    #[serde(default)]
+    pub params: BTreeMap<String, u64>,
+}
+
+impl Fork {
+    /// Create a fork without parameter changes
+    pub fn new(name: impl Into<String>, block: BlockNumber) -> Self {
+        Self {
+            name: name.into(),
+            block,
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Override a protocol parameter from this fork onwards
+    pub fn with_param(mut self, key: impl Into<String>, value: u64) -> Self {
+        self.params.insert(key.into(), value);
+        self
+    }
+}
+
+/// Identifies the fork rules a node follows at its current head
+///
+/// Nodes exchange fork ids to detect peers that follow a different schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkId {
+    /// Checksum of the forks active at the head
+    pub hash: u32,
+    /// Activation block of the next scheduled fork, if any
+    pub next: Option<BlockNumber>,
+}
+
+/// Upgrades ordered by activation block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Fork>", into = "Vec<Fork>")]
+pub struct ForkSchedule {
+    /// Forks sorted by activation block
+    forks: Vec<Fork>,
+}
+
+impl ForkSchedule {
+    /// Create a schedule, ordering forks by activation block
+    pub fn new(mut forks: Vec<Fork>) -> CoreResult<Self> {
+        let mut names = HashSet::new();
+        for fork in &forks {
+            if fork.name.is_empty() {
+                return Err(CoreError::InvalidConfig(
+                    "Fork name must not be empty".to_string(),
+                ));
+            }
+            if !names.insert(fork.name.as_str()) {
+                return Err(CoreError::InvalidConfig(format!(
+                    "Duplicate fork name: {}",
+                    fork.name
+                )));
+            }
+        }
+
+        // Stable sort keeps the listed order for forks at the same block
+        forks.sort_by_key(|fork| fork.block);
+        Ok(Self { forks })
+    }
+
+    /// Get all forks in activation order
+    pub fn forks(&self) -> &[Fork] {
+        &self.forks
+    }
+
+    /// Get the activation block of a fork
+    pub fn activation_block(&self, name: &str) -> Option<BlockNumber> {
+        self.forks.iter().find(|f| f.name == name).map(|f| f.block)
+    }
+
+    /// Check if a fork is active at a block
+    pub fn is_active(&self, name: &str, block: BlockNumber) -> bool {
+        self.activation_block(name)
+            .is_some_and(|activation| activation <= block)
+    }
+
+    /// Iterate over the forks active at a block
+    pub fn active_forks(&self, block: BlockNumber) -> impl Iterator<Item = &Fork> {
+        self.forks.iter().take_while(move |f| f.block <= block)
+    }
+
+    /// Get the value of a parameter at a block, set by the latest active fork
+    pub fn param(&self, key: &str, block: BlockNumber) -> Option<u64> {
+        self.active_forks(block)
+            .filter_map(|f| f.params.get(key).copied())
+            .last()
+    }
+
+    /// Get the value a parameter changes to exactly at a block
+    pub fn param_change_at(&self, key: &str, block: BlockNumber) -> Option<u64> {
+        self.forks
+            .iter()
+            .rev()
+            .filter(|f| f.block == block)
+            .find_map(|f| f.params.get(key).copied())
+    }
+
+    /// Get all parameter values at a block
+    pub fn params(&self, block: BlockNumber) -> BTreeMap<String, u64> {
+        let mut params = BTreeMap::new();
+        for fork in self.active_forks(block) {
+            params.extend(fork.params.iter().map(|(k, v)| (k.clone(), *v)));
+        }
+        params
+    }
+
+    /// Get the activation block of the first fork after a block
+    pub fn next_fork(&self, block: BlockNumber) -> Option<BlockNumber> {
+        self.forks.iter().map(|f| f.block).find(|b| *b > block)
+    }
+
+    /// Get the fork id at a head block
+    pub fn fork_id(&self, head: BlockNumber) -> ForkId {
+        ForkId {
+            hash: self.fork_hash(self.active_forks(head).count()),
+            next: self.next_fork(head),
+        }
+    }
+
+    /// Check whether a peer with the given fork id follows this schedule
+    ///
+    /// A peer is compatible if it has applied exactly the forks we have, or if
+    /// one of us is behind but the fork it expects next is the one the other
+    /// applied.
+    pub fn is_compatible(&self, head: BlockNumber, remote: &ForkId) -> bool {
+        let local_count = self.active_forks(head).count();
+
+        for count in 0..=self.forks.len() {
+            if self.fork_hash(count) != remote.hash {
+                continue;
+            }
+
+            return match count.cmp(&local_count) {
+                // Same rules: the peer must not expect a fork we already passed
+                std::cmp::Ordering::Equal => remote.next.is_none_or(|next| next > head),
+                // Peer is behind: its next fork must be the next one we applied
+                std::cmp::Ordering::Less => remote.next == Some(self.forks[count].block),
+                // Peer is ahead on our own schedule
+                std::cmp::Ordering::Greater => true,
+            };
+        }
+
+        false
+    }
+
+    /// Checksum of the first `count` forks
+    fn fork_hash(&self, count: usize) -> u32 {
+        let mut hasher = blake3::Hasher::new();
+        for fork in &self.forks[..count] {
+            hasher.update(fork.name.as_bytes());
+            hasher.update(&fork.block.to_be_bytes());
+            for (key, value) in &fork.params {
+                hasher.update(key.as_bytes());
+                hasher.update(&value.to_be_bytes());
+            }
+        }
+        let hash = hasher.finalize();
+        u32::from_be_bytes(hash.as_bytes()[..4].try_into().unwrap())
+    }
+}
+
+impl TryFrom<Vec<Fork>> for ForkSchedule {
+    type Error = CoreError;
+
+    fn try_from(forks: Vec<Fork>) -> CoreResult<Self> {
+        Self::new(forks)
+    }
+}
+
+impl From<ForkSchedule> for Vec<Fork> {
+    fn from(schedule: ForkSchedule) -> Self {
+        schedule.forks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_schedule() -> ForkSchedule {
+        ForkSchedule::new(vec![
+            Fork::new("faster_slots", 200).with_param("slot_duration", 2000),
+            Fork::new("cheaper_transfers", 100).with_param("gas.balance_transfer", 5000),
+            Fork::new("slower_slots", 300).with_param("slot_duration", 4000),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_activation_and_params() {
+        let schedule = test_schedule();
+        assert_eq!(schedule.forks()[0].name, "cheaper_transfers");
+
+        assert!(!schedule.is_active("faster_slots", 199));
+        assert!(schedule.is_active("faster_slots", 200));
+        assert!(!schedule.is_active("unknown", 1000));
+
+        assert_eq!(schedule.param("slot_duration", 199), None);
+        assert_eq!(schedule.param("slot_duration", 250), Some(2000));
+        assert_eq!(schedule.param("slot_duration", 300), Some(4000));
+        assert_eq!(schedule.param_change_at("slot_duration", 200), Some(2000));
+        assert_eq!(schedule.param_change_at("slot_duration", 201), None);
+        assert_eq!(schedule.params(300).len(), 2);
+        assert_eq!(schedule.next_fork(200), Some(300));
+    }
+
+    #[test]
+    fn test_duplicate_names_rejected() {
+        let result = ForkSchedule::new(vec![Fork::new("a", 1), Fork::new("a", 2)]);
+        assert!(result.is_err());
+
+        let json = r#"[{"name": "a", "block": 1}, {"name": "a", "block": 2}]"#;
+        assert!(serde_json::from_str::<ForkSchedule>(json).is_err());
+    }
+
+    #[test]
+    fn test_fork_id_compatibility() {
+        let schedule = test_schedule();
+        let at_150 = schedule.fork_id(150);
+        assert_eq!(at_150.next, Some(200));
+
+        // Same head
+        assert!(schedule.is_compatible(150, &at_150));
+        // Peer behind us, about to apply the fork we applied
+        assert!(schedule.is_compatible(250, &at_150));
+        // Peer ahead of us
+        assert!(schedule.is_compatible(50, &at_150));
+
+        // Peer that never schedules the fork at 200
+        let other = ForkSchedule::new(vec![
+            Fork::new("cheaper_transfers", 100).with_param("gas.balance_transfer", 5000)
+        ])
+        .unwrap();
+        assert!(!schedule.is_compatible(250, &other.fork_id(250)));
+
+        // Different parameters give a different hash
+        let changed = ForkSchedule::new(vec![
+            Fork::new("cheaper_transfers", 100).with_param("gas.balance_transfer", 6000)
+        ])
+        .unwrap();
+        assert!(!schedule.is_compatible(150, &changed.fork_id(150)));
+    }
+}
@@ -4,10 +4,16 @@
 //! - Basic types (Hash, Address, BlockNumber, etc.)
 //! - Transaction and Block structures  
 //! - Trie interface for state management
+//! - Merkle range proofs over state entries
+//! - Block reward and fee distribution parameters
+//! - Protocol upgrade scheduling
 //! - Cryptographic utilities
 
 pub mod block;
 pub mod error;
+pub mod fork;
+pub mod merkle;
+pub mod rewards;
 pub mod transaction;
 pub mod trie;
 pub mod types;
@@ -15,6 +21,9 @@ pub mod types;
 // Re-export commonly used types
 pub use block::*;
 pub use error::*;
+pub use fork::*;
+pub use merkle::*;
+pub use rewards::*;
 pub use transaction::*;
 pub use trie::*;
 pub use types::*;
@@ -0,0 +1,301 @@
+//! Merkle commitments over sorted state entries
+//!
+//! State is committed to as a binary Merkle tree whose leaves are the
+//! key-value entries in key order. The tree for `n` leaves splits them at the
+//! largest power of two below `n`, so its shape depends only on the leaf count.
+//! A [`RangeProof`] shows that a run of consecutive entries sits at a given
+//! position in the tree, which lets state be downloaded in verified pieces.
+//! A [`MerkleTree`] keeps every node of the tree, so proofs can be served
+//! repeatedly without rehashing the state.
+
+use crate::{CoreError, CoreResult, Hash};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// A state key and its value
+pub type StateEntry = (Vec<u8>, Vec<u8>);
+
+/// Domain separator for leaf hashes
+const LEAF_PREFIX: u8 = 0;
+/// Domain separator for inner node hashes
+const NODE_PREFIX: u8 = 1;
+
+/// Compute the root of entries sorted by key
+///
+/// The root of an empty state is the zero hash.
+pub fn entries_root(entries: &[StateEntry]) -> Hash {
+    if entries.is_empty() {
+        return Hash::zero();
+    }
+    let leaves: Vec<Hash> = entries.iter().map(leaf_hash).collect();
+    subtree_root(&leaves)
+}
+
+/// Proof that consecutive entries sit at a position in the tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeProof {
+    /// Number of entries in the whole state
+    pub leaf_count: u64,
+    /// Position of the first proven entry
+    pub first_index: u64,
+    /// Roots of the subtrees outside the range, left to right
+    pub hashes: Vec<Hash>,
+}
+
+impl RangeProof {
+    /// Prove the entries in `start..end` of the full, sorted state
+    pub fn generate(entries: &[StateEntry], start: usize, end: usize) -> Self {
+        MerkleTree::new(entries).prove(start, end)
+    }
+
+    /// Check that `entries` are the proven range of the state with `root`
+    pub fn verify(&self, root: &Hash, entries: &[StateEntry]) -> CoreResult<()> {
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(invalid_proof("entries are not sorted by key"));
+        }
+
+        let start = self.first_index;
+        let end = start
+            .checked_add(entries.len() as u64)
+            .filter(|end| *end <= self.leaf_count)
+            .ok_or_else(|| invalid_proof("range exceeds the leaf count"))?;
+
+        if self.leaf_count == 0 {
+            if self.hashes.is_empty() && start == 0 && *root == Hash::zero() {
+                return Ok(());
+            }
+            return Err(invalid_proof("malformed proof for an empty state"));
+        }
+
+        let leaves: Vec<Hash> = entries.iter().map(leaf_hash).collect();
+        let mut hashes = self.hashes.iter();
+        let computed = rebuild(0, self.leaf_count, start, end, &leaves, &mut hashes)?;
+
+        if hashes.next().is_some() {
+            return Err(invalid_proof("unused proof hashes"));
+        }
+        if computed != *root {
+            return Err(invalid_proof("root mismatch"));
+        }
+        Ok(())
+    }
+}
+
+/// Every node of the tree over a sorted state
+///
+/// Nodes are stored in pre-order: a subtree of `n` leaves takes `2n - 1`
+/// slots, its root first, followed by its left and right subtrees.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MerkleTree {
+    /// Number of leaves
+    leaf_count: usize,
+    /// Node hashes in pre-order
+    nodes: Vec<Hash>,
+}
+
+impl MerkleTree {
+    /// Build the tree over entries sorted by key
+    pub fn new(entries: &[StateEntry]) -> Self {
+        let leaves: Vec<Hash> = entries.iter().map(leaf_hash).collect();
+        let mut nodes = Vec::with_capacity((2 * leaves.len()).saturating_sub(1));
+        if !leaves.is_empty() {
+            build_nodes(&leaves, &mut nodes);
+        }
+        Self {
+            leaf_count: leaves.len(),
+            nodes,
+        }
+    }
+
+    /// Get the root, the zero hash for an empty state
+    pub fn root(&self) -> Hash {
+        self.nodes.first().copied().unwrap_or_else(Hash::zero)
+    }
+
+    /// Get the number of entries committed to
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Prove the entries in `start..end`
+    pub fn prove(&self, start: usize, end: usize) -> RangeProof {
+        let mut hashes = Vec::new();
+        if self.leaf_count > 0 {
+            self.collect_siblings(0, 0, self.leaf_count, start, end, &mut hashes);
+        }
+
+        RangeProof {
+            leaf_count: self.leaf_count as u64,
+            first_index: start as u64,
+            hashes,
+        }
+    }
+
+    /// Collect the roots of subtrees disjoint from `start..end`
+    fn collect_siblings(
+        &self,
+        index: usize,
+        offset: usize,
+        size: usize,
+        start: usize,
+        end: usize,
+        hashes: &mut Vec<Hash>,
+    ) {
+        if offset >= end || offset + size <= start {
+            hashes.push(self.nodes[index]);
+        } else if offset < start || offset + size > end {
+            let split = split_point(size as u64) as usize;
+            self.collect_siblings(index + 1, offset, split, start, end, hashes);
+            self.collect_siblings(
+                index + 2 * split,
+                offset + split,
+                size - split,
+                start,
+                end,
+                hashes,
+            );
+        }
+    }
+}
+
+fn invalid_proof(reason: &str) -> CoreError {
+    CoreError::Trie(format!("Invalid range proof: {}", reason))
+}
+
+fn leaf_hash((key, value): &StateEntry) -> Hash {
+    let mut hasher = Keccak256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update((key.len() as u32).to_be_bytes());
+    hasher.update(key);
+    hasher.update(value);
+    Hash::from_slice(hasher.finalize().as_slice())
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Keccak256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    Hash::from_slice(hasher.finalize().as_slice())
+}
+
+/// Number of leaves in the left subtree of a tree with `size > 1` leaves
+fn split_point(size: u64) -> u64 {
+    1 << (u64::BITS - 1 - (size - 1).leading_zeros())
+}
+
+fn subtree_root(leaves: &[Hash]) -> Hash {
+    if leaves.len() == 1 {
+        return leaves[0];
+    }
+    let split = split_point(leaves.len() as u64) as usize;
+    node_hash(
+        &subtree_root(&leaves[..split]),
+        &subtree_root(&leaves[split..]),
+    )
+}
+
+/// Append the nodes of a subtree in pre-order and return its root
+fn build_nodes(leaves: &[Hash], nodes: &mut Vec<Hash>) -> Hash {
+    let index = nodes.len();
+    nodes.push(Hash::zero());
+    let root = if leaves.len() == 1 {
+        leaves[0]
+    } else {
+        let split = split_point(leaves.len() as u64) as usize;
+        let left = build_nodes(&leaves[..split], nodes);
+        let right = build_nodes(&leaves[split..], nodes);
+        node_hash(&left, &right)
+    };
+    nodes[index] = root;
+    root
+}
+
+/// Recompute the root of a subtree from the range leaves and proof hashes
+fn rebuild<'a>(
+    offset: u64,
+    size: u64,
+    start: u64,
+    end: u64,
+    leaves: &[Hash],
+    hashes: &mut impl Iterator<Item = &'a Hash>,
+) -> CoreResult<Hash> {
+    if offset >= end || offset + size <= start {
+        return hashes
+            .next()
+            .copied()
+            .ok_or_else(|| invalid_proof("missing proof hashes"));
+    }
+    if offset >= start && offset + size <= end {
+        let first = (offset - start) as usize;
+        return Ok(subtree_root(&leaves[first..first + size as usize]));
+    }
+
+    let split = split_point(size);
+    let left = rebuild(offset, split, start, end, leaves, hashes)?;
+    let right = rebuild(offset + split, size - split, start, end, leaves, hashes)?;
+    Ok(node_hash(&left, &right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_entries(count: u32) -> Vec<StateEntry> {
+        (0..count)
+            .map(|i| (i.to_be_bytes().to_vec(), vec![i as u8; 4]))
+            .collect()
+    }
+
+    #[test]
+    fn test_range_proofs() {
+        let entries = test_entries(13);
+        let root = entries_root(&entries);
+
+        for start in 0..=entries.len() {
+            for end in start..=entries.len() {
+                let proof = RangeProof::generate(&entries, start, end);
+                assert!(proof.verify(&root, &entries[start..end]).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_matches_entries_root() {
+        for count in [0, 1, 2, 5, 8, 13] {
+            let entries = test_entries(count);
+            let tree = MerkleTree::new(&entries);
+            assert_eq!(tree.root(), entries_root(&entries));
+            assert_eq!(tree.leaf_count(), entries.len());
+        }
+    }
+
+    #[test]
+    fn test_invalid_proofs_rejected() {
+        let entries = test_entries(10);
+        let root = entries_root(&entries);
+        let proof = RangeProof::generate(&entries, 3, 7);
+
+        // Tampered value
+        let mut tampered = entries[3..7].to_vec();
+        tampered[1].1 = vec![0xff];
+        assert!(proof.verify(&root, &tampered).is_err());
+
+        // Entry left out of the middle of the range
+        let mut skipped = entries[3..8].to_vec();
+        skipped.remove(2);
+        assert!(proof.verify(&root, &skipped).is_err());
+
+        // Right entries claimed at the wrong position
+        let shifted = RangeProof {
+            first_index: 4,
+            ..proof.clone()
+        };
+        assert!(shifted.verify(&root, &entries[3..7]).is_err());
+
+        // Empty state
+        let empty = RangeProof::generate(&[], 0, 0);
+        assert!(empty.verify(&Hash::zero(), &[]).is_ok());
+        assert!(empty.verify(&root, &[]).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Block reward, inflation and fee distribution parameters
+//!
+//! All amounts are computed with integer arithmetic so every node derives
+//! exactly the same end-of-block balances.
+
+use crate::error::{CoreError, CoreResult};
+use crate::types::Address;
+use serde::{Deserialize, Serialize};
+
+/// Denominator for rates expressed in basis points
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Issuance and fee distribution configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewardConfig {
+    /// Fixed amount minted for the proposer of every block
+    pub block_reward: u64,
+    /// Yearly inflation of the total issuance in basis points (0 disables inflation)
+    pub inflation_bps: u64,
+    /// Expected number of blocks per year, used to spread inflation over blocks
+    pub blocks_per_year: u64,
+    /// Share of transaction fees that is burned, in basis points
+    pub fee_burn_bps: u64,
+    /// Share of transaction fees paid to the treasury, in basis points
+    pub fee_treasury_bps: u64,
+    /// Treasury account receiving its share of fees
+    pub treasury: Option<Address>,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        Self {
+            block_reward: 0,
+            inflation_bps: 0,
+            blocks_per_year: 10_512_000, // 3 second blocks
+            fee_burn_bps: 0,
+            fee_treasury_bps: 0,
+            treasury: None,
+        }
+    }
+}
+
+/// How the fees of a block are distributed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeSplit {
+    /// Paid to the block proposer
+    pub proposer: u64,
+    /// Paid to the treasury
+    pub treasury: u64,
+    /// Removed from circulation
+    pub burned: u64,
+}
+
+impl RewardConfig {
+    /// Validate the configuration
+    pub fn validate(&self) -> CoreResult<()> {
+        if self.inflation_bps > 0 && self.blocks_per_year == 0 {
+            return Err(CoreError::InvalidConfig(
+                "Blocks per year must be greater than 0 when inflation is enabled".to_string(),
+            ));
+        }
+
+        if self.fee_burn_bps + self.fee_treasury_bps > BASIS_POINTS {
+            return Err(CoreError::InvalidConfig(format!(
+                "Fee burn and treasury shares exceed {} basis points",
+                BASIS_POINTS
+            )));
+        }
+
+        if self.fee_treasury_bps > 0 && self.treasury.is_none() {
+            return Err(CoreError::InvalidConfig(
+                "A treasury account is required for a treasury fee share".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Amount minted for a block given the current total issuance
+    ///
+    /// This is the fixed block reward plus the per-block share of yearly inflation.
+    pub fn issuance_for_block(&self, total_issuance: u128) -> u64 {
+        let inflation = if self.inflation_bps > 0 && self.blocks_per_year > 0 {
+            total_issuance * self.inflation_bps as u128
+                / BASIS_POINTS as u128
+                / self.blocks_per_year as u128
+        } else {
+            0
+        };
+
+        self.block_reward
+            .saturating_add(inflation.min(u64::MAX as u128) as u64)
+    }
+
+    /// Split the fees of a block between burning, the treasury and the proposer
+    ///
+    /// Rounding remainders go to the proposer so no fees are lost.
+    pub fn split_fees(&self, fees: u64) -> FeeSplit {
+        let share = |bps: u64| (fees as u128 * bps as u128 / BASIS_POINTS as u128) as u64;
+
+        let burned = share(self.fee_burn_bps);
+        let treasury = if self.treasury.is_some() {
+            share(self.fee_treasury_bps)
+        } else {
+            0
+        };
+
+        FeeSplit {
+            proposer: fees - burned - treasury,
+            treasury,
+            burned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issuance_for_block() {
+        let config = RewardConfig {
+            block_reward: 5,
+            inflation_bps: 500, // 5% per year
+            blocks_per_year: 100,
+            ..Default::default()
+        };
+
+        // 5% of 1_000_000 spread over 100 blocks
+        assert_eq!(config.issuance_for_block(1_000_000), 5 + 500);
+        assert_eq!(RewardConfig::default().issuance_for_block(1_000_000), 0);
+    }
+
+    #[test]
+    fn test_split_fees() {
+        let config = RewardConfig {
+            fee_burn_bps: 3_000,
+            fee_treasury_bps: 2_000,
+            treasury: Some(Address::new([7u8; 20])),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+
+        let split = config.split_fees(1_001);
+        assert_eq!(split.burned, 300);
+        assert_eq!(split.treasury, 200);
+        assert_eq!(split.proposer, 501);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = RewardConfig {
+            fee_treasury_bps: 100,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.treasury = Some(Address::zero());
+        config.fee_burn_bps = BASIS_POINTS;
+        assert!(config.validate().is_err());
+    }
+}
@@ -41,6 +41,52 @@ impl Signature {
 
         Ok(Self { r, s, v })
     }
+
+    /// Sign a 32-byte hash with a secp256k1 private key
+    pub fn sign_hash(hash: &Hash, private_key: &[u8]) -> CoreResult<Self> {
+        let secp = secp256k1::Secp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(private_key)
+            .map_err(|e| CoreError::Crypto(e.to_string()))?;
+        let message = secp256k1::Message::from_digest_slice(hash.as_bytes())
+            .map_err(|e| CoreError::Crypto(e.to_string()))?;
+
+        let sig = secp.sign_ecdsa_recoverable(message, &secret_key);
+        let (recovery_id, sig_bytes) = sig.serialize_compact();
+
+        // Extract r, s, v
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        r.copy_from_slice(&sig_bytes[0..32]);
+        s.copy_from_slice(&sig_bytes[32..64]);
+        Ok(Self::new(r, s, recovery_id as u8))
+    }
+
+    /// Recover the address that signed a hash
+    pub fn recover_address(&self, hash: &Hash) -> CoreResult<Address> {
+        let secp = secp256k1::Secp256k1::new();
+        let recovery_id = secp256k1::ecdsa::RecoveryId::from_u8_masked(self.v);
+
+        let mut sig_bytes = [0u8; 64];
+        sig_bytes[0..32].copy_from_slice(&self.r);
+        sig_bytes[32..64].copy_from_slice(&self.s);
+
+        let recoverable_sig =
+            secp256k1::ecdsa::RecoverableSignature::from_compact(&sig_bytes, recovery_id)
+                .map_err(|e| CoreError::Crypto(e.to_string()))?;
+        let message = secp256k1::Message::from_digest_slice(hash.as_bytes())
+            .map_err(|e| CoreError::Crypto(e.to_string()))?;
+        let public_key = secp
+            .recover_ecdsa(message, &recoverable_sig)
+            .map_err(|e| CoreError::Crypto(e.to_string()))?;
+
+        // Convert public key to address (last 20 bytes of Keccak256 hash)
+        let pubkey_bytes = public_key.serialize_uncompressed();
+        let pubkey_hash = Keccak256::digest(&pubkey_bytes[1..]); // Skip first byte (0x04)
+        let mut addr_bytes = [0u8; 20];
+        addr_bytes.copy_from_slice(&pubkey_hash[12..32]); // Last 20 bytes
+
+        Ok(Address::new(addr_bytes))
+    }
 }
 
 /// Transaction data structure
@@ -131,27 +177,7 @@ impl Transaction {
     /// Sign the transaction with private key
     pub fn sign(&mut self, private_key: &[u8]) -> CoreResult<()> {
         let signing_hash = self.signing_hash()?;
-
-        // Create secp256k1 context
-        let secp = secp256k1::Secp256k1::new();
-        let secret_key = secp256k1::SecretKey::from_slice(private_key)
-            .map_err(|e| CoreError::Crypto(e.to_string()))?;
-
-        // Sign the hash
-        let message = secp256k1::Message::from_digest_slice(signing_hash.as_bytes())
-            .map_err(|e| CoreError::Crypto(e.to_string()))?;
-
-        let sig = secp.sign_ecdsa_recoverable(message, &secret_key);
-        let (recovery_id, sig_bytes) = sig.serialize_compact();
-
-        // Extract r, s, v
-        let mut r = [0u8; 32];
-        let mut s = [0u8; 32];
-        r.copy_from_slice(&sig_bytes[0..32]);
-        s.copy_from_slice(&sig_bytes[32..64]);
-        let v = recovery_id as u8;
-
-        self.signature = Some(Signature::new(r, s, v));
+        self.signature = Some(Signature::sign_hash(&signing_hash, private_key)?);
         Ok(())
     }
 
@@ -196,37 +222,7 @@ impl Transaction {
             None => return Err(CoreError::InvalidSignature),
         };
 
-        let signing_hash = self.signing_hash()?;
-
-        // Create secp256k1 context
-        let secp = secp256k1::Secp256k1::new();
-
-        // Recreate signature
-        let recovery_id = secp256k1::ecdsa::RecoveryId::from_u8_masked(signature.v);
-
-        let mut sig_bytes = [0u8; 64];
-        sig_bytes[0..32].copy_from_slice(&signature.r);
-        sig_bytes[32..64].copy_from_slice(&signature.s);
-
-        let recoverable_sig =
-            secp256k1::ecdsa::RecoverableSignature::from_compact(&sig_bytes, recovery_id)
-                .map_err(|e| CoreError::Crypto(e.to_string()))?;
-
-        // Recover public key
-        let message = secp256k1::Message::from_digest_slice(signing_hash.as_bytes())
-            .map_err(|e| CoreError::Crypto(e.to_string()))?;
-
-        let public_key = secp
-            .recover_ecdsa(message, &recoverable_sig)
-            .map_err(|e| CoreError::Crypto(e.to_string()))?;
-
-        // Convert public key to address (last 20 bytes of Keccak256 hash)
-        let pubkey_bytes = public_key.serialize_uncompressed();
-        let pubkey_hash = Keccak256::digest(&pubkey_bytes[1..]); // Skip first byte (0x04)
-        let mut addr_bytes = [0u8; 20];
-        addr_bytes.copy_from_slice(&pubkey_hash[12..32]); // Last 20 bytes
-
-        Ok(Address::new(addr_bytes))
+        signature.recover_address(&self.signing_hash()?)
     }
 }
 
@@ -276,4 +272,21 @@ mod tests {
         assert_eq!(tx.to, None);
         assert_eq!(tx.data, code);
     }
+
+    #[test]
+    fn test_sign_hash_recovers_signer() {
+        let key = [7u8; 32];
+        let mut tx = Transaction::transfer(0, Address::zero(), 1, 1, 21_000);
+        tx.sign(&key).unwrap();
+
+        let hash = Hash::from_slice(&[1u8; 32]);
+        let signature = Signature::sign_hash(&hash, &key).unwrap();
+        assert_eq!(
+            signature.recover_address(&hash).unwrap(),
+            tx.sender().unwrap()
+        );
+
+        let other = Hash::from_slice(&[2u8; 32]);
+        assert_ne!(signature.recover_address(&other).ok(), tx.sender().ok());
+    }
 }
@@ -20,6 +20,8 @@ pub enum ColumnFamily {
     State,
     /// Block number to hash index (block_number -> block_hash)
     Indices,
+    /// Peer reputations and bans (peer_id or ip -> JSON record)
+    Peers,
 }
 
 impl ColumnFamily {
@@ -32,6 +34,7 @@ impl ColumnFamily {
             ColumnFamily::Receipts => "receipts",
             ColumnFamily::State => "state",
             ColumnFamily::Indices => "indices",
+            ColumnFamily::Peers => "peers",
         }
     }
 
@@ -44,6 +47,7 @@ impl ColumnFamily {
             ColumnFamily::Receipts,
             ColumnFamily::State,
             ColumnFamily::Indices,
+            ColumnFamily::Peers,
         ]
     }
 
@@ -56,6 +60,7 @@ impl ColumnFamily {
             "receipts" => Some(ColumnFamily::Receipts),
             "state" => Some(ColumnFamily::State),
             "indices" => Some(ColumnFamily::Indices),
+            "peers" => Some(ColumnFamily::Peers),
             _ => None,
         }
     }
@@ -151,7 +156,9 @@ pub fn get_column_family_configs() -> HashMap<ColumnFamily, ColumnFamilyConfig>
     configs.insert(ColumnFamily::Blocks, blocks_config);
     configs.insert(ColumnFamily::Receipts, default_config.clone());
     configs.insert(ColumnFamily::State, state_config);
-    configs.insert(ColumnFamily::Indices, indices_config);
+    configs.insert(ColumnFamily::Indices, indices_config.clone());
+    // Peers: few small records, tuned like indices
+    configs.insert(ColumnFamily::Peers, indices_config);
     
     configs
 }
@@ -168,6 +175,7 @@ mod tests {
         assert_eq!(ColumnFamily::Receipts.name(), "receipts");
         assert_eq!(ColumnFamily::State.name(), "state");
         assert_eq!(ColumnFamily::Indices.name(), "indices");
+        assert_eq!(ColumnFamily::Peers.name(), "peers");
     }
 
     #[test]
@@ -180,7 +188,7 @@ mod tests {
     #[test]
     fn test_all_column_families() {
         let all = ColumnFamily::all();
-        assert_eq!(all.len(), 6);
+        assert_eq!(all.len(), 7);
         assert!(all.contains(&ColumnFamily::Default));
         assert!(all.contains(&ColumnFamily::Blocks));
     }
@@ -188,7 +196,7 @@ mod tests {
     #[test]
     fn test_column_family_configs() {
         let configs = get_column_family_configs();
-        assert_eq!(configs.len(), 6);
+        assert_eq!(configs.len(), 7);
         assert!(configs.contains_key(&ColumnFamily::State));
         
         let state_config = &configs[&ColumnFamily::State];
@@ -5,12 +5,7 @@ use thiserror::Error;
 /// Database error type
 #[derive(Error, Debug)]
 pub enum DbError {
-    /// RocksDB error
-    #[error("RocksDB error: {0}")]
-    RocksDb(#[from] rocksdb::Error),
-
     /// Sled error
-    #[cfg(feature = "sled-backend")]
     #[error("Sled error: {0}")]
     Sled(#[from] sled::Error),
 
@@ -47,8 +42,14 @@ pub enum DbError {
     Other(String),
 }
 
-impl From<bincode::Error> for DbError {
-    fn from(err: bincode::Error) -> Self {
+impl From<bincode::error::EncodeError> for DbError {
+    fn from(err: bincode::error::EncodeError) -> Self {
+        DbError::Serialization(err.to_string())
+    }
+}
+
+impl From<bincode::error::DecodeError> for DbError {
+    fn from(err: bincode::error::DecodeError) -> Self {
         DbError::Serialization(err.to_string())
     }
 }
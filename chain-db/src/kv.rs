@@ -0,0 +1,388 @@
+//! Key-value database backed by sled
+//!
+//! Every column family is a sled tree of the same name. Transactions buffer
+//! their writes and commit them atomically across trees.
+
+use crate::column_families::ColumnFamily;
+use crate::traits::{ColumnFamilyStats, DatabaseStats, DbTx, KeyValueDB, SnapshotReader};
+use crate::{DbError, DbResult};
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::Transactional;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Default page cache size in bytes
+pub const DEFAULT_CACHE_CAPACITY: u64 = 256 * 1024 * 1024;
+
+/// Default interval between background flushes in milliseconds
+pub const DEFAULT_FLUSH_EVERY_MS: u64 = 500;
+
+/// Database configuration
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    /// Directory holding the database files
+    pub path: PathBuf,
+    /// Page cache size in bytes
+    pub cache_capacity: u64,
+    /// Interval between background flushes, `None` to flush only on demand
+    pub flush_every_ms: Option<u64>,
+}
+
+impl DatabaseConfig {
+    /// Create a configuration for a database directory
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            flush_every_ms: Some(DEFAULT_FLUSH_EVERY_MS),
+        }
+    }
+
+    /// Set the page cache size in bytes
+    pub fn with_cache_capacity(mut self, cache_capacity: u64) -> Self {
+        self.cache_capacity = cache_capacity;
+        self
+    }
+
+    /// Set the background flush interval
+    pub fn with_flush_every_ms(mut self, flush_every_ms: Option<u64>) -> Self {
+        self.flush_every_ms = flush_every_ms;
+        self
+    }
+}
+
+/// Column family trees by name
+type Trees = Arc<HashMap<&'static str, sled::Tree>>;
+
+/// Persistent [`KeyValueDB`] holding every [`ColumnFamily`]
+pub struct Database {
+    /// Underlying sled database
+    db: sled::Db,
+    /// Trees of the column families
+    trees: Trees,
+}
+
+impl Database {
+    /// Open or create a database with the default configuration
+    pub fn open(path: impl AsRef<Path>) -> DbResult<Self> {
+        Self::open_with_config(&DatabaseConfig::new(path))
+    }
+
+    /// Open or create a database
+    pub fn open_with_config(config: &DatabaseConfig) -> DbResult<Self> {
+        let db = sled::Config::new()
+            .path(&config.path)
+            .cache_capacity(config.cache_capacity)
+            .flush_every_ms(config.flush_every_ms)
+            .open()?;
+        let trees = ColumnFamily::all()
+            .iter()
+            .map(|cf| Ok((cf.name(), db.open_tree(cf.name())?)))
+            .collect::<DbResult<HashMap<_, _>>>()?;
+        Ok(Self {
+            db,
+            trees: Arc::new(trees),
+        })
+    }
+}
+
+/// Get the tree of a column family
+fn tree<'a>(trees: &'a Trees, cf: &str) -> DbResult<&'a sled::Tree> {
+    trees
+        .get(cf)
+        .ok_or_else(|| DbError::Config(format!("Unknown column family: {}", cf)))
+}
+
+/// Get the name of a known column family
+fn column(trees: &Trees, cf: &str) -> DbResult<&'static str> {
+    trees
+        .get_key_value(cf)
+        .map(|(name, _)| *name)
+        .ok_or_else(|| DbError::Config(format!("Unknown column family: {}", cf)))
+}
+
+/// Decode a sled entry
+fn entry(item: sled::Result<(sled::IVec, sled::IVec)>) -> DbResult<(Vec<u8>, Vec<u8>)> {
+    let (key, value) = item?;
+    Ok((key.to_vec(), value.to_vec()))
+}
+
+impl KeyValueDB for Database {
+    fn get(&self, cf: &str, key: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        Ok(tree(&self.trees, cf)?.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn put(&self, cf: &str, key: &[u8], value: &[u8]) -> DbResult<()> {
+        tree(&self.trees, cf)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn delete(&self, cf: &str, key: &[u8]) -> DbResult<()> {
+        tree(&self.trees, cf)?.remove(key)?;
+        Ok(())
+    }
+
+    fn exists(&self, cf: &str, key: &[u8]) -> DbResult<bool> {
+        Ok(tree(&self.trees, cf)?.contains_key(key)?)
+    }
+
+    fn transaction(&self) -> Box<dyn DbTx> {
+        Box::new(Transaction {
+            trees: self.trees.clone(),
+            writes: BTreeMap::new(),
+        })
+    }
+
+    /// Copy the column families, since sled has no point-in-time reads
+    ///
+    /// This reads the whole database, so snapshots are meant for tests and
+    /// offline tools rather than hot paths. Read errors fail the snapshot
+    /// instead of leaving entries out of it.
+    fn snapshot(&self) -> DbResult<Box<dyn SnapshotReader>> {
+        let data = self
+            .trees
+            .iter()
+            .map(|(name, tree)| {
+                let entries = tree.iter().map(entry).collect::<DbResult<Entries>>()?;
+                Ok((*name, entries))
+            })
+            .collect::<DbResult<HashMap<_, _>>>()?;
+        Ok(Box::new(Snapshot {
+            data: Arc::new(data),
+        }))
+    }
+
+    fn iter(&self, cf: &str) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>> {
+        Ok(Box::new(tree(&self.trees, cf)?.iter().map(entry)))
+    }
+
+    fn iter_prefix(
+        &self,
+        cf: &str,
+        prefix: &[u8],
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>> {
+        Ok(Box::new(
+            tree(&self.trees, cf)?.scan_prefix(prefix).map(entry),
+        ))
+    }
+
+    fn iter_from(
+        &self,
+        cf: &str,
+        start: &[u8],
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>> {
+        Ok(Box::new(
+            tree(&self.trees, cf)?.range(start.to_vec()..).map(entry),
+        ))
+    }
+
+    /// Sled compacts its log in the background, so this only flushes
+    fn compact(&self) -> DbResult<()> {
+        self.flush()
+    }
+
+    fn compact_range(&self, cf: &str, _start: Option<&[u8]>, _end: Option<&[u8]>) -> DbResult<()> {
+        tree(&self.trees, cf)?.flush()?;
+        Ok(())
+    }
+
+    fn flush(&self) -> DbResult<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn stats(&self) -> DbResult<DatabaseStats> {
+        let mut cf_stats = HashMap::new();
+        for (name, tree) in self.trees.iter() {
+            let mut stats = ColumnFamilyStats {
+                size: 0,
+                num_keys: 0,
+                num_files: 0,
+            };
+            for item in tree.iter() {
+                let (key, value) = item?;
+                stats.size += (key.len() + value.len()) as u64;
+                stats.num_keys += 1;
+            }
+            cf_stats.insert(name.to_string(), stats);
+        }
+        Ok(DatabaseStats {
+            total_size: self.db.size_on_disk()?,
+            num_keys: cf_stats.values().map(|stats| stats.num_keys).sum(),
+            memory_usage: 0,
+            cf_stats,
+        })
+    }
+}
+
+/// Buffered writes committed atomically across column families
+struct Transaction {
+    /// Trees of the column families
+    trees: Trees,
+    /// Pending writes by column family and key, `None` for deletes
+    writes: BTreeMap<(&'static str, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl Transaction {
+    fn write(&mut self, cf: &str, key: &[u8], value: Option<Vec<u8>>) -> DbResult<()> {
+        let name = column(&self.trees, cf)?;
+        self.writes.insert((name, key.to_vec()), value);
+        Ok(())
+    }
+}
+
+impl DbTx for Transaction {
+    fn get(&self, cf: &str, key: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        let name = column(&self.trees, cf)?;
+        if let Some(value) = self.writes.get(&(name, key.to_vec())) {
+            return Ok(value.clone());
+        }
+        Ok(self.trees[name].get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn put(&mut self, cf: &str, key: &[u8], value: &[u8]) -> DbResult<()> {
+        self.write(cf, key, Some(value.to_vec()))
+    }
+
+    fn delete(&mut self, cf: &str, key: &[u8]) -> DbResult<()> {
+        self.write(cf, key, None)
+    }
+
+    fn commit(self: Box<Self>) -> DbResult<()> {
+        // Writes are sorted by column family, so each tree appears once
+        let mut names: Vec<&'static str> = self.writes.keys().map(|(name, _)| *name).collect();
+        names.dedup();
+        let index: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (*name, i))
+            .collect();
+        let trees: Vec<&sled::Tree> = names.iter().map(|name| &self.trees[name]).collect();
+
+        trees
+            .as_slice()
+            .transaction(|views| {
+                for ((name, key), value) in &self.writes {
+                    let view = &views[index[name]];
+                    match value {
+                        Some(value) => view.insert(key.as_slice(), value.as_slice())?,
+                        None => view.remove(key.as_slice())?,
+                    };
+                }
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Storage(err) => DbError::Sled(err),
+                TransactionError::Abort(()) => DbError::Transaction("Aborted".to_string()),
+            })
+    }
+
+    fn rollback(self: Box<Self>) -> DbResult<()> {
+        Ok(())
+    }
+}
+
+/// Entries of a column family in key order
+type Entries = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Copy of every column family at the time of the snapshot
+struct Snapshot {
+    /// Entries of each column family
+    data: Arc<HashMap<&'static str, Entries>>,
+}
+
+impl Snapshot {
+    fn column(&self, cf: &str) -> DbResult<&Entries> {
+        self.data
+            .get(cf)
+            .ok_or_else(|| DbError::Config(format!("Unknown column family: {}", cf)))
+    }
+}
+
+impl SnapshotReader for Snapshot {
+    fn get(&self, cf: &str, key: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        Ok(self.column(cf)?.get(key).cloned())
+    }
+
+    fn exists(&self, cf: &str, key: &[u8]) -> DbResult<bool> {
+        Ok(self.column(cf)?.contains_key(key))
+    }
+
+    fn iter(&self, cf: &str) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>> {
+        let entries: Vec<_> = self
+            .column(cf)?
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn clone_snapshot(&self) -> Box<dyn SnapshotReader> {
+        Box::new(Snapshot {
+            data: self.data.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_data_survives_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        {
+            let db = Database::open(temp_dir.path()).unwrap();
+            db.put("headers", b"a", b"1").unwrap();
+            db.put("blocks", b"a", b"2").unwrap();
+            db.delete("blocks", b"a").unwrap();
+            db.flush().unwrap();
+        }
+
+        let db = Database::open(temp_dir.path()).unwrap();
+        assert_eq!(db.get("headers", b"a").unwrap(), Some(b"1".to_vec()));
+        assert!(!db.exists("blocks", b"a").unwrap());
+        assert!(db.get("unknown", b"a").is_err());
+    }
+
+    #[test]
+    fn test_transaction_commits_atomically() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = Database::open(temp_dir.path()).unwrap();
+        db.put("state", b"old", b"1").unwrap();
+        let snapshot = db.snapshot().unwrap();
+
+        let mut tx = db.transaction();
+        tx.put("state", b"k1", b"v1").unwrap();
+        tx.put("indices", b"k2", b"v2").unwrap();
+        tx.delete("state", b"old").unwrap();
+        assert_eq!(tx.get("state", b"k1").unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(tx.get("state", b"old").unwrap(), None);
+        assert!(db.get("state", b"k1").unwrap().is_none());
+        tx.commit().unwrap();
+
+        assert_eq!(db.get("indices", b"k2").unwrap(), Some(b"v2".to_vec()));
+        assert!(!db.exists("state", b"old").unwrap());
+        let keys: Vec<_> = db
+            .iter_prefix("state", b"k")
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![b"k1".to_vec()]);
+        let seek = |start: &[u8]| -> Vec<_> {
+            db.iter_from("state", start)
+                .unwrap()
+                .map(|entry| entry.unwrap().0)
+                .collect()
+        };
+        assert_eq!(seek(b"k0"), vec![b"k1".to_vec()]);
+        assert_eq!(seek(b"k1"), vec![b"k1".to_vec()]);
+        assert!(seek(b"k2").is_empty());
+
+        // The snapshot still sees the state before the transaction
+        assert!(snapshot.exists("state", b"old").unwrap());
+        assert!(!snapshot.exists("state", b"k1").unwrap());
+    }
+}
@@ -682,8 +682,12 @@ mod tests {
         let db = Database::open(temp_dir.path()).unwrap();
         let db: Arc<dyn KeyValueDB> = Arc::new(db);
         
-        let config = SnapshotConfig::default();
+        let config = SnapshotConfig {
+            snapshot_dir: temp_dir.path().join("snapshots"),
+            ..SnapshotConfig::default()
+        };
         let (service, handle) = SnapshotService::new(config, db);
+        tokio::spawn(service.run());
         
         // Test that we can get the handle
         assert!(handle.get_progress().await.is_ok());
@@ -21,7 +21,7 @@ pub trait KeyValueDB: Send + Sync {
     fn transaction(&self) -> Box<dyn DbTx>;
 
     /// Create a snapshot reader
-    fn snapshot(&self) -> Box<dyn SnapshotReader>;
+    fn snapshot(&self) -> DbResult<Box<dyn SnapshotReader>>;
 
     /// Get iterator over keys in a column family
     fn iter(&self, cf: &str) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>>;
@@ -29,6 +29,9 @@ pub trait KeyValueDB: Send + Sync {
     /// Get iterator with prefix
     fn iter_prefix(&self, cf: &str, prefix: &[u8]) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>>;
 
+    /// Get iterator over keys not below `start`, in key order
+    fn iter_from(&self, cf: &str, start: &[u8]) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>>>>;
+
     /// Compact database
     fn compact(&self) -> DbResult<()>;
 
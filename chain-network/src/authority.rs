@@ -0,0 +1,403 @@
+//! Authority discovery for the validator network
+//!
+//! Validators publish an [`AuthorityRecord`] in the Kademlia DHT, signed with
+//! their consensus key, that maps their consensus address to their peer id and
+//! listen addresses. Every node looks up the records of the current authority
+//! set. Valid records of authorities are added to the [`ReservedPeers`], so
+//! validators stay connected to each other regardless of the connection
+//! limits. Consensus messages travel on the
+//! [`VALIDATORS_TOPIC`](crate::gossip::VALIDATORS_TOPIC) and are only accepted
+//! from peers known to belong to an authority.
+
+use crate::connections::ReservedPeers;
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Address, Hash, Signature};
+use libp2p::multiaddr::Protocol;
+use libp2p::{kad, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default time between publishing our record and looking up authorities
+pub const DEFAULT_AUTHORITY_DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Domain separating authority record signatures from other signed data
+const RECORD_DOMAIN: &[u8] = b"chain/authority-record/v1";
+
+/// Signed mapping of a consensus address to a peer id and its addresses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorityRecord {
+    /// Consensus address of the validator
+    pub address: Address,
+    /// Peer id of the validator's node
+    #[serde(with = "peer_id_bytes")]
+    pub peer_id: PeerId,
+    /// Addresses the node listens on
+    pub addresses: Vec<Multiaddr>,
+    /// Creation time in milliseconds since the Unix epoch; newer records win
+    pub timestamp: u64,
+    /// Signature by the consensus key over all other fields
+    pub signature: Signature,
+}
+
+impl AuthorityRecord {
+    /// Create a record signed with a consensus private key
+    pub fn new(
+        private_key: &[u8],
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+        timestamp: u64,
+    ) -> NetworkResult<Self> {
+        let hash = signing_hash(&peer_id, &addresses, timestamp);
+        let signature = Signature::sign_hash(&hash, private_key)
+            .map_err(|e| NetworkError::InvalidAuthorityRecord(e.to_string()))?;
+        let address = signature
+            .recover_address(&hash)
+            .map_err(|e| NetworkError::InvalidAuthorityRecord(e.to_string()))?;
+        Ok(Self {
+            address,
+            peer_id,
+            addresses,
+            timestamp,
+            signature,
+        })
+    }
+
+    /// Check that the record was signed by the key of its address
+    pub fn verify(&self) -> NetworkResult<()> {
+        let hash = signing_hash(&self.peer_id, &self.addresses, self.timestamp);
+        let signer = self
+            .signature
+            .recover_address(&hash)
+            .map_err(|e| NetworkError::InvalidAuthorityRecord(e.to_string()))?;
+        if signer != self.address {
+            return Err(NetworkError::InvalidAuthorityRecord(format!(
+                "Record of {} is signed by {}",
+                self.address, signer
+            )));
+        }
+        Ok(())
+    }
+
+    /// Encode the record as a DHT record
+    pub fn to_kad_record(&self) -> NetworkResult<kad::Record> {
+        Ok(kad::Record::new(
+            authority_key(&self.address),
+            serde_json::to_vec(self)?,
+        ))
+    }
+
+    /// Decode and verify a DHT record
+    pub fn from_kad_record(record: &kad::Record) -> NetworkResult<Self> {
+        let authority: Self = serde_json::from_slice(&record.value)?;
+        if record.key != authority_key(&authority.address) {
+            return Err(NetworkError::InvalidAuthorityRecord(format!(
+                "Record of {} stored under a foreign key",
+                authority.address
+            )));
+        }
+        authority.verify()?;
+        Ok(authority)
+    }
+}
+
+/// Hash signed by an authority record
+fn signing_hash(peer_id: &PeerId, addresses: &[Multiaddr], timestamp: u64) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(RECORD_DOMAIN);
+    let peer_id = peer_id.to_bytes();
+    hasher.update(&(peer_id.len() as u64).to_be_bytes());
+    hasher.update(&peer_id);
+    for addr in addresses {
+        hasher.update(&(addr.len() as u64).to_be_bytes());
+        hasher.update(&addr.to_vec());
+    }
+    hasher.update(&timestamp.to_be_bytes());
+    Hash::from_slice(hasher.finalize().as_bytes())
+}
+
+/// Get the DHT key an authority publishes its record under
+pub fn authority_key(address: &Address) -> kad::RecordKey {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(RECORD_DOMAIN);
+    hasher.update(address.as_bytes());
+    kad::RecordKey::new(hasher.finalize().as_bytes())
+}
+
+/// Serializes peer ids as their multihash bytes
+mod peer_id_bytes {
+    use libp2p::PeerId;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(peer_id: &PeerId, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&peer_id.to_bytes())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PeerId, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        PeerId::from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
+
+/// Consensus key of the local validator
+struct LocalAuthority {
+    private_key: Vec<u8>,
+    address: Address,
+}
+
+/// Publishes our authority record and keeps the authority set connected
+pub struct AuthorityDiscovery {
+    /// Our consensus key, if this node is a validator
+    local: RwLock<Option<LocalAuthority>>,
+    /// Addresses of the current authority set
+    authorities: RwLock<HashSet<Address>>,
+    /// Newest valid record of each authority
+    records: RwLock<HashMap<Address, AuthorityRecord>>,
+    /// Peers kept connected regardless of the connection limits
+    reserved: Arc<ReservedPeers>,
+}
+
+impl AuthorityDiscovery {
+    /// Create discovery that adds authorities to the reserved peers
+    pub fn new(reserved: Arc<ReservedPeers>) -> Self {
+        Self {
+            local: RwLock::new(None),
+            authorities: RwLock::new(HashSet::new()),
+            records: RwLock::new(HashMap::new()),
+            reserved,
+        }
+    }
+
+    /// Publish records signed with a consensus private key
+    pub fn set_key(&self, private_key: &[u8]) -> NetworkResult<()> {
+        let record = AuthorityRecord::new(private_key, PeerId::random(), Vec::new(), 0)?;
+        *self.local.write().unwrap() = Some(LocalAuthority {
+            private_key: private_key.to_vec(),
+            address: record.address,
+        });
+        Ok(())
+    }
+
+    /// Get our consensus address, if this node is a validator
+    pub fn local_address(&self) -> Option<Address> {
+        self.local
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|local| local.address)
+    }
+
+    /// Replace the authority set
+    ///
+    /// Peers of authorities leaving the set are no longer reserved. Records of
+    /// new authorities are found by the next lookup.
+    pub fn set_authorities(&self, authorities: impl IntoIterator<Item = Address>) {
+        let authorities: HashSet<Address> = authorities.into_iter().collect();
+        let mut records = self.records.write().unwrap();
+        records.retain(|address, record| {
+            let keep = authorities.contains(address);
+            if !keep {
+                self.reserved.remove(&record.peer_id);
+            }
+            keep
+        });
+        *self.authorities.write().unwrap() = authorities;
+    }
+
+    /// Get the addresses of the current authority set
+    pub fn authorities(&self) -> Vec<Address> {
+        self.authorities.read().unwrap().iter().copied().collect()
+    }
+
+    /// Check whether we belong to the current authority set
+    pub fn is_authority(&self) -> bool {
+        self.local_address()
+            .is_some_and(|address| self.authorities.read().unwrap().contains(&address))
+    }
+
+    /// Get the authority a peer belongs to
+    pub fn authority_of(&self, peer_id: &PeerId) -> Option<Address> {
+        self.records
+            .read()
+            .unwrap()
+            .values()
+            .find(|record| record.peer_id == *peer_id)
+            .map(|record| record.address)
+    }
+
+    /// Get the newest known record of an authority
+    pub fn record(&self, address: &Address) -> Option<AuthorityRecord> {
+        self.records.read().unwrap().get(address).cloned()
+    }
+
+    /// Sign and publish our record
+    ///
+    /// Does nothing unless this node is a validator.
+    pub fn publish(
+        &self,
+        kad: &mut kad::Behaviour<kad::store::MemoryStore>,
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+    ) -> NetworkResult<()> {
+        let local = self.local.read().unwrap();
+        let Some(local) = local.as_ref() else {
+            return Ok(());
+        };
+        let record = AuthorityRecord::new(&local.private_key, peer_id, addresses, now_millis())?;
+        kad.put_record(record.to_kad_record()?, kad::Quorum::One)
+            .map_err(|e| NetworkError::InvalidAuthorityRecord(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Start DHT lookups of the records of all other authorities
+    pub fn lookup(&self, kad: &mut kad::Behaviour<kad::store::MemoryStore>) {
+        let local = self.local_address();
+        for address in self.authorities() {
+            if Some(address) != local {
+                kad.get_record(authority_key(&address));
+            }
+        }
+    }
+
+    /// Handle a record found in the DHT
+    ///
+    /// Returns the record if it is the newest of an authority, after reserving
+    /// a connection to its peer. Records of other addresses and stale records
+    /// are ignored; invalid records are an error.
+    pub fn handle_record(&self, record: &kad::Record) -> NetworkResult<Option<AuthorityRecord>> {
+        let authority = AuthorityRecord::from_kad_record(record)?;
+        let listed = self
+            .authorities
+            .read()
+            .unwrap()
+            .contains(&authority.address);
+        if !listed || Some(authority.address) == self.local_address() {
+            return Ok(None);
+        }
+
+        let mut records = self.records.write().unwrap();
+        if let Some(known) = records.get(&authority.address) {
+            if known.timestamp >= authority.timestamp {
+                return Ok(None);
+            }
+            if known.peer_id != authority.peer_id {
+                self.reserved.remove(&known.peer_id);
+            }
+        }
+
+        let addresses = authority
+            .addresses
+            .iter()
+            .map(|addr| with_peer_id(addr.clone(), authority.peer_id))
+            .collect();
+        self.reserved.add(authority.peer_id, addresses);
+        records.insert(authority.address, authority.clone());
+        tracing::debug!(
+            "Authority {} is peer {}",
+            authority.address,
+            authority.peer_id
+        );
+        Ok(Some(authority))
+    }
+}
+
+impl std::fmt::Debug for AuthorityDiscovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthorityDiscovery")
+            .field("local_address", &self.local_address())
+            .field("authorities", &self.authorities.read().unwrap().len())
+            .field("records", &self.records.read().unwrap().len())
+            .finish()
+    }
+}
+
+/// Append a peer id to an address that does not end with one
+fn with_peer_id(addr: Multiaddr, peer_id: PeerId) -> Multiaddr {
+    match addr.iter().last() {
+        Some(Protocol::P2p(_)) => addr,
+        _ => addr.with(Protocol::P2p(peer_id)),
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: [u8; 32] = [1; 32];
+    const KEY_B: [u8; 32] = [2; 32];
+
+    fn record(key: &[u8], timestamp: u64) -> AuthorityRecord {
+        let addr: Multiaddr = "/ip4/10.0.0.1/tcp/30333".parse().unwrap();
+        AuthorityRecord::new(key, PeerId::random(), vec![addr], timestamp).unwrap()
+    }
+
+    #[test]
+    fn test_record_sign_verify() {
+        let authority = record(&KEY_A, 1);
+        let decoded = AuthorityRecord::from_kad_record(&authority.to_kad_record().unwrap());
+        assert_eq!(decoded.unwrap(), authority);
+
+        // Changing any signed field invalidates the record
+        let mut tampered = authority.clone();
+        tampered.peer_id = PeerId::random();
+        assert!(tampered.verify().is_err());
+
+        // A record claiming another validator's address is rejected
+        let mut forged = record(&KEY_B, 1);
+        forged.address = authority.address;
+        assert!(forged.verify().is_err());
+
+        // A valid record stored under another authority's key is rejected
+        let mut misplaced = record(&KEY_B, 1).to_kad_record().unwrap();
+        misplaced.key = authority_key(&authority.address);
+        assert!(AuthorityRecord::from_kad_record(&misplaced).is_err());
+    }
+
+    #[test]
+    fn test_authorities_reserved() {
+        let reserved = Arc::new(ReservedPeers::new());
+        let discovery = AuthorityDiscovery::new(reserved.clone());
+        discovery.set_key(&KEY_A).unwrap();
+        let local = discovery.local_address().unwrap();
+        assert!(!discovery.is_authority());
+
+        // Records of addresses outside the set are ignored
+        let old = record(&KEY_B, 1);
+        let kad_record = old.to_kad_record().unwrap();
+        assert_eq!(discovery.handle_record(&kad_record).unwrap(), None);
+        assert!(reserved.is_empty());
+
+        discovery.set_authorities([local, old.address]);
+        assert!(discovery.is_authority());
+        assert_eq!(
+            discovery.handle_record(&kad_record).unwrap(),
+            Some(old.clone())
+        );
+        assert!(reserved.contains(&old.peer_id));
+        assert_eq!(discovery.authority_of(&old.peer_id), Some(old.address));
+
+        // A newer record moves the reservation to the new peer
+        let new = record(&KEY_B, 2);
+        discovery
+            .handle_record(&new.to_kad_record().unwrap())
+            .unwrap();
+        assert!(!reserved.contains(&old.peer_id));
+        assert!(reserved.contains(&new.peer_id));
+        assert_eq!(discovery.handle_record(&kad_record).unwrap(), None);
+        assert_eq!(discovery.authority_of(&old.peer_id), None);
+
+        // Leaving the set drops the reservation
+        discovery.set_authorities([local]);
+        assert!(reserved.is_empty());
+        assert_eq!(discovery.authority_of(&new.peer_id), None);
+    }
+}
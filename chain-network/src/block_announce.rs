@@ -0,0 +1,578 @@
+//! Block announcement validation and import hand-off
+//!
+//! Announced headers are checked with a pluggable [`HeaderVerifier`], usually
+//! backed by the consensus engine. Announcements without a block are completed
+//! by requesting the body from the announcing peer. Compact blocks are rebuilt
+//! from the transaction pool, requesting only the transactions it lacks, and
+//! downloaded in full if the rebuilt block does not match its header.
+//! Complete blocks whose body matches the header are delivered on the bounded
+//! import queue as
+//! [`QueuedBlock`]s; blocks arriving while it is full are dropped and can be
+//! announced again. Peers sending bad announcements or bodies are reported to
+//! the [`ReputationManager`].
+
+use crate::compact::{CompactBlock, PartialBlock};
+use crate::message::BlockAnnounce;
+use crate::reputation::{Misbehaviour, ReputationManager};
+use crate::seen_cache::{SeenCache, SeenCacheStats, DEFAULT_SEEN_TTL};
+use crate::sync::SyncManager;
+use crate::transactions::GossipPool;
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Block, BlockHeader, Hash, Transaction};
+use libp2p::PeerId;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Default number of announced block hashes remembered for deduplication
+pub const DEFAULT_MAX_KNOWN_BLOCKS: usize = 1_024;
+
+/// Default number of blocks waiting on the import queue
+pub const DEFAULT_IMPORT_QUEUE_SIZE: usize = 256;
+
+/// Checks announced headers before their blocks are fetched
+pub trait HeaderVerifier: Send + Sync {
+    /// Check a header under consensus rules
+    ///
+    /// Fails with [`NetworkError::InvalidBlock`] if the header is invalid.
+    fn verify_header(&self, header: &BlockHeader) -> NetworkResult<()>;
+}
+
+/// Block ready for import, with the peer it came from
+#[derive(Debug, Clone)]
+pub struct QueuedBlock {
+    /// Peer that announced the block
+    pub peer_id: PeerId,
+    /// The complete block
+    pub block: Block,
+}
+
+/// Outcome of handling a block announcement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnounceOutcome {
+    /// The block was put on the import queue
+    Queued,
+    /// The block was already announced
+    Known,
+    /// The header or body was invalid and the peer was reported
+    Invalid,
+    /// The import queue was full, so the block was not queued
+    Dropped,
+}
+
+/// Validates block announcements and feeds the import queue
+pub struct BlockAnnounceHandler {
+    /// Consensus check of announced headers
+    verifier: Arc<dyn HeaderVerifier>,
+    /// Sync requests used to fetch missing bodies
+    sync: SyncManager,
+    /// Reputation of announcing peers
+    reputation: Arc<ReputationManager>,
+    /// Import queue
+    queue: mpsc::Sender<QueuedBlock>,
+    /// Hashes of blocks queued or with an invalid header
+    known: SeenCache<Hash>,
+    /// Pending transactions compact blocks are rebuilt from
+    pool: Option<Arc<dyn GossipPool>>,
+}
+
+impl BlockAnnounceHandler {
+    /// Create a handler and the receiving end of its import queue
+    pub fn new(
+        verifier: Arc<dyn HeaderVerifier>,
+        sync: SyncManager,
+        reputation: Arc<ReputationManager>,
+    ) -> (Self, mpsc::Receiver<QueuedBlock>) {
+        Self::with_capacity(verifier, sync, reputation, DEFAULT_IMPORT_QUEUE_SIZE)
+    }
+
+    /// Create a handler whose import queue holds up to `capacity` blocks
+    pub fn with_capacity(
+        verifier: Arc<dyn HeaderVerifier>,
+        sync: SyncManager,
+        reputation: Arc<ReputationManager>,
+        capacity: usize,
+    ) -> (Self, mpsc::Receiver<QueuedBlock>) {
+        let (queue, queue_rx) = mpsc::channel(capacity.max(1));
+        let handler = Self {
+            verifier,
+            sync,
+            reputation,
+            queue,
+            known: SeenCache::new(DEFAULT_MAX_KNOWN_BLOCKS, DEFAULT_SEEN_TTL),
+            pool: None,
+        };
+        (handler, queue_rx)
+    }
+
+    /// Set the number of block hashes remembered for deduplication
+    pub fn with_max_known(mut self, max_known: usize) -> Self {
+        self.known = SeenCache::new(max_known, DEFAULT_SEEN_TTL);
+        self
+    }
+
+    /// Rebuild compact blocks from the transactions of a pool
+    ///
+    /// Without a pool, every transaction of a compact block is requested.
+    pub fn with_pool(mut self, pool: Arc<dyn GossipPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Check whether a block was already announced
+    pub fn is_known(&self, hash: &Hash) -> bool {
+        self.known.contains(hash)
+    }
+
+    /// Get hit and eviction counts of the known-block cache
+    pub fn seen_stats(&self) -> &SeenCacheStats {
+        self.known.stats()
+    }
+
+    /// Validate an announcement and queue its block for import
+    pub async fn handle_announce(
+        &mut self,
+        announce: BlockAnnounce,
+        peer_id: PeerId,
+    ) -> NetworkResult<AnnounceOutcome> {
+        let hash = announce
+            .header
+            .hash()
+            .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+        if let Some(outcome) = self.check_header(hash, &announce.header, peer_id) {
+            return Ok(outcome);
+        }
+
+        let transactions = match announce.block {
+            Some(block) if block.header == announce.header => block.transactions,
+            Some(_) => {
+                tracing::debug!(
+                    "Announcement {} from {} has a mismatched block",
+                    hash,
+                    peer_id
+                );
+                // Another peer may still serve the right block
+                self.known.remove(&hash);
+                self.report(&peer_id, Misbehaviour::InvalidBlock);
+                return Ok(AnnounceOutcome::Invalid);
+            }
+            None => match self.fetch_body(hash, peer_id).await {
+                Ok(transactions) => transactions,
+                Err(e) => {
+                    // Let a later announcement of the block try again
+                    self.known.remove(&hash);
+                    return Err(e);
+                }
+            },
+        };
+
+        self.enqueue(hash, Block::new(announce.header, transactions), peer_id)
+    }
+
+    /// Validate a compact block and queue the block rebuilt from it
+    ///
+    /// Transactions missing from the pool are requested from the announcing
+    /// peer. If the rebuilt block does not match its header, e.g. because
+    /// of a short id collision, the body is downloaded in full instead.
+    pub async fn handle_compact_block(
+        &mut self,
+        compact: CompactBlock,
+        peer_id: PeerId,
+    ) -> NetworkResult<AnnounceOutcome> {
+        let hash = compact.block_hash()?;
+        if let Some(outcome) = self.check_header(hash, &compact.header, peer_id) {
+            return Ok(outcome);
+        }
+
+        let pending = self
+            .pool
+            .as_ref()
+            .map(|pool| pool.pending())
+            .unwrap_or_default();
+        let partial = match PartialBlock::new(&compact, &pending) {
+            Ok(partial) => partial,
+            Err(e) => {
+                tracing::debug!("Malformed compact block {} from {}: {}", hash, peer_id, e);
+                self.known.remove(&hash);
+                self.report(&peer_id, Misbehaviour::MalformedMessage);
+                return Ok(AnnounceOutcome::Invalid);
+            }
+        };
+
+        let transactions = match self.rebuild(partial, hash, peer_id).await {
+            Ok(Some(block)) => Ok(block.transactions),
+            Ok(None) => self.fetch_body(hash, peer_id).await,
+            Err(e) => Err(e),
+        };
+        let transactions = match transactions {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                // Let a later announcement of the block try again
+                self.known.remove(&hash);
+                return Err(e);
+            }
+        };
+
+        self.enqueue(hash, Block::new(compact.header, transactions), peer_id)
+    }
+
+    /// Remember an announced block and check its header
+    ///
+    /// Returns the outcome if the announcement goes no further.
+    fn check_header(
+        &mut self,
+        hash: Hash,
+        header: &BlockHeader,
+        peer_id: PeerId,
+    ) -> Option<AnnounceOutcome> {
+        if !self.known.insert(hash) {
+            return Some(AnnounceOutcome::Known);
+        }
+
+        if let Err(e) = self.verifier.verify_header(header) {
+            tracing::debug!("Invalid announcement {} from {}: {}", hash, peer_id, e);
+            self.report(&peer_id, Misbehaviour::InvalidBlock);
+            return Some(AnnounceOutcome::Invalid);
+        }
+        None
+    }
+
+    /// Complete a block rebuilt from the pool with the transactions the
+    /// announcing peer sends
+    ///
+    /// Returns `None` if the rebuilt block does not match its header.
+    async fn rebuild(
+        &self,
+        mut partial: PartialBlock,
+        hash: Hash,
+        peer_id: PeerId,
+    ) -> NetworkResult<Option<Block>> {
+        let missing = partial.missing_indices();
+        if !missing.is_empty() {
+            tracing::debug!(
+                "Requesting {} transactions of {} from {}",
+                missing.len(),
+                hash,
+                peer_id
+            );
+            let result = self
+                .sync
+                .request_block_transactions(peer_id, hash, missing)
+                .await;
+            match result {
+                Ok(transactions) => partial.fill(transactions)?,
+                Err(e) => {
+                    self.report(&peer_id, Misbehaviour::Timeout);
+                    return Err(e);
+                }
+            }
+        }
+
+        match partial.into_block() {
+            Ok(block) => Ok(Some(block)),
+            Err(e) => {
+                tracing::debug!("Failed to rebuild {} from {}: {}", hash, peer_id, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Check a complete block against its header and queue it for import
+    fn enqueue(
+        &mut self,
+        hash: Hash,
+        block: Block,
+        peer_id: PeerId,
+    ) -> NetworkResult<AnnounceOutcome> {
+        let root = block
+            .calculate_transactions_root()
+            .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+        if root != block.header.transactions_root {
+            tracing::debug!(
+                "Body of {} from {} does not match its header",
+                hash,
+                peer_id
+            );
+            self.known.remove(&hash);
+            self.report(&peer_id, Misbehaviour::InvalidBlock);
+            return Ok(AnnounceOutcome::Invalid);
+        }
+
+        match self.queue.try_send(QueuedBlock { peer_id, block }) {
+            Ok(()) => Ok(AnnounceOutcome::Queued),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::warn!("Import queue is full, dropping block {}", hash);
+                self.known.remove(&hash);
+                Ok(AnnounceOutcome::Dropped)
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                Err(NetworkError::Sync("Import queue is closed".to_string()))
+            }
+        }
+    }
+
+    /// Request the body of an announced block from the announcing peer
+    async fn fetch_body(&self, hash: Hash, peer_id: PeerId) -> NetworkResult<Vec<Transaction>> {
+        let result = self.sync.request_bodies(peer_id, vec![hash]).await;
+        match result.map(|bodies| bodies.into_iter().next()) {
+            Ok(Some(body)) => Ok(body),
+            Ok(None) => {
+                self.report(&peer_id, Misbehaviour::Timeout);
+                Err(NetworkError::Sync(format!(
+                    "Peer {} did not serve announced block {}",
+                    peer_id, hash
+                )))
+            }
+            Err(e) => {
+                self.report(&peer_id, Misbehaviour::Timeout);
+                Err(e)
+            }
+        }
+    }
+
+    fn report(&self, peer_id: &PeerId, misbehaviour: Misbehaviour) {
+        if let Err(e) = self.reputation.report(peer_id, misbehaviour) {
+            tracing::warn!("Failed to report {}: {}", peer_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryChain;
+    use crate::reputation::{MemoryReputationStore, ReputationConfig};
+    use crate::sync::{SyncCommand, SyncHandler};
+    use crate::transactions::MemoryPool;
+
+    /// Accepts headers with an even timestamp
+    struct EvenTimestamps;
+
+    impl HeaderVerifier for EvenTimestamps {
+        fn verify_header(&self, header: &BlockHeader) -> NetworkResult<()> {
+            if header.timestamp.is_multiple_of(2) {
+                Ok(())
+            } else {
+                Err(NetworkError::InvalidBlock("Odd timestamp".to_string()))
+            }
+        }
+    }
+
+    fn test_block(timestamp: u64) -> Block {
+        let transactions = (0..3)
+            .map(|nonce| Transaction::new(nonce, 1, 21_000, None, 0, vec![]))
+            .collect();
+        let mut block = Block::new(BlockHeader::genesis(), transactions);
+        block.header.number = 1;
+        block.header.timestamp = timestamp;
+        block.header.transactions_root = block.calculate_transactions_root().unwrap();
+        block
+    }
+
+    async fn setup(
+        chain: Arc<MemoryChain>,
+    ) -> (
+        BlockAnnounceHandler,
+        mpsc::Receiver<QueuedBlock>,
+        Arc<ReputationManager>,
+        PeerId,
+    ) {
+        setup_with_capacity(chain, DEFAULT_IMPORT_QUEUE_SIZE).await
+    }
+
+    async fn setup_with_capacity(
+        chain: Arc<MemoryChain>,
+        capacity: usize,
+    ) -> (
+        BlockAnnounceHandler,
+        mpsc::Receiver<QueuedBlock>,
+        Arc<ReputationManager>,
+        PeerId,
+    ) {
+        let (sync, mut sync_rx) = SyncManager::new();
+        let server = SyncHandler::new(chain);
+        tokio::spawn(async move {
+            while let Some(SyncCommand::SendRequest {
+                peer_id,
+                request,
+                response_sender,
+            }) = sync_rx.recv().await
+            {
+                let response = server.handle_request(request, peer_id).await;
+                let _ = response_sender.send(Ok(response));
+            }
+        });
+
+        let (reputation, _bans) = ReputationManager::new(
+            ReputationConfig::default(),
+            Arc::new(MemoryReputationStore::default()),
+        )
+        .unwrap();
+        let reputation = Arc::new(reputation);
+
+        let (handler, queue) = BlockAnnounceHandler::with_capacity(
+            Arc::new(EvenTimestamps),
+            sync,
+            reputation.clone(),
+            capacity,
+        );
+        (handler, queue, reputation, PeerId::random())
+    }
+
+    #[tokio::test]
+    async fn test_announce_fetches_body() {
+        let chain = Arc::new(MemoryChain::new());
+        let block = test_block(2);
+        chain.insert_block(block.clone()).unwrap();
+        let (mut handler, mut queue, _reputation, peer_id) = setup(chain).await;
+
+        let announce = BlockAnnounce::new(block.header.clone());
+        let outcome = handler
+            .handle_announce(announce.clone(), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+
+        let queued = queue.try_recv().unwrap();
+        assert_eq!(queued.peer_id, peer_id);
+        assert_eq!(queued.block, block);
+
+        let outcome = handler.handle_announce(announce, peer_id).await.unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Known);
+        assert!(queue.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compact_blocks_rebuilt_from_pool() {
+        let chain = Arc::new(MemoryChain::new());
+        let (served, mismatched) = (test_block(2), test_block(8));
+        chain.insert_block(served.clone()).unwrap();
+        chain.insert_block(mismatched.clone()).unwrap();
+        let (handler, mut queue, reputation, peer_id) = setup(chain).await;
+        let pool = Arc::new(MemoryPool::new(10));
+        pool.import(served.transactions[0].clone()).unwrap();
+        let mut handler = handler.with_pool(pool.clone());
+
+        // Transactions missing from the pool are requested from the peer
+        let compact = CompactBlock::from_block(&served).unwrap();
+        let outcome = handler
+            .handle_compact_block(compact, peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+        assert_eq!(queue.try_recv().unwrap().block, served);
+
+        // A block whose transactions are all pooled needs no request, so the
+        // peer does not have to serve it
+        for transaction in &served.transactions {
+            pool.import(transaction.clone()).unwrap();
+        }
+        let pooled = test_block(4);
+        let compact = CompactBlock::from_block(&pooled).unwrap();
+        let outcome = handler
+            .handle_compact_block(compact, peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+        assert_eq!(queue.try_recv().unwrap().block, pooled);
+
+        // A rebuilt block that does not match its header is downloaded in full
+        let mut compact = CompactBlock::from_block(&mismatched)
+            .unwrap()
+            .with_prefilled(&mismatched, &[0])
+            .unwrap();
+        compact.prefilled[0].transaction = Transaction::new(9, 1, 21_000, None, 0, vec![]);
+        let outcome = handler
+            .handle_compact_block(compact, peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+        assert_eq!(queue.try_recv().unwrap().block, mismatched);
+        assert_eq!(reputation.score(&peer_id), 0.0);
+
+        // Compact blocks repeating a short id are rejected
+        let mut malformed = CompactBlock::from_block(&test_block(6)).unwrap();
+        malformed.short_ids[1] = malformed.short_ids[0];
+        let outcome = handler
+            .handle_compact_block(malformed, peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Invalid);
+        assert!(!handler.is_known(&test_block(6).hash().unwrap()));
+        assert!(reputation.score(&peer_id) < 0.0);
+        assert!(queue.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bad_announcements_penalised() {
+        let (mut handler, mut queue, reputation, peer_id) =
+            setup(Arc::new(MemoryChain::new())).await;
+
+        // Header rejected by the verifier
+        let invalid = BlockAnnounce::new(test_block(3).header);
+        let outcome = handler.handle_announce(invalid, peer_id).await.unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Invalid);
+        assert!(handler.is_known(&test_block(3).hash().unwrap()));
+
+        // Body that does not match the header
+        let mut block = test_block(4);
+        let header = block.header.clone();
+        block.transactions.pop();
+        let mismatched =
+            BlockAnnounce::new(header).with_block(Block::new(block.header, block.transactions));
+        let outcome = handler.handle_announce(mismatched, peer_id).await.unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Invalid);
+
+        // Block whose header differs from the announced one
+        let other = BlockAnnounce::new(test_block(4).header).with_block(test_block(8));
+        let outcome = handler.handle_announce(other, peer_id).await.unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Invalid);
+
+        // Neither bad body is held against the block itself
+        let block = test_block(4);
+        assert!(!handler.is_known(&block.hash().unwrap()));
+        let complete = BlockAnnounce::new(block.header.clone()).with_block(block.clone());
+        let outcome = handler.handle_announce(complete, peer_id).await.unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+        assert_eq!(queue.try_recv().unwrap().block, block);
+
+        // Body the peer cannot serve
+        let missing = BlockAnnounce::new(test_block(6).header);
+        assert!(handler.handle_announce(missing, peer_id).await.is_err());
+
+        assert!(queue.try_recv().is_err());
+        let expected = 3.0 * Misbehaviour::InvalidBlock.cost() + Misbehaviour::Timeout.cost();
+        assert!((reputation.score(&peer_id) - expected).abs() < 1.0);
+        assert!(reputation.is_banned(&peer_id));
+    }
+
+    #[tokio::test]
+    async fn test_full_import_queue_drops_blocks() {
+        let (mut handler, mut queue, reputation, peer_id) =
+            setup_with_capacity(Arc::new(MemoryChain::new()), 1).await;
+        let announce =
+            |block: &Block| BlockAnnounce::new(block.header.clone()).with_block(block.clone());
+        let (first, second) = (test_block(2), test_block(4));
+
+        let outcome = handler
+            .handle_announce(announce(&first), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+        let outcome = handler
+            .handle_announce(announce(&second), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Dropped);
+        assert!(!handler.is_known(&second.hash().unwrap()));
+
+        // Once the importer catches up, the block is taken on announcement
+        assert_eq!(queue.try_recv().unwrap().block, first);
+        let outcome = handler
+            .handle_announce(announce(&second), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome, AnnounceOutcome::Queued);
+        assert_eq!(queue.try_recv().unwrap().block, second);
+        assert_eq!(reputation.score(&peer_id), 0.0);
+    }
+}
@@ -0,0 +1,209 @@
+//! Read access to the local chain
+//!
+//! The network layer does not own chain storage. Nodes plug their database in
+//! through [`ChainReader`] so that inbound sync requests can be answered.
+
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Block, BlockHeader, BlockNumber, Hash, RangeProof, Transaction};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+pub use chain_core::StateEntry;
+
+/// Read-only view of the chain used to serve sync requests
+pub trait ChainReader: Send + Sync {
+    /// Get a header by block hash
+    fn header(&self, hash: &Hash) -> NetworkResult<Option<BlockHeader>>;
+
+    /// Get the hash of the canonical block at a height
+    fn hash_at(&self, number: BlockNumber) -> NetworkResult<Option<Hash>>;
+
+    /// Get the transactions of a block
+    fn body(&self, hash: &Hash) -> NetworkResult<Option<Vec<Transaction>>>;
+
+    /// Get the encoded receipts of a block
+    fn receipts(&self, hash: &Hash) -> NetworkResult<Option<Vec<u8>>>;
+
+    /// Get up to `limit` state entries under `prefix`, in key order, from
+    /// the first key not below `start`
+    ///
+    /// Returns `None` if the state at `root` is not available.
+    fn state_entries(
+        &self,
+        root: &Hash,
+        prefix: &[u8],
+        start: &[u8],
+        limit: usize,
+    ) -> NetworkResult<Option<Vec<StateEntry>>>;
+
+    /// Get up to `limit` consecutive state entries with a proof against `root`
+    ///
+    /// The range starts at the first key not below `start`. The default
+    /// implementation rebuilds the commitment from the whole state, so large
+    /// stores should override it.
+    fn state_range(
+        &self,
+        root: &Hash,
+        start: &[u8],
+        limit: usize,
+    ) -> NetworkResult<Option<(Vec<StateEntry>, RangeProof)>> {
+        let Some(entries) = self.state_entries(root, &[], &[], usize::MAX)? else {
+            return Ok(None);
+        };
+
+        let first = entries.partition_point(|(key, _)| key.as_slice() < start);
+        let end = first.saturating_add(limit).min(entries.len());
+        let proof = RangeProof::generate(&entries, first, end);
+        Ok(Some((entries[first..end].to_vec(), proof)))
+    }
+
+    /// Get the canonical header at a height
+    fn header_at(&self, number: BlockNumber) -> NetworkResult<Option<BlockHeader>> {
+        match self.hash_at(number)? {
+            Some(hash) => self.header(&hash),
+            None => Ok(None),
+        }
+    }
+}
+
+/// In-memory chain, useful for tests and nodes without storage
+#[derive(Debug, Default)]
+pub struct MemoryChain {
+    inner: RwLock<MemoryChainInner>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryChainInner {
+    headers: HashMap<Hash, BlockHeader>,
+    canonical: BTreeMap<BlockNumber, Hash>,
+    bodies: HashMap<Hash, Vec<Transaction>>,
+    receipts: HashMap<Hash, Vec<u8>>,
+    state_root: Hash,
+    state: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryChain {
+    /// Create an empty chain
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a header without its body and make it canonical at its height
+    pub fn insert_header(&self, header: BlockHeader) -> NetworkResult<Hash> {
+        let hash = header
+            .hash()
+            .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+        let mut inner = self.inner.write().unwrap();
+        inner.canonical.insert(header.number, hash);
+        inner.headers.insert(hash, header);
+        Ok(hash)
+    }
+
+    /// Insert a block and make it canonical at its height
+    pub fn insert_block(&self, block: Block) -> NetworkResult<Hash> {
+        let hash = block
+            .hash()
+            .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+        let mut inner = self.inner.write().unwrap();
+        inner.canonical.insert(block.header.number, hash);
+        inner.headers.insert(hash, block.header);
+        inner.bodies.insert(hash, block.transactions);
+        Ok(hash)
+    }
+
+    /// Store the encoded receipts of a block
+    pub fn insert_receipts(&self, hash: Hash, receipts: Vec<u8>) {
+        self.inner.write().unwrap().receipts.insert(hash, receipts);
+    }
+
+    /// Replace the served state
+    pub fn set_state(&self, root: Hash, entries: impl IntoIterator<Item = StateEntry>) {
+        let mut inner = self.inner.write().unwrap();
+        inner.state_root = root;
+        inner.state = entries.into_iter().collect();
+    }
+}
+
+impl ChainReader for MemoryChain {
+    fn header(&self, hash: &Hash) -> NetworkResult<Option<BlockHeader>> {
+        Ok(self.inner.read().unwrap().headers.get(hash).cloned())
+    }
+
+    fn hash_at(&self, number: BlockNumber) -> NetworkResult<Option<Hash>> {
+        Ok(self.inner.read().unwrap().canonical.get(&number).copied())
+    }
+
+    fn body(&self, hash: &Hash) -> NetworkResult<Option<Vec<Transaction>>> {
+        Ok(self.inner.read().unwrap().bodies.get(hash).cloned())
+    }
+
+    fn receipts(&self, hash: &Hash) -> NetworkResult<Option<Vec<u8>>> {
+        Ok(self.inner.read().unwrap().receipts.get(hash).cloned())
+    }
+
+    fn state_entries(
+        &self,
+        root: &Hash,
+        prefix: &[u8],
+        start: &[u8],
+        limit: usize,
+    ) -> NetworkResult<Option<Vec<StateEntry>>> {
+        let inner = self.inner.read().unwrap();
+        if inner.state_root != *root {
+            return Ok(None);
+        }
+
+        let entries = inner
+            .state
+            .range(prefix.max(start).to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Ok(Some(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_chain_state_prefix() {
+        let chain = MemoryChain::new();
+        let root = Hash::new([1; 32]);
+        chain.set_state(
+            root,
+            vec![
+                (b"a1".to_vec(), vec![1]),
+                (b"b1".to_vec(), vec![2]),
+                (b"b2".to_vec(), vec![3]),
+                (b"c1".to_vec(), vec![4]),
+            ],
+        );
+
+        let entries = chain.state_entries(&root, b"b", b"", 10).unwrap().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, b"b1".to_vec());
+
+        // Continuing a page within the prefix
+        let entries = chain
+            .state_entries(&root, b"b", b"b2", 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entries, vec![(b"b2".to_vec(), vec![3])]);
+
+        assert_eq!(
+            chain
+                .state_entries(&root, b"", b"", 3)
+                .unwrap()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(chain
+            .state_entries(&Hash::zero(), b"", b"", 3)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,925 @@
+//! Headers-first block synchronization
+//!
+//! [`ChainSync`] brings the local chain up to the best chain announced by our
+//! peers. Each round it finds the common ancestor with the best peer,
+//! downloads and verifies a batch of headers from it, then fetches the
+//! matching bodies in parallel from every peer that has them before handing
+//! the blocks to the importer.
+//!
+//! With a [`WarpImport`] configured, a node far behind first downloads the
+//! state at a recent pivot block instead of replaying history; see [`warp`].
+
+use crate::chain::ChainReader;
+use crate::message::limits;
+use crate::reputation::{Misbehaviour, ReputationManager};
+use crate::sync::{SyncManager, SyncStats};
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Block, BlockHeader, BlockNumber, Hash, Transaction};
+use futures::stream::{FuturesUnordered, StreamExt};
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+pub mod warp;
+
+pub use warp::WarpImport;
+
+/// Reputation gained by a peer that served useful data
+pub const USEFUL_RESPONSE_REWARD: f64 = 1.0;
+/// Default number of failed attempts allowed per body batch
+pub const DEFAULT_MAX_RETRIES: usize = 3;
+/// Default number of blocks between the pivot and the best announced block
+pub const DEFAULT_PIVOT_DISTANCE: u64 = 64;
+/// Default number of peers that must have the warp pivot
+pub const DEFAULT_PIVOT_PEERS: usize = 2;
+
+/// Consensus and storage hooks used by [`ChainSync`]
+pub trait BlockImport: Send + Sync {
+    /// Get the head of the local chain
+    fn best_header(&self) -> BlockHeader;
+
+    /// Check a downloaded header against its parent under consensus rules
+    ///
+    /// Called right before the block is imported, once its parent is.
+    fn verify_header(&self, parent: &BlockHeader, header: &BlockHeader) -> NetworkResult<()>;
+
+    /// Import a block whose header has been verified
+    fn import_block(&self, block: Block) -> NetworkResult<()>;
+}
+
+/// Phase of the sync state machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    /// No peer is ahead of us
+    Idle,
+    /// Looking for the common ancestor with a peer
+    AncestorSearch,
+    /// Downloading headers
+    Headers,
+    /// Downloading bodies
+    Bodies,
+    /// Importing downloaded blocks
+    Importing,
+    /// Downloading state at the warp pivot
+    WarpState,
+    /// Re-requesting state ranges that are still missing
+    WarpHealing,
+}
+
+/// What we know about a peer's chain
+#[derive(Debug, Clone)]
+struct PeerHead {
+    /// Number of the peer's best block
+    best_number: BlockNumber,
+    /// Highest block we share with the peer, once known
+    common_number: Option<BlockNumber>,
+}
+
+/// Bodies still to download for a range of headers
+#[derive(Debug, Clone, Copy)]
+struct BodyBatch {
+    /// First header index
+    start: usize,
+    /// One past the last header index
+    end: usize,
+    /// Failed attempts so far
+    attempts: usize,
+}
+
+/// Headers-first sync state machine
+pub struct ChainSync {
+    /// Sends sync requests to peers
+    sync: SyncManager,
+    /// Reputation of the peers we sync from
+    reputation: Arc<ReputationManager>,
+    /// Local chain
+    chain: Arc<dyn ChainReader>,
+    /// Verifies headers and imports blocks
+    import: Arc<dyn BlockImport>,
+    /// Best blocks announced by peers
+    heads: HashMap<PeerId, PeerHead>,
+    /// Current phase
+    state: SyncState,
+    /// Progress counters
+    stats: SyncStats,
+    /// Failed attempts allowed per body batch or state range
+    max_retries: usize,
+    /// Installs warp-synced state, until warp sync has run
+    warp: Option<Arc<dyn WarpImport>>,
+    /// Blocks between the warp pivot and the best announced block
+    pivot_distance: u64,
+    /// Peers that must have the warp pivot
+    pivot_peers: usize,
+}
+
+impl ChainSync {
+    /// Create a chain sync over the given local chain
+    pub fn new(
+        sync: SyncManager,
+        reputation: Arc<ReputationManager>,
+        chain: Arc<dyn ChainReader>,
+        import: Arc<dyn BlockImport>,
+    ) -> Self {
+        Self {
+            sync,
+            reputation,
+            chain,
+            import,
+            heads: HashMap::new(),
+            state: SyncState::Idle,
+            stats: SyncStats::new(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            warp: None,
+            pivot_distance: DEFAULT_PIVOT_DISTANCE,
+            pivot_peers: DEFAULT_PIVOT_PEERS,
+        }
+    }
+
+    /// Set the number of failed attempts allowed per body batch or state range
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Warp sync through the given importer before importing blocks
+    pub fn with_warp_import(mut self, import: Arc<dyn WarpImport>) -> Self {
+        self.warp = Some(import);
+        self
+    }
+
+    /// Set how far the warp pivot stays behind the best announced block
+    ///
+    /// Blocks this deep are treated as final.
+    pub fn with_pivot_distance(mut self, pivot_distance: u64) -> Self {
+        self.pivot_distance = pivot_distance;
+        self
+    }
+
+    /// Set how many peers must have the warp pivot on their canonical chain
+    pub fn with_pivot_peers(mut self, pivot_peers: usize) -> Self {
+        self.pivot_peers = pivot_peers.max(1);
+        self
+    }
+
+    /// Get the current phase
+    pub fn state(&self) -> SyncState {
+        self.state
+    }
+
+    /// Get the progress counters
+    pub fn stats(&self) -> &SyncStats {
+        &self.stats
+    }
+
+    /// Record the best block announced by a peer
+    pub fn update_peer_head(&mut self, peer_id: PeerId, best_number: BlockNumber) {
+        let head = self.heads.entry(peer_id).or_insert(PeerHead {
+            best_number,
+            common_number: None,
+        });
+        head.best_number = head.best_number.max(best_number);
+        self.stats.target_block = self.stats.target_block.max(best_number);
+    }
+
+    /// Forget a disconnected peer
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.heads.remove(peer_id);
+    }
+
+    /// Sync until no peer is ahead of us
+    ///
+    /// Runs warp sync first if it is configured and has not run yet.
+    /// Returns the number of imported blocks.
+    pub async fn sync_to_best(&mut self) -> NetworkResult<u64> {
+        if self.warp.is_some() {
+            self.warp_sync().await?;
+        }
+
+        let mut imported = 0;
+        loop {
+            let count = self.sync_step().await?;
+            if count == 0 {
+                return Ok(imported);
+            }
+            imported += count;
+        }
+    }
+
+    /// Run one round: import one batch of blocks from the best peer
+    ///
+    /// Peers that fail the round are skipped in favour of the next best one.
+    /// Returns the number of imported blocks, zero once we are up to date.
+    pub async fn sync_step(&mut self) -> NetworkResult<u64> {
+        let mut skipped = HashSet::new();
+
+        while let Some(target) = self.best_peer(&skipped) {
+            match self.sync_from(target).await {
+                Ok(imported) => {
+                    self.state = SyncState::Idle;
+                    return Ok(imported);
+                }
+                Err(e) => {
+                    tracing::warn!("Sync from {} failed: {}", target, e);
+                    skipped.insert(target);
+                }
+            }
+        }
+
+        self.state = SyncState::Idle;
+        Ok(0)
+    }
+
+    /// Pick the peer with the highest best block ahead of ours
+    fn best_peer(&self, skipped: &HashSet<PeerId>) -> Option<PeerId> {
+        let local = self.import.best_header().number;
+        self.heads
+            .iter()
+            .filter(|(peer_id, head)| head.best_number > local && !skipped.contains(peer_id))
+            .max_by_key(|(_, head)| head.best_number)
+            .map(|(peer_id, _)| *peer_id)
+    }
+
+    /// Import one batch of blocks from a peer
+    async fn sync_from(&mut self, peer_id: PeerId) -> NetworkResult<u64> {
+        self.state = SyncState::AncestorSearch;
+        let common = self.find_common_ancestor(peer_id).await?;
+
+        self.state = SyncState::Headers;
+        let best = self.peer_head(&peer_id)?.best_number;
+        let headers = self.download_headers(peer_id, common, best).await?;
+        if headers.is_empty() {
+            return Ok(0);
+        }
+
+        self.state = SyncState::Bodies;
+        let bodies = self.download_bodies(&headers).await?;
+
+        self.state = SyncState::Importing;
+        let mut parent = self.local_header(common)?;
+        let mut count = 0;
+        let mut result = Ok(());
+        for (header, body) in headers.into_iter().zip(bodies) {
+            // Consensus checks may depend on earlier blocks of the batch, such
+            // as the authority set elected by one of them
+            if let Err(e) = self.import.verify_header(&parent, &header) {
+                self.report(&peer_id, Misbehaviour::InvalidBlock);
+                result = Err(e);
+                break;
+            }
+            parent = header.clone();
+            if let Err(e) = self.import.import_block(Block::new(header, body)) {
+                result = Err(e);
+                break;
+            }
+            count += 1;
+        }
+
+        if count > 0 {
+            if let Some(head) = self.heads.get_mut(&peer_id) {
+                head.common_number = Some(parent.number);
+            }
+            self.stats.best_block = parent.number;
+        }
+        result.map(|()| count)
+    }
+
+    /// Find the highest block of our chain that the peer also has
+    async fn find_common_ancestor(&mut self, peer_id: PeerId) -> NetworkResult<BlockNumber> {
+        let head = self.peer_head(&peer_id)?;
+        let mut high = self.import.best_header().number.min(head.best_number);
+
+        // The previous ancestor is usually still shared
+        if let Some(common) = head.common_number.filter(|common| *common <= high) {
+            if self.peer_has_block(peer_id, common).await? {
+                if common == high {
+                    return Ok(common);
+                }
+                return self.search_ancestor(peer_id, common, high).await;
+            }
+            high = common;
+        }
+
+        if !self.peer_has_block(peer_id, 0).await? {
+            self.report(&peer_id, Misbehaviour::InvalidBlock);
+            return Err(NetworkError::Sync(format!(
+                "Peer {} follows a different genesis",
+                peer_id
+            )));
+        }
+        self.search_ancestor(peer_id, 0, high).await
+    }
+
+    /// Binary search for the common ancestor, given that `low` is shared
+    async fn search_ancestor(
+        &mut self,
+        peer_id: PeerId,
+        mut low: BlockNumber,
+        mut high: BlockNumber,
+    ) -> NetworkResult<BlockNumber> {
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if self.peer_has_block(peer_id, mid).await? {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        if let Some(head) = self.heads.get_mut(&peer_id) {
+            head.common_number = Some(low);
+        }
+        Ok(low)
+    }
+
+    /// Check whether the peer has our canonical block at a height
+    async fn peer_has_block(
+        &mut self,
+        peer_id: PeerId,
+        number: BlockNumber,
+    ) -> NetworkResult<bool> {
+        let hash = self.chain.hash_at(number)?.ok_or_else(|| {
+            NetworkError::Sync(format!("Local chain has no block at height {}", number))
+        })?;
+
+        let headers = self.request_headers(peer_id, hash, 1).await?;
+        match headers.first() {
+            Some(header) if header_hash(header)? == hash => Ok(true),
+            Some(_) => {
+                self.report(&peer_id, Misbehaviour::InvalidBlock);
+                Err(NetworkError::Sync(format!(
+                    "Peer {} answered with the wrong header",
+                    peer_id
+                )))
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Download a batch of headers after `common`, up to `target`, checking
+    /// that they form a chain
+    ///
+    /// Consensus checks are left to the caller, as they may need the blocks
+    /// before each header to be imported first.
+    async fn download_headers(
+        &mut self,
+        peer_id: PeerId,
+        common: BlockNumber,
+        target: BlockNumber,
+    ) -> NetworkResult<Vec<BlockHeader>> {
+        let amount = target
+            .saturating_sub(common)
+            .min(u64::from(limits::MAX_HEADERS_PER_REQUEST) - 1) as u32;
+        if amount == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut parent = self.local_header(common)?;
+        let start = header_hash(&parent)?;
+
+        // The response starts with the ancestor itself
+        let response = self.request_headers(peer_id, start, amount + 1).await?;
+        if response.len() <= 1 {
+            self.report(&peer_id, Misbehaviour::Timeout);
+            return Err(NetworkError::Sync(format!(
+                "Peer {} served no headers after block {}",
+                peer_id, common
+            )));
+        }
+
+        let mut headers = Vec::with_capacity(response.len() - 1);
+        for header in response.into_iter().skip(1) {
+            if let Err(e) = check_link(&parent, &header) {
+                self.report(&peer_id, Misbehaviour::InvalidBlock);
+                return Err(e);
+            }
+            parent = header.clone();
+            headers.push(header);
+        }
+
+        self.reward(&peer_id);
+        self.stats.record_headers_synced(headers.len() as u64);
+        Ok(headers)
+    }
+
+    /// Get a header of the local chain by height
+    fn local_header(&self, number: BlockNumber) -> NetworkResult<BlockHeader> {
+        self.chain.header_at(number)?.ok_or_else(|| {
+            NetworkError::Sync(format!("Local chain has no header at height {}", number))
+        })
+    }
+
+    /// Download the bodies of the given headers from all peers that have them
+    async fn download_bodies(
+        &mut self,
+        headers: &[BlockHeader],
+    ) -> NetworkResult<Vec<Vec<Transaction>>> {
+        let hashes = headers
+            .iter()
+            .map(header_hash)
+            .collect::<NetworkResult<Vec<_>>>()?;
+        let mut bodies: Vec<Option<Vec<Transaction>>> = vec![None; headers.len()];
+
+        let mut queue: VecDeque<BodyBatch> = (0..headers.len())
+            .step_by(limits::MAX_BODIES_PER_REQUEST)
+            .map(|start| BodyBatch {
+                start,
+                end: (start + limits::MAX_BODIES_PER_REQUEST).min(headers.len()),
+                attempts: 0,
+            })
+            .collect();
+        let mut busy = HashSet::new();
+        let mut failed = HashSet::new();
+        let mut in_flight = FuturesUnordered::new();
+
+        loop {
+            // Hand out batches to idle peers that have the blocks
+            while let Some(batch) = queue.pop_front() {
+                let last = headers[batch.end - 1].number;
+                let Some(peer_id) = self.idle_peer(last, &busy, &failed) else {
+                    queue.push_front(batch);
+                    break;
+                };
+
+                busy.insert(peer_id);
+                self.stats.record_request_sent();
+                let sync = self.sync.clone();
+                let request = hashes[batch.start..batch.end].to_vec();
+                in_flight.push(async move {
+                    let result = sync.request_bodies(peer_id, request).await;
+                    (peer_id, batch, result)
+                });
+            }
+
+            let Some((peer_id, batch, result)) = in_flight.next().await else {
+                if queue.is_empty() {
+                    break;
+                }
+                return Err(NetworkError::Sync(
+                    "No peer left to download bodies from".to_string(),
+                ));
+            };
+            busy.remove(&peer_id);
+
+            let served = match result {
+                Ok(served) => {
+                    self.stats.record_response_received();
+                    served
+                }
+                Err(e) => {
+                    tracing::debug!("Body request to {} failed: {}", peer_id, e);
+                    self.stats.record_request_failed();
+                    self.report(&peer_id, Misbehaviour::Timeout);
+                    failed.insert(peer_id);
+                    self.retry(&mut queue, batch)?;
+                    continue;
+                }
+            };
+
+            // Peers serve bodies for a prefix of the requested hashes, cut
+            // short by their response size limit
+            let mut received = 0;
+            let mut mismatch = false;
+            for (index, body) in (batch.start..batch.end).zip(served) {
+                let block = Block::new(headers[index].clone(), body);
+                let root = block
+                    .calculate_transactions_root()
+                    .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+                if root != block.header.transactions_root {
+                    mismatch = true;
+                    break;
+                }
+                bodies[index] = Some(block.transactions);
+                received += 1;
+            }
+
+            let served_all = batch.start + received == batch.end;
+            if mismatch {
+                self.report(&peer_id, Misbehaviour::InvalidBlock);
+                failed.insert(peer_id);
+            } else if received == 0 {
+                self.report(&peer_id, Misbehaviour::Timeout);
+                failed.insert(peer_id);
+            } else {
+                self.reward(&peer_id);
+            }
+            self.stats.record_bodies_synced(received as u64);
+
+            if !served_all {
+                let rest = BodyBatch {
+                    start: batch.start + received,
+                    ..batch
+                };
+                if received == 0 {
+                    self.retry(&mut queue, rest)?;
+                } else {
+                    queue.push_back(rest);
+                }
+            }
+        }
+
+        Ok(bodies.into_iter().flatten().collect())
+    }
+
+    /// Requeue a batch after a failed attempt
+    fn retry(&self, queue: &mut VecDeque<BodyBatch>, batch: BodyBatch) -> NetworkResult<()> {
+        if batch.attempts >= self.max_retries {
+            return Err(NetworkError::Sync(format!(
+                "Giving up on {} bodies after {} attempts",
+                batch.end - batch.start,
+                batch.attempts + 1
+            )));
+        }
+        queue.push_back(BodyBatch {
+            attempts: batch.attempts + 1,
+            ..batch
+        });
+        Ok(())
+    }
+
+    /// Pick a peer that has a block and is not busy
+    fn idle_peer(
+        &self,
+        number: BlockNumber,
+        busy: &HashSet<PeerId>,
+        failed: &HashSet<PeerId>,
+    ) -> Option<PeerId> {
+        self.heads
+            .iter()
+            .filter(|(peer_id, head)| {
+                head.best_number >= number && !busy.contains(peer_id) && !failed.contains(peer_id)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .next()
+    }
+
+    async fn request_headers(
+        &mut self,
+        peer_id: PeerId,
+        start: Hash,
+        amount: u32,
+    ) -> NetworkResult<Vec<BlockHeader>> {
+        self.stats.record_request_sent();
+        match self.sync.request_headers(peer_id, start, amount).await {
+            Ok(headers) => {
+                self.stats.record_response_received();
+                Ok(headers)
+            }
+            Err(e) => {
+                self.stats.record_request_failed();
+                self.report(&peer_id, Misbehaviour::Timeout);
+                Err(e)
+            }
+        }
+    }
+
+    fn peer_head(&self, peer_id: &PeerId) -> NetworkResult<PeerHead> {
+        self.heads
+            .get(peer_id)
+            .cloned()
+            .ok_or_else(|| NetworkError::PeerNotFound(peer_id.to_string()))
+    }
+
+    fn report(&self, peer_id: &PeerId, misbehaviour: Misbehaviour) {
+        if let Err(e) = self.reputation.report(peer_id, misbehaviour) {
+            tracing::warn!("Failed to report {}: {}", peer_id, e);
+        }
+    }
+
+    fn reward(&self, peer_id: &PeerId) {
+        if let Err(e) = self.reputation.reward(peer_id, USEFUL_RESPONSE_REWARD) {
+            tracing::warn!("Failed to reward {}: {}", peer_id, e);
+        }
+    }
+}
+
+/// Check that a header links to its parent
+fn check_link(parent: &BlockHeader, header: &BlockHeader) -> NetworkResult<()> {
+    if header.parent_hash != header_hash(parent)? || header.number != parent.number + 1 {
+        return Err(NetworkError::Sync(format!(
+            "Header {} does not extend block {}",
+            header.number, parent.number
+        )));
+    }
+    Ok(())
+}
+
+fn header_hash(header: &BlockHeader) -> NetworkResult<Hash> {
+    header
+        .hash()
+        .map_err(|e| NetworkError::Encoding(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{MemoryChain, StateEntry};
+    use crate::reputation::{MemoryReputationStore, ReputationConfig};
+    use crate::sync::{SyncCommand, SyncHandler};
+    use std::sync::RwLock;
+
+    /// Importer that stores blocks in a memory chain
+    pub(super) struct TestImport {
+        pub(super) chain: Arc<MemoryChain>,
+        pub(super) best: RwLock<BlockHeader>,
+    }
+
+    impl WarpImport for TestImport {
+        fn import_headers(&self, headers: Vec<BlockHeader>) -> NetworkResult<()> {
+            for header in headers {
+                self.chain.insert_header(header)?;
+            }
+            Ok(())
+        }
+
+        fn import_state(&self, pivot: &BlockHeader, entries: Vec<StateEntry>) -> NetworkResult<()> {
+            self.chain.set_state(pivot.state_root, entries);
+            *self.best.write().unwrap() = pivot.clone();
+            Ok(())
+        }
+    }
+
+    impl BlockImport for TestImport {
+        fn best_header(&self) -> BlockHeader {
+            self.best.read().unwrap().clone()
+        }
+
+        fn verify_header(&self, parent: &BlockHeader, header: &BlockHeader) -> NetworkResult<()> {
+            if header.timestamp <= parent.timestamp {
+                return Err(NetworkError::Sync("Timestamp not increasing".to_string()));
+            }
+            Ok(())
+        }
+
+        fn import_block(&self, block: Block) -> NetworkResult<()> {
+            *self.best.write().unwrap() = block.header.clone();
+            self.chain.insert_block(block)?;
+            Ok(())
+        }
+    }
+
+    fn build_chain(genesis_extra: &[u8], length: u64) -> Vec<Block> {
+        build_chain_with_state(genesis_extra, length, Hash::zero())
+    }
+
+    /// Build a chain whose headers all commit to the same state
+    pub(super) fn build_chain_with_state(
+        genesis_extra: &[u8],
+        length: u64,
+        state_root: Hash,
+    ) -> Vec<Block> {
+        let mut genesis = BlockHeader::genesis();
+        genesis.extra_data = genesis_extra.to_vec();
+        genesis.state_root = state_root;
+        let mut blocks = vec![Block::new(genesis, vec![])];
+        for number in 1..length {
+            let parent = &blocks[blocks.len() - 1].header;
+            let mut header = parent.clone();
+            header.parent_hash = parent.hash().unwrap();
+            header.number = number;
+            header.timestamp = parent.timestamp + 1;
+            blocks.push(Block::new(header, vec![]));
+        }
+        blocks
+    }
+
+    /// Build a chain with `count` transactions in each block after genesis
+    fn build_chain_with_transactions(length: u64, count: u64) -> Vec<Block> {
+        let mut blocks = build_chain(b"genesis", 1);
+        for number in 1..length {
+            let parent = &blocks[blocks.len() - 1].header;
+            let mut header = parent.clone();
+            header.parent_hash = parent.hash().unwrap();
+            header.number = number;
+            header.timestamp = parent.timestamp + 1;
+            let transactions = (0..count)
+                .map(|nonce| {
+                    Transaction::transfer(nonce, chain_core::Address::new([1; 20]), 1, 1, 21_000)
+                })
+                .collect();
+            let mut block = Block::new(header, transactions);
+            block.header.transactions_root = block.calculate_transactions_root().unwrap();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    pub(super) fn memory_chain(blocks: &[Block]) -> Arc<MemoryChain> {
+        let chain = Arc::new(MemoryChain::new());
+        for block in blocks {
+            chain.insert_block(block.clone()).unwrap();
+        }
+        chain
+    }
+
+    /// Answer sync requests as if each peer served its own chain
+    pub(super) fn spawn_peers(handlers: HashMap<PeerId, SyncHandler>) -> SyncManager {
+        let (manager, mut rx) = SyncManager::new();
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                if let SyncCommand::SendRequest {
+                    peer_id,
+                    request,
+                    response_sender,
+                } = command
+                {
+                    let result = match handlers.get(&peer_id) {
+                        Some(handler) => Ok(handler.handle_request(request, peer_id).await),
+                        None => Err(NetworkError::Timeout),
+                    };
+                    let _ = response_sender.send(result);
+                }
+            }
+        });
+        manager
+    }
+
+    async fn setup(
+        blocks: &[Block],
+        local_length: usize,
+        handlers: HashMap<PeerId, SyncHandler>,
+    ) -> (ChainSync, Arc<MemoryChain>, Arc<ReputationManager>) {
+        let (sync, import, reputation) = setup_with_import(blocks, local_length, handlers).await;
+        (sync, import.chain.clone(), reputation)
+    }
+
+    pub(super) async fn setup_with_import(
+        blocks: &[Block],
+        local_length: usize,
+        handlers: HashMap<PeerId, SyncHandler>,
+    ) -> (ChainSync, Arc<TestImport>, Arc<ReputationManager>) {
+        let local = memory_chain(&blocks[..local_length]);
+        let import = Arc::new(TestImport {
+            chain: local.clone(),
+            best: RwLock::new(blocks[local_length - 1].header.clone()),
+        });
+
+        let (reputation, _bans) = ReputationManager::new(
+            ReputationConfig::default(),
+            Arc::new(MemoryReputationStore::default()),
+        )
+        .unwrap();
+        let reputation = Arc::new(reputation);
+
+        let sync = ChainSync::new(
+            spawn_peers(handlers),
+            reputation.clone(),
+            local,
+            import.clone(),
+        );
+        (sync, import, reputation)
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_multiple_peers() {
+        let blocks = build_chain(b"genesis", 300);
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let mut handlers = HashMap::new();
+        handlers.insert(first, SyncHandler::new(memory_chain(&blocks)));
+        handlers.insert(second, SyncHandler::new(memory_chain(&blocks[..250])));
+
+        let (mut sync, local, _) = setup(&blocks, 20, handlers).await;
+        sync.update_peer_head(first, 299);
+        sync.update_peer_head(second, 249);
+
+        assert_eq!(sync.sync_to_best().await.unwrap(), 280);
+        assert_eq!(sync.state(), SyncState::Idle);
+
+        let tip = blocks[299].hash().unwrap();
+        assert_eq!(local.hash_at(299).unwrap(), Some(tip));
+        assert_eq!(sync.stats().headers_synced, 280);
+        assert_eq!(sync.stats().bodies_synced, 280);
+        assert_eq!(sync.stats().best_block, 299);
+        assert_eq!(sync.stats().target_block, 299);
+        assert_eq!(sync.stats().requests_failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_bad_peers_are_penalised() {
+        let blocks = build_chain(b"genesis", 60);
+        let (good, forked, silent) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut handlers = HashMap::new();
+        handlers.insert(good, SyncHandler::new(memory_chain(&blocks)));
+        handlers.insert(
+            forked,
+            SyncHandler::new(memory_chain(&build_chain(b"other", 100))),
+        );
+
+        let (mut sync, local, reputation) = setup(&blocks, 10, handlers).await;
+        sync.update_peer_head(good, 59);
+        sync.update_peer_head(forked, 99);
+        sync.update_peer_head(silent, 80);
+
+        assert_eq!(sync.sync_to_best().await.unwrap(), 50);
+        assert!(local.hash_at(59).unwrap().is_some());
+
+        assert!(reputation.score(&good) > 0.0);
+        assert!(reputation.score(&forked) <= Misbehaviour::InvalidBlock.cost() + 1.0);
+        assert!(reputation.score(&silent) < 0.0);
+        assert!(sync.stats().requests_failed > 0);
+    }
+
+    #[tokio::test]
+    async fn test_body_root_mismatch_penalised() {
+        let blocks = build_chain(b"genesis", 80);
+        let (good, liar) = (PeerId::random(), PeerId::random());
+
+        // The liar has the right headers but serves other bodies
+        let lying = memory_chain(&blocks);
+        for block in &blocks[1..] {
+            let transaction =
+                Transaction::transfer(0, chain_core::Address::new([1; 20]), 1, 1, 21_000);
+            lying
+                .insert_block(Block::new(block.header.clone(), vec![transaction]))
+                .unwrap();
+        }
+
+        let mut handlers = HashMap::new();
+        handlers.insert(good, SyncHandler::new(memory_chain(&blocks)));
+        handlers.insert(liar, SyncHandler::new(lying));
+
+        let (mut sync, local, reputation) = setup(&blocks, 10, handlers).await;
+        sync.update_peer_head(good, 79);
+        sync.update_peer_head(liar, 79);
+
+        assert_eq!(sync.sync_to_best().await.unwrap(), 70);
+        assert_eq!(local.hash_at(79).unwrap(), Some(blocks[79].hash().unwrap()));
+
+        // A wrong first body costs as much as any other wrong body
+        let liar_score = reputation.score(&liar);
+        assert!(liar_score <= Misbehaviour::InvalidBlock.cost() + USEFUL_RESPONSE_REWARD + 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_size_capped_bodies_are_not_penalised() {
+        let blocks = build_chain_with_transactions(60, 20);
+        let peer_id = PeerId::random();
+        let mut handlers = HashMap::new();
+        handlers.insert(
+            peer_id,
+            SyncHandler::new(memory_chain(&blocks)).with_max_response_size(4 * 1024),
+        );
+
+        // The only peer serves a few bodies per page, fewer than the headers
+        // it serves, which still completes the sync
+        let (mut sync, local, reputation) = setup(&blocks, 10, handlers).await;
+        sync.update_peer_head(peer_id, 59);
+
+        assert_eq!(sync.sync_to_best().await.unwrap(), 50);
+        assert_eq!(
+            local.body(&blocks[59].hash().unwrap()).unwrap(),
+            Some(blocks[59].transactions.clone())
+        );
+        assert!(reputation.score(&peer_id) > 0.0);
+        assert_eq!(sync.stats().requests_failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_warp_sync_then_import() {
+        // Keys spread over the whole key space
+        let entries: Vec<StateEntry> = {
+            let mut entries: Vec<StateEntry> = (0u32..3000)
+                .map(|i| {
+                    let key = i.wrapping_mul(2_654_435_761).to_be_bytes().to_vec();
+                    (key, i.to_le_bytes().to_vec())
+                })
+                .collect();
+            entries.sort();
+            entries
+        };
+        let root = chain_core::entries_root(&entries);
+        let blocks = build_chain_with_state(b"genesis", 200, root);
+
+        let serving = |state: &[StateEntry]| {
+            let chain = memory_chain(&blocks);
+            chain.set_state(root, state.to_vec());
+            SyncHandler::new(chain)
+        };
+        let mut corrupted = entries.clone();
+        corrupted[1500].1 = vec![0xff];
+
+        let (first, second, liar) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut handlers = HashMap::new();
+        handlers.insert(first, serving(&entries));
+        handlers.insert(second, serving(&entries));
+        handlers.insert(liar, serving(&corrupted));
+
+        let (sync, import, reputation) = setup_with_import(&blocks, 1, handlers).await;
+        let mut sync = sync.with_warp_import(import.clone());
+        for peer_id in [first, second, liar] {
+            sync.update_peer_head(peer_id, 199);
+        }
+
+        // Only the blocks after the pivot are imported
+        assert_eq!(sync.sync_to_best().await.unwrap(), DEFAULT_PIVOT_DISTANCE);
+        assert_eq!(import.best_header().number, 199);
+        assert_eq!(sync.stats().state_entries_synced as usize, entries.len());
+
+        let stored = import
+            .chain
+            .state_entries(&root, &[], &[], usize::MAX)
+            .unwrap();
+        assert_eq!(stored, Some(entries));
+
+        assert!(reputation.score(&liar) < 0.0);
+    }
+}
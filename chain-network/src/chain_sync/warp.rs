@@ -0,0 +1,498 @@
+//! Warp sync: download the state at a pivot block instead of replaying history
+//!
+//! The pivot is the block [`DEFAULT_PIVOT_DISTANCE`](super::DEFAULT_PIVOT_DISTANCE)
+//! below the highest head announced by at least
+//! [`DEFAULT_PIVOT_PEERS`](super::DEFAULT_PIVOT_PEERS) peers, so a single peer
+//! cannot choose it by announcing a far-off head. Headers up to the pivot are
+//! downloaded and checked to link up, and that many peers must serve the pivot
+//! as part of their canonical chain. Consensus checks need the state the
+//! headers were produced on, such as the authority set, so the engine takes
+//! over from the pivot once its state is installed. The state under the pivot's `state_root` is fetched
+//! in key ranges from all peers, each range checked with a [`RangeProof`].
+//! Ranges that are still missing afterwards are healed from other peers, and
+//! the assembled state must hash to the pivot's root before it is installed.
+//! Regular block import continues from the pivot.
+
+use super::{header_hash, ChainSync, SyncState};
+use crate::message::limits;
+use crate::reputation::Misbehaviour;
+use crate::{NetworkError, NetworkResult};
+use chain_core::{entries_root, BlockHeader, BlockNumber, Hash, StateEntry};
+use futures::stream::{FuturesUnordered, StreamExt};
+use libp2p::PeerId;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+/// Number of key ranges the state download starts with
+const STATE_SPLITS: u8 = 16;
+
+/// Storage hooks for installing warp-synced data
+pub trait WarpImport: Send + Sync {
+    /// Store verified headers without their bodies
+    ///
+    /// The headers must become readable through the sync's chain reader.
+    fn import_headers(&self, headers: Vec<BlockHeader>) -> NetworkResult<()>;
+
+    /// Install the verified state at the pivot and make it the best block
+    ///
+    /// Fails if the pivot does not pass the consensus engine's checks
+    /// against that state.
+    fn import_state(&self, pivot: &BlockHeader, entries: Vec<StateEntry>) -> NetworkResult<()>;
+}
+
+/// State entries still to download, starting at a key
+#[derive(Debug, Clone)]
+struct RangeTask {
+    /// Download from the first key not below this one
+    start: Vec<u8>,
+    /// Failed attempts so far
+    attempts: usize,
+}
+
+impl RangeTask {
+    fn new(start: Vec<u8>) -> Self {
+        Self { start, attempts: 0 }
+    }
+}
+
+impl ChainSync {
+    /// Warp to a recent pivot if we are far enough behind our peers
+    ///
+    /// Returns the pivot number, or `None` if regular import is enough or
+    /// too few peers are known to agree on a pivot.
+    pub async fn warp_sync(&mut self) -> NetworkResult<Option<BlockNumber>> {
+        let Some(warp) = self.warp.clone() else {
+            return Ok(None);
+        };
+
+        let local = self.import.best_header().number;
+        let Some(agreed) = self.agreed_head() else {
+            return Ok(None);
+        };
+        if agreed <= local.saturating_add(self.pivot_distance) {
+            self.warp = None;
+            return Ok(None);
+        }
+        let pivot_number = agreed - self.pivot_distance;
+        let target = self.best_peer(&HashSet::new()).ok_or_else(|| {
+            NetworkError::Sync("No peer to download the pivot headers from".to_string())
+        })?;
+
+        // Linked header chain up to the pivot
+        self.state = SyncState::AncestorSearch;
+        let mut common = self.find_common_ancestor(target).await?;
+        self.state = SyncState::Headers;
+        while common < pivot_number {
+            let headers = self.download_headers(target, common, pivot_number).await?;
+            common = headers.last().map_or(common, |header| header.number);
+            warp.import_headers(headers)?;
+        }
+        let pivot = self.chain.header_at(pivot_number)?.ok_or_else(|| {
+            NetworkError::Sync(format!("Pivot header {} was not stored", pivot_number))
+        })?;
+        self.confirm_pivot(target, &pivot).await?;
+
+        let entries = self.download_state(&pivot).await?;
+        if let Err(e) = warp.import_state(&pivot, entries) {
+            self.report(&target, Misbehaviour::InvalidBlock);
+            return Err(e);
+        }
+
+        tracing::info!("Warp synced to block {}", pivot_number);
+        self.stats.best_block = pivot_number;
+        self.state = SyncState::Idle;
+        self.warp = None;
+        Ok(Some(pivot_number))
+    }
+
+    /// Get the highest head reached by enough peers to pick a pivot below it
+    fn agreed_head(&self) -> Option<BlockNumber> {
+        let mut heads: Vec<BlockNumber> =
+            self.heads.values().map(|head| head.best_number).collect();
+        heads.sort_unstable_by(|a, b| b.cmp(a));
+        heads.get(self.pivot_peers - 1).copied()
+    }
+
+    /// Check that enough peers serve the pivot as part of their canonical chain
+    ///
+    /// `source` served the pivot header and counts as one of them. Peers
+    /// without the pivot may follow another fork and are not penalised.
+    async fn confirm_pivot(&mut self, source: PeerId, pivot: &BlockHeader) -> NetworkResult<()> {
+        let hash = header_hash(pivot)?;
+        let candidates: Vec<PeerId> = self
+            .heads
+            .iter()
+            .filter(|(peer_id, head)| **peer_id != source && head.best_number >= pivot.number)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+
+        let mut confirmations = 1;
+        for peer_id in candidates {
+            if confirmations >= self.pivot_peers {
+                break;
+            }
+            match self.request_headers(peer_id, hash, 1).await {
+                Ok(headers) if headers.first() == Some(pivot) => confirmations += 1,
+                Ok(_) => tracing::debug!("{} does not have pivot {}", peer_id, hash),
+                Err(e) => tracing::debug!("Pivot request to {} failed: {}", peer_id, e),
+            }
+        }
+
+        if confirmations < self.pivot_peers {
+            return Err(NetworkError::Sync(format!(
+                "Pivot {} is known to {} of {} required peers",
+                hash, confirmations, self.pivot_peers
+            )));
+        }
+        Ok(())
+    }
+
+    /// Download and verify the state under the pivot's state root
+    async fn download_state(&mut self, pivot: &BlockHeader) -> NetworkResult<Vec<StateEntry>> {
+        let root = pivot.state_root;
+        if root == Hash::zero() {
+            return Ok(Vec::new());
+        }
+
+        let step = u8::MAX / STATE_SPLITS + 1;
+        let mut tasks: VecDeque<RangeTask> = (0..STATE_SPLITS)
+            .map(|split| match split {
+                0 => RangeTask::new(Vec::new()),
+                _ => RangeTask::new(vec![split * step]),
+            })
+            .collect();
+        let mut received = BTreeMap::new();
+        let mut leaf_count = None;
+
+        self.state = SyncState::WarpState;
+        for _ in 0..=self.max_retries {
+            self.download_ranges(pivot, tasks, &mut received, &mut leaf_count)
+                .await?;
+
+            let gaps = missing_ranges(&received, leaf_count.unwrap_or(0));
+            if gaps.is_empty() {
+                let entries: Vec<StateEntry> = received.into_values().collect();
+                if entries_root(&entries) != root {
+                    return Err(NetworkError::Sync(format!(
+                        "Downloaded state does not match root {}",
+                        root
+                    )));
+                }
+                return Ok(entries);
+            }
+
+            // Continue each gap from the entry just before it
+            self.state = SyncState::WarpHealing;
+            tasks = gaps
+                .into_iter()
+                .map(|index| match index.checked_sub(1) {
+                    Some(previous) => RangeTask::new(successor(&received[&previous].0)),
+                    None => RangeTask::new(Vec::new()),
+                })
+                .collect();
+        }
+
+        Err(NetworkError::Sync(format!(
+            "State under root {} is still incomplete",
+            root
+        )))
+    }
+
+    /// Download ranges in parallel until every task has been served
+    async fn download_ranges(
+        &mut self,
+        pivot: &BlockHeader,
+        mut tasks: VecDeque<RangeTask>,
+        received: &mut BTreeMap<u64, StateEntry>,
+        leaf_count: &mut Option<u64>,
+    ) -> NetworkResult<()> {
+        let root = pivot.state_root;
+        let mut busy = HashSet::new();
+        let mut failed = HashSet::new();
+        let mut in_flight = FuturesUnordered::new();
+
+        loop {
+            while let Some(task) = tasks.pop_front() {
+                let Some(peer_id) = self.idle_peer(pivot.number, &busy, &failed) else {
+                    tasks.push_front(task);
+                    break;
+                };
+
+                busy.insert(peer_id);
+                self.stats.record_request_sent();
+                let sync = self.sync.clone();
+                let start = task.start.clone();
+                in_flight.push(async move {
+                    let result = sync
+                        .request_state_range(
+                            peer_id,
+                            root,
+                            start,
+                            limits::MAX_STATE_ENTRIES_PER_REQUEST,
+                        )
+                        .await;
+                    (peer_id, task, result)
+                });
+            }
+
+            let Some((peer_id, task, result)) = in_flight.next().await else {
+                if tasks.is_empty() {
+                    return Ok(());
+                }
+                return Err(NetworkError::Sync(
+                    "No peer left to download state from".to_string(),
+                ));
+            };
+            busy.remove(&peer_id);
+
+            let (entries, proof) = match result {
+                Ok(range) => {
+                    self.stats.record_response_received();
+                    range
+                }
+                Err(e) => {
+                    tracing::debug!("State request to {} failed: {}", peer_id, e);
+                    self.stats.record_request_failed();
+                    self.report(&peer_id, Misbehaviour::Timeout);
+                    failed.insert(peer_id);
+                    self.retry_range(&mut tasks, task)?;
+                    continue;
+                }
+            };
+
+            let verified = match *leaf_count {
+                Some(count) if count != proof.leaf_count => Err(NetworkError::Sync(format!(
+                    "Leaf count {} differs from {}",
+                    proof.leaf_count, count
+                ))),
+                _ => proof
+                    .verify(&root, &entries)
+                    .map_err(|e| NetworkError::Sync(e.to_string())),
+            };
+            if let Err(e) = verified {
+                tracing::debug!("Invalid state range from {}: {}", peer_id, e);
+                self.report(&peer_id, Misbehaviour::InvalidBlock);
+                failed.insert(peer_id);
+                self.retry_range(&mut tasks, task)?;
+                continue;
+            }
+
+            self.reward(&peer_id);
+            *leaf_count = Some(proof.leaf_count);
+
+            // Keep going until we reach a range another request already covered
+            let next = proof.first_index + entries.len() as u64;
+            let last_key = entries.last().map(|(key, _)| successor(key));
+            let mut new_entries = 0;
+            for (index, entry) in (proof.first_index..).zip(entries) {
+                if received.insert(index, entry).is_none() {
+                    new_entries += 1;
+                }
+            }
+            self.stats.record_state_entries_synced(new_entries);
+            if let Some(start) = last_key {
+                if next < proof.leaf_count && !received.contains_key(&next) {
+                    tasks.push_back(RangeTask::new(start));
+                }
+            }
+        }
+    }
+
+    /// Requeue a range after a failed attempt
+    fn retry_range(&self, tasks: &mut VecDeque<RangeTask>, task: RangeTask) -> NetworkResult<()> {
+        if task.attempts >= self.max_retries {
+            return Err(NetworkError::Sync(format!(
+                "Giving up on state range after {} attempts",
+                task.attempts + 1
+            )));
+        }
+        tasks.push_back(RangeTask {
+            attempts: task.attempts + 1,
+            ..task
+        });
+        Ok(())
+    }
+}
+
+/// Smallest key greater than `key`
+fn successor(key: &[u8]) -> Vec<u8> {
+    let mut next = key.to_vec();
+    next.push(0);
+    next
+}
+
+/// Positions that start a run of missing entries
+fn missing_ranges(received: &BTreeMap<u64, StateEntry>, leaf_count: u64) -> Vec<u64> {
+    let mut gaps = Vec::new();
+    let mut expected = 0;
+    for index in received.keys() {
+        if *index > expected {
+            gaps.push(expected);
+        }
+        expected = index + 1;
+    }
+    if expected < leaf_count {
+        gaps.push(expected);
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_sync::tests::{build_chain_with_state, memory_chain, setup_with_import};
+    use crate::chain_sync::{BlockImport, DEFAULT_PIVOT_DISTANCE};
+    use crate::sync::SyncHandler;
+    use chain_core::Block;
+    use std::collections::HashMap;
+
+    fn test_state() -> (Vec<StateEntry>, Hash) {
+        let entries: Vec<StateEntry> = (0u8..100).map(|i| (vec![i], vec![i; 4])).collect();
+        let root = entries_root(&entries);
+        (entries, root)
+    }
+
+    fn serving(blocks: &[Block], root: Hash, state: &[StateEntry]) -> SyncHandler {
+        let chain = memory_chain(blocks);
+        chain.set_state(root, state.to_vec());
+        SyncHandler::new(chain)
+    }
+
+    #[test]
+    fn test_successor() {
+        let key = b"ab".to_vec();
+        let next = successor(&key);
+        assert_eq!(next, b"ab\0".to_vec());
+        assert!(next > key);
+
+        // Nothing sorts between a key and its successor
+        assert!(next < b"ab\x00\x00".to_vec());
+        assert!(next < b"ab\x01".to_vec());
+        assert_eq!(successor(&[]), vec![0]);
+    }
+
+    #[test]
+    fn test_missing_ranges() {
+        let received = |indices: &[u64]| -> BTreeMap<u64, StateEntry> {
+            indices
+                .iter()
+                .map(|&index| (index, (vec![index as u8], vec![])))
+                .collect()
+        };
+
+        assert_eq!(missing_ranges(&received(&[]), 3), vec![0]);
+        assert_eq!(missing_ranges(&received(&[0, 1, 2]), 3), Vec::<u64>::new());
+        assert_eq!(
+            missing_ranges(&received(&[0, 1, 4, 5, 7]), 10),
+            vec![2, 6, 8]
+        );
+        assert_eq!(missing_ranges(&received(&[3]), 4), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_range_gives_up() {
+        let (_, root) = test_state();
+        let blocks = build_chain_with_state(b"genesis", 2, root);
+        let (sync, _, _) = setup_with_import(&blocks, 1, HashMap::new()).await;
+        let sync = sync.with_max_retries(1);
+
+        let mut tasks = VecDeque::new();
+        sync.retry_range(&mut tasks, RangeTask::new(vec![7]))
+            .unwrap();
+        let task = tasks.pop_front().unwrap();
+        assert_eq!((task.start.clone(), task.attempts), (vec![7], 1));
+
+        assert!(sync.retry_range(&mut tasks, task).is_err());
+        assert!(tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bad_range_proof_rejected() {
+        let (entries, root) = test_state();
+        let blocks = build_chain_with_state(b"genesis", 200, root);
+        let mut corrupted = entries.clone();
+        corrupted[50].1 = vec![0xff];
+
+        let (honest, liar) = (PeerId::random(), PeerId::random());
+        let mut handlers = HashMap::new();
+        handlers.insert(liar, serving(&blocks, root, &corrupted));
+        let (mut sync, _, reputation) = setup_with_import(&blocks, 1, handlers).await;
+        sync.update_peer_head(liar, 199);
+
+        // A range that does not prove against the root is never stored
+        let pivot = &blocks[100].header;
+        assert!(sync.download_state(pivot).await.is_err());
+        let score = reputation.score(&liar);
+        assert!(score <= Misbehaviour::InvalidBlock.cost() + 1.0);
+
+        // Ranges from another peer heal the download
+        let mut handlers = HashMap::new();
+        handlers.insert(honest, serving(&blocks, root, &entries));
+        handlers.insert(liar, serving(&blocks, root, &corrupted));
+        let (mut sync, _, _) = setup_with_import(&blocks, 1, handlers).await;
+        sync.update_peer_head(honest, 199);
+        sync.update_peer_head(liar, 199);
+        assert_eq!(sync.download_state(pivot).await.unwrap(), entries);
+    }
+
+    #[tokio::test]
+    async fn test_pivot_agreed_by_peers() {
+        let (entries, root) = test_state();
+        let blocks = build_chain_with_state(b"genesis", 200, root);
+        let (first, second, boaster) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        // A single peer cannot pick the pivot
+        let mut handlers = HashMap::new();
+        handlers.insert(boaster, serving(&blocks, root, &entries));
+        let (sync, import, _) = setup_with_import(&blocks, 1, handlers).await;
+        let mut sync = sync.with_warp_import(import.clone());
+        sync.update_peer_head(boaster, 10_000);
+        assert_eq!(sync.warp_sync().await.unwrap(), None);
+        assert_eq!(import.best_header().number, 0);
+
+        // The pivot is taken below the head several peers reached, not the highest one
+        let mut handlers = HashMap::new();
+        for peer_id in [first, second, boaster] {
+            handlers.insert(peer_id, serving(&blocks, root, &entries));
+        }
+        let (sync, import, _) = setup_with_import(&blocks, 1, handlers).await;
+        let mut sync = sync.with_warp_import(import.clone());
+        sync.update_peer_head(first, 199);
+        sync.update_peer_head(second, 199);
+        sync.update_peer_head(boaster, 10_000);
+
+        let pivot = 199 - DEFAULT_PIVOT_DISTANCE;
+        assert_eq!(sync.warp_sync().await.unwrap(), Some(pivot));
+        assert_eq!(import.best_header(), blocks[pivot as usize].header);
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_pivot_rejected() {
+        let (entries, root) = test_state();
+        let blocks = build_chain_with_state(b"genesis", 200, root);
+
+        // The forked peer shares the first 50 blocks
+        let mut other = blocks[..50].to_vec();
+        for number in 50..200 {
+            let parent = &other[other.len() - 1].header;
+            let mut header = parent.clone();
+            header.parent_hash = parent.hash().unwrap();
+            header.number = number;
+            header.timestamp = parent.timestamp + 2;
+            other.push(Block::new(header, vec![]));
+        }
+        let (first, forked) = (PeerId::random(), PeerId::random());
+
+        let mut handlers = HashMap::new();
+        handlers.insert(first, serving(&blocks, root, &entries));
+        handlers.insert(forked, serving(&other, root, &entries));
+        let (sync, import, _) = setup_with_import(&blocks, 1, handlers).await;
+        let mut sync = sync.with_warp_import(import.clone());
+        sync.update_peer_head(first, 199);
+        sync.update_peer_head(forked, 199);
+
+        // The peers agree on a height but not on the block at the pivot
+        let err = sync.warp_sync().await.unwrap_err();
+        assert!(err.to_string().contains("required peers"));
+        assert_eq!(import.best_header().number, 0);
+    }
+}
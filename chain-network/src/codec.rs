@@ -0,0 +1,243 @@
+//! Wire codec for the sync request-response protocol
+//!
+//! Every request and response is a single frame: a big-endian `u32` length
+//! followed by the bincode encoding of the message. Frames larger than the
+//! configured limit are rejected before they are read into memory.
+
+use crate::message::{limits, protocols, SyncRequest, SyncResponse};
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::StreamProtocol;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::time::Duration;
+
+/// Request-response behaviour speaking the sync protocol
+pub type SyncBehaviour = request_response::Behaviour<SyncCodec>;
+
+/// Length-prefixed bincode codec for [`SyncRequest`] and [`SyncResponse`]
+#[derive(Debug, Clone, Copy)]
+pub struct SyncCodec {
+    /// Maximum encoded request size in bytes
+    max_request_size: usize,
+    /// Maximum encoded response size in bytes
+    max_response_size: usize,
+}
+
+impl SyncCodec {
+    /// Create a codec limiting messages to `max_message_size` bytes
+    ///
+    /// Requests are additionally capped at [`limits::MAX_SYNC_REQUEST_SIZE`].
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_request_size: max_message_size.min(limits::MAX_SYNC_REQUEST_SIZE),
+            max_response_size: max_message_size,
+        }
+    }
+
+    /// Get the maximum response size in bytes
+    pub fn max_response_size(&self) -> usize {
+        self.max_response_size
+    }
+}
+
+impl Default for SyncCodec {
+    fn default() -> Self {
+        Self::new(limits::MAX_SYNC_RESPONSE_SIZE)
+    }
+}
+
+/// Build the sync behaviour with the given message limit and request timeout
+pub fn sync_behaviour(max_message_size: usize, request_timeout: Duration) -> SyncBehaviour {
+    request_response::Behaviour::with_codec(
+        SyncCodec::new(max_message_size),
+        [(
+            StreamProtocol::new(protocols::SYNC_REQUEST),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default().with_request_timeout(request_timeout),
+    )
+}
+
+/// Encode a message into a length-prefixed frame
+pub fn encode_frame<M: Serialize>(message: &M, max_size: usize) -> io::Result<Vec<u8>> {
+    let data = bincode::serde::encode_to_vec(message, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    check_size(data.len(), max_size)?;
+
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&data);
+    Ok(frame)
+}
+
+/// Get the encoded size of a message, without the length prefix
+pub fn encoded_len<M: Serialize>(message: &M) -> Option<usize> {
+    bincode::serde::encode_to_vec(message, bincode::config::standard())
+        .ok()
+        .map(|data| data.len())
+}
+
+/// Read and decode a length-prefixed frame
+pub async fn read_frame<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut len = [0u8; 4];
+    io.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    check_size(len, max_size)?;
+
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+
+    let (message, _) = bincode::serde::decode_from_slice(&data, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(message)
+}
+
+async fn write_frame<T, M>(io: &mut T, message: &M, max_size: usize) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    io.write_all(&encode_frame(message, max_size)?).await?;
+    io.close().await
+}
+
+fn check_size(len: usize, max_size: usize) -> io::Result<()> {
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Message of {} bytes exceeds limit of {} bytes",
+                len, max_size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl request_response::Codec for SyncCodec {
+    type Protocol = StreamProtocol;
+    type Request = SyncRequest;
+    type Response = SyncResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<SyncRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_frame(io, self.max_request_size).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<SyncResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_frame(io, self.max_response_size).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: SyncRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &request, self.max_request_size).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: SyncResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &response, self.max_response_size).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_core::{BlockHeader, Hash};
+    use futures::io::Cursor;
+    use request_response::Codec;
+
+    fn protocol() -> StreamProtocol {
+        StreamProtocol::new(protocols::SYNC_REQUEST)
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let mut codec = SyncCodec::default();
+
+        let mut buffer = Cursor::new(Vec::new());
+        codec
+            .write_request(
+                &protocol(),
+                &mut buffer,
+                SyncRequest::headers(Hash::zero(), 10),
+            )
+            .await
+            .unwrap();
+        buffer.set_position(0);
+        match codec.read_request(&protocol(), &mut buffer).await.unwrap() {
+            SyncRequest::GetHeaders { amount, .. } => assert_eq!(amount, 10),
+            _ => panic!("Wrong request type"),
+        }
+
+        let headers = vec![BlockHeader::genesis(); 3];
+        let mut buffer = Cursor::new(Vec::new());
+        codec
+            .write_response(
+                &protocol(),
+                &mut buffer,
+                SyncResponse::headers(headers.clone()),
+            )
+            .await
+            .unwrap();
+        buffer.set_position(0);
+        match codec.read_response(&protocol(), &mut buffer).await.unwrap() {
+            SyncResponse::Headers { headers: decoded } => assert_eq!(decoded, headers),
+            _ => panic!("Wrong response type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_size_limits() {
+        let response = SyncResponse::headers(vec![BlockHeader::genesis(); 100]);
+        let frame = encode_frame(&response, usize::MAX).unwrap();
+
+        // The sender refuses to write an oversized frame
+        let mut codec = SyncCodec::new(frame.len() / 2);
+        let mut buffer = Cursor::new(Vec::new());
+        assert!(codec
+            .write_response(&protocol(), &mut buffer, response)
+            .await
+            .is_err());
+
+        // The receiver rejects it from the length prefix alone
+        let mut buffer = Cursor::new(frame[..4].to_vec());
+        let error = codec
+            .read_response(&protocol(), &mut buffer)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Requests are capped below the message limit
+        assert_eq!(
+            SyncCodec::new(usize::MAX).max_request_size,
+            limits::MAX_SYNC_REQUEST_SIZE
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Compact block relay
+//!
+//! A [`CompactBlock`] carries the header and a short id per transaction
+//! instead of the transactions themselves. Short ids are 48-bit SipHash-2-4
+//! values keyed by the block hash and a random salt, so collisions differ from
+//! one announcement to the next. The receiver rebuilds the block from its
+//! transaction pool with a [`PartialBlock`] and fetches only the transactions
+//! it lacks with [`SyncRequest::GetBlockTransactions`](crate::SyncRequest).
+
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Block, BlockHeader, Hash, Transaction};
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher24;
+use std::collections::HashMap;
+
+/// Short ids keep the low 48 bits of the SipHash output
+const SHORT_ID_MASK: u64 = 0xffff_ffff_ffff;
+
+/// Transaction sent in full inside a compact block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefilledTransaction {
+    /// Position in the block
+    pub index: u32,
+    /// The transaction
+    pub transaction: Transaction,
+}
+
+/// Block announcement with short transaction ids
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactBlock {
+    /// Block header
+    pub header: BlockHeader,
+    /// Salt for the short id keys
+    pub salt: u64,
+    /// Short ids of the transactions that are not prefilled, in block order
+    pub short_ids: Vec<u64>,
+    /// Transactions sent in full, by ascending index
+    pub prefilled: Vec<PrefilledTransaction>,
+}
+
+impl CompactBlock {
+    /// Build a compact block with a random salt
+    pub fn from_block(block: &Block) -> NetworkResult<Self> {
+        Self::with_salt(block, rand::random())
+    }
+
+    /// Build a compact block with the given salt
+    pub fn with_salt(block: &Block, salt: u64) -> NetworkResult<Self> {
+        let mut compact = Self {
+            header: block.header.clone(),
+            salt,
+            short_ids: Vec::with_capacity(block.transactions.len()),
+            prefilled: Vec::new(),
+        };
+
+        let hasher = compact.hasher()?;
+        for tx in &block.transactions {
+            compact.short_ids.push(short_id(&hasher, &tx_hash(tx)?));
+        }
+        Ok(compact)
+    }
+
+    /// Send the transactions at the given positions in full
+    ///
+    /// Useful for transactions the sender knows peers cannot have yet.
+    pub fn with_prefilled(mut self, block: &Block, indices: &[u32]) -> NetworkResult<Self> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        // Remove from the back so earlier positions stay valid
+        for index in indices.iter().rev() {
+            let transaction = block.transactions.get(*index as usize).ok_or_else(|| {
+                NetworkError::Encoding(format!("No transaction at index {}", index))
+            })?;
+            let before = self.prefilled.iter().filter(|p| p.index < *index).count();
+            if self.prefilled.iter().any(|p| p.index == *index) {
+                continue;
+            }
+            self.short_ids.remove(*index as usize - before);
+            self.prefilled.push(PrefilledTransaction {
+                index: *index,
+                transaction: transaction.clone(),
+            });
+        }
+        self.prefilled.sort_by_key(|prefilled| prefilled.index);
+        Ok(self)
+    }
+
+    /// Get the hash of the announced block
+    pub fn block_hash(&self) -> NetworkResult<Hash> {
+        self.header
+            .hash()
+            .map_err(|e| NetworkError::Encoding(e.to_string()))
+    }
+
+    /// Get the number of transactions in the block
+    pub fn transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    /// Compute the short id of a transaction for this block
+    pub fn short_id(&self, transaction: &Transaction) -> NetworkResult<u64> {
+        Ok(short_id(&self.hasher()?, &tx_hash(transaction)?))
+    }
+
+    /// SipHash keyed by the block hash and the salt
+    fn hasher(&self) -> NetworkResult<SipHasher24> {
+        let mut input = self.block_hash()?.as_bytes().to_vec();
+        input.extend_from_slice(&self.salt.to_le_bytes());
+        let key = blake3::hash(&input);
+        let key = key.as_bytes();
+        Ok(SipHasher24::new_with_keys(
+            u64::from_le_bytes(key[..8].try_into().unwrap()),
+            u64::from_le_bytes(key[8..16].try_into().unwrap()),
+        ))
+    }
+}
+
+/// A block being rebuilt from a compact announcement
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    /// Announced header
+    header: BlockHeader,
+    /// Transactions by position, `None` where still missing
+    slots: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Fill in the transactions the pool already holds
+    ///
+    /// Pool transactions whose short ids collide are left out, so the
+    /// affected positions are requested instead.
+    pub fn new<'a>(
+        compact: &CompactBlock,
+        pool: impl IntoIterator<Item = &'a Transaction>,
+    ) -> NetworkResult<Self> {
+        let count = compact.transaction_count();
+        let mut slots: Vec<Option<Transaction>> = vec![None; count];
+
+        // Positions of the short ids, skipping over prefilled transactions
+        let mut positions = HashMap::with_capacity(compact.short_ids.len());
+        let mut prefilled = compact.prefilled.iter().peekable();
+        let mut short_ids = compact.short_ids.iter();
+        for (index, slot) in slots.iter_mut().enumerate() {
+            if let Some(tx) = prefilled.next_if(|p| p.index as usize == index) {
+                *slot = Some(tx.transaction.clone());
+                continue;
+            }
+            let id = short_ids.next().ok_or_else(|| {
+                NetworkError::Encoding("Compact block has too few short ids".to_string())
+            })?;
+            if positions.insert(*id, index).is_some() {
+                return Err(NetworkError::Encoding(
+                    "Compact block repeats a short id".to_string(),
+                ));
+            }
+        }
+        if prefilled.next().is_some() {
+            return Err(NetworkError::Encoding(
+                "Compact block has prefilled transactions out of range or order".to_string(),
+            ));
+        }
+
+        let hasher = compact.hasher()?;
+        let mut matched: HashMap<usize, Option<Transaction>> = HashMap::new();
+        for tx in pool {
+            let id = short_id(&hasher, &tx_hash(tx)?);
+            if let Some(index) = positions.get(&id) {
+                // A second match makes the position ambiguous
+                matched
+                    .entry(*index)
+                    .and_modify(|found| *found = None)
+                    .or_insert_with(|| Some(tx.clone()));
+            }
+        }
+        for (index, tx) in matched {
+            slots[index] = tx;
+        }
+
+        Ok(Self {
+            header: compact.header.clone(),
+            slots,
+        })
+    }
+
+    /// Get the positions of the transactions still missing
+    pub fn missing_indices(&self) -> Vec<u32> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    /// Check whether every transaction is present
+    pub fn is_complete(&self) -> bool {
+        self.slots.iter().all(Option::is_some)
+    }
+
+    /// Fill the missing positions, in order, with fetched transactions
+    pub fn fill(&mut self, transactions: Vec<Transaction>) -> NetworkResult<()> {
+        let missing = self.missing_indices();
+        if transactions.len() != missing.len() {
+            return Err(NetworkError::Sync(format!(
+                "Expected {} missing transactions, got {}",
+                missing.len(),
+                transactions.len()
+            )));
+        }
+        for (index, tx) in missing.into_iter().zip(transactions) {
+            self.slots[index as usize] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Assemble the block, checking it against the header's transactions root
+    ///
+    /// Fails on a short id collision with a different transaction; the block
+    /// should then be downloaded in full.
+    pub fn into_block(self) -> NetworkResult<Block> {
+        let transactions = self
+            .slots
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| {
+                NetworkError::Sync("Block still has missing transactions".to_string())
+            })?;
+
+        let block = Block::new(self.header, transactions);
+        let root = block
+            .calculate_transactions_root()
+            .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+        if root != block.header.transactions_root {
+            return Err(NetworkError::Sync(
+                "Rebuilt block does not match its transactions root".to_string(),
+            ));
+        }
+        Ok(block)
+    }
+}
+
+fn short_id(hasher: &SipHasher24, tx_hash: &Hash) -> u64 {
+    hasher.hash(tx_hash.as_bytes()) & SHORT_ID_MASK
+}
+
+fn tx_hash(transaction: &Transaction) -> NetworkResult<Hash> {
+    transaction
+        .hash()
+        .map_err(|e| NetworkError::Encoding(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoded_len;
+
+    fn test_block(count: u64) -> Block {
+        let transactions: Vec<Transaction> = (0..count)
+            .map(|nonce| Transaction::new(nonce, 1, 21_000, None, nonce.into(), vec![0; 100]))
+            .collect();
+        let mut block = Block::new(BlockHeader::genesis(), transactions);
+        block.header.transactions_root = block.calculate_transactions_root().unwrap();
+        block
+    }
+
+    #[test]
+    fn test_rebuild_from_pool() {
+        let block = test_block(20);
+        let compact = CompactBlock::from_block(&block).unwrap();
+        assert!(encoded_len(&compact).unwrap() * 4 < encoded_len(&block).unwrap());
+
+        let partial = PartialBlock::new(&compact, &block.transactions).unwrap();
+        assert!(partial.is_complete());
+        assert_eq!(partial.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn test_missing_transactions_are_requested() {
+        let block = test_block(10);
+        let compact = CompactBlock::with_salt(&block, 7)
+            .unwrap()
+            .with_prefilled(&block, &[0, 4])
+            .unwrap();
+        assert_eq!(compact.short_ids.len(), 8);
+
+        // The pool lacks transactions 3 and 8 and holds an unrelated one
+        let mut pool: Vec<Transaction> = block
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != 3 && *index != 8)
+            .map(|(_, tx)| tx.clone())
+            .collect();
+        pool.push(Transaction::new(99, 1, 21_000, None, 0, vec![]));
+
+        let mut partial = PartialBlock::new(&compact, &pool).unwrap();
+        assert_eq!(partial.missing_indices(), vec![3, 8]);
+
+        let fetched = vec![block.transactions[3].clone(), block.transactions[8].clone()];
+        partial.fill(fetched).unwrap();
+        assert_eq!(partial.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn test_wrong_transactions_rejected() {
+        let block = test_block(3);
+        let compact = CompactBlock::from_block(&block).unwrap();
+
+        let mut partial = PartialBlock::new(&compact, &block.transactions[..2]).unwrap();
+        partial
+            .fill(vec![Transaction::new(42, 1, 21_000, None, 0, vec![])])
+            .unwrap();
+        assert!(partial.into_block().is_err());
+    }
+}
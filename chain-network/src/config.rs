@@ -1,33 +1,73 @@
 //! Network configuration
 
+use crate::authority::DEFAULT_AUTHORITY_DISCOVERY_INTERVAL;
+use crate::bootstrap::BootstrapNodes;
+use crate::discovery::{peer_id_of, DEFAULT_RANDOM_WALK_INTERVAL};
+use crate::nat::DEFAULT_MAX_RELAY_RESERVATIONS;
+use crate::reputation::ReputationConfig;
+use crate::transport::TransportConfig;
+use chain_core::{BlockNumber, ForkId, ForkSchedule};
 use libp2p::Multiaddr;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::time::Duration;
 
+/// Prefix of the identify agent version carrying the fork id
+pub const FORK_AGENT_PREFIX: &str = "chain/fork/";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
     /// Node's listening addresses
     pub listen_addresses: Vec<Multiaddr>,
 
+    /// Addresses we are known to be reachable on, announced to peers without
+    /// waiting for AutoNAT to confirm them
+    #[serde(default)]
+    pub external_addresses: Vec<Multiaddr>,
+
     /// Bootstrap nodes to connect to
     pub bootstrap_nodes: Vec<Multiaddr>,
 
     /// Path to store node identity keypair
     pub keystore_path: PathBuf,
 
-    /// Maximum number of peer connections
+    /// Maximum number of connected peers
     pub max_peers: usize,
 
+    /// Maximum number of peers connected to us
+    #[serde(default = "default_max_inbound_peers")]
+    pub max_inbound_peers: usize,
+
+    /// Maximum number of peers we connect to
+    #[serde(default = "default_max_outbound_peers")]
+    pub max_outbound_peers: usize,
+
+    /// Maximum number of peers connected from a single IP address
+    #[serde(default = "default_max_peers_per_ip")]
+    pub max_peers_per_ip: usize,
+
+    /// Peers, as addresses ending in `/p2p/<peer id>`, that are kept connected
+    /// regardless of the limits
+    #[serde(default)]
+    pub reserved_peers: Vec<Multiaddr>,
+
     /// Connection timeout
     pub connection_timeout: Duration,
 
+    /// TCP and QUIC transport options
+    #[serde(default)]
+    pub transport: TransportConfig,
+
     /// Heartbeat interval for gossip
     pub gossip_heartbeat: Duration,
 
-    /// Maximum message size for gossip
+    /// Maximum message size for gossip and sync messages
     pub max_message_size: usize,
 
+    /// Time to wait for a sync response before the request fails
+    #[serde(default = "default_sync_request_timeout")]
+    pub sync_request_timeout: Duration,
+
     /// Mesh network parameters
     pub mesh_n: usize,
     pub mesh_n_low: usize,
@@ -37,6 +77,78 @@ pub struct NetworkConfig {
     pub enable_gossip: bool,
     pub enable_sync: bool,
     pub enable_mdns: bool,
+
+    /// Detect NAT with AutoNAT, listen through relays when behind it and
+    /// hole punch relayed connections
+    #[serde(default = "default_enable_nat_traversal")]
+    pub enable_nat_traversal: bool,
+
+    /// Relay connections for peers behind NAT
+    #[serde(default)]
+    pub enable_relay_server: bool,
+
+    /// Relays, as addresses ending in `/p2p/<peer id>`, to listen through when
+    /// behind NAT, in addition to relays found through identify
+    #[serde(default)]
+    pub relay_nodes: Vec<Multiaddr>,
+
+    /// Maximum number of relays to listen through at once
+    #[serde(default = "default_max_relay_reservations")]
+    pub max_relay_reservations: usize,
+
+    /// Time between Kademlia random walks looking for new peers
+    #[serde(default = "default_random_walk_interval")]
+    pub random_walk_interval: Duration,
+
+    /// Time between publishing our authority record and looking up the
+    /// records of the authority set
+    #[serde(default = "default_authority_discovery_interval")]
+    pub authority_discovery_interval: Duration,
+
+    /// Protocol upgrades, used to reject peers following other fork rules
+    #[serde(default)]
+    pub fork_schedule: ForkSchedule,
+
+    /// Best block number when the service starts, used to compute the fork
+    /// id announced to peers until the node reports a new best block
+    #[serde(skip)]
+    pub local_head: BlockNumber,
+
+    /// Misbehaviour scoring and bans
+    #[serde(default)]
+    pub reputation: ReputationConfig,
+}
+
+fn default_sync_request_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_inbound_peers() -> usize {
+    35
+}
+
+fn default_max_outbound_peers() -> usize {
+    15
+}
+
+fn default_max_peers_per_ip() -> usize {
+    4
+}
+
+fn default_enable_nat_traversal() -> bool {
+    true
+}
+
+fn default_max_relay_reservations() -> usize {
+    DEFAULT_MAX_RELAY_RESERVATIONS
+}
+
+fn default_random_walk_interval() -> Duration {
+    DEFAULT_RANDOM_WALK_INTERVAL
+}
+
+fn default_authority_discovery_interval() -> Duration {
+    DEFAULT_AUTHORITY_DISCOVERY_INTERVAL
 }
 
 impl Default for NetworkConfig {
@@ -46,18 +158,34 @@ impl Default for NetworkConfig {
                 "/ip4/0.0.0.0/tcp/30333".parse().unwrap(),
                 "/ip6/::/tcp/30333".parse().unwrap(),
             ],
+            external_addresses: Vec::new(),
             bootstrap_nodes: Vec::new(),
             keystore_path: PathBuf::from("./keystore"),
             max_peers: 50,
+            max_inbound_peers: default_max_inbound_peers(),
+            max_outbound_peers: default_max_outbound_peers(),
+            max_peers_per_ip: default_max_peers_per_ip(),
+            reserved_peers: Vec::new(),
             connection_timeout: Duration::from_secs(10),
+            transport: TransportConfig::default(),
             gossip_heartbeat: Duration::from_secs(1),
             max_message_size: 128 * 1024, // 128 KB
+            sync_request_timeout: default_sync_request_timeout(),
             mesh_n: 12,
             mesh_n_low: 9,
             mesh_n_high: 15,
             enable_gossip: true,
             enable_sync: true,
             enable_mdns: true,
+            enable_nat_traversal: default_enable_nat_traversal(),
+            enable_relay_server: false,
+            relay_nodes: Vec::new(),
+            max_relay_reservations: default_max_relay_reservations(),
+            random_walk_interval: default_random_walk_interval(),
+            authority_discovery_interval: default_authority_discovery_interval(),
+            fork_schedule: ForkSchedule::default(),
+            local_head: 0,
+            reputation: ReputationConfig::default(),
         }
     }
 }
@@ -80,6 +208,36 @@ impl NetworkConfig {
         self
     }
 
+    /// Use the bootstrap nodes of a named network (`mainnet`, `testnet` or `local`)
+    pub fn with_network(mut self, network: &str) -> Self {
+        self.bootstrap_nodes = BootstrapNodes::for_network(network);
+        self
+    }
+
+    /// Keep connections to the given peers regardless of the limits
+    pub fn with_reserved_peers(mut self, peers: Vec<Multiaddr>) -> Self {
+        self.reserved_peers = peers;
+        self
+    }
+
+    /// Set TCP and QUIC transport options
+    pub fn with_transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Set addresses we are known to be reachable on
+    pub fn with_external_addresses(mut self, addresses: Vec<Multiaddr>) -> Self {
+        self.external_addresses = addresses;
+        self
+    }
+
+    /// Set relays to listen through when behind NAT
+    pub fn with_relay_nodes(mut self, relays: Vec<Multiaddr>) -> Self {
+        self.relay_nodes = relays;
+        self
+    }
+
     /// Set keystore path
     pub fn with_keystore_path(mut self, path: PathBuf) -> Self {
         self.keystore_path = path;
@@ -92,6 +250,33 @@ impl NetworkConfig {
         self
     }
 
+    /// Set the fork schedule
+    pub fn with_fork_schedule(mut self, fork_schedule: ForkSchedule) -> Self {
+        self.fork_schedule = fork_schedule;
+        self
+    }
+
+    /// Set the best block number the node starts from
+    pub fn with_local_head(mut self, head: BlockNumber) -> Self {
+        self.local_head = head;
+        self
+    }
+
+    /// Fork id to announce to peers at the given head
+    pub fn fork_id(&self, head: BlockNumber) -> ForkId {
+        self.fork_schedule.fork_id(head)
+    }
+
+    /// Check whether a peer announcing `remote` follows our fork schedule
+    pub fn is_compatible_peer(&self, head: BlockNumber, remote: &ForkId) -> bool {
+        self.fork_schedule.is_compatible(head, remote)
+    }
+
+    /// Identify agent version announcing our fork id at the local head
+    pub fn agent_version(&self) -> String {
+        fork_agent_version(&self.fork_id(self.local_head))
+    }
+
     /// Validate configuration
     pub fn validate(&self) -> Result<(), String> {
         if self.listen_addresses.is_empty() {
@@ -102,6 +287,44 @@ impl NetworkConfig {
             return Err("Maximum peers must be greater than 0".to_string());
         }
 
+        if self.max_peers_per_ip == 0 {
+            return Err("Maximum peers per IP must be greater than 0".to_string());
+        }
+
+        if let Some(addr) = self
+            .reserved_peers
+            .iter()
+            .find(|addr| peer_id_of(addr).is_none())
+        {
+            return Err(format!("Reserved peer {} has no /p2p peer id", addr));
+        }
+
+        if let Some(addr) = self
+            .relay_nodes
+            .iter()
+            .find(|addr| peer_id_of(addr).is_none())
+        {
+            return Err(format!("Relay node {} has no /p2p peer id", addr));
+        }
+
+        self.transport.validate()?;
+
+        if self.sync_request_timeout.is_zero() {
+            return Err("Sync request timeout must be greater than zero".to_string());
+        }
+
+        if self.random_walk_interval.is_zero() {
+            return Err("Random walk interval must be greater than zero".to_string());
+        }
+
+        if self.authority_discovery_interval.is_zero() {
+            return Err("Authority discovery interval must be greater than zero".to_string());
+        }
+
+        if self.reputation.ban_threshold >= self.reputation.good_threshold {
+            return Err("Ban threshold must be below the good peer threshold".to_string());
+        }
+
         if self.mesh_n_low >= self.mesh_n || self.mesh_n >= self.mesh_n_high {
             return Err("Invalid mesh parameters: mesh_n_low < mesh_n < mesh_n_high".to_string());
         }
@@ -110,6 +333,29 @@ impl NetworkConfig {
     }
 }
 
+/// Encode a fork id as an identify agent version
+pub fn fork_agent_version(fork_id: &ForkId) -> String {
+    match fork_id.next {
+        Some(next) => format!("{}{:08x}/{}", FORK_AGENT_PREFIX, fork_id.hash, next),
+        None => format!("{}{:08x}", FORK_AGENT_PREFIX, fork_id.hash),
+    }
+}
+
+/// Decode the fork id announced in an identify agent version
+///
+/// Returns `None` for agents that announce no fork id.
+pub fn parse_fork_agent_version(agent_version: &str) -> Option<ForkId> {
+    let fork_id = agent_version.strip_prefix(FORK_AGENT_PREFIX)?;
+    let (hash, next) = match fork_id.split_once('/') {
+        Some((hash, next)) => (hash, Some(next.parse().ok()?)),
+        None => (fork_id, None),
+    };
+    Some(ForkId {
+        hash: u32::from_str_radix(hash, 16).ok()?,
+        next,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,6 +378,12 @@ mod tests {
         assert_eq!(config.keystore_path, PathBuf::from("/tmp/test"));
     }
 
+    #[test]
+    fn test_with_network() {
+        let config = NetworkConfig::new().with_network("testnet");
+        assert_eq!(config.bootstrap_nodes, BootstrapNodes::testnet_nodes());
+    }
+
     #[test]
     fn test_config_validation() {
         let mut config = NetworkConfig::default();
@@ -139,7 +391,56 @@ mod tests {
         assert!(config.validate().is_err());
 
         config.max_peers = 10;
+        config.reserved_peers = vec!["/ip4/10.0.0.1/tcp/30333".parse().unwrap()];
+        assert!(config.validate().is_err());
+
+        config.reserved_peers.clear();
+        config.relay_nodes = vec!["/ip4/10.0.0.1/tcp/30333".parse().unwrap()];
+        assert!(config.validate().is_err());
+
+        config.relay_nodes.clear();
+        config.transport.quic_max_streams = 0;
+        assert!(config.validate().is_err());
+
+        config.transport.quic_max_streams = 256;
         config.mesh_n_low = 15;
         assert!(config.validate().is_err());
     }
+
+    #[test]
+    fn test_fork_compatibility() {
+        use chain_core::Fork;
+
+        let forks = ForkSchedule::new(vec![Fork::new("upgrade", 100)]).unwrap();
+        let config = NetworkConfig::new().with_fork_schedule(forks);
+        let legacy = NetworkConfig::new();
+
+        assert_eq!(config.fork_id(50).next, Some(100));
+        assert!(config.is_compatible_peer(50, &config.fork_id(50)));
+        assert!(config.is_compatible_peer(150, &config.fork_id(50)));
+
+        // A peer without the upgrade is rejected once we applied it
+        assert!(!config.is_compatible_peer(150, &legacy.fork_id(150)));
+    }
+
+    #[test]
+    fn test_fork_agent_version() {
+        use chain_core::Fork;
+
+        let forks = ForkSchedule::new(vec![Fork::new("upgrade", 100)]).unwrap();
+        let config = NetworkConfig::new().with_fork_schedule(forks);
+        for head in [50, 150] {
+            let config = config.clone().with_local_head(head);
+            assert_eq!(
+                parse_fork_agent_version(&config.agent_version()),
+                Some(config.fork_id(head))
+            );
+        }
+
+        assert_eq!(parse_fork_agent_version("rust-libp2p/0.53.0"), None);
+        assert_eq!(
+            parse_fork_agent_version(&format!("{}zz", FORK_AGENT_PREFIX)),
+            None
+        );
+    }
 }
@@ -0,0 +1,650 @@
+//! Connection limits and reserved peers
+//!
+//! [`ConnectionManager`] caps the number of connected peers, inbound and
+//! outbound, in total and from a single IP address. These limits count
+//! distinct peers, so a peer connected more than once takes one slot.
+//! Separately, the total number of connections, counting every connection of
+//! a peer, is capped by the transport's
+//! [`max_connections`](crate::transport::TransportConfig::max_connections).
+//! When a slot is needed and none is free, the connections of the
+//! lowest-scored peer are closed, provided that peer scores below the
+//! newcomer. [`ReservedPeers`], such as validators and configured peers, are
+//! exempt from all limits, are never evicted and are redialed while
+//! disconnected.
+
+use crate::reputation::{ip_of, ReputationManager};
+use crate::NetworkConfig;
+use libp2p::core::transport::PortUse;
+use libp2p::core::Endpoint;
+use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
+use libp2p::swarm::{
+    dummy, CloseConnection, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+    THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Time between attempts to reconnect to disconnected reserved peers
+pub const RESERVED_REDIAL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Connection slot limits
+///
+/// All limits but `max_connections` count distinct peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Maximum number of connected peers
+    pub max_peers: usize,
+    /// Maximum number of connections, counting every connection of a peer
+    pub max_connections: usize,
+    /// Maximum number of peers with a connection they opened
+    pub max_inbound: usize,
+    /// Maximum number of peers with a connection we opened
+    pub max_outbound: usize,
+    /// Maximum number of peers connected from a single IP address
+    pub max_per_ip: usize,
+}
+
+impl ConnectionLimits {
+    /// Take the limits from the network configuration
+    pub fn from_config(config: &NetworkConfig) -> Self {
+        Self {
+            max_peers: config.max_peers,
+            max_connections: config.transport.max_connections as usize,
+            max_inbound: config.max_inbound_peers,
+            max_outbound: config.max_outbound_peers,
+            max_per_ip: config.max_peers_per_ip,
+        }
+    }
+}
+
+/// Which side opened a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Opened by the remote peer
+    Inbound,
+    /// Opened by us
+    Outbound,
+}
+
+/// Reason a connection was refused for lack of slots
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LimitExceeded {
+    #[error("{direction:?} peer limit of {limit} reached")]
+    Direction { direction: Direction, limit: usize },
+
+    #[error("Peer limit of {0} reached")]
+    Peers(usize),
+
+    #[error("Connection limit of {0} reached")]
+    Connections(usize),
+
+    #[error("Limit of {limit} peers from {ip} reached")]
+    PerIp { ip: IpAddr, limit: usize },
+}
+
+/// Peers that are always kept connected
+#[derive(Debug, Default)]
+pub struct ReservedPeers {
+    /// Reserved peers and the addresses to dial them at
+    peers: RwLock<HashMap<PeerId, Vec<Multiaddr>>>,
+}
+
+impl ReservedPeers {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve a slot for a peer, dialing it at the given addresses
+    ///
+    /// With no addresses, the peer is dialed at addresses known to the DHT.
+    pub fn add(&self, peer_id: PeerId, addresses: Vec<Multiaddr>) {
+        let mut peers = self.peers.write().unwrap();
+        let known = peers.entry(peer_id).or_default();
+        for addr in addresses {
+            if !known.contains(&addr) {
+                known.push(addr);
+            }
+        }
+    }
+
+    /// Release the slot of a peer
+    pub fn remove(&self, peer_id: &PeerId) -> bool {
+        self.peers.write().unwrap().remove(peer_id).is_some()
+    }
+
+    /// Check whether a peer is reserved
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.read().unwrap().contains_key(peer_id)
+    }
+
+    /// Get the reserved peers
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.peers.read().unwrap().keys().copied().collect()
+    }
+
+    /// Get the number of reserved peers
+    pub fn len(&self) -> usize {
+        self.peers.read().unwrap().len()
+    }
+
+    /// Check whether no peer is reserved
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn entries(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let peers = self.peers.read().unwrap();
+        peers
+            .iter()
+            .map(|(peer_id, addrs)| (*peer_id, addrs.clone()))
+            .collect()
+    }
+}
+
+/// An established connection
+#[derive(Debug, Clone, Copy)]
+struct Connection {
+    peer_id: PeerId,
+    direction: Direction,
+    ip: Option<IpAddr>,
+}
+
+/// Swarm behaviour enforcing connection limits and keeping reserved peers
+pub struct ConnectionManager {
+    /// Slot limits
+    limits: ConnectionLimits,
+    /// Peers exempt from limits
+    reserved: Arc<ReservedPeers>,
+    /// Scores used to pick connections to evict
+    reputation: Arc<ReputationManager>,
+    /// Established connections
+    connections: HashMap<ConnectionId, Connection>,
+    /// Connections evicted to free a slot, waiting to be closed
+    to_close: VecDeque<(PeerId, ConnectionId)>,
+    /// Reserved peers waiting to be dialed
+    to_dial: VecDeque<(PeerId, Vec<Multiaddr>)>,
+    /// Reserved peer redial timer, started on the first poll
+    redial: Option<Interval>,
+}
+
+impl ConnectionManager {
+    /// Create a manager enforcing the given limits
+    pub fn new(
+        limits: ConnectionLimits,
+        reserved: Arc<ReservedPeers>,
+        reputation: Arc<ReputationManager>,
+    ) -> Self {
+        Self {
+            limits,
+            reserved,
+            reputation,
+            connections: HashMap::new(),
+            to_close: VecDeque::new(),
+            to_dial: VecDeque::new(),
+            redial: None,
+        }
+    }
+
+    /// Get the number of connected peers
+    pub fn peer_count(&self) -> usize {
+        self.peers_where(|_| true).len()
+    }
+
+    /// Get the number of established connections
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Get the number of peers with a connection in a direction
+    pub fn count(&self, direction: Direction) -> usize {
+        self.peers_where(|connection| connection.direction == direction)
+            .len()
+    }
+
+    /// Get the number of peers connected from an IP address
+    pub fn count_from(&self, ip: &IpAddr) -> usize {
+        self.peers_where(|connection| connection.ip.as_ref() == Some(ip))
+            .len()
+    }
+
+    /// Get the peers with a connection matching a filter
+    fn peers_where(&self, filter: impl Fn(&Connection) -> bool) -> HashSet<PeerId> {
+        self.connections
+            .values()
+            .filter(|connection| filter(connection))
+            .map(|connection| connection.peer_id)
+            .collect()
+    }
+
+    /// Check whether a peer has a connection matching a filter
+    fn has_connection(&self, peer_id: &PeerId, filter: impl Fn(&Connection) -> bool) -> bool {
+        self.connections
+            .values()
+            .any(|connection| connection.peer_id == *peer_id && filter(connection))
+    }
+
+    /// Check the peer limit of an IP address
+    ///
+    /// Before the handshake the peer is unknown, so it is checked as a new
+    /// peer.
+    fn check_ip(
+        &self,
+        ip: Option<IpAddr>,
+        peer_id: Option<PeerId>,
+    ) -> Result<(), ConnectionDenied> {
+        let known = |ip: &IpAddr| {
+            peer_id.is_some_and(|peer_id| {
+                self.has_connection(&peer_id, |connection| connection.ip.as_ref() == Some(ip))
+            })
+        };
+        match ip {
+            Some(ip) if !known(&ip) && self.count_from(&ip) >= self.limits.max_per_ip => {
+                Err(ConnectionDenied::new(LimitExceeded::PerIp {
+                    ip,
+                    limit: self.limits.max_per_ip,
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decide whether a connection gets a slot, evicting a lower-scored peer
+    /// if that frees one
+    fn admit(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        direction: Direction,
+        ip: Option<IpAddr>,
+    ) -> Result<(), ConnectionDenied> {
+        let reserved = self.reserved.contains(&peer_id);
+        if !reserved {
+            self.check_ip(ip, Some(peer_id))?;
+        }
+
+        if let Some(exceeded) = self.exceeded(Some(peer_id), direction) {
+            // Free a slot in the direction that is full, or anywhere if only
+            // a total is
+            let scope = match exceeded {
+                LimitExceeded::Direction { .. } => Some(direction),
+                _ => None,
+            };
+            let score = self.reputation.score(&peer_id);
+            match self.lowest_scored(scope) {
+                Some((victim, victim_score)) if reserved || victim_score < score => {
+                    self.evict(victim)
+                }
+                // Reserved peers are admitted even when nobody can be evicted
+                _ if reserved => {}
+                _ => return Err(ConnectionDenied::new(exceeded)),
+            }
+        }
+
+        self.connections.insert(
+            connection_id,
+            Connection {
+                peer_id,
+                direction,
+                ip,
+            },
+        );
+        Ok(())
+    }
+
+    /// Get the limit a new connection in a direction would exceed, if any
+    ///
+    /// A peer that is already connected takes no further peer slot.
+    fn exceeded(&self, peer_id: Option<PeerId>, direction: Direction) -> Option<LimitExceeded> {
+        let connected = |filter: &dyn Fn(&Connection) -> bool| {
+            peer_id.is_some_and(|p| self.has_connection(&p, filter))
+        };
+        let limit = match direction {
+            Direction::Inbound => self.limits.max_inbound,
+            Direction::Outbound => self.limits.max_outbound,
+        };
+        if !connected(&|connection| connection.direction == direction)
+            && self.count(direction) >= limit
+        {
+            Some(LimitExceeded::Direction { direction, limit })
+        } else if !connected(&|_| true) && self.peer_count() >= self.limits.max_peers {
+            Some(LimitExceeded::Peers(self.limits.max_peers))
+        } else if self.connections.len() >= self.limits.max_connections {
+            Some(LimitExceeded::Connections(self.limits.max_connections))
+        } else {
+            None
+        }
+    }
+
+    /// Find the lowest-scored peer that is not reserved
+    fn lowest_scored(&self, direction: Option<Direction>) -> Option<(PeerId, f64)> {
+        self.peers_where(|connection| direction.is_none_or(|d| connection.direction == d))
+            .into_iter()
+            .filter(|peer_id| !self.reserved.contains(peer_id))
+            .map(|peer_id| (peer_id, self.reputation.score(&peer_id)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    /// Close every connection of a peer
+    fn evict(&mut self, peer_id: PeerId) {
+        tracing::debug!("Evicting {} to free a slot", peer_id);
+        let evicted: Vec<ConnectionId> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.peer_id == peer_id)
+            .map(|(id, _)| *id)
+            .collect();
+        for connection_id in evicted {
+            self.connections.remove(&connection_id);
+            self.to_close.push_back((peer_id, connection_id));
+        }
+    }
+
+    /// Queue dials to reserved peers without a connection
+    fn redial_reserved(&mut self) {
+        for (peer_id, addresses) in self.reserved.entries() {
+            let connected = self
+                .connections
+                .values()
+                .any(|connection| connection.peer_id == peer_id);
+            if !connected {
+                self.to_dial.push_back((peer_id, addresses));
+            }
+        }
+    }
+}
+
+impl NetworkBehaviour for ConnectionManager {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        // The peer is only known after the handshake, so with reserved peers
+        // around the address limit is checked then
+        if self.reserved.is_empty() {
+            self.check_ip(ip_of(remote_addr), None)?;
+        }
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.admit(connection_id, peer, Direction::Inbound, ip_of(remote_addr))?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        _addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        let reserved = maybe_peer.is_some_and(|peer_id| self.reserved.contains(&peer_id));
+        if !reserved {
+            // Only dial if a slot is free; lower-scored peers are not evicted
+            // for our own dials
+            if let Some(exceeded) = self.exceeded(maybe_peer, Direction::Outbound) {
+                return Err(ConnectionDenied::new(exceeded));
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.admit(connection_id, peer, Direction::Outbound, ip_of(addr))?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            self.connections.remove(&closed.connection_id);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some((peer_id, connection_id)) = self.to_close.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection_id),
+            });
+        }
+
+        let redial = self.redial.get_or_insert_with(|| {
+            let mut redial = tokio::time::interval(RESERVED_REDIAL_INTERVAL);
+            redial.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            redial
+        });
+        if redial.poll_tick(cx).is_ready() {
+            self.redial_reserved();
+        }
+
+        if let Some((peer_id, addresses)) = self.to_dial.pop_front() {
+            let opts = DialOpts::peer_id(peer_id)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .addresses(addresses)
+                .build();
+            return Poll::Ready(ToSwarm::Dial { opts });
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reputation::{MemoryReputationStore, Misbehaviour, ReputationConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    fn manager(limits: ConnectionLimits) -> (ConnectionManager, Arc<ReputationManager>) {
+        let (reputation, _bans) = ReputationManager::new(
+            ReputationConfig::default(),
+            Arc::new(MemoryReputationStore::default()),
+        )
+        .unwrap();
+        let reputation = Arc::new(reputation);
+        let manager = ConnectionManager::new(limits, Arc::default(), reputation.clone());
+        (manager, reputation)
+    }
+
+    fn limits() -> ConnectionLimits {
+        ConnectionLimits {
+            max_peers: 3,
+            max_connections: 4,
+            max_inbound: 2,
+            max_outbound: 2,
+            max_per_ip: 2,
+        }
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([10, 0, 0, last]))
+    }
+
+    fn admit(
+        manager: &mut ConnectionManager,
+        peer_id: PeerId,
+        direction: Direction,
+        ip: Option<IpAddr>,
+    ) -> bool {
+        manager
+            .admit(
+                ConnectionId::new_unchecked(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+                peer_id,
+                direction,
+                ip,
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn test_direction_and_ip_limits() {
+        let (mut manager, _) = manager(limits());
+
+        assert!(admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Inbound,
+            ip(1)
+        ));
+        assert!(admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Inbound,
+            ip(1)
+        ));
+        // Third connection from the same address
+        assert!(!admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Outbound,
+            ip(1)
+        ));
+        // Inbound slots are full, outbound ones are not
+        assert!(!admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Inbound,
+            ip(2)
+        ));
+        assert!(admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Outbound,
+            ip(2)
+        ));
+        // The peer limit applies across directions
+        assert!(!admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Outbound,
+            ip(3)
+        ));
+        assert_eq!(manager.count(Direction::Inbound), 2);
+        assert_eq!(manager.count(Direction::Outbound), 1);
+    }
+
+    #[test]
+    fn test_lowest_scored_peer_evicted() {
+        let (mut manager, reputation) = manager(limits());
+        let (bad, good, newcomer) = (PeerId::random(), PeerId::random(), PeerId::random());
+        reputation.report(&bad, Misbehaviour::Timeout).unwrap();
+
+        assert!(admit(&mut manager, good, Direction::Inbound, ip(1)));
+        assert!(admit(&mut manager, bad, Direction::Inbound, ip(2)));
+        assert!(admit(&mut manager, newcomer, Direction::Inbound, ip(3)));
+
+        assert_eq!(manager.to_close.len(), 1);
+        assert_eq!(manager.to_close[0].0, bad);
+        assert_eq!(manager.count(Direction::Inbound), 2);
+
+        // Nobody left scores below an unknown peer
+        assert!(!admit(
+            &mut manager,
+            PeerId::random(),
+            Direction::Inbound,
+            ip(4)
+        ));
+    }
+
+    #[test]
+    fn test_reserved_peers_bypass_limits() {
+        let (mut manager, reputation) = manager(limits());
+        let validator = PeerId::random();
+        manager.reserved.add(validator, vec![]);
+        reputation
+            .report(&validator, Misbehaviour::Timeout)
+            .unwrap();
+
+        let others: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+        for peer_id in &others {
+            assert!(admit(&mut manager, *peer_id, Direction::Inbound, ip(1)));
+        }
+        assert!(admit(&mut manager, validator, Direction::Inbound, ip(1)));
+        assert_eq!(manager.to_close.len(), 1);
+        assert!(others.contains(&manager.to_close[0].0));
+
+        // The reserved peer is never picked for eviction
+        reputation
+            .report(&validator, Misbehaviour::InvalidBlock)
+            .unwrap();
+        let (victim, _) = manager.lowest_scored(Some(Direction::Inbound)).unwrap();
+        assert_ne!(victim, validator);
+    }
+
+    #[test]
+    fn test_peers_and_connections_counted_apart() {
+        let (mut manager, reputation) = manager(limits());
+        let (first, second, third) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        // A second connection of a peer takes no further peer slot
+        assert!(admit(&mut manager, first, Direction::Inbound, ip(1)));
+        assert!(admit(&mut manager, first, Direction::Inbound, ip(1)));
+        assert!(admit(&mut manager, second, Direction::Inbound, ip(1)));
+        assert!(admit(&mut manager, third, Direction::Outbound, ip(2)));
+        assert_eq!(manager.peer_count(), 3);
+        assert_eq!(manager.connection_count(), 4);
+        assert_eq!(manager.count(Direction::Inbound), 2);
+        assert_eq!(manager.count_from(&ip(1).unwrap()), 2);
+
+        // The connection limit holds even for connected peers
+        assert!(!admit(&mut manager, second, Direction::Inbound, ip(1)));
+
+        // Eviction closes every connection of the lowest-scored peer
+        reputation.report(&first, Misbehaviour::Timeout).unwrap();
+        assert!(admit(&mut manager, second, Direction::Inbound, ip(1)));
+        assert_eq!(manager.to_close.len(), 2);
+        assert!(manager
+            .to_close
+            .iter()
+            .all(|(peer_id, _)| *peer_id == first));
+        assert_eq!(manager.peer_count(), 2);
+        assert_eq!(manager.connection_count(), 3);
+    }
+
+    #[test]
+    fn test_limits_from_config() {
+        let mut config = NetworkConfig::default();
+        config.transport.max_connections = 7;
+        let limits = ConnectionLimits::from_config(&config);
+        assert_eq!(limits.max_peers, config.max_peers);
+        assert_eq!(limits.max_connections, 7);
+    }
+}
@@ -0,0 +1,198 @@
+//! Peer discovery through the Kademlia DHT and mDNS
+//!
+//! Bootstrap nodes that carry a `/p2p/` peer id seed the Kademlia routing
+//! table. The service then runs a random walk every
+//! [`random_walk_interval`](crate::NetworkConfig::random_walk_interval),
+//! looking up the peers closest to a random id, which fills the table with
+//! peers across the key space. With mDNS enabled, nodes on the same LAN find
+//! each other without any bootstrap node. Every discovered peer is recorded in
+//! the [`PeerManager`].
+
+use crate::peer::PeerManager;
+use libp2p::multiaddr::Protocol;
+use libp2p::{kad, mdns, Multiaddr, PeerId};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default time between Kademlia random walks
+pub const DEFAULT_RANDOM_WALK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Discovery statistics
+#[derive(Debug, Default, Clone)]
+pub struct DiscoveryStats {
+    /// Random walks started
+    pub random_walks: u64,
+    /// Peers added to the routing table by Kademlia
+    pub kad_discovered: u64,
+    /// Peers found on the local network by mDNS
+    pub mdns_discovered: u64,
+}
+
+/// Feeds peers found by Kademlia and mDNS into the peer manager
+pub struct Discovery {
+    /// Known peers
+    peers: Arc<PeerManager>,
+    /// Statistics
+    stats: DiscoveryStats,
+}
+
+impl Discovery {
+    /// Create discovery feeding a peer manager
+    pub fn new(peers: Arc<PeerManager>) -> Self {
+        Self {
+            peers,
+            stats: DiscoveryStats::default(),
+        }
+    }
+
+    /// Get discovery statistics
+    pub fn stats(&self) -> &DiscoveryStats {
+        &self.stats
+    }
+
+    /// Seed the routing table with bootstrap nodes and start a bootstrap query
+    ///
+    /// Nodes without a peer id are left to the dialer; identify adds them to
+    /// the routing table once connected.
+    pub fn bootstrap(
+        &mut self,
+        kad: &mut kad::Behaviour<kad::store::MemoryStore>,
+        nodes: &[Multiaddr],
+    ) {
+        for addr in nodes {
+            if let Some(peer_id) = peer_id_of(addr) {
+                kad.add_address(&peer_id, addr.clone());
+            }
+        }
+        if let Err(e) = kad.bootstrap() {
+            tracing::debug!("Kademlia bootstrap deferred: {}", e);
+        }
+    }
+
+    /// Look up the peers closest to a random id
+    pub fn random_walk(&mut self, kad: &mut kad::Behaviour<kad::store::MemoryStore>) {
+        self.stats.random_walks += 1;
+        kad.get_closest_peers(PeerId::random());
+    }
+
+    /// Record peers learned from a Kademlia event
+    ///
+    /// Returns the peers that were new to the routing table.
+    pub async fn handle_kad_event(&mut self, event: kad::Event) -> Vec<PeerId> {
+        match event {
+            kad::Event::RoutingUpdated {
+                peer,
+                is_new_peer,
+                addresses,
+                ..
+            } => {
+                self.add_peer(peer, addresses.into_vec()).await;
+                if is_new_peer {
+                    self.stats.kad_discovered += 1;
+                    return vec![peer];
+                }
+            }
+            kad::Event::OutboundQueryProgressed {
+                result: kad::QueryResult::GetClosestPeers(Ok(ok)),
+                ..
+            } => {
+                tracing::debug!("Random walk found {} peers", ok.peers.len());
+                for peer in ok.peers {
+                    self.add_peer(peer.peer_id, peer.addrs).await;
+                }
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    /// Record peers found on the local network
+    ///
+    /// Discovered addresses are added to the routing table. Returns the
+    /// peers and addresses to dial.
+    pub async fn handle_mdns_event(
+        &mut self,
+        event: mdns::Event,
+        kad: &mut kad::Behaviour<kad::store::MemoryStore>,
+    ) -> Vec<(PeerId, Multiaddr)> {
+        match event {
+            mdns::Event::Discovered(found) => {
+                for (peer_id, addr) in &found {
+                    tracing::debug!("mDNS discovered {} at {}", peer_id, addr);
+                    kad.add_address(peer_id, addr.clone());
+                    self.add_peer(*peer_id, vec![addr.clone()]).await;
+                }
+                self.stats.mdns_discovered += found.len() as u64;
+                found
+            }
+            mdns::Event::Expired(expired) => {
+                for (peer_id, addr) in expired {
+                    tracing::debug!("mDNS record of {} at {} expired", peer_id, addr);
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    async fn add_peer(&self, peer_id: PeerId, addresses: Vec<Multiaddr>) {
+        if let Err(e) = self.peers.add_peer(peer_id, addresses).await {
+            tracing::warn!("Failed to record discovered peer {}: {}", peer_id, e);
+        }
+    }
+}
+
+/// Get the peer id an address ends with, if any
+pub fn peer_id_of(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last()? {
+        Protocol::P2p(peer_id) => Some(peer_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kad_behaviour() -> kad::Behaviour<kad::store::MemoryStore> {
+        let peer_id = PeerId::random();
+        kad::Behaviour::new(peer_id, kad::store::MemoryStore::new(peer_id))
+    }
+
+    #[test]
+    fn test_peer_id_of() {
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/30333".parse().unwrap();
+        assert_eq!(peer_id_of(&addr), None);
+        assert_eq!(
+            peer_id_of(&addr.with(Protocol::P2p(peer_id))),
+            Some(peer_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mdns_peers_recorded() {
+        let peers = Arc::new(PeerManager::new(10, Duration::from_secs(10)));
+        let mut discovery = Discovery::new(peers.clone());
+        let mut kad = kad_behaviour();
+
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/192.168.1.20/tcp/30333".parse().unwrap();
+        let found = mdns::Event::Discovered(vec![(peer_id, addr.clone())]);
+        let to_dial = discovery.handle_mdns_event(found, &mut kad).await;
+
+        assert_eq!(to_dial, vec![(peer_id, addr.clone())]);
+        assert_eq!(discovery.stats().mdns_discovered, 1);
+        assert!(peers
+            .get_peer(&peer_id)
+            .await
+            .unwrap()
+            .addresses
+            .contains(&addr));
+        assert_eq!(
+            kad.kbuckets()
+                .map(|bucket| bucket.num_entries())
+                .sum::<usize>(),
+            1
+        );
+    }
+}
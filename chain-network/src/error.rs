@@ -41,6 +41,15 @@ pub enum NetworkError {
     #[error("Gossip error: {0}")]
     Gossip(String),
 
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(String),
+
+    #[error("Invalid block: {0}")]
+    InvalidBlock(String),
+
+    #[error("Invalid authority record: {0}")]
+    InvalidAuthorityRecord(String),
+
     #[error("Configuration error: {0}")]
     Config(String),
 }
@@ -1,13 +1,34 @@
 //! Gossip-based message propagation
-
-use crate::message::{BlockAnnounce, GossipMessage, TransactionPropagate};
+//!
+//! [`GossipManager`] publishes messages through the network service.
+//! [`GossipHandler`] checks inbound messages and returns a
+//! [`GossipValidation`]. The service uses that verdict to decide whether
+//! gossipsub forwards the message, so invalid messages stop at the first
+//! honest node.
+
+use crate::block_announce::{AnnounceOutcome, BlockAnnounceHandler};
+use crate::compact::CompactBlock;
+use crate::message::{
+    BlockAnnounce, ConsensusMessage, GossipMessage, TransactionAnnounce, TransactionPropagate,
+};
+use crate::seen_cache::{SeenCache, SeenCacheStats};
+use crate::transactions::{FetchedTransactions, TransactionFetch, TransactionGossip};
 use crate::{NetworkError, NetworkResult};
 use libp2p::PeerId;
-use std::collections::HashSet;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+/// Topic carrying block announcements
+pub const BLOCKS_TOPIC: &str = "blocks";
+
+/// Topic carrying transaction propagation
+pub const TRANSACTIONS_TOPIC: &str = "transactions";
+
+/// Topic carrying consensus messages, joined only by validators
+pub const VALIDATORS_TOPIC: &str = "validators";
+
 /// Gossip manager handles message propagation via GossipSub
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GossipManager {
     /// Channel for sending gossip messages
     tx: mpsc::UnboundedSender<GossipCommand>,
@@ -41,15 +62,34 @@ impl GossipManager {
     /// Publish a block announcement
     pub async fn announce_block(&self, announce: BlockAnnounce) -> NetworkResult<()> {
         let message = GossipMessage::BlockAnnounce(Box::new(announce));
-        self.publish("blocks".to_string(), message).await
+        self.publish(BLOCKS_TOPIC.to_string(), message).await
     }
+
+    /// Publish a compact block announcement
+    pub async fn announce_compact_block(&self, compact: CompactBlock) -> NetworkResult<()> {
+        let message = GossipMessage::CompactBlock(Box::new(compact));
+        self.publish(BLOCKS_TOPIC.to_string(), message).await
+    }
+
     /// Propagate transactions
     pub async fn propagate_transactions(
         &self,
         propagate: TransactionPropagate,
     ) -> NetworkResult<()> {
         let message = GossipMessage::TransactionPropagate(propagate);
-        self.publish("transactions".to_string(), message).await
+        self.publish(TRANSACTIONS_TOPIC.to_string(), message).await
+    }
+
+    /// Announce transaction hashes
+    pub async fn announce_transactions(&self, announce: TransactionAnnounce) -> NetworkResult<()> {
+        let message = GossipMessage::TransactionAnnounce(announce);
+        self.publish(TRANSACTIONS_TOPIC.to_string(), message).await
+    }
+
+    /// Publish a consensus message to the other validators
+    pub async fn publish_consensus(&self, message: ConsensusMessage) -> NetworkResult<()> {
+        let message = GossipMessage::Consensus(message);
+        self.publish(VALIDATORS_TOPIC.to_string(), message).await
     }
 
     /// Publish a message to a topic
@@ -102,101 +142,252 @@ impl Default for GossipManager {
     }
 }
 
+/// Verdict on an inbound gossip message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GossipValidation {
+    /// Valid, so forward it to other peers
+    Accept,
+    /// Invalid, so drop it and hold it against the sender
+    Reject,
+    /// Drop it without forwarding, e.g. a duplicate or a message whose valid
+    /// part is passed on in another form
+    Ignore,
+}
+
+/// Verdict on an inbound gossip message, or what it waits on
+pub enum GossipStep {
+    /// The message was handled
+    Done(GossipValidation),
+    /// Announced transactions to fetch before the verdict
+    Fetch(TransactionFetch),
+}
+
 /// Gossip message handler
 pub struct GossipHandler {
-    /// Set of seen message hashes to prevent loops
-    seen_messages: HashSet<Vec<u8>>,
-    /// Maximum number of seen messages to track
-    max_seen_messages: usize,
+    /// Ids of seen messages to prevent loops
+    seen_messages: SeenCache<Vec<u8>>,
+    /// Validation and import hand-off of announced blocks
+    blocks: Option<BlockAnnounceHandler>,
+    /// Validation and re-propagation of transactions
+    transactions: Option<TransactionGossip>,
 }
 
 impl GossipHandler {
     /// Create a new gossip handler
     pub fn new() -> Self {
         Self {
-            seen_messages: HashSet::new(),
-            max_seen_messages: 10_000,
+            seen_messages: SeenCache::default(),
+            blocks: None,
+            transactions: None,
         }
     }
 
+    /// Validate block announcements and compact blocks, and queue their
+    /// blocks for import
+    pub fn with_block_announces(mut self, blocks: BlockAnnounceHandler) -> Self {
+        self.blocks = Some(blocks);
+        self
+    }
+
+    /// Validate transactions and feed them to a pool
+    pub fn with_transaction_gossip(mut self, transactions: TransactionGossip) -> Self {
+        self.transactions = Some(transactions);
+        self
+    }
+
+    /// Remember up to `capacity` message ids for `ttl` each
+    pub fn with_seen_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.seen_messages = SeenCache::new(capacity, ttl);
+        self
+    }
+
+    /// Get hit and eviction counts of the seen-message cache
+    pub fn seen_stats(&self) -> &SeenCacheStats {
+        self.seen_messages.stats()
+    }
+
     /// Check if a message has been seen before
-    pub fn is_seen(&self, message_hash: &[u8]) -> bool {
-        self.seen_messages.contains(message_hash)
+    pub fn is_seen(&self, message_id: &[u8]) -> bool {
+        self.seen_messages.contains(message_id)
     }
 
     /// Mark a message as seen
-    pub fn mark_seen(&mut self, message_hash: Vec<u8>) {
-        if self.seen_messages.len() >= self.max_seen_messages {
-            // Remove oldest entries (simplified - in practice would use LRU)
-            self.seen_messages.clear();
-        }
-        self.seen_messages.insert(message_hash);
+    ///
+    /// Returns `true` if the message was not seen before.
+    pub fn mark_seen(&mut self, message_id: Vec<u8>) -> bool {
+        self.seen_messages.insert(message_id)
     }
 
     /// Process an incoming gossip message
+    ///
+    /// Messages of a kind without a configured handler are accepted unchecked.
     pub async fn handle_message(
         &mut self,
         message: GossipMessage,
         peer_id: PeerId,
-    ) -> NetworkResult<()> {
-        // Calculate message hash for deduplication
-        let message_bytes = serde_json::to_vec(&message).map_err(NetworkError::Json)?;
-        let message_hash = blake3::hash(&message_bytes).as_bytes().to_vec();
+    ) -> NetworkResult<GossipValidation> {
+        match self.start_message(message, peer_id).await? {
+            GossipStep::Done(validation) => Ok(validation),
+            GossipStep::Fetch(fetch) => self.finish_transaction_fetch(fetch.run().await).await,
+        }
+    }
 
-        // Skip if already seen
-        if self.is_seen(&message_hash) {
-            return Ok(());
+    /// Process an incoming gossip message, leaving transaction fetches to the
+    /// caller
+    ///
+    /// Announced transactions come back as a [`TransactionFetch`] to run
+    /// concurrently with other messages; pass its result to
+    /// [`finish_transaction_fetch`](Self::finish_transaction_fetch) for the
+    /// verdict.
+    pub async fn start_message(
+        &mut self,
+        message: GossipMessage,
+        peer_id: PeerId,
+    ) -> NetworkResult<GossipStep> {
+        // Transactions are deduplicated by transaction hash instead
+        let message = match message {
+            GossipMessage::TransactionPropagate(propagate) => {
+                let validation = self
+                    .handle_transaction_propagate(propagate, peer_id)
+                    .await?;
+                return Ok(GossipStep::Done(validation));
+            }
+            GossipMessage::TransactionAnnounce(announce) => {
+                return Ok(self.handle_transaction_announce(announce, peer_id));
+            }
+            message => message,
+        };
+
+        // Skip if already seen, or if only the header is announced of a block
+        // seen in full
+        let message_id = message.message_id()?;
+        let block_seen = match &message {
+            GossipMessage::BlockAnnounce(announce) if announce.block.is_none() => {
+                let hash = announce
+                    .header
+                    .hash()
+                    .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+                self.is_seen(hash.as_bytes())
+            }
+            _ => false,
+        };
+        if block_seen || !self.mark_seen(message_id.as_bytes().to_vec()) {
+            return Ok(GossipStep::Done(GossipValidation::Ignore));
         }
 
-        // Mark as seen
-        self.mark_seen(message_hash);
         // Process message based on type
-        match message {
+        let validation = match message {
             GossipMessage::BlockAnnounce(announce) => {
                 self.handle_block_announce(*announce, peer_id).await
             }
-            GossipMessage::TransactionPropagate(propagate) => {
-                self.handle_transaction_propagate(propagate, peer_id).await
+            GossipMessage::CompactBlock(compact) => {
+                self.handle_compact_block(*compact, peer_id).await
             }
+            // Consensus messages are checked against the authority set by
+            // the service
+            GossipMessage::TransactionPropagate(_)
+            | GossipMessage::TransactionAnnounce(_)
+            | GossipMessage::Consensus(_) => Ok(GossipValidation::Accept),
+        }?;
+        Ok(GossipStep::Done(validation))
+    }
+
+    /// Give the verdict on a transaction announcement once its transactions
+    /// are fetched
+    pub async fn finish_transaction_fetch(
+        &mut self,
+        fetched: FetchedTransactions,
+    ) -> NetworkResult<GossipValidation> {
+        match &mut self.transactions {
+            Some(transactions) => transactions.finish_announcement(fetched).await,
+            None => Ok(GossipValidation::Ignore),
         }
     }
 
     /// Handle block announcement
     async fn handle_block_announce(
-        &self,
+        &mut self,
         announce: BlockAnnounce,
         peer_id: PeerId,
-    ) -> NetworkResult<()> {
+    ) -> NetworkResult<GossipValidation> {
         tracing::info!(
             "Received block announcement: block #{} from peer {}",
             announce.block_number(),
             peer_id
         );
 
-        // TODO: Validate block header
-        // TODO: Forward to block processing
-        // TODO: Request full block if needed
+        let Some(blocks) = &mut self.blocks else {
+            return Ok(GossipValidation::Accept);
+        };
+        let outcome = blocks.handle_announce(announce, peer_id).await?;
+        tracing::debug!("Block announcement from {}: {:?}", peer_id, outcome);
+        Ok(block_validation(outcome))
+    }
 
-        Ok(())
+    /// Handle compact block announcement
+    async fn handle_compact_block(
+        &mut self,
+        compact: CompactBlock,
+        peer_id: PeerId,
+    ) -> NetworkResult<GossipValidation> {
+        tracing::info!(
+            "Received compact block #{} with {} transactions from peer {}",
+            compact.header.number,
+            compact.transaction_count(),
+            peer_id
+        );
+
+        let Some(blocks) = &mut self.blocks else {
+            return Ok(GossipValidation::Accept);
+        };
+        let outcome = blocks.handle_compact_block(compact, peer_id).await?;
+        tracing::debug!("Compact block from {}: {:?}", peer_id, outcome);
+        Ok(block_validation(outcome))
     }
 
     /// Handle transaction propagation
     async fn handle_transaction_propagate(
-        &self,
+        &mut self,
         propagate: TransactionPropagate,
         peer_id: PeerId,
-    ) -> NetworkResult<()> {
-        tracing::info!(
+    ) -> NetworkResult<GossipValidation> {
+        tracing::debug!(
             "Received {} transactions from peer {}",
             propagate.len(),
             peer_id
         );
 
-        // TODO: Validate transactions
-        // TODO: Add to mempool
-        // TODO: Re-propagate if valid
+        let Some(transactions) = &mut self.transactions else {
+            return Ok(GossipValidation::Accept);
+        };
+        transactions.validate_transactions(propagate, peer_id).await
+    }
 
-        Ok(())
+    /// Handle transaction hash announcement
+    fn handle_transaction_announce(
+        &mut self,
+        announce: TransactionAnnounce,
+        peer_id: PeerId,
+    ) -> GossipStep {
+        tracing::debug!(
+            "Received {} transaction hashes from peer {}",
+            announce.len(),
+            peer_id
+        );
+
+        let Some(transactions) = &mut self.transactions else {
+            return GossipStep::Done(GossipValidation::Accept);
+        };
+        transactions.start_announcement(announce, peer_id)
+    }
+}
+
+/// Forward block messages whose block was queued for import
+fn block_validation(outcome: AnnounceOutcome) -> GossipValidation {
+    match outcome {
+        AnnounceOutcome::Queued => GossipValidation::Accept,
+        AnnounceOutcome::Invalid => GossipValidation::Reject,
+        AnnounceOutcome::Known | AnnounceOutcome::Dropped => GossipValidation::Ignore,
     }
 }
 
@@ -269,14 +460,81 @@ mod tests {
         let announce = BlockAnnounce::new(header);
         let message = GossipMessage::BlockAnnounce(Box::new(announce));
 
-        // Handle message first time - should succeed
-        handler
+        // Handle message first time - accepted unchecked without a block handler
+        let validation = handler
             .handle_message(message.clone(), peer_id)
             .await
             .unwrap();
+        assert_eq!(validation, GossipValidation::Accept);
+
+        // Handle same message again - deduplicated, so not forwarded again
+        let validation = handler.handle_message(message, peer_id).await.unwrap();
+        assert_eq!(validation, GossipValidation::Ignore);
+    }
 
-        // Handle same message again - should still succeed but be deduplicated
-        handler.handle_message(message, peer_id).await.unwrap();
+    #[tokio::test]
+    async fn test_block_messages_deduplicated_by_hash() {
+        let mut handler = GossipHandler::new().with_seen_cache(3, Duration::from_secs(60));
+        let peer_id = PeerId::random();
+
+        let headers: Vec<BlockHeader> = (0..3)
+            .map(|number| {
+                let mut header = BlockHeader::genesis();
+                header.number = number;
+                header
+            })
+            .collect();
+        let block = chain_core::Block::new(headers[0].clone(), vec![]);
+        let announce = BlockAnnounce::new(headers[0].clone());
+        let message =
+            |announce: &BlockAnnounce| GossipMessage::BlockAnnounce(Box::new(announce.clone()));
+
+        // A header-only announcement does not stand in for the block
+        let validation = handler
+            .handle_message(message(&announce), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Accept);
+        let with_block = announce.clone().with_block(block.clone());
+        let validation = handler
+            .handle_message(message(&with_block), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Accept);
+        assert_eq!(handler.seen_stats().hits, 0);
+
+        // Once the block was seen, neither a compact nor a header-only
+        // announcement of it is
+        let compact =
+            GossipMessage::CompactBlock(Box::new(CompactBlock::from_block(&block).unwrap()));
+        let validation = handler.handle_message(compact, peer_id).await.unwrap();
+        assert_eq!(validation, GossipValidation::Ignore);
+        assert_eq!(handler.seen_stats().hits, 1);
+        let mut fresh = GossipHandler::new();
+        fresh
+            .handle_message(message(&with_block), peer_id)
+            .await
+            .unwrap();
+        let validation = fresh
+            .handle_message(message(&announce), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Ignore);
+
+        // Filling the cache evicts only the oldest id, the block id having
+        // been seen again through the compact block
+        for header in &headers[1..] {
+            let announce = BlockAnnounce::new(header.clone());
+            handler
+                .handle_message(message(&announce), peer_id)
+                .await
+                .unwrap();
+        }
+        assert_eq!(handler.seen_stats().evictions, 1);
+        assert!(!handler.is_seen(message(&announce).message_id().unwrap().as_bytes()));
+        assert!(handler.is_seen(headers[0].hash().unwrap().as_bytes()));
+        let last = message(&BlockAnnounce::new(headers[2].clone()));
+        assert!(handler.is_seen(last.message_id().unwrap().as_bytes()));
     }
 
     #[test]
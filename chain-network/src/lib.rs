@@ -2,24 +2,50 @@
 //!
 //! This crate provides P2P networking functionality for the blockchain,
 //! including peer discovery, message propagation, and synchronization protocols.
+//! [`NetworkService`] drives the libp2p swarm behind these components.
 
+pub mod authority;
+pub mod block_announce;
 pub mod bootstrap;
+pub mod chain;
+pub mod chain_sync;
+pub mod codec;
+pub mod compact;
 pub mod config;
+pub mod connections;
+pub mod discovery;
 pub mod error;
 pub mod gossip;
 pub mod identity;
 pub mod message;
+pub mod nat;
 pub mod peer;
+pub mod reputation;
+pub mod seen_cache;
+pub mod service;
 pub mod sync;
+pub mod transactions;
 pub mod transport;
 
+pub use authority::{AuthorityDiscovery, AuthorityRecord};
+pub use block_announce::{BlockAnnounceHandler, HeaderVerifier, QueuedBlock};
+pub use chain::{ChainReader, MemoryChain};
+pub use chain_sync::{BlockImport, ChainSync, SyncState};
+pub use compact::{CompactBlock, PartialBlock};
 pub use config::NetworkConfig;
+pub use connections::{ConnectionLimits, ConnectionManager, ReservedPeers};
+pub use discovery::{Discovery, DiscoveryStats};
 pub use error::{NetworkError, NetworkResult};
-pub use gossip::GossipManager;
+pub use gossip::{GossipHandler, GossipManager, GossipStep, GossipValidation};
 pub use identity::{NodeId, PeerIdentity};
-pub use message::{SyncRequest, SyncResponse};
+pub use message::{ConsensusMessage, ConsensusMessageKind, SyncRequest, SyncResponse};
+pub use nat::{NatStats, NatTraversal};
 pub use peer::{Peer, PeerManager};
-pub use sync::SyncManager;
+pub use reputation::{Misbehaviour, ReputationManager, ReputationStore};
+pub use seen_cache::{SeenCache, SeenCacheStats};
+pub use service::{NetworkEvent, NetworkHandle, NetworkService};
+pub use sync::{SyncHandler, SyncManager};
+pub use transactions::{FetchedTransactions, GossipPool, TransactionFetch, TransactionGossip};
 
 /// Re-export commonly used types
 pub use libp2p::{Multiaddr, PeerId};
@@ -1,6 +1,8 @@
 //! Network message types and protocols
 
-use chain_core::{Block, BlockHeader, Hash, Transaction};
+use crate::compact::CompactBlock;
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Block, BlockHeader, Hash, RangeProof, StateEntry, Transaction};
 use serde::{Deserialize, Serialize};
 
 /// Protocol identifiers
@@ -9,8 +11,8 @@ pub mod protocols {
     pub const BLOCK_ANNOUNCE: &str = "/chain/block/announce/1.0.0";
     /// Transaction propagation gossip
     pub const TX_PROPAGATE: &str = "/chain/tx/propagate/1.0.0";
-    /// Sync request-response
-    pub const SYNC_REQUEST: &str = "/chain/sync/request/1.0.0";
+    /// Sync request-response, framed by [`SyncCodec`](crate::codec::SyncCodec)
+    pub const SYNC_REQUEST: &str = "/chain/sync/1";
     /// State sync
     pub const STATE_SYNC: &str = "/chain/state/sync/1.0.0";
 }
@@ -20,8 +22,65 @@ pub mod protocols {
 pub enum GossipMessage {
     /// Block announcement
     BlockAnnounce(Box<BlockAnnounce>),
+    /// Block announcement with short transaction ids
+    CompactBlock(Box<CompactBlock>),
     /// Transaction propagation
     TransactionPropagate(TransactionPropagate),
+    /// Transaction hash announcement
+    TransactionAnnounce(TransactionAnnounce),
+    /// Consensus message exchanged between validators
+    Consensus(ConsensusMessage),
+}
+
+impl GossipMessage {
+    /// Get a cheap id for deduplication
+    ///
+    /// Block messages carrying the block are identified by the block hash, so
+    /// a full and a compact announcement of the same block share an id.
+    /// Header-only announcements get an id of their own, as they do not stand
+    /// in for the block. Transaction messages hash the transaction hashes they
+    /// carry, and consensus messages their payload.
+    pub fn message_id(&self) -> NetworkResult<Hash> {
+        match self {
+            Self::BlockAnnounce(announce) if announce.block.is_none() => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(b"header");
+                hasher.update(header_hash(&announce.header)?.as_bytes());
+                Ok(Hash::from_slice(hasher.finalize().as_bytes()))
+            }
+            Self::BlockAnnounce(announce) => header_hash(&announce.header),
+            Self::CompactBlock(compact) => compact.block_hash(),
+            Self::TransactionPropagate(propagate) => {
+                let mut hasher = blake3::Hasher::new();
+                for tx in &propagate.transactions {
+                    let hash = tx
+                        .hash()
+                        .map_err(|e| NetworkError::Encoding(e.to_string()))?;
+                    hasher.update(hash.as_bytes());
+                }
+                Ok(Hash::from_slice(hasher.finalize().as_bytes()))
+            }
+            Self::TransactionAnnounce(announce) => {
+                let mut hasher = blake3::Hasher::new();
+                for hash in &announce.hashes {
+                    hasher.update(hash.as_bytes());
+                }
+                Ok(Hash::from_slice(hasher.finalize().as_bytes()))
+            }
+            Self::Consensus(message) => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[message.kind as u8]);
+                hasher.update(&message.payload);
+                Ok(Hash::from_slice(hasher.finalize().as_bytes()))
+            }
+        }
+    }
+}
+
+fn header_hash(header: &BlockHeader) -> NetworkResult<Hash> {
+    header
+        .hash()
+        .map_err(|e| NetworkError::Encoding(e.to_string()))
 }
 
 /// Block announcement message
@@ -81,6 +140,57 @@ impl TransactionPropagate {
     }
 }
 
+/// Transaction hash announcement
+///
+/// Receivers fetch the transactions they lack with
+/// [`SyncRequest::GetTransactions`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionAnnounce {
+    /// Hashes of the announced transactions
+    pub hashes: Vec<Hash>,
+}
+
+impl TransactionAnnounce {
+    pub fn new(hashes: Vec<Hash>) -> Self {
+        Self { hashes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+}
+
+/// Kind of a consensus message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsensusMessageKind {
+    /// Block proposal
+    Proposal,
+    /// Vote on a proposal
+    Vote,
+}
+
+/// Consensus message gossiped between validators
+///
+/// The payload is encoded by the consensus engine; the network only checks
+/// that the message comes from an authority.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusMessage {
+    /// Kind of message
+    pub kind: ConsensusMessageKind,
+    /// Encoded proposal or vote
+    pub payload: Vec<u8>,
+}
+
+impl ConsensusMessage {
+    pub fn new(kind: ConsensusMessageKind, payload: Vec<u8>) -> Self {
+        Self { kind, payload }
+    }
+}
+
 /// Sync request-response messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SyncMessage {
@@ -118,9 +228,33 @@ pub enum SyncRequest {
         root: Hash,
         /// Key prefix
         prefix: Vec<u8>,
+        /// Serve entries from the first key not below this one, to continue
+        /// an earlier page
+        start: Vec<u8>,
         /// Maximum number of entries
         limit: u32,
     },
+    /// Get consecutive state entries with a Merkle range proof
+    GetStateRange {
+        /// State root the proof is made against
+        root: Hash,
+        /// Serve entries from the first key not below this one
+        start: Vec<u8>,
+        /// Maximum number of entries
+        limit: u32,
+    },
+    /// Get transactions of a block by position, to complete a compact block
+    GetBlockTransactions {
+        /// Block hash
+        block_hash: Hash,
+        /// Positions of the transactions in the block
+        indices: Vec<u32>,
+    },
+    /// Get pending transactions by hash
+    GetTransactions {
+        /// Transaction hashes
+        hashes: Vec<Hash>,
+    },
 }
 
 /// Sync response types  
@@ -145,8 +279,25 @@ pub enum SyncResponse {
     StateSnapshot {
         /// State entries
         entries: Vec<(Vec<u8>, Vec<u8>)>,
-        /// Whether this is the last chunk
-        complete: bool,
+        /// Key to request the next page from, if entries remain
+        next: Option<Vec<u8>>,
+    },
+    /// State range response
+    StateRange {
+        /// Consecutive state entries in key order
+        entries: Vec<StateEntry>,
+        /// Proof of the entries' position under the requested root
+        proof: RangeProof,
+    },
+    /// Block transactions response
+    BlockTransactions {
+        /// Transactions in the requested order
+        transactions: Vec<Transaction>,
+    },
+    /// Pending transactions response
+    Transactions {
+        /// The requested transactions that are known, in request order
+        transactions: Vec<Transaction>,
     },
     /// Error response
     Error {
@@ -176,14 +327,33 @@ impl SyncRequest {
         Self::GetReceipts { hashes }
     }
 
-    /// Create a state snapshot request
-    pub fn state_snapshot(root: Hash, prefix: Vec<u8>, limit: u32) -> Self {
+    /// Create a state snapshot request for entries under `prefix` from `start`
+    pub fn state_snapshot(root: Hash, prefix: Vec<u8>, start: Vec<u8>, limit: u32) -> Self {
         Self::GetStateSnapshot {
             root,
             prefix,
+            start,
             limit,
         }
     }
+
+    /// Create a state range request
+    pub fn state_range(root: Hash, start: Vec<u8>, limit: u32) -> Self {
+        Self::GetStateRange { root, start, limit }
+    }
+
+    /// Create a block transactions request
+    pub fn block_transactions(block_hash: Hash, indices: Vec<u32>) -> Self {
+        Self::GetBlockTransactions {
+            block_hash,
+            indices,
+        }
+    }
+
+    /// Create a pending transactions request
+    pub fn transactions(hashes: Vec<Hash>) -> Self {
+        Self::GetTransactions { hashes }
+    }
 }
 
 impl SyncResponse {
@@ -197,6 +367,21 @@ impl SyncResponse {
         Self::Bodies { bodies }
     }
 
+    /// Create a state range response
+    pub fn state_range(entries: Vec<StateEntry>, proof: RangeProof) -> Self {
+        Self::StateRange { entries, proof }
+    }
+
+    /// Create a block transactions response
+    pub fn block_transactions(transactions: Vec<Transaction>) -> Self {
+        Self::BlockTransactions { transactions }
+    }
+
+    /// Create a pending transactions response
+    pub fn transactions(transactions: Vec<Transaction>) -> Self {
+        Self::Transactions { transactions }
+    }
+
     /// Create an error response
     pub fn error(message: String) -> Self {
         Self::Error { message }
@@ -215,6 +400,12 @@ pub mod limits {
     pub const MAX_HEADERS_PER_REQUEST: u32 = 192;
     /// Maximum number of bodies per request
     pub const MAX_BODIES_PER_REQUEST: usize = 32;
+    /// Maximum number of state entries per range request
+    pub const MAX_STATE_ENTRIES_PER_REQUEST: u32 = 1024;
+    /// Maximum number of pending transactions per request
+    pub const MAX_TRANSACTIONS_PER_REQUEST: usize = 256;
+    /// Maximum size of a transaction's data (64 KB)
+    pub const MAX_TRANSACTION_DATA_SIZE: usize = 64 * 1024;
 }
 
 #[cfg(test)]
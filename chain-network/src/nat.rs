@@ -0,0 +1,226 @@
+//! NAT traversal through AutoNAT, circuit relays and hole punching
+//!
+//! AutoNAT asks connected peers to dial us back to learn whether we are
+//! publicly reachable. Behind a NAT, the node reserves a slot on up to
+//! [`max_relay_reservations`](crate::NetworkConfig::max_relay_reservations)
+//! relays and advertises the resulting `/p2p-circuit` addresses, so other
+//! peers can reach it through the relay. Once a relayed connection is up,
+//! DCUtR tries to replace it with a direct one by hole punching. Relays are
+//! taken from the configured relay nodes and from peers that announce the relay
+//! hop protocol through identify.
+
+use crate::discovery::peer_id_of;
+use libp2p::autonat::NatStatus;
+use libp2p::core::transport::ListenerId;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashMap;
+
+/// Default number of relays a node behind NAT reserves a slot on
+pub const DEFAULT_MAX_RELAY_RESERVATIONS: usize = 2;
+
+/// NAT traversal statistics
+#[derive(Debug, Default, Clone)]
+pub struct NatStats {
+    /// Relay reservations accepted, including renewals
+    pub reservations: u64,
+    /// Relayed connections upgraded to direct ones
+    pub hole_punches: u64,
+    /// Failed hole punching attempts
+    pub hole_punch_failures: u64,
+}
+
+/// Tracks our reachability and the relays we listen through
+pub struct NatTraversal {
+    /// Reachability reported by AutoNAT
+    status: NatStatus,
+    /// Known relays and the address to reach each
+    relays: HashMap<PeerId, Multiaddr>,
+    /// Relay listeners and the relay each listens through
+    listeners: HashMap<ListenerId, PeerId>,
+    /// Maximum number of relay listeners
+    max_reservations: usize,
+    /// Statistics
+    stats: NatStats,
+}
+
+impl NatTraversal {
+    /// Create NAT traversal using the given relays, as addresses ending in
+    /// `/p2p/<peer id>`
+    pub fn new(relays: &[Multiaddr], max_reservations: usize) -> Self {
+        let relays = relays
+            .iter()
+            .filter_map(|addr| Some((peer_id_of(addr)?, addr.clone())))
+            .collect();
+        Self {
+            status: NatStatus::Unknown,
+            relays,
+            listeners: HashMap::new(),
+            max_reservations,
+            stats: NatStats::default(),
+        }
+    }
+
+    /// Get our reachability
+    pub fn status(&self) -> &NatStatus {
+        &self.status
+    }
+
+    /// Check whether AutoNAT found us unreachable
+    pub fn is_private(&self) -> bool {
+        self.status == NatStatus::Private
+    }
+
+    /// Get NAT traversal statistics
+    pub fn stats(&self) -> &NatStats {
+        &self.stats
+    }
+
+    /// Record a peer offering to relay
+    ///
+    /// The first known address of a relay is kept.
+    pub fn add_relay(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        let addr = match peer_id_of(&addr) {
+            Some(_) => addr,
+            None => addr.with(Protocol::P2p(peer_id)),
+        };
+        self.relays.entry(peer_id).or_insert(addr);
+    }
+
+    /// Get the relays listened through
+    pub fn relayed_through(&self) -> Vec<PeerId> {
+        self.listeners.values().copied().collect()
+    }
+
+    /// Record a new reachability
+    ///
+    /// Returns the relay listeners to close once we are reachable directly.
+    pub fn set_status(&mut self, status: NatStatus) -> Vec<ListenerId> {
+        self.status = status;
+        if self.status.is_public() {
+            std::mem::take(&mut self.listeners).into_keys().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Get circuit addresses to listen on while behind NAT
+    ///
+    /// Each is a relay we do not listen through yet, up to the reservation
+    /// limit.
+    pub fn relays_to_reserve(&self) -> Vec<(PeerId, Multiaddr)> {
+        if !self.is_private() {
+            return Vec::new();
+        }
+        let free = self.max_reservations.saturating_sub(self.listeners.len());
+        self.relays
+            .iter()
+            .filter(|(peer_id, _)| !self.listeners.values().any(|relay| relay == *peer_id))
+            .take(free)
+            .map(|(peer_id, addr)| (*peer_id, circuit_address(addr)))
+            .collect()
+    }
+
+    /// Record a listener opened through a relay
+    pub fn listening_through(&mut self, relay: PeerId, listener: ListenerId) {
+        self.listeners.insert(listener, relay);
+    }
+
+    /// Forget a closed listener, so its relay can be reserved again
+    ///
+    /// Returns the relay the listener went through, if it was a relay
+    /// listener.
+    pub fn listener_closed(&mut self, listener: ListenerId) -> Option<PeerId> {
+        self.listeners.remove(&listener)
+    }
+
+    /// Forget a relay that could not be listened through
+    pub fn remove_relay(&mut self, relay: &PeerId) {
+        self.relays.remove(relay);
+    }
+
+    /// Count an accepted relay reservation
+    pub fn record_reservation(&mut self) {
+        self.stats.reservations += 1;
+    }
+
+    /// Count the outcome of a hole punching attempt
+    pub fn record_hole_punch(&mut self, success: bool) {
+        if success {
+            self.stats.hole_punches += 1;
+        } else {
+            self.stats.hole_punch_failures += 1;
+        }
+    }
+}
+
+/// Get the address to listen on for connections relayed by a relay
+pub fn circuit_address(relay: &Multiaddr) -> Multiaddr {
+    relay.clone().with(Protocol::P2pCircuit)
+}
+
+/// Check whether an address goes through a relay
+pub fn is_relayed(addr: &Multiaddr) -> bool {
+    addr.iter().any(|protocol| protocol == Protocol::P2pCircuit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay(port: u16) -> (PeerId, Multiaddr) {
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = format!("/ip4/203.0.113.1/tcp/{}", port).parse().unwrap();
+        (peer_id, addr.with(Protocol::P2p(peer_id)))
+    }
+
+    #[test]
+    fn test_relays_reserved_only_behind_nat() {
+        let (peer_a, addr_a) = relay(1);
+        let (peer_b, addr_b) = relay(2);
+        let mut nat = NatTraversal::new(std::slice::from_ref(&addr_a), 1);
+        assert!(nat.relays_to_reserve().is_empty());
+
+        nat.set_status(NatStatus::Private);
+        let to_reserve = nat.relays_to_reserve();
+        assert_eq!(to_reserve, vec![(peer_a, circuit_address(&addr_a))]);
+        assert!(is_relayed(&to_reserve[0].1));
+        assert!(!is_relayed(&addr_a));
+
+        // The limit is reached, so further relays are not used
+        nat.listening_through(peer_a, ListenerId::next());
+        nat.add_relay(peer_b, addr_b.clone());
+        assert!(nat.relays_to_reserve().is_empty());
+        assert_eq!(nat.relayed_through(), vec![peer_a]);
+
+        // Relay listeners close once we are reachable
+        let public: Multiaddr = "/ip4/198.51.100.7/tcp/30333".parse().unwrap();
+        assert_eq!(nat.set_status(NatStatus::Public(public)).len(), 1);
+        assert!(nat.relayed_through().is_empty());
+        assert!(nat.relays_to_reserve().is_empty());
+    }
+
+    #[test]
+    fn test_closed_listener_frees_relay() {
+        let (peer_a, addr_a) = relay(1);
+        let mut nat = NatTraversal::new(&[], DEFAULT_MAX_RELAY_RESERVATIONS);
+        nat.set_status(NatStatus::Private);
+
+        // Addresses learned through identify get the relay's peer id
+        nat.add_relay(peer_a, "/ip4/203.0.113.1/tcp/1".parse().unwrap());
+        assert_eq!(
+            nat.relays_to_reserve(),
+            vec![(peer_a, circuit_address(&addr_a))]
+        );
+
+        let listener = ListenerId::next();
+        nat.listening_through(peer_a, listener);
+        assert!(nat.relays_to_reserve().is_empty());
+        assert_eq!(nat.listener_closed(listener), Some(peer_a));
+        assert_eq!(nat.listener_closed(listener), None);
+        assert_eq!(nat.relays_to_reserve().len(), 1);
+
+        nat.remove_relay(&peer_a);
+        assert!(nat.relays_to_reserve().is_empty());
+    }
+}
@@ -1,9 +1,11 @@
 //! Peer management and connection handling
 
 use crate::identity::PeerInfo;
+use crate::reputation::ReputationManager;
 use crate::NetworkResult;
 use libp2p::{Multiaddr, PeerId};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
 
@@ -18,6 +20,8 @@ pub struct PeerManager {
     max_peers: usize,
     /// Connection timeout
     connection_timeout: Duration,
+    /// Misbehaviour scores and bans, if tracked
+    reputation: Option<Arc<ReputationManager>>,
 }
 
 impl PeerManager {
@@ -27,11 +31,35 @@ impl PeerManager {
             peers: RwLock::new(HashMap::new()),
             max_peers,
             connection_timeout,
+            reputation: None,
+        }
+    }
+
+    /// Skip banned peers and judge peers by their reputation
+    pub fn with_reputation(mut self, reputation: Arc<ReputationManager>) -> Self {
+        self.reputation = Some(reputation);
+        self
+    }
+
+    /// Check whether a peer is good, by reputation if tracked and by
+    /// connection score otherwise
+    fn is_good(&self, peer: &PeerInfo) -> bool {
+        match &self.reputation {
+            Some(reputation) => reputation.is_good_peer(&peer.peer_id),
+            None => peer.is_good_peer(),
         }
     }
 
     /// Add a discovered peer
+    ///
+    /// Banned peers are ignored.
     pub async fn add_peer(&self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> NetworkResult<()> {
+        if let Some(reputation) = &self.reputation {
+            if reputation.is_banned(&peer_id) {
+                return Ok(());
+            }
+        }
+
         let mut peers = self.peers.write().await;
 
         match peers.get_mut(&peer_id) {
@@ -79,7 +107,7 @@ impl PeerManager {
         let peers = self.peers.read().await;
         peers
             .values()
-            .filter(|peer| peer.is_good_peer())
+            .filter(|peer| self.is_good(peer))
             .cloned()
             .collect()
     }
@@ -133,7 +161,7 @@ impl PeerManager {
         let peers = self.peers.read().await;
         let mut good_peers: Vec<_> = peers
             .values()
-            .filter(|peer| peer.is_good_peer() && !peer.addresses.is_empty())
+            .filter(|peer| self.is_good(peer) && !peer.addresses.is_empty())
             .cloned()
             .collect();
 
@@ -258,4 +286,32 @@ mod tests {
         let peer_info = manager.get_peer(&peer_id).await.unwrap();
         assert!(peer_info.protocols.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_banned_peers_skipped() {
+        use crate::reputation::{MemoryReputationStore, Misbehaviour, ReputationConfig};
+
+        let (reputation, _bans) = ReputationManager::new(
+            ReputationConfig::default(),
+            Arc::new(MemoryReputationStore::default()),
+        )
+        .unwrap();
+        let reputation = Arc::new(reputation);
+        let manager =
+            PeerManager::new(50, Duration::from_secs(10)).with_reputation(reputation.clone());
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/30333".parse().unwrap();
+
+        let (good, bad, banned) = (PeerId::random(), PeerId::random(), PeerId::random());
+        for peer_id in [good, bad] {
+            manager.add_peer(peer_id, vec![addr.clone()]).await.unwrap();
+        }
+        reputation.report(&bad, Misbehaviour::InvalidBlock).unwrap();
+        reputation.ban_peer(&banned, None).unwrap();
+        manager.add_peer(banned, vec![addr]).await.unwrap();
+
+        assert!(manager.get_peer(&banned).await.is_none());
+        let to_connect = manager.get_peers_to_connect(10).await;
+        assert_eq!(to_connect.len(), 1);
+        assert_eq!(to_connect[0].peer_id, good);
+    }
 }
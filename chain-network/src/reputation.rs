@@ -0,0 +1,749 @@
+//! Peer reputation, bans and connection gating
+//!
+//! [`ReputationManager`] charges peers for named [`Misbehaviour`]s. Scores
+//! decay towards zero with a configurable half-life, so old offences are
+//! forgiven. A peer whose score drops to the ban threshold is banned for a
+//! while; operators can also ban peers or IP addresses temporarily or for
+//! good. Scores and bans are written to a [`ReputationStore`] so they survive
+//! restarts.
+//!
+//! [`ReputationGate`] enforces bans in the swarm: connections to and from
+//! banned peers and addresses are denied before any protocol runs, and open
+//! connections are closed as soon as a ban is issued.
+
+use crate::NetworkResult;
+use libp2p::core::transport::PortUse;
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    dummy, CloseConnection, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+    THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// Lowest and highest reputation score
+const SCORE_BOUND: f64 = 100.0;
+
+/// Unbanned peers whose score decays closer to zero than this are forgotten
+const FORGET_SCORE: f64 = 0.5;
+
+/// Peer behaviour that costs reputation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Misbehaviour {
+    /// Announced or served a block that failed validation
+    InvalidBlock,
+    /// Sent a transaction that failed validation
+    InvalidTransaction,
+    /// Did not answer a request in time
+    Timeout,
+    /// Sent unrequested or excessive messages
+    Spam,
+    /// Sent a message that could not be decoded
+    MalformedMessage,
+}
+
+impl Misbehaviour {
+    /// Get the score change charged for this misbehaviour
+    pub fn cost(&self) -> f64 {
+        match self {
+            Misbehaviour::InvalidBlock => -20.0,
+            Misbehaviour::InvalidTransaction => -10.0,
+            Misbehaviour::Timeout => -5.0,
+            Misbehaviour::Spam => -2.0,
+            Misbehaviour::MalformedMessage => -10.0,
+        }
+    }
+}
+
+/// Reputation settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReputationConfig {
+    /// Time for a score to decay halfway to zero
+    pub decay_half_life: Duration,
+    /// Peers scoring above this are good peers
+    pub good_threshold: f64,
+    /// Peers scoring at or below this are banned
+    pub ban_threshold: f64,
+    /// Length of bans issued for a low score
+    pub ban_duration: Duration,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            decay_half_life: Duration::from_secs(10 * 60),
+            good_threshold: -10.0,
+            ban_threshold: -50.0,
+            ban_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// A ban of a peer or address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    /// Unix time the ban ends, `None` for a permanent ban
+    pub until: Option<u64>,
+}
+
+impl Ban {
+    /// Ban that never ends
+    pub fn permanent() -> Self {
+        Self { until: None }
+    }
+
+    /// Check whether the ban is in force at the given unix time
+    pub fn is_active(&self, now: u64) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+/// Stored reputation of a peer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Reputation {
+    /// Score when last updated
+    pub score: f64,
+    /// Unix time of the last update
+    pub updated_at: u64,
+    /// Ban of the peer, if any
+    pub ban: Option<Ban>,
+}
+
+impl Reputation {
+    fn new(now: u64) -> Self {
+        Self {
+            score: 0.0,
+            updated_at: now,
+            ban: None,
+        }
+    }
+
+    /// Score decayed to the given unix time
+    fn score_at(&self, now: u64, half_life: Duration) -> f64 {
+        let elapsed = now.saturating_sub(self.updated_at) as f64;
+        let half_life = half_life.as_secs_f64().max(1.0);
+        self.score * 0.5f64.powf(elapsed / half_life)
+    }
+
+    fn is_banned(&self, now: u64) -> bool {
+        self.ban.is_some_and(|ban| ban.is_active(now))
+    }
+
+    /// Check whether the peer has nothing left worth keeping
+    fn is_forgotten(&self, now: u64, half_life: Duration) -> bool {
+        !self.is_banned(now) && self.score_at(now, half_life).abs() < FORGET_SCORE
+    }
+}
+
+/// Something to close connections of after a ban
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BanTarget {
+    /// A banned peer
+    Peer(PeerId),
+    /// A banned address
+    Ip(IpAddr),
+}
+
+/// Persists reputations and bans across restarts
+pub trait ReputationStore: Send + Sync {
+    /// Load the saved reputations of all peers
+    fn load_peers(&self) -> NetworkResult<Vec<(PeerId, Reputation)>>;
+
+    /// Save the reputation of a peer, or remove it with `None`
+    fn save_peer(&self, peer_id: &PeerId, reputation: Option<&Reputation>) -> NetworkResult<()>;
+
+    /// Load the saved address bans
+    fn load_ip_bans(&self) -> NetworkResult<Vec<(IpAddr, Ban)>>;
+
+    /// Save an address ban, or remove it with `None`
+    fn save_ip_ban(&self, ip: &IpAddr, ban: Option<&Ban>) -> NetworkResult<()>;
+}
+
+/// In-memory [`ReputationStore`], for tests and nodes without a database
+#[derive(Debug, Default)]
+pub struct MemoryReputationStore {
+    peers: Mutex<HashMap<PeerId, Reputation>>,
+    ip_bans: Mutex<HashMap<IpAddr, Ban>>,
+}
+
+impl ReputationStore for MemoryReputationStore {
+    fn load_peers(&self) -> NetworkResult<Vec<(PeerId, Reputation)>> {
+        let peers = self.peers.lock().unwrap();
+        Ok(peers
+            .iter()
+            .map(|(peer_id, rep)| (*peer_id, *rep))
+            .collect())
+    }
+
+    fn save_peer(&self, peer_id: &PeerId, reputation: Option<&Reputation>) -> NetworkResult<()> {
+        let mut peers = self.peers.lock().unwrap();
+        match reputation {
+            Some(reputation) => peers.insert(*peer_id, *reputation),
+            None => peers.remove(peer_id),
+        };
+        Ok(())
+    }
+
+    fn load_ip_bans(&self) -> NetworkResult<Vec<(IpAddr, Ban)>> {
+        let ip_bans = self.ip_bans.lock().unwrap();
+        Ok(ip_bans.iter().map(|(ip, ban)| (*ip, *ban)).collect())
+    }
+
+    fn save_ip_ban(&self, ip: &IpAddr, ban: Option<&Ban>) -> NetworkResult<()> {
+        let mut ip_bans = self.ip_bans.lock().unwrap();
+        match ban {
+            Some(ban) => ip_bans.insert(*ip, *ban),
+            None => ip_bans.remove(ip),
+        };
+        Ok(())
+    }
+}
+
+/// Tracks peer reputations and bans
+pub struct ReputationManager {
+    /// Settings
+    config: ReputationConfig,
+    /// Where reputations and bans are saved
+    store: RwLock<Arc<dyn ReputationStore>>,
+    /// Reputation of each peer
+    peers: RwLock<HashMap<PeerId, Reputation>>,
+    /// Banned addresses
+    ip_bans: RwLock<HashMap<IpAddr, Ban>>,
+    /// New bans, for the connection gate to act on
+    bans: mpsc::UnboundedSender<BanTarget>,
+}
+
+impl ReputationManager {
+    /// Create a manager and the receiving end of its ban notifications
+    ///
+    /// Saved reputations and bans are loaded from the store.
+    pub fn new(
+        config: ReputationConfig,
+        store: Arc<dyn ReputationStore>,
+    ) -> NetworkResult<(Self, mpsc::UnboundedReceiver<BanTarget>)> {
+        let (bans, bans_rx) = mpsc::unbounded_channel();
+        let manager = Self {
+            config,
+            store: RwLock::new(Arc::new(MemoryReputationStore::default())),
+            peers: RwLock::new(HashMap::new()),
+            ip_bans: RwLock::new(HashMap::new()),
+            bans,
+        };
+        manager.set_store(store)?;
+        Ok((manager, bans_rx))
+    }
+
+    /// Save to another store, loading the reputations and bans it holds
+    ///
+    /// Entries that have decayed away since they were saved are pruned.
+    pub fn set_store(&self, store: Arc<dyn ReputationStore>) -> NetworkResult<()> {
+        let peers = store.load_peers()?;
+        let ip_bans = store.load_ip_bans()?;
+        self.peers.write().unwrap().extend(peers);
+        self.ip_bans.write().unwrap().extend(ip_bans);
+        *self.store.write().unwrap() = store;
+        self.prune_at(now())
+    }
+
+    /// Get the reputation settings
+    pub fn config(&self) -> &ReputationConfig {
+        &self.config
+    }
+
+    /// Charge a peer for misbehaving
+    ///
+    /// Returns `true` if the peer got banned as a result.
+    pub fn report(&self, peer_id: &PeerId, misbehaviour: Misbehaviour) -> NetworkResult<bool> {
+        self.report_at(peer_id, misbehaviour, now())
+    }
+
+    /// Raise the score of a peer for useful behaviour
+    pub fn reward(&self, peer_id: &PeerId, amount: f64) -> NetworkResult<()> {
+        self.adjust_at(peer_id, amount.abs(), now()).map(|_| ())
+    }
+
+    /// Get the current, decayed score of a peer
+    pub fn score(&self, peer_id: &PeerId) -> f64 {
+        self.score_at(peer_id, now())
+    }
+
+    /// Check whether a peer is not banned and scores above the good threshold
+    pub fn is_good_peer(&self, peer_id: &PeerId) -> bool {
+        !self.is_banned(peer_id) && self.score(peer_id) > self.config.good_threshold
+    }
+
+    /// Ban a peer, for good if `duration` is `None`
+    pub fn ban_peer(&self, peer_id: &PeerId, duration: Option<Duration>) -> NetworkResult<()> {
+        let now = now();
+        let ban = ban_for(now, duration);
+        let reputation = {
+            let mut peers = self.peers.write().unwrap();
+            let reputation = peers
+                .entry(*peer_id)
+                .or_insert_with(|| Reputation::new(now));
+            reputation.ban = Some(ban);
+            *reputation
+        };
+        tracing::info!("Banned peer {} until {:?}", peer_id, ban.until);
+        let _ = self.bans.send(BanTarget::Peer(*peer_id));
+        self.store().save_peer(peer_id, Some(&reputation))
+    }
+
+    /// Lift the ban of a peer
+    pub fn unban_peer(&self, peer_id: &PeerId) -> NetworkResult<()> {
+        let reputation = {
+            let mut peers = self.peers.write().unwrap();
+            match peers.get_mut(peer_id) {
+                Some(reputation) => {
+                    reputation.ban = None;
+                    *reputation
+                }
+                None => return Ok(()),
+            }
+        };
+        self.store().save_peer(peer_id, Some(&reputation))
+    }
+
+    /// Check whether a peer is banned
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        let peers = self.peers.read().unwrap();
+        peers
+            .get(peer_id)
+            .is_some_and(|reputation| reputation.is_banned(now()))
+    }
+
+    /// Ban an IP address, for good if `duration` is `None`
+    pub fn ban_ip(&self, ip: IpAddr, duration: Option<Duration>) -> NetworkResult<()> {
+        let ban = ban_for(now(), duration);
+        self.ip_bans.write().unwrap().insert(ip, ban);
+        tracing::info!("Banned address {} until {:?}", ip, ban.until);
+        let _ = self.bans.send(BanTarget::Ip(ip));
+        self.store().save_ip_ban(&ip, Some(&ban))
+    }
+
+    /// Lift the ban of an IP address
+    pub fn unban_ip(&self, ip: &IpAddr) -> NetworkResult<()> {
+        if self.ip_bans.write().unwrap().remove(ip).is_some() {
+            self.store().save_ip_ban(ip, None)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether an IP address is banned
+    pub fn is_ip_banned(&self, ip: &IpAddr) -> bool {
+        let ip_bans = self.ip_bans.read().unwrap();
+        ip_bans.get(ip).is_some_and(|ban| ban.is_active(now()))
+    }
+
+    /// Check whether a multiaddress points at a banned IP address
+    pub fn is_address_banned(&self, addr: &Multiaddr) -> bool {
+        ip_of(addr).is_some_and(|ip| self.is_ip_banned(&ip))
+    }
+
+    /// Forget unbanned peers whose score has decayed to about zero, and
+    /// expired address bans
+    pub fn prune(&self) -> NetworkResult<()> {
+        self.prune_at(now())
+    }
+
+    fn report_at(
+        &self,
+        peer_id: &PeerId,
+        misbehaviour: Misbehaviour,
+        now: u64,
+    ) -> NetworkResult<bool> {
+        tracing::debug!("Peer {} reported for {:?}", peer_id, misbehaviour);
+        self.adjust_at(peer_id, misbehaviour.cost(), now)
+    }
+
+    fn score_at(&self, peer_id: &PeerId, now: u64) -> f64 {
+        let peers = self.peers.read().unwrap();
+        peers.get(peer_id).map_or(0.0, |reputation| {
+            reputation.score_at(now, self.config.decay_half_life)
+        })
+    }
+
+    /// Apply a score change at the given unix time, banning the peer if its
+    /// score reaches the ban threshold
+    fn adjust_at(&self, peer_id: &PeerId, delta: f64, now: u64) -> NetworkResult<bool> {
+        let (reputation, banned) = {
+            let mut peers = self.peers.write().unwrap();
+            let reputation = peers
+                .entry(*peer_id)
+                .or_insert_with(|| Reputation::new(now));
+            reputation.score = (reputation.score_at(now, self.config.decay_half_life) + delta)
+                .clamp(-SCORE_BOUND, SCORE_BOUND);
+            reputation.updated_at = now;
+
+            let banned =
+                reputation.score <= self.config.ban_threshold && !reputation.is_banned(now);
+            if banned {
+                reputation.ban = Some(ban_for(now, Some(self.config.ban_duration)));
+            }
+            (*reputation, banned)
+        };
+
+        if banned {
+            tracing::info!(
+                "Banned peer {} with score {:.1} for {:?}",
+                peer_id,
+                reputation.score,
+                self.config.ban_duration
+            );
+            let _ = self.bans.send(BanTarget::Peer(*peer_id));
+        }
+        self.store().save_peer(peer_id, Some(&reputation))?;
+        Ok(banned)
+    }
+
+    fn prune_at(&self, now: u64) -> NetworkResult<()> {
+        let half_life = self.config.decay_half_life;
+        let mut forgotten = Vec::new();
+        self.peers.write().unwrap().retain(|peer_id, reputation| {
+            let forget = reputation.is_forgotten(now, half_life);
+            if forget {
+                forgotten.push(*peer_id);
+            }
+            !forget
+        });
+        let mut expired = Vec::new();
+        self.ip_bans.write().unwrap().retain(|ip, ban| {
+            let active = ban.is_active(now);
+            if !active {
+                expired.push(*ip);
+            }
+            active
+        });
+
+        if !forgotten.is_empty() || !expired.is_empty() {
+            tracing::debug!(
+                "Forgot {} idle peers and {} expired address bans",
+                forgotten.len(),
+                expired.len()
+            );
+        }
+        let store = self.store();
+        for peer_id in &forgotten {
+            store.save_peer(peer_id, None)?;
+        }
+        for ip in &expired {
+            store.save_ip_ban(ip, None)?;
+        }
+        Ok(())
+    }
+
+    fn store(&self) -> Arc<dyn ReputationStore> {
+        self.store.read().unwrap().clone()
+    }
+}
+
+impl std::fmt::Debug for ReputationManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReputationManager")
+            .field("config", &self.config)
+            .field("peers", &self.peers.read().unwrap().len())
+            .field("ip_bans", &self.ip_bans.read().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Error returned for connections refused because of a ban
+#[derive(Debug, thiserror::Error)]
+#[error("{0} is banned")]
+pub struct Banned(String);
+
+/// Swarm behaviour refusing connections to and from banned peers and addresses
+pub struct ReputationGate {
+    /// Ban state
+    reputation: Arc<ReputationManager>,
+    /// New bans to close connections for
+    bans: mpsc::UnboundedReceiver<BanTarget>,
+    /// Remote IP address of each open connection
+    connections: HashMap<ConnectionId, (PeerId, Option<IpAddr>)>,
+    /// Connections waiting to be closed
+    to_close: VecDeque<(PeerId, CloseConnection)>,
+}
+
+impl ReputationGate {
+    /// Create a gate from a manager and its ban notifications
+    pub fn new(
+        reputation: Arc<ReputationManager>,
+        bans: mpsc::UnboundedReceiver<BanTarget>,
+    ) -> Self {
+        Self {
+            reputation,
+            bans,
+            connections: HashMap::new(),
+            to_close: VecDeque::new(),
+        }
+    }
+
+    fn check_peer(&self, peer_id: &PeerId) -> Result<(), ConnectionDenied> {
+        if self.reputation.is_banned(peer_id) {
+            return Err(ConnectionDenied::new(Banned(format!("Peer {}", peer_id))));
+        }
+        Ok(())
+    }
+
+    fn check_address(&self, addr: &Multiaddr) -> Result<(), ConnectionDenied> {
+        if self.reputation.is_address_banned(addr) {
+            return Err(ConnectionDenied::new(Banned(format!("Address {}", addr))));
+        }
+        Ok(())
+    }
+
+    fn on_ban(&mut self, target: BanTarget) {
+        match target {
+            BanTarget::Peer(peer_id) => {
+                self.to_close.push_back((peer_id, CloseConnection::All));
+            }
+            BanTarget::Ip(ip) => {
+                for (connection_id, (peer_id, _)) in self
+                    .connections
+                    .iter()
+                    .filter(|(_, (_, remote))| *remote == Some(ip))
+                {
+                    self.to_close
+                        .push_back((*peer_id, CloseConnection::One(*connection_id)));
+                }
+            }
+        }
+    }
+}
+
+impl NetworkBehaviour for ReputationGate {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.check_address(remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check_peer(&peer)?;
+        self.connections
+            .insert(connection_id, (peer, ip_of(remote_addr)));
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        _addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer_id) = maybe_peer {
+            self.check_peer(&peer_id)?;
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check_peer(&peer)?;
+        self.check_address(addr)?;
+        self.connections.insert(connection_id, (peer, ip_of(addr)));
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            self.connections.remove(&closed.connection_id);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        while let Poll::Ready(Some(target)) = self.bans.poll_recv(cx) {
+            self.on_ban(target);
+        }
+        match self.to_close.pop_front() {
+            Some((peer_id, connection)) => Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection,
+            }),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Get the IP address a multiaddress starts with, if any
+pub fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next()? {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    }
+}
+
+fn ban_for(now: u64, duration: Option<Duration>) -> Ban {
+    Ban {
+        until: duration.map(|duration| now.saturating_add(duration.as_secs())),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(store: Arc<dyn ReputationStore>) -> ReputationManager {
+        ReputationManager::new(ReputationConfig::default(), store)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_scores_decay() {
+        let reputation = manager(Arc::new(MemoryReputationStore::default()));
+        let half_life = reputation.config().decay_half_life.as_secs();
+        let peer_id = PeerId::random();
+        let start = now();
+
+        reputation.adjust_at(&peer_id, -40.0, start).unwrap();
+        assert_eq!(reputation.score_at(&peer_id, start), -40.0);
+        assert_eq!(reputation.score_at(&peer_id, start + half_life), -20.0);
+        assert_eq!(reputation.score_at(&peer_id, start + 2 * half_life), -10.0);
+
+        // Later penalties apply to the decayed score
+        reputation
+            .adjust_at(&peer_id, -5.0, start + half_life)
+            .unwrap();
+        assert_eq!(reputation.score_at(&peer_id, start + half_life), -25.0);
+    }
+
+    #[test]
+    fn test_low_score_bans_peer() {
+        let store = Arc::new(MemoryReputationStore::default());
+        let (reputation, mut bans) =
+            ReputationManager::new(ReputationConfig::default(), store).unwrap();
+        let peer_id = PeerId::random();
+        let start = now();
+
+        let report = |misbehaviour| reputation.report_at(&peer_id, misbehaviour, start).unwrap();
+        assert!(!report(Misbehaviour::InvalidBlock));
+        assert!(!reputation.is_good_peer(&peer_id));
+        assert!(!report(Misbehaviour::InvalidBlock));
+        assert!(!reputation.is_banned(&peer_id));
+        assert!(report(Misbehaviour::MalformedMessage));
+        assert!(reputation.is_banned(&peer_id));
+        assert_eq!(bans.try_recv().unwrap(), BanTarget::Peer(peer_id));
+
+        // Further reports do not issue another ban
+        assert!(!report(Misbehaviour::Spam));
+        assert!(bans.try_recv().is_err());
+
+        reputation.unban_peer(&peer_id).unwrap();
+        assert!(!reputation.is_banned(&peer_id));
+    }
+
+    #[test]
+    fn test_bans_expire() {
+        let ban = ban_for(1_000, Some(Duration::from_secs(60)));
+        assert!(ban.is_active(1_059));
+        assert!(!ban.is_active(1_060));
+        assert!(Ban::permanent().is_active(u64::MAX));
+    }
+
+    #[test]
+    fn test_idle_peers_are_forgotten() {
+        let store: Arc<dyn ReputationStore> = Arc::new(MemoryReputationStore::default());
+        let reputation = manager(store.clone());
+        let half_life = reputation.config().decay_half_life.as_secs();
+        let (idle, banned) = (PeerId::random(), PeerId::random());
+        let start = now();
+
+        reputation.adjust_at(&idle, -10.0, start).unwrap();
+        reputation.adjust_at(&banned, -60.0, start).unwrap();
+        reputation.prune_at(start + half_life).unwrap();
+        assert_eq!(store.load_peers().unwrap().len(), 2);
+
+        // The ban outlasts the score
+        let later = start + 5 * half_life;
+        reputation.prune_at(later).unwrap();
+        let peers = store.load_peers().unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].0, banned);
+        assert!(reputation.peers.read().unwrap().get(&idle).is_none());
+
+        // Peers that decayed away while the node was down are dropped on load
+        drop(reputation);
+        let stale = Reputation {
+            score: -10.0,
+            updated_at: start - 5 * half_life,
+            ban: None,
+        };
+        store.save_peer(&idle, Some(&stale)).unwrap();
+        manager(store.clone());
+        assert_eq!(store.load_peers().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_bans_persist() {
+        let store: Arc<dyn ReputationStore> = Arc::new(MemoryReputationStore::default());
+        let peer_id = PeerId::random();
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+        let addr: Multiaddr = "/ip4/10.0.0.7/tcp/30333".parse().unwrap();
+
+        let reputation = manager(store.clone());
+        reputation.ban_peer(&peer_id, None).unwrap();
+        reputation
+            .ban_ip(ip, Some(Duration::from_secs(60)))
+            .unwrap();
+        reputation
+            .report(&PeerId::random(), Misbehaviour::Timeout)
+            .unwrap();
+        drop(reputation);
+
+        let restarted = manager(store.clone());
+        assert!(restarted.is_banned(&peer_id));
+        assert!(restarted.is_address_banned(&addr));
+        assert_eq!(store.load_peers().unwrap().len(), 2);
+
+        restarted.unban_ip(&ip).unwrap();
+        assert!(!manager(store).is_address_banned(&addr));
+    }
+}
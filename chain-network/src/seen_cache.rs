@@ -0,0 +1,243 @@
+//! Bounded, time-expiring cache of seen message ids
+//!
+//! Gossip handlers remember what they have already processed so that
+//! re-broadcast messages are dropped cheaply. [`SeenCache`] keeps at most
+//! `capacity` ids, forgets ids after `ttl` and evicts the least recently seen
+//! id when full, so dedup state is never wiped all at once.
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Default number of ids a seen cache holds
+pub const DEFAULT_SEEN_CAPACITY: usize = 10_000;
+
+/// Default time an id is remembered after it was last seen
+pub const DEFAULT_SEEN_TTL: Duration = Duration::from_secs(300);
+
+/// Seen cache statistics
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SeenCacheStats {
+    /// Inserts of ids that were already present
+    pub hits: u64,
+    /// Inserts of new ids
+    pub misses: u64,
+    /// Ids evicted to make room for new ones
+    pub evictions: u64,
+    /// Ids dropped because they were not seen within the ttl
+    pub expirations: u64,
+}
+
+/// When an id was seen, numbered to tell sightings at the same instant apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sighting {
+    at: Instant,
+    seq: u64,
+}
+
+/// Bounded least-recently-seen cache with expiry
+#[derive(Debug)]
+pub struct SeenCache<K> {
+    /// Last sighting of each id
+    entries: HashMap<K, Sighting>,
+    /// Ids in order of sighting; sightings superseded by a later one are
+    /// skipped when popped
+    order: VecDeque<(K, Sighting)>,
+    /// Number of the next sighting
+    next_seq: u64,
+    /// Maximum number of ids
+    capacity: usize,
+    /// Time an id is remembered
+    ttl: Duration,
+    /// Statistics
+    stats: SeenCacheStats,
+}
+
+impl<K: Eq + Hash + Clone> SeenCache<K> {
+    /// Create a cache holding up to `capacity` ids for `ttl` each
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            next_seq: 0,
+            capacity: capacity.max(1),
+            ttl,
+            stats: SeenCacheStats::default(),
+        }
+    }
+
+    /// Check whether an id was seen within the ttl
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.contains_at(key, Instant::now())
+    }
+
+    /// Record an id as seen
+    ///
+    /// Returns `true` if the id is new, like [`HashSet::insert`](std::collections::HashSet::insert).
+    pub fn insert(&mut self, key: K) -> bool {
+        self.insert_at(key, Instant::now())
+    }
+
+    /// Forget an id
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.remove(key).is_some()
+    }
+
+    /// Get the number of ids held, including ones that expired but were not
+    /// dropped yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the maximum number of ids
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get cache statistics
+    pub fn stats(&self) -> &SeenCacheStats {
+        &self.stats
+    }
+
+    fn contains_at<Q>(&self, key: &Q, now: Instant) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries
+            .get(key)
+            .is_some_and(|seen| now.saturating_duration_since(seen.at) < self.ttl)
+    }
+
+    fn insert_at(&mut self, key: K, now: Instant) -> bool {
+        self.expire(now);
+
+        let new = !self.entries.contains_key(&key);
+        if new {
+            self.stats.misses += 1;
+            while self.entries.len() >= self.capacity && self.evict_oldest() {}
+        } else {
+            self.stats.hits += 1;
+        }
+
+        let sighting = Sighting {
+            at: now,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        self.entries.insert(key.clone(), sighting);
+        self.order.push_back((key, sighting));
+        if self.order.len() > 2 * self.capacity {
+            self.compact();
+        }
+        new
+    }
+
+    /// Drop ids not seen within the ttl
+    fn expire(&mut self, now: Instant) {
+        while let Some((key, seen)) = self.order.front() {
+            if now.saturating_duration_since(seen.at) < self.ttl {
+                break;
+            }
+            if self.entries.get(key) == Some(seen) {
+                self.entries.remove(key);
+                self.stats.expirations += 1;
+            }
+            self.order.pop_front();
+        }
+    }
+
+    /// Drop the least recently seen id, returning false if there is none
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((key, seen)) = self.order.pop_front() {
+            if self.entries.get(&key) == Some(&seen) {
+                self.entries.remove(&key);
+                self.stats.evictions += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Rebuild the order queue without superseded sightings
+    fn compact(&mut self) {
+        let entries = &self.entries;
+        self.order
+            .retain(|(key, seen)| entries.get(key) == Some(seen));
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for SeenCache<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEEN_CAPACITY, DEFAULT_SEEN_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_seen() {
+        let mut cache = SeenCache::new(3, DEFAULT_SEEN_TTL);
+        let now = Instant::now();
+
+        assert!(cache.insert_at(1, now));
+        assert!(cache.insert_at(2, now));
+        assert!(cache.insert_at(3, now));
+        // Seeing 1 again makes 2 the oldest
+        assert!(!cache.insert_at(1, now));
+        assert!(cache.insert_at(4, now));
+
+        assert!(cache.contains_at(&1, now));
+        assert!(!cache.contains_at(&2, now));
+        assert!(cache.contains_at(&3, now));
+        assert!(cache.contains_at(&4, now));
+        assert_eq!(cache.len(), 3);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 4, 1));
+    }
+
+    #[test]
+    fn test_ids_expire() {
+        let ttl = Duration::from_secs(10);
+        let mut cache = SeenCache::new(100, ttl);
+        let start = Instant::now();
+
+        cache.insert_at("a", start);
+        cache.insert_at("b", start + Duration::from_secs(5));
+        assert!(!cache.contains_at(&"a", start + ttl));
+        assert!(cache.contains_at(&"b", start + ttl));
+
+        // Expired ids are dropped on the next insert and count as new
+        assert!(cache.insert_at("a", start + ttl));
+        assert_eq!(cache.stats().expirations, 1);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_order_stays_bounded() {
+        let mut cache = SeenCache::new(4, DEFAULT_SEEN_TTL);
+        let now = Instant::now();
+        for round in 0..100 {
+            cache.insert_at(round % 4, now);
+        }
+        assert!(cache.order.len() <= 8);
+        assert_eq!(cache.stats().evictions, 0);
+    }
+}
@@ -0,0 +1,1920 @@
+//! libp2p network service
+//!
+//! [`NetworkService`] owns the libp2p [`Swarm`] and drives it together with
+//! the commands issued through [`GossipManager`] and [`SyncManager`]. Inbound
+//! gossip is decoded and checked by the service's [`GossipHandler`] on a task
+//! of its own; gossipsub forwards only the messages it accepts, which are
+//! also surfaced as typed [`NetworkEvent`]s. Inbound sync requests are
+//! answered by the service's [`SyncHandler`] on blocking tasks, so slow chain
+//! reads do not hold up the swarm. Peers that time out
+//! or send undecodable gossip are reported to the [`ReputationManager`], whose
+//! bans are enforced by the [`ReputationGate`] in the swarm. Validators publish
+//! their [`AuthorityRecord`](crate::AuthorityRecord) and exchange consensus
+//! messages with the authorities found by [`AuthorityDiscovery`]. Nodes behind
+//! NAT listen through relays and hole punch relayed connections, as tracked by
+//! [`NatTraversal`]. The fork id announced in identify and checked against
+//! peers follows the best block reported through [`NetworkHandle::set_best_block`].
+
+use crate::authority::AuthorityDiscovery;
+use crate::codec::{sync_behaviour, SyncBehaviour};
+use crate::compact::CompactBlock;
+use crate::config::parse_fork_agent_version;
+use crate::connections::{ConnectionLimits, ConnectionManager, ReservedPeers};
+use crate::discovery::{peer_id_of, Discovery};
+use crate::gossip::{
+    GossipCommand, GossipHandler, GossipManager, GossipStep, GossipValidation, BLOCKS_TOPIC,
+    TRANSACTIONS_TOPIC, VALIDATORS_TOPIC,
+};
+use crate::identity::PeerIdentity;
+use crate::message::{
+    BlockAnnounce, ConsensusMessage, GossipMessage, SyncRequest, SyncResponse, TransactionAnnounce,
+    TransactionPropagate,
+};
+use crate::nat::{is_relayed, NatTraversal};
+use crate::peer::PeerManager;
+use crate::reputation::{
+    MemoryReputationStore, Misbehaviour, ReputationGate, ReputationManager, ReputationStore,
+};
+use crate::sync::{SyncCommand, SyncHandler, SyncManager};
+use crate::transport::{build_relay_transport, build_transport};
+use crate::{NetworkConfig, NetworkError, NetworkResult};
+use chain_core::{Address, BlockNumber, ForkId};
+use futures::StreamExt;
+use libp2p::autonat::{self, NatStatus};
+use libp2p::core::transport::ListenerId;
+use libp2p::request_response::{self, OutboundFailure, OutboundRequestId, ResponseChannel};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::behaviour::{ExternalAddrConfirmed, FromSwarm, NewListenAddr};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+use libp2p::{
+    dcutr, gossipsub, identify, kad, mdns, ping, relay, Multiaddr, PeerId, StreamProtocol, Swarm,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{debug, info, warn};
+
+/// Identify protocol version announced to peers
+pub const IDENTIFY_PROTOCOL_VERSION: &str = "/chain/1.0.0";
+
+/// Kademlia protocol name, separating our DHT from other libp2p networks
+pub const KAD_PROTOCOL: &str = "/chain/kad/1.0.0";
+
+/// Idle connections are closed after this long
+const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Combined libp2p behaviour of a node
+///
+/// The ban gate and connection limits come first so that refused connections
+/// never reach the protocol behaviours.
+#[derive(NetworkBehaviour)]
+pub struct ChainBehaviour {
+    /// Refuses connections of banned peers and addresses
+    pub gate: ReputationGate,
+    /// Connection limits and reserved peers
+    pub connections: ConnectionManager,
+    /// Block and transaction gossip
+    pub gossipsub: gossipsub::Behaviour,
+    /// Exchange of listen addresses and supported protocols
+    pub identify: identify::Behaviour,
+    /// Connection liveness
+    pub ping: ping::Behaviour,
+    /// Peer routing
+    pub kad: kad::Behaviour<kad::store::MemoryStore>,
+    /// Local network discovery, if enabled
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    /// Reachability detection, if NAT traversal is enabled
+    pub autonat: Toggle<autonat::Behaviour>,
+    /// Listening and dialing through relays, if NAT traversal is enabled
+    pub relay_client: Toggle<relay::client::Behaviour>,
+    /// Hole punching of relayed connections, if NAT traversal is enabled
+    pub dcutr: Toggle<dcutr::Behaviour>,
+    /// Relaying for peers behind NAT, if enabled
+    pub relay: Toggle<relay::Behaviour>,
+    /// Sync requests and responses
+    pub sync: SyncBehaviour,
+}
+
+impl ChainBehaviour {
+    /// Build the behaviour from the node key, network configuration, ban gate,
+    /// connection manager and relay client
+    ///
+    /// NAT traversal is enabled when a relay client, created together with
+    /// its transport, is given.
+    pub fn new(
+        keypair: &libp2p::identity::Keypair,
+        config: &NetworkConfig,
+        gate: ReputationGate,
+        connections: ConnectionManager,
+        relay_client: Option<relay::client::Behaviour>,
+    ) -> NetworkResult<Self> {
+        let peer_id = PeerId::from(keypair.public());
+
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .heartbeat_interval(config.gossip_heartbeat)
+            .max_transmit_size(config.max_message_size)
+            .mesh_n(config.mesh_n)
+            .mesh_n_low(config.mesh_n_low)
+            .mesh_n_high(config.mesh_n_high)
+            .validation_mode(gossipsub::ValidationMode::Strict)
+            // Messages are forwarded once the gossip handler accepts them
+            .validate_messages()
+            .message_id_fn(|message: &gossipsub::Message| {
+                gossipsub::MessageId::from(blake3::hash(&message.data).as_bytes().to_vec())
+            })
+            .build()
+            .map_err(|e| NetworkError::Config(format!("Gossipsub config error: {}", e)))?;
+        let gossipsub = gossipsub::Behaviour::new(
+            gossipsub::MessageAuthenticity::Signed(keypair.clone()),
+            gossipsub_config,
+        )
+        .map_err(|e| NetworkError::Config(format!("Gossipsub error: {}", e)))?;
+
+        let identify = identify_behaviour(keypair.public(), config);
+
+        let mut kad = kad::Behaviour::with_config(
+            peer_id,
+            kad::store::MemoryStore::new(peer_id),
+            kad::Config::new(StreamProtocol::new(KAD_PROTOCOL)),
+        );
+        // Answer DHT queries even before an external address is confirmed
+        kad.set_mode(Some(kad::Mode::Server));
+
+        let mdns = if config.enable_mdns {
+            let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)
+                .map_err(|e| NetworkError::Config(format!("mDNS error: {}", e)))?;
+            Some(mdns)
+        } else {
+            None
+        };
+
+        let nat_traversal = relay_client.is_some();
+        let autonat =
+            nat_traversal.then(|| autonat::Behaviour::new(peer_id, autonat::Config::default()));
+        let dcutr = nat_traversal.then(|| dcutr::Behaviour::new(peer_id));
+        let relay = config
+            .enable_relay_server
+            .then(|| relay::Behaviour::new(peer_id, relay::Config::default()));
+
+        let sync = sync_behaviour(config.max_message_size, config.sync_request_timeout);
+
+        Ok(Self {
+            gate,
+            connections,
+            gossipsub,
+            identify,
+            ping: ping::Behaviour::default(),
+            kad,
+            mdns: mdns.into(),
+            autonat: autonat.into(),
+            relay_client: relay_client.into(),
+            dcutr: dcutr.into(),
+            relay: relay.into(),
+            sync,
+        })
+    }
+}
+
+/// Identify behaviour announcing the fork id at the configured head
+fn identify_behaviour(
+    public_key: libp2p::identity::PublicKey,
+    config: &NetworkConfig,
+) -> identify::Behaviour {
+    identify::Behaviour::new(
+        identify::Config::new(IDENTIFY_PROTOCOL_VERSION.to_string(), public_key)
+            .with_agent_version(config.agent_version()),
+    )
+}
+
+/// Events surfaced by the network service
+///
+/// Gossip events are only surfaced for messages the service's
+/// [`GossipHandler`] accepted.
+#[derive(Debug, Clone)]
+pub enum NetworkEvent {
+    /// Started listening on an address
+    Listening(Multiaddr),
+    /// Connection to a peer established
+    PeerConnected(PeerId),
+    /// Last connection to a peer closed
+    PeerDisconnected(PeerId),
+    /// A new peer was found by Kademlia or mDNS
+    PeerDiscovered(PeerId),
+    /// A peer subscribed to a gossip topic
+    PeerSubscribed { peer_id: PeerId, topic: String },
+    /// Block announcement received over gossip
+    BlockAnnounce {
+        /// Peer that propagated the announcement
+        peer_id: PeerId,
+        /// The announcement
+        announce: Box<BlockAnnounce>,
+    },
+    /// Compact block received over gossip
+    CompactBlock {
+        /// Peer that propagated the block
+        peer_id: PeerId,
+        /// The compact block, already rebuilt and queued for import if the
+        /// gossip handler checks block announcements
+        compact: Box<CompactBlock>,
+    },
+    /// Transactions received over gossip
+    Transactions {
+        /// Peer that propagated the transactions
+        peer_id: PeerId,
+        /// The transactions
+        propagate: TransactionPropagate,
+    },
+    /// Transaction hashes announced over gossip
+    TransactionAnnounce {
+        /// Peer that announced the hashes
+        peer_id: PeerId,
+        /// The announcement
+        announce: TransactionAnnounce,
+    },
+    /// The peer of an authority was found in the DHT
+    AuthorityDiscovered {
+        /// Consensus address of the authority
+        authority: Address,
+        /// Peer the authority runs on
+        peer_id: PeerId,
+    },
+    /// AutoNAT found a new reachability
+    NatStatusChanged(NatStatus),
+    /// A relay accepted to relay connections to us
+    RelayReserved(PeerId),
+    /// A relayed connection was replaced by a direct one through hole punching
+    HolePunched(PeerId),
+    /// Consensus message from an authority
+    Consensus {
+        /// Consensus address of the author
+        authority: Address,
+        /// Peer that authored the message
+        peer_id: PeerId,
+        /// The message
+        message: ConsensusMessage,
+    },
+}
+
+/// Handle used by the rest of the node to talk to a running [`NetworkService`]
+///
+/// The service stops once the handle is dropped.
+#[derive(Debug)]
+pub struct NetworkHandle {
+    /// Publishes gossip messages
+    pub gossip: GossipManager,
+    /// Sends sync requests
+    pub sync: SyncManager,
+    /// Events surfaced by the service
+    pub events: mpsc::UnboundedReceiver<NetworkEvent>,
+    /// Peers found through discovery
+    pub peers: Arc<PeerManager>,
+    /// Misbehaviour scores and bans
+    pub reputation: Arc<ReputationManager>,
+    /// Peers kept connected regardless of the connection limits
+    pub reserved: Arc<ReservedPeers>,
+    /// Authority set and the peers its validators run on
+    pub authorities: Arc<AuthorityDiscovery>,
+    /// Our peer id
+    pub local_peer_id: PeerId,
+    /// Best block number, deciding the fork id we announce and accept
+    best_block: watch::Sender<BlockNumber>,
+}
+
+impl NetworkHandle {
+    /// Report a new best block
+    ///
+    /// Once the block activates a fork, the service announces the new fork id
+    /// and disconnects peers that did not apply the fork.
+    pub fn set_best_block(&self, number: BlockNumber) {
+        self.best_block.send_replace(number);
+    }
+}
+
+/// Response to an inbound sync request, with the peer and channel it goes to
+type ServedRequest = (PeerId, ResponseChannel<SyncResponse>, SyncResponse);
+
+/// Inbound gossip awaiting a verdict
+struct InboundGossip {
+    /// Gossipsub id of the message
+    message_id: gossipsub::MessageId,
+    /// Peer that propagated the message
+    peer_id: PeerId,
+    /// The decoded message
+    message: GossipMessage,
+}
+
+/// Inbound gossip with the verdict of the gossip handler
+type ValidatedGossip = (InboundGossip, GossipValidation);
+
+/// Runs inbound gossip through a [`GossipHandler`]
+///
+/// Handlers await sync requests, which only the service loop sends, so the
+/// validator runs on a task of its own. Announced transactions are fetched on
+/// tasks of their own, so a peer slow to serve them does not hold up the
+/// messages behind its announcement.
+struct GossipValidator {
+    /// Checks the messages
+    handler: GossipHandler,
+    /// Messages from the service
+    inbound: mpsc::UnboundedReceiver<InboundGossip>,
+    /// Verdicts back to the service
+    verdicts: mpsc::UnboundedSender<ValidatedGossip>,
+}
+
+impl GossipValidator {
+    /// Validate messages as they arrive and their fetches complete, until
+    /// the service stops
+    async fn run(mut self) {
+        let (fetched_sender, mut fetched) = mpsc::unbounded_channel();
+        loop {
+            let (gossip, result) = tokio::select! {
+                gossip = self.inbound.recv() => {
+                    let Some(gossip) = gossip else { break };
+                    let step = self
+                        .handler
+                        .start_message(gossip.message.clone(), gossip.peer_id)
+                        .await;
+                    match step {
+                        Ok(GossipStep::Done(validation)) => (gossip, Ok(validation)),
+                        Ok(GossipStep::Fetch(fetch)) => {
+                            let fetched_sender = fetched_sender.clone();
+                            tokio::spawn(async move {
+                                let _ = fetched_sender.send((gossip, fetch.run().await));
+                            });
+                            continue;
+                        }
+                        Err(e) => (gossip, Err(e)),
+                    }
+                }
+                Some((gossip, transactions)) = fetched.recv() => {
+                    let result = self.handler.finish_transaction_fetch(transactions).await;
+                    (gossip, result)
+                }
+            };
+            let validation = result.unwrap_or_else(|e| {
+                debug!("Failed to validate gossip from {}: {}", gossip.peer_id, e);
+                GossipValidation::Ignore
+            });
+            if self.verdicts.send((gossip, validation)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Drives the libp2p swarm and the gossip and sync command channels
+pub struct NetworkService {
+    /// Network configuration
+    config: NetworkConfig,
+    /// The libp2p swarm
+    swarm: Swarm<ChainBehaviour>,
+    /// Commands from the gossip manager
+    gossip_commands: mpsc::UnboundedReceiver<GossipCommand>,
+    /// Commands from the sync manager
+    sync_commands: mpsc::UnboundedReceiver<SyncCommand>,
+    /// Answers inbound sync requests
+    sync_handler: Arc<SyncHandler>,
+    /// Answers to inbound sync requests, sent back by the serving tasks
+    sync_responses: mpsc::UnboundedReceiver<ServedRequest>,
+    /// Handed to the tasks serving inbound sync requests
+    sync_response_sender: mpsc::UnboundedSender<ServedRequest>,
+    /// Checks inbound gossip, spawned once the service runs
+    gossip_validator: Option<GossipValidator>,
+    /// Inbound gossip sent to the validator
+    inbound_gossip: mpsc::UnboundedSender<InboundGossip>,
+    /// Verdicts on inbound gossip
+    gossip_verdicts: mpsc::UnboundedReceiver<ValidatedGossip>,
+    /// Outbound sync requests awaiting a response
+    pending_requests: HashMap<OutboundRequestId, oneshot::Sender<NetworkResult<SyncResponse>>>,
+    /// Feeds Kademlia and mDNS results into the peer manager
+    discovery: Discovery,
+    /// Misbehaviour scores and bans
+    reputation: Arc<ReputationManager>,
+    /// Publishes our authority record and finds the other authorities
+    authorities: Arc<AuthorityDiscovery>,
+    /// Reachability and relay listeners
+    nat: NatTraversal,
+    /// Event notifications
+    event_sender: mpsc::UnboundedSender<NetworkEvent>,
+    /// Best block reported by the node
+    best_block: watch::Receiver<BlockNumber>,
+    /// Our public key, announced through identify
+    public_key: libp2p::identity::PublicKey,
+    /// Fork ids announced by connected peers
+    peer_fork_ids: HashMap<PeerId, ForkId>,
+}
+
+impl NetworkService {
+    /// Create a network service and the handle used to control it
+    pub fn new(
+        config: NetworkConfig,
+        identity: &PeerIdentity,
+    ) -> NetworkResult<(Self, NetworkHandle)> {
+        config.validate().map_err(NetworkError::Config)?;
+
+        let (reputation, bans) = ReputationManager::new(
+            config.reputation.clone(),
+            Arc::new(MemoryReputationStore::default()),
+        )?;
+        let reputation = Arc::new(reputation);
+        let gate = ReputationGate::new(reputation.clone(), bans);
+
+        let reserved = Arc::new(ReservedPeers::new());
+        for addr in &config.reserved_peers {
+            if let Some(peer_id) = peer_id_of(addr) {
+                reserved.add(peer_id, vec![addr.clone()]);
+            }
+        }
+        let authorities = Arc::new(AuthorityDiscovery::new(reserved.clone()));
+        let connections = ConnectionManager::new(
+            ConnectionLimits::from_config(&config),
+            reserved.clone(),
+            reputation.clone(),
+        );
+
+        let keypair = identity.keypair();
+        let (transport, relay_client) = if config.enable_nat_traversal {
+            let (relay_transport, relay_client) = relay::client::new(identity.peer_id());
+            let transport = build_relay_transport(keypair, &config, relay_transport)?;
+            (transport, Some(relay_client))
+        } else {
+            (build_transport(keypair, &config)?, None)
+        };
+        let behaviour = ChainBehaviour::new(keypair, &config, gate, connections, relay_client)?;
+        let swarm = Swarm::new(
+            transport,
+            behaviour,
+            identity.peer_id(),
+            libp2p::swarm::Config::with_tokio_executor()
+                .with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT),
+        );
+
+        let (gossip, gossip_commands) = GossipManager::new();
+        let (sync, sync_commands) = SyncManager::new();
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let (sync_response_sender, sync_responses) = mpsc::unbounded_channel();
+        let (inbound_gossip, inbound) = mpsc::unbounded_channel();
+        let (verdicts, gossip_verdicts) = mpsc::unbounded_channel();
+        let (best_block_sender, best_block) = watch::channel(config.local_head);
+
+        let sync_handler =
+            SyncHandler::default().with_max_response_size(config.max_message_size);
+        let peers = Arc::new(
+            PeerManager::new(config.max_peers, config.connection_timeout)
+                .with_reputation(reputation.clone()),
+        );
+
+        let nat = NatTraversal::new(&config.relay_nodes, config.max_relay_reservations);
+        let service = Self {
+            config,
+            swarm,
+            gossip_commands,
+            sync_commands,
+            sync_handler: Arc::new(sync_handler),
+            sync_responses,
+            sync_response_sender,
+            gossip_validator: Some(GossipValidator {
+                handler: GossipHandler::new(),
+                inbound,
+                verdicts,
+            }),
+            inbound_gossip,
+            gossip_verdicts,
+            pending_requests: HashMap::new(),
+            discovery: Discovery::new(peers.clone()),
+            reputation: reputation.clone(),
+            authorities: authorities.clone(),
+            nat,
+            event_sender,
+            best_block,
+            public_key: keypair.public(),
+            peer_fork_ids: HashMap::new(),
+        };
+        let handle = NetworkHandle {
+            gossip,
+            sync,
+            events,
+            peers,
+            reputation,
+            reserved,
+            authorities,
+            local_peer_id: identity.peer_id(),
+            best_block: best_block_sender,
+        };
+
+        Ok((service, handle))
+    }
+
+    /// Answer inbound sync requests with the given handler
+    ///
+    /// Responses are capped at the configured maximum message size.
+    pub fn with_sync_handler(mut self, handler: SyncHandler) -> Self {
+        let max_response_size = handler.max_response_size().min(self.config.max_message_size);
+        self.sync_handler = Arc::new(handler.with_max_response_size(max_response_size));
+        self
+    }
+
+    /// Check inbound gossip with the given handler
+    ///
+    /// Without handlers for a kind of message, such messages are forwarded
+    /// unchecked.
+    pub fn with_gossip_handler(mut self, handler: GossipHandler) -> Self {
+        if let Some(validator) = &mut self.gossip_validator {
+            validator.handler = handler;
+        }
+        self
+    }
+
+    /// Keep reputations and bans in the given store, loading those it holds
+    pub fn with_reputation_store(self, store: Arc<dyn ReputationStore>) -> NetworkResult<Self> {
+        self.reputation.set_store(store)?;
+        Ok(self)
+    }
+
+    /// Run as a validator with the given consensus private key
+    ///
+    /// The node joins the validator topic and publishes its authority record.
+    pub fn with_authority_key(self, private_key: &[u8]) -> NetworkResult<Self> {
+        self.authorities.set_key(private_key)?;
+        Ok(self)
+    }
+
+    /// Get our peer id
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Listen on the configured addresses, subscribe to topics, dial bootstrap
+    /// nodes and seed the DHT with them
+    fn start(&mut self) -> NetworkResult<()> {
+        for addr in self.config.listen_addresses.clone() {
+            self.swarm.listen_on(addr)?;
+        }
+        for addr in self.config.external_addresses.clone() {
+            self.swarm.add_external_address(addr);
+        }
+
+        if self.config.enable_gossip {
+            for topic in [BLOCKS_TOPIC, TRANSACTIONS_TOPIC] {
+                self.subscribe(topic)?;
+            }
+            if self.authorities.local_address().is_some() {
+                self.subscribe(VALIDATORS_TOPIC)?;
+            }
+        }
+
+        for addr in self.config.bootstrap_nodes.clone() {
+            if let Err(e) = self.swarm.dial(addr.clone()) {
+                warn!("Failed to dial bootstrap node {}: {}", addr, e);
+            }
+            // Bootstrap nodes also tell us whether we are reachable
+            if let (Some(autonat), Some(peer_id)) = (
+                self.swarm.behaviour_mut().autonat.as_mut(),
+                peer_id_of(&addr),
+            ) {
+                autonat.add_server(peer_id, Some(addr));
+            }
+        }
+        let kad = &mut self.swarm.behaviour_mut().kad;
+        self.discovery.bootstrap(kad, &self.config.bootstrap_nodes);
+
+        info!("Network service started as {}", self.local_peer_id());
+        Ok(())
+    }
+
+    /// Run the service until the handle is dropped
+    pub async fn run(mut self) -> NetworkResult<()> {
+        self.start()?;
+
+        let period = self.config.random_walk_interval;
+        let start = tokio::time::Instant::now() + period;
+        let mut random_walk = tokio::time::interval_at(start, period);
+        let mut authority_discovery =
+            tokio::time::interval(self.config.authority_discovery_interval);
+        // Scores barely move within a half life, so pruning more often is wasted
+        let half_life = self.reputation.config().decay_half_life;
+        let mut reputation_prune = tokio::time::interval(half_life.max(Duration::from_secs(1)));
+        if let Some(validator) = self.gossip_validator.take() {
+            tokio::spawn(validator.run());
+        }
+
+        loop {
+            tokio::select! {
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event).await,
+                _ = random_walk.tick() => {
+                    self.discovery.random_walk(&mut self.swarm.behaviour_mut().kad);
+                    // Replace relays we lost
+                    self.reserve_relays();
+                }
+                _ = authority_discovery.tick() => self.discover_authorities(),
+                _ = reputation_prune.tick() => {
+                    if let Err(e) = self.reputation.prune() {
+                        warn!("Failed to prune peer reputations: {}", e);
+                    }
+                }
+                Ok(()) = self.best_block.changed() => {
+                    let head = *self.best_block.borrow_and_update();
+                    self.set_local_head(head);
+                }
+                command = self.gossip_commands.recv() => match command {
+                    Some(command) => self.handle_gossip_command(command),
+                    None => break,
+                },
+                command = self.sync_commands.recv() => match command {
+                    Some(command) => self.handle_sync_command(command),
+                    None => break,
+                },
+                Some((peer, channel, response)) = self.sync_responses.recv() => {
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .sync
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        debug!("Sync response to {} dropped, connection closed", peer);
+                    }
+                }
+                Some((gossip, validation)) = self.gossip_verdicts.recv() => {
+                    self.apply_verdict(gossip, validation);
+                }
+            }
+        }
+
+        info!("Network service stopped");
+        Ok(())
+    }
+
+    /// Follow a new best block, announcing a new fork id if it activates a fork
+    fn set_local_head(&mut self, head: BlockNumber) {
+        let previous = self.config.fork_id(self.config.local_head);
+        self.config.local_head = head;
+        if self.config.fork_id(head) == previous {
+            return;
+        }
+        info!("Announcing fork id {:?} at block #{}", self.config.fork_id(head), head);
+
+        // Identify hands its agent version to each new connection, so it is
+        // rebuilt with our current addresses
+        let mut identify = identify_behaviour(self.public_key.clone(), &self.config);
+        for addr in self.swarm.listeners() {
+            identify.on_swarm_event(FromSwarm::NewListenAddr(NewListenAddr {
+                listener_id: ListenerId::next(),
+                addr,
+            }));
+        }
+        for addr in self.swarm.external_addresses() {
+            identify.on_swarm_event(FromSwarm::ExternalAddrConfirmed(ExternalAddrConfirmed {
+                addr,
+            }));
+        }
+        self.swarm.behaviour_mut().identify = identify;
+
+        let incompatible: Vec<PeerId> = self
+            .peer_fork_ids
+            .iter()
+            .filter(|(_, remote)| !self.config.is_compatible_peer(head, remote))
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in incompatible {
+            warn!("Disconnecting {}: fork id no longer compatible", peer_id);
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+    }
+
+    /// Subscribe to a gossip topic
+    fn subscribe(&mut self, topic: &str) -> NetworkResult<()> {
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&gossipsub::IdentTopic::new(topic))
+            .map_err(|e| {
+                NetworkError::Gossip(format!("Failed to subscribe to {}: {}", topic, e))
+            })?;
+        Ok(())
+    }
+
+    /// Record a new reachability, listening through relays while behind NAT
+    fn set_nat_status(&mut self, status: NatStatus) {
+        for listener in self.nat.set_status(status.clone()) {
+            self.swarm.remove_listener(listener);
+        }
+        self.reserve_relays();
+        self.send_event(NetworkEvent::NatStatusChanged(status));
+    }
+
+    /// Listen through known relays, up to the reservation limit
+    fn reserve_relays(&mut self) {
+        for (relay, addr) in self.nat.relays_to_reserve() {
+            match self.swarm.listen_on(addr.clone()) {
+                Ok(listener) => self.nat.listening_through(relay, listener),
+                Err(e) => {
+                    warn!("Failed to listen on {}: {}", addr, e);
+                    self.nat.remove_relay(&relay);
+                }
+            }
+        }
+    }
+
+    /// Forget a closed relay listener and try another relay
+    fn relay_listener_closed(&mut self, listener: ListenerId, failed: bool) {
+        let Some(relay) = self.nat.listener_closed(listener) else {
+            return;
+        };
+        if failed {
+            // Likely not a relay after all, or out of slots
+            debug!("Relay {} refused to relay for us", relay);
+            self.nat.remove_relay(&relay);
+        }
+        self.reserve_relays();
+    }
+
+    /// Publish our authority record and look up the records of the others
+    fn discover_authorities(&mut self) {
+        let mut addresses: Vec<Multiaddr> = self.swarm.external_addresses().cloned().collect();
+        if addresses.is_empty() {
+            addresses = self.swarm.listeners().cloned().collect();
+        }
+        let peer_id = self.local_peer_id();
+        let kad = &mut self.swarm.behaviour_mut().kad;
+        if let Err(e) = self.authorities.publish(kad, peer_id, addresses) {
+            warn!("Failed to publish authority record: {}", e);
+        }
+        self.authorities.lookup(kad);
+    }
+
+    /// Reserve and dial the peer of an authority found in the DHT
+    fn handle_authority_record(&mut self, record: &kad::Record) {
+        let authority = match self.authorities.handle_record(record) {
+            Ok(Some(authority)) => authority,
+            Ok(None) => return,
+            Err(e) => {
+                // Anyone can store records, so the serving peer is not at fault
+                debug!("Ignoring authority record: {}", e);
+                return;
+            }
+        };
+
+        self.send_event(NetworkEvent::AuthorityDiscovered {
+            authority: authority.address,
+            peer_id: authority.peer_id,
+        });
+        if !self.swarm.is_connected(&authority.peer_id) {
+            let opts = DialOpts::peer_id(authority.peer_id)
+                .addresses(authority.addresses)
+                .build();
+            if let Err(e) = self.swarm.dial(opts) {
+                debug!("Failed to dial authority {}: {}", authority.address, e);
+            }
+        }
+    }
+
+    /// Execute a command from the gossip manager
+    fn handle_gossip_command(&mut self, command: GossipCommand) {
+        match command {
+            GossipCommand::Publish { topic, message } => {
+                let result = serde_json::to_vec(&message)
+                    .map_err(NetworkError::Json)
+                    .and_then(|data| {
+                        self.swarm
+                            .behaviour_mut()
+                            .gossipsub
+                            .publish(gossipsub::IdentTopic::new(&topic), data)
+                            .map_err(|e| NetworkError::Gossip(e.to_string()))
+                    });
+                if let Err(e) = result {
+                    warn!("Failed to publish to {}: {}", topic, e);
+                }
+            }
+            GossipCommand::Subscribe(topic) => {
+                if let Err(e) = self.subscribe(&topic) {
+                    warn!("{}", e);
+                }
+            }
+            GossipCommand::Unsubscribe(topic) => {
+                self.swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .unsubscribe(&gossipsub::IdentTopic::new(topic));
+            }
+            GossipCommand::ListPeers { topic, response } => {
+                let hash = gossipsub::IdentTopic::new(topic).hash();
+                let peers = self
+                    .swarm
+                    .behaviour()
+                    .gossipsub
+                    .all_peers()
+                    .filter(|(_, topics)| topics.contains(&&hash))
+                    .map(|(peer_id, _)| *peer_id)
+                    .collect();
+                let _ = response.send(peers);
+            }
+        }
+    }
+
+    /// Execute a command from the sync manager
+    fn handle_sync_command(&mut self, command: SyncCommand) {
+        match command {
+            SyncCommand::SendRequest {
+                peer_id,
+                request,
+                response_sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_request(&peer_id, request);
+                self.pending_requests.insert(request_id, response_sender);
+            }
+            SyncCommand::HandleRequest {
+                peer_id,
+                request,
+                response_sender,
+            } => {
+                let handler = self.sync_handler.clone();
+                tokio::task::spawn_blocking(move || {
+                    let _ = response_sender.send(handler.serve(request, peer_id));
+                });
+            }
+        }
+    }
+
+    /// React to a swarm event
+    async fn handle_swarm_event(&mut self, event: SwarmEvent<ChainBehaviourEvent>) {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Listening on {}", address);
+                if is_relayed(&address) {
+                    // Let peers learn the relayed address through identify
+                    self.swarm.add_external_address(address.clone());
+                }
+                self.send_event(NetworkEvent::Listening(address));
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established.get() == 1 {
+                    debug!("Connected to {}", peer_id);
+                    self.send_event(NetworkEvent::PeerConnected(peer_id));
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established == 0 {
+                    debug!("Disconnected from {}", peer_id);
+                    self.peer_fork_ids.remove(&peer_id);
+                    self.send_event(NetworkEvent::PeerDisconnected(peer_id));
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Gossipsub(event)) => {
+                self.handle_gossip_event(event)
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } if is_relayed(&address) => {
+                self.swarm.remove_external_address(&address);
+            }
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                reason,
+                ..
+            } => self.relay_listener_closed(listener_id, reason.is_err()),
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+                ..
+            })) => {
+                if let Some(remote) = parse_fork_agent_version(&info.agent_version) {
+                    if !self
+                        .config
+                        .is_compatible_peer(self.config.local_head, &remote)
+                    {
+                        warn!("Disconnecting {}: incompatible fork id {:?}", peer_id, remote);
+                        let _ = self.swarm.disconnect_peer_id(peer_id);
+                        return;
+                    }
+                    self.peer_fork_ids.insert(peer_id, remote);
+                }
+                if info.protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+                    let direct = info.listen_addrs.iter().find(|addr| !is_relayed(addr));
+                    if let Some(addr) = direct {
+                        self.nat.add_relay(peer_id, addr.clone());
+                        self.reserve_relays();
+                    }
+                }
+                for addr in info.listen_addrs {
+                    self.swarm.behaviour_mut().kad.add_address(&peer_id, addr);
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Kad(event)) => {
+                if let kad::Event::OutboundQueryProgressed {
+                    result: kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(found))),
+                    ..
+                } = &event
+                {
+                    self.handle_authority_record(&found.record);
+                }
+                for peer_id in self.discovery.handle_kad_event(event).await {
+                    self.send_event(NetworkEvent::PeerDiscovered(peer_id));
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Mdns(event)) => {
+                let kad = &mut self.swarm.behaviour_mut().kad;
+                for (peer_id, addr) in self.discovery.handle_mdns_event(event, kad).await {
+                    if self.swarm.is_connected(&peer_id) {
+                        continue;
+                    }
+                    self.send_event(NetworkEvent::PeerDiscovered(peer_id));
+                    if let Err(e) = self.swarm.dial(addr.clone()) {
+                        debug!("Failed to dial {} at {}: {}", peer_id, addr, e);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Autonat(
+                autonat::Event::StatusChanged { old, new },
+            )) => {
+                info!("NAT status changed from {:?} to {:?}", old, new);
+                self.set_nat_status(new);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::RelayClient(
+                relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    ..
+                },
+            )) => {
+                self.nat.record_reservation();
+                if !renewal {
+                    info!("Relay {} accepted our reservation", relay_peer_id);
+                    self.send_event(NetworkEvent::RelayReserved(relay_peer_id));
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            })) => {
+                self.nat.record_hole_punch(result.is_ok());
+                match result {
+                    Ok(_) => {
+                        debug!("Hole punched a direct connection to {}", remote_peer_id);
+                        self.send_event(NetworkEvent::HolePunched(remote_peer_id));
+                    }
+                    Err(e) => debug!("Hole punching to {} failed: {}", remote_peer_id, e),
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Sync(event)) => {
+                self.handle_sync_event(event)
+            }
+            _ => {}
+        }
+    }
+
+    /// Decode inbound gossip and pass it on for validation
+    fn handle_gossip_event(&mut self, event: gossipsub::Event) {
+        match event {
+            gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            } => {
+                let source = message.source;
+                let message = match serde_json::from_slice::<GossipMessage>(&message.data) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("Undecodable gossip from {}: {}", propagation_source, e);
+                        self.report(&propagation_source, Misbehaviour::MalformedMessage);
+                        self.report_validation(
+                            &message_id,
+                            &propagation_source,
+                            GossipValidation::Reject,
+                        );
+                        return;
+                    }
+                };
+
+                match message {
+                    GossipMessage::Consensus(message) => {
+                        let validation = self.handle_consensus(message, source, propagation_source);
+                        self.report_validation(&message_id, &propagation_source, validation);
+                    }
+                    message => {
+                        // The validator stops only with the service
+                        let _ = self.inbound_gossip.send(InboundGossip {
+                            message_id,
+                            peer_id: propagation_source,
+                            message,
+                        });
+                    }
+                }
+            }
+            gossipsub::Event::Subscribed { peer_id, topic } => {
+                self.send_event(NetworkEvent::PeerSubscribed {
+                    peer_id,
+                    topic: topic.into_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Accept consensus messages authored by authorities
+    fn handle_consensus(
+        &mut self,
+        message: ConsensusMessage,
+        source: Option<PeerId>,
+        propagation_source: PeerId,
+    ) -> GossipValidation {
+        let author = source.and_then(|peer_id| {
+            let authority = self.authorities.authority_of(&peer_id)?;
+            Some((peer_id, authority))
+        });
+        let Some((peer_id, authority)) = author else {
+            debug!("Consensus message from non-authority {:?}", source);
+            // Authorities may relay messages of authorities we have not
+            // found yet
+            if self.authorities.authority_of(&propagation_source).is_some() {
+                return GossipValidation::Ignore;
+            }
+            self.report(&propagation_source, Misbehaviour::Spam);
+            return GossipValidation::Reject;
+        };
+
+        self.send_event(NetworkEvent::Consensus {
+            authority,
+            peer_id,
+            message,
+        });
+        GossipValidation::Accept
+    }
+
+    /// Forward validated gossip and surface what was accepted
+    fn apply_verdict(&mut self, gossip: InboundGossip, validation: GossipValidation) {
+        self.report_validation(&gossip.message_id, &gossip.peer_id, validation);
+        if validation != GossipValidation::Accept {
+            return;
+        }
+
+        let peer_id = gossip.peer_id;
+        self.send_event(match gossip.message {
+            GossipMessage::BlockAnnounce(announce) => {
+                NetworkEvent::BlockAnnounce { peer_id, announce }
+            }
+            GossipMessage::CompactBlock(compact) => NetworkEvent::CompactBlock { peer_id, compact },
+            GossipMessage::TransactionPropagate(propagate) => {
+                NetworkEvent::Transactions { peer_id, propagate }
+            }
+            GossipMessage::TransactionAnnounce(announce) => {
+                NetworkEvent::TransactionAnnounce { peer_id, announce }
+            }
+            // Handled by the service without the validator
+            GossipMessage::Consensus(_) => return,
+        });
+    }
+
+    /// Tell gossipsub whether to forward a message
+    fn report_validation(
+        &mut self,
+        message_id: &gossipsub::MessageId,
+        peer_id: &PeerId,
+        validation: GossipValidation,
+    ) {
+        let acceptance = match validation {
+            GossipValidation::Accept => gossipsub::MessageAcceptance::Accept,
+            GossipValidation::Reject => gossipsub::MessageAcceptance::Reject,
+            GossipValidation::Ignore => gossipsub::MessageAcceptance::Ignore,
+        };
+        // Messages expire from the cache if validation takes too long
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(message_id, peer_id, acceptance);
+    }
+
+    /// Answer inbound sync requests and resolve outbound ones
+    fn handle_sync_event(
+        &mut self,
+        event: request_response::Event<SyncRequest, SyncResponse>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    // Answered off the swarm loop, which sends the response
+                    // once it comes back
+                    let handler = self.sync_handler.clone();
+                    let responses = self.sync_response_sender.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let response = handler.serve(request, peer);
+                        let _ = responses.send((peer, channel, response));
+                    });
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(sender) = self.pending_requests.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                if let Some(sender) = self.pending_requests.remove(&request_id) {
+                    let error = match error {
+                        OutboundFailure::Timeout => {
+                            self.report(&peer, Misbehaviour::Timeout);
+                            NetworkError::Timeout
+                        }
+                        error => {
+                            NetworkError::Sync(format!("Request to {} failed: {}", peer, error))
+                        }
+                    };
+                    let _ = sender.send(Err(error));
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Inbound sync request from {} failed: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    /// Charge a peer for misbehaving
+    fn report(&self, peer_id: &PeerId, misbehaviour: Misbehaviour) {
+        if let Err(e) = self.reputation.report(peer_id, misbehaviour) {
+            warn!("Failed to save reputation of {}: {}", peer_id, e);
+        }
+    }
+
+    /// Send event notification
+    fn send_event(&self, event: NetworkEvent) {
+        // The receiver may be gone if the node does not consume network events
+        let _ = self.event_sender.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_announce::{BlockAnnounceHandler, HeaderVerifier};
+    use crate::chain::{ChainReader, MemoryChain, StateEntry};
+    use crate::message::ConsensusMessageKind;
+    use crate::transactions::{GossipPool, MemoryPool, TransactionGossip};
+    use crate::transport::AddressFilter;
+    use chain_core::{Block, BlockHeader, Hash, Transaction};
+    use libp2p::multiaddr::Protocol;
+    use tokio::time::timeout;
+
+    fn local_config() -> NetworkConfig {
+        let mut config = NetworkConfig::new()
+            .with_listen_addresses(vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]);
+        config.gossip_heartbeat = Duration::from_millis(100);
+        config.enable_mdns = false;
+        config
+    }
+
+    /// Wait for the first event matching `f`
+    async fn next_event<T>(
+        handle: &mut NetworkHandle,
+        mut f: impl FnMut(NetworkEvent) -> Option<T>,
+    ) -> T {
+        timeout(Duration::from_secs(20), async {
+            loop {
+                let event = handle.events.recv().await.expect("service stopped");
+                if let Some(value) = f(event) {
+                    return value;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for network event")
+    }
+
+    #[tokio::test]
+    async fn test_two_nodes_exchange_block_announce() {
+        let (service_a, mut handle_a) =
+            NetworkService::new(local_config(), &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_a.run());
+
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let config_b = local_config().with_bootstrap_nodes(vec![addr_a]);
+        let (service_b, mut handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_b.run());
+
+        // Wait until B has seen A join the blocks topic
+        let peer_a = handle_a.local_peer_id;
+        next_event(&mut handle_b, |event| match event {
+            NetworkEvent::PeerSubscribed { peer_id, topic }
+                if peer_id == peer_a && topic == BLOCKS_TOPIC =>
+            {
+                Some(())
+            }
+            _ => None,
+        })
+        .await;
+
+        let header = BlockHeader {
+            parent_hash: Hash::zero(),
+            number: 42,
+            ..BlockHeader::genesis()
+        };
+        handle_b
+            .gossip
+            .announce_block(BlockAnnounce::new(header.clone()))
+            .await
+            .unwrap();
+
+        let peer_b = handle_b.local_peer_id;
+        let (source, announce) = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::BlockAnnounce { peer_id, announce } => Some((peer_id, announce)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(source, peer_b);
+        assert_eq!(announce.block_number(), 42);
+        assert_eq!(announce.block_hash(), header.hash().unwrap());
+
+        // Sync requests are answered by the remote handler
+        let headers = handle_b
+            .sync
+            .request_headers(peer_a, Hash::zero(), 10)
+            .await
+            .unwrap();
+        assert!(headers.is_empty());
+
+        // Requests to unreachable peers fail instead of hanging
+        let result = handle_b
+            .sync
+            .request_headers(PeerId::random(), Hash::zero(), 10)
+            .await;
+        assert!(result.is_err());
+    }
+
+    /// Start A with a gossip handler built from its handle, and B and C
+    /// connected only to A and serving `chain`, once both see A on `topic`
+    async fn star_network(
+        handler: impl FnOnce(&NetworkHandle) -> GossipHandler,
+        topic: &str,
+        chain: Arc<MemoryChain>,
+    ) -> (NetworkHandle, NetworkHandle, NetworkHandle) {
+        let (service_a, mut handle_a) =
+            NetworkService::new(local_config(), &PeerIdentity::generate()).unwrap();
+        let service_a = service_a.with_gossip_handler(handler(&handle_a));
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let leaf_config = || {
+            let mut config = local_config().with_bootstrap_nodes(vec![addr_a.clone()]);
+            config.max_inbound_peers = 0;
+            config.max_outbound_peers = 1;
+            config
+        };
+        let leaf = || {
+            let (service, handle) =
+                NetworkService::new(leaf_config(), &PeerIdentity::generate()).unwrap();
+            tokio::spawn(
+                service
+                    .with_sync_handler(SyncHandler::new(chain.clone()))
+                    .run(),
+            );
+            handle
+        };
+        let (mut handle_b, mut handle_c) = (leaf(), leaf());
+
+        let peer_a = handle_a.local_peer_id;
+        for handle in [&mut handle_b, &mut handle_c] {
+            next_event(handle, |event| match event {
+                NetworkEvent::PeerSubscribed {
+                    peer_id,
+                    topic: subscribed,
+                } if peer_id == peer_a && subscribed == topic => Some(()),
+                _ => None,
+            })
+            .await;
+        }
+        // Give A a few heartbeats to add B and C to its mesh
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        (handle_a, handle_b, handle_c)
+    }
+
+    #[tokio::test]
+    async fn test_only_accepted_gossip_forwarded() {
+        let pool = Arc::new(MemoryPool::new(100));
+        let (handle_a, handle_b, mut handle_c) = star_network(
+            |handle| {
+                let transactions = TransactionGossip::new(
+                    pool.clone(),
+                    handle.gossip.clone(),
+                    handle.sync.clone(),
+                    handle.reputation.clone(),
+                );
+                GossipHandler::new().with_transaction_gossip(transactions)
+            },
+            TRANSACTIONS_TOPIC,
+            Arc::new(MemoryChain::new()),
+        )
+        .await;
+
+        let unsigned = Transaction::new(0, 1, 21_000, None, 0, vec![]);
+        let mut valid = Transaction::new(0, 1, 21_000, None, 0, vec![]);
+        valid.sign(&[7u8; 32]).unwrap();
+        for transaction in [unsigned, valid.clone()] {
+            handle_b
+                .gossip
+                .propagate_transactions(TransactionPropagate::new(vec![transaction]))
+                .await
+                .unwrap();
+        }
+
+        // The invalid transaction stops at A
+        let propagate = next_event(&mut handle_c, |event| match event {
+            NetworkEvent::Transactions { propagate, .. } => Some(propagate),
+            _ => None,
+        })
+        .await;
+        assert_eq!(propagate.transactions, vec![valid.clone()]);
+        assert!(pool.contains(&valid.hash().unwrap()));
+        assert!(handle_a.reputation.score(&handle_b.local_peer_id) < 0.0);
+    }
+
+    /// Accepts headers of the first few blocks
+    struct LowBlocks;
+
+    impl HeaderVerifier for LowBlocks {
+        fn verify_header(&self, header: &BlockHeader) -> NetworkResult<()> {
+            if header.number < 10 {
+                Ok(())
+            } else {
+                Err(NetworkError::InvalidBlock("Too high".to_string()))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announced_blocks_checked_before_forwarding() {
+        let mut queue = None;
+        let (handle_a, handle_b, mut handle_c) = star_network(
+            |handle| {
+                let (blocks, queued) = BlockAnnounceHandler::new(
+                    Arc::new(LowBlocks),
+                    handle.sync.clone(),
+                    handle.reputation.clone(),
+                );
+                queue = Some(queued);
+                GossipHandler::new().with_block_announces(blocks)
+            },
+            BLOCKS_TOPIC,
+            Arc::new(MemoryChain::new()),
+        )
+        .await;
+        let mut queue = queue.unwrap();
+
+        let block_at = |number| {
+            let mut block = Block::new(BlockHeader::genesis(), vec![]);
+            block.header.number = number;
+            block
+        };
+        for block in [block_at(100), block_at(1)] {
+            let announce = BlockAnnounce::new(block.header.clone()).with_block(block);
+            handle_b.gossip.announce_block(announce).await.unwrap();
+        }
+
+        // Only the valid block is queued for import and forwarded
+        let queued = timeout(Duration::from_secs(20), queue.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(queued.block.header.number, 1);
+        assert_eq!(queued.peer_id, handle_b.local_peer_id);
+        let announce = next_event(&mut handle_c, |event| match event {
+            NetworkEvent::BlockAnnounce { announce, .. } => Some(announce),
+            _ => None,
+        })
+        .await;
+        assert_eq!(announce.block_number(), 1);
+        assert!(queue.try_recv().is_err());
+        assert!(handle_a.reputation.score(&handle_b.local_peer_id) < 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_block_gossip_deduplicated_by_block() {
+        let (_handle_a, handle_b, mut handle_c) = star_network(
+            |_| GossipHandler::new(),
+            BLOCKS_TOPIC,
+            Arc::new(MemoryChain::new()),
+        )
+        .await;
+
+        let block = Block::new(BlockHeader::genesis(), vec![]);
+        let mut next = BlockHeader::genesis();
+        next.number = 1;
+        let announce = BlockAnnounce::new(block.header.clone());
+        handle_b
+            .gossip
+            .announce_block(announce.clone())
+            .await
+            .unwrap();
+        handle_b
+            .gossip
+            .announce_block(announce.with_block(block.clone()))
+            .await
+            .unwrap();
+        handle_b
+            .gossip
+            .announce_compact_block(CompactBlock::from_block(&block).unwrap())
+            .await
+            .unwrap();
+        handle_b
+            .gossip
+            .announce_block(BlockAnnounce::new(next))
+            .await
+            .unwrap();
+
+        // The header-only announcement does not hold back the block, while
+        // the compact block adds nothing once the block was seen
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            let event = next_event(&mut handle_c, |event| match event {
+                NetworkEvent::BlockAnnounce { announce, .. } => {
+                    Some((announce.block_number(), announce.block.is_some()))
+                }
+                NetworkEvent::CompactBlock { .. } => panic!("Compact block forwarded"),
+                _ => None,
+            })
+            .await;
+            received.push(event);
+        }
+        assert_eq!(received, vec![(0, false), (0, true), (1, false)]);
+    }
+
+    #[tokio::test]
+    async fn test_compact_block_rebuilt_and_forwarded() {
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|nonce| Transaction::new(nonce, 1, 21_000, None, 0, vec![]))
+            .collect();
+        let mut block = Block::new(BlockHeader::genesis(), transactions);
+        block.header.number = 1;
+        block.header.transactions_root = block.calculate_transactions_root().unwrap();
+        let chain = Arc::new(MemoryChain::new());
+        chain.insert_block(block.clone()).unwrap();
+
+        // A holds one of the transactions and fetches the others from B
+        let pool = Arc::new(MemoryPool::new(10));
+        pool.import(block.transactions[1].clone()).unwrap();
+        let mut queue = None;
+        let (_handle_a, handle_b, mut handle_c) = star_network(
+            |handle| {
+                let (blocks, queued) = BlockAnnounceHandler::new(
+                    Arc::new(LowBlocks),
+                    handle.sync.clone(),
+                    handle.reputation.clone(),
+                );
+                queue = Some(queued);
+                GossipHandler::new().with_block_announces(blocks.with_pool(pool.clone()))
+            },
+            BLOCKS_TOPIC,
+            chain,
+        )
+        .await;
+        let mut queue = queue.unwrap();
+
+        handle_b
+            .gossip
+            .announce_compact_block(CompactBlock::from_block(&block).unwrap())
+            .await
+            .unwrap();
+
+        let queued = timeout(Duration::from_secs(20), queue.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(queued.block, block);
+        assert_eq!(queued.peer_id, handle_b.local_peer_id);
+        let compact = next_event(&mut handle_c, |event| match event {
+            NetworkEvent::CompactBlock { compact, .. } => Some(compact),
+            _ => None,
+        })
+        .await;
+        assert_eq!(compact.block_hash().unwrap(), block.hash().unwrap());
+    }
+
+    /// Chain whose bodies take a while to read
+    struct SlowChain(MemoryChain);
+
+    impl ChainReader for SlowChain {
+        fn header(&self, hash: &Hash) -> NetworkResult<Option<BlockHeader>> {
+            self.0.header(hash)
+        }
+
+        fn hash_at(&self, number: u64) -> NetworkResult<Option<Hash>> {
+            self.0.hash_at(number)
+        }
+
+        fn body(&self, hash: &Hash) -> NetworkResult<Option<Vec<chain_core::Transaction>>> {
+            std::thread::sleep(Duration::from_secs(3));
+            self.0.body(hash)
+        }
+
+        fn receipts(&self, hash: &Hash) -> NetworkResult<Option<Vec<u8>>> {
+            self.0.receipts(hash)
+        }
+
+        fn state_entries(
+            &self,
+            root: &Hash,
+            prefix: &[u8],
+            start: &[u8],
+            limit: usize,
+        ) -> NetworkResult<Option<Vec<StateEntry>>> {
+            self.0.state_entries(root, prefix, start, limit)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_slow_sync_requests_do_not_block_the_service() {
+        let chain = Arc::new(SlowChain(MemoryChain::new()));
+        let genesis = chain.0.insert_header(BlockHeader::genesis()).unwrap();
+        let (service_a, mut handle_a) =
+            NetworkService::new(local_config(), &PeerIdentity::generate()).unwrap();
+        let service_a = service_a.with_sync_handler(SyncHandler::new(chain));
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let config_b = local_config().with_bootstrap_nodes(vec![addr_a]);
+        let (service_b, handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // A headers request is answered while a bodies request is still read
+        let peer_a = handle_a.local_peer_id;
+        let sync = handle_b.sync.clone();
+        let bodies =
+            tokio::spawn(async move { sync.request_bodies(peer_a, vec![genesis]).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let headers = timeout(
+            Duration::from_secs(2),
+            handle_b.sync.request_headers(peer_a, genesis, 1),
+        )
+        .await
+        .expect("headers request waited for the bodies request")
+        .unwrap();
+        assert_eq!(headers.len(), 1);
+        assert!(!bodies.is_finished());
+        bodies.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peers_discovered_through_dht() {
+        let mut config = local_config();
+        config.random_walk_interval = Duration::from_millis(500);
+
+        let (service_a, mut handle_a) =
+            NetworkService::new(config.clone(), &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await
+        .with(Protocol::P2p(handle_a.local_peer_id));
+
+        // B and C only know A; C learns about B from A's routing table
+        let config_b = config.clone().with_bootstrap_nodes(vec![addr_a.clone()]);
+        let (service_b, _handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerDiscovered(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        let config_c = config.with_bootstrap_nodes(vec![addr_a]);
+        let (service_c, mut handle_c) =
+            NetworkService::new(config_c, &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_c.run());
+        next_event(&mut handle_c, |event| match event {
+            NetworkEvent::PeerDiscovered(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        assert!(handle_c.peers.get_peer(&peer_b).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_banned_peer_disconnected_and_refused() {
+        let (service_a, mut handle_a) =
+            NetworkService::new(local_config(), &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let config_b = local_config().with_bootstrap_nodes(vec![addr_a.clone()]);
+        let (service_b, handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        handle_a.reputation.ban_peer(&peer_b, None).unwrap();
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerDisconnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // A peer that redials is refused during the handshake
+        let result = handle_b
+            .sync
+            .request_headers(handle_a.local_peer_id, Hash::zero(), 1)
+            .await;
+        assert!(result.is_err());
+        let reconnected = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        });
+        assert!(timeout(Duration::from_secs(1), reconnected).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peer_on_other_fork_disconnected() {
+        use chain_core::{Fork, ForkSchedule};
+
+        // A has applied an upgrade that B does not know about
+        let forks = ForkSchedule::new(vec![Fork::new("upgrade", 100)]).unwrap();
+        let config_a = local_config().with_fork_schedule(forks).with_local_head(150);
+        let (service_a, mut handle_a) =
+            NetworkService::new(config_a, &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let config_b = local_config()
+            .with_bootstrap_nodes(vec![addr_a])
+            .with_local_head(150);
+        let (service_b, _handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerDisconnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_fork_id_follows_best_block() {
+        use chain_core::{Fork, ForkSchedule};
+
+        // Both nodes are before the upgrade, which only A knows about
+        let forks = ForkSchedule::new(vec![Fork::new("upgrade", 100)]).unwrap();
+        let config_a = local_config().with_fork_schedule(forks).with_local_head(50);
+        let (service_a, mut handle_a) =
+            NetworkService::new(config_a, &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let config_b = local_config().with_bootstrap_nodes(vec![addr_a]);
+        let (service_b, _handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // Give identify time to run, B must stay connected before the fork
+        let early = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerDisconnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        });
+        assert!(timeout(Duration::from_secs(1), early).await.is_err());
+
+        // Once A imports the fork block, B no longer follows its rules
+        handle_a.set_best_block(100);
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerDisconnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_validators_discover_each_other() {
+        let mut config = local_config();
+        config.authority_discovery_interval = Duration::from_millis(500);
+        let keys = [[1u8; 32], [2u8; 32]];
+
+        let (service_a, mut handle_a) =
+            NetworkService::new(config.clone(), &PeerIdentity::generate()).unwrap();
+        let service_a = service_a.with_authority_key(&keys[0]).unwrap();
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) => Some(addr),
+            _ => None,
+        })
+        .await
+        .with(Protocol::P2p(handle_a.local_peer_id));
+
+        let config_b = config.with_bootstrap_nodes(vec![addr_a]);
+        let (service_b, mut handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let service_b = service_b.with_authority_key(&keys[1]).unwrap();
+        tokio::spawn(service_b.run());
+
+        let authority_a = handle_a.authorities.local_address().unwrap();
+        let authority_b = handle_b.authorities.local_address().unwrap();
+        for handle in [&handle_a, &handle_b] {
+            handle
+                .authorities
+                .set_authorities([authority_a, authority_b]);
+        }
+
+        // Each validator finds the other's record and reserves its peer
+        let peer_a = handle_a.local_peer_id;
+        next_event(&mut handle_b, |event| match event {
+            NetworkEvent::AuthorityDiscovered { authority, peer_id }
+                if authority == authority_a && peer_id == peer_a =>
+            {
+                Some(())
+            }
+            _ => None,
+        })
+        .await;
+        assert!(handle_b.reserved.contains(&peer_a));
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerSubscribed { peer_id, topic }
+                if peer_id == handle_b.local_peer_id && topic == VALIDATORS_TOPIC =>
+            {
+                Some(())
+            }
+            _ => None,
+        })
+        .await;
+
+        let message = ConsensusMessage::new(ConsensusMessageKind::Vote, vec![1, 2, 3]);
+        handle_a
+            .gossip
+            .publish_consensus(message.clone())
+            .await
+            .unwrap();
+        let received = next_event(&mut handle_b, |event| match event {
+            NetworkEvent::Consensus {
+                authority,
+                peer_id,
+                message,
+            } => Some((authority, peer_id, message)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(received, (authority_a, peer_a, message));
+    }
+
+    #[tokio::test]
+    async fn test_private_node_reachable_through_relay() {
+        // Relays only accept reservations once they know their external address
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+        let mut config_relay = local_config()
+            .with_listen_addresses(vec![addr.clone()])
+            .with_external_addresses(vec![addr.clone()]);
+        config_relay.enable_relay_server = true;
+        let (service_relay, mut handle_relay) =
+            NetworkService::new(config_relay, &PeerIdentity::generate()).unwrap();
+        let peer_relay = service_relay.local_peer_id();
+        tokio::spawn(service_relay.run());
+        next_event(&mut handle_relay, |event| match event {
+            NetworkEvent::Listening(_) => Some(()),
+            _ => None,
+        })
+        .await;
+        let addr_relay = addr.with(Protocol::P2p(peer_relay));
+
+        // A only listens through the relay once AutoNAT finds it private
+        let config_a = local_config().with_relay_nodes(vec![addr_relay.clone()]);
+        let (mut service_a, mut handle_a) =
+            NetworkService::new(config_a, &PeerIdentity::generate()).unwrap();
+        let peer_a = service_a.local_peer_id();
+        service_a.set_nat_status(NatStatus::Private);
+        tokio::spawn(service_a.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::RelayReserved(relay) if relay == peer_relay => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // B reaches A through its circuit address
+        let circuit = addr_relay
+            .with(Protocol::P2pCircuit)
+            .with(Protocol::P2p(peer_a));
+        let config_b = local_config().with_bootstrap_nodes(vec![circuit]);
+        let (service_b, _handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_nodes_connect_over_quic() {
+        let quic_config = || {
+            local_config().with_listen_addresses(vec![
+                "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+                "/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap(),
+            ])
+        };
+        let (service_a, mut handle_a) =
+            NetworkService::new(quic_config(), &PeerIdentity::generate()).unwrap();
+        tokio::spawn(service_a.run());
+        let addr_a = next_event(&mut handle_a, |event| match event {
+            NetworkEvent::Listening(addr) if AddressFilter::is_quic_address(&addr) => Some(addr),
+            _ => None,
+        })
+        .await;
+
+        let config_b = quic_config().with_bootstrap_nodes(vec![addr_a]);
+        let (service_b, handle_b) =
+            NetworkService::new(config_b, &PeerIdentity::generate()).unwrap();
+        let peer_b = service_b.local_peer_id();
+        tokio::spawn(service_b.run());
+        next_event(&mut handle_a, |event| match event {
+            NetworkEvent::PeerConnected(peer_id) if peer_id == peer_b => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // Sync requests run over the QUIC connection
+        let headers = handle_b
+            .sync
+            .request_headers(handle_a.local_peer_id, Hash::zero(), 10)
+            .await
+            .unwrap();
+        assert!(headers.is_empty());
+    }
+}
@@ -1,12 +1,16 @@
 //! Blockchain synchronization protocols
-use crate::message::{SyncRequest, SyncResponse};
+use crate::chain::{ChainReader, MemoryChain};
+use crate::codec::encoded_len;
+use crate::message::{limits, SyncRequest, SyncResponse};
+use crate::transactions::GossipPool;
 use crate::{NetworkError, NetworkResult};
-use chain_core::{BlockHeader, Hash};
+use chain_core::{BlockHeader, Hash, RangeProof, StateEntry, Transaction};
 use libp2p::PeerId;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
 /// Sync manager handles blockchain synchronization
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SyncManager {
     /// Channel for sending sync commands
     tx: mpsc::UnboundedSender<SyncCommand>,
@@ -78,8 +82,139 @@ impl SyncManager {
         }
     }
 
-    /// Send a sync request to a peer
-    async fn send_request(
+    /// Request receipts from a peer
+    pub async fn request_receipts(
+        &self,
+        peer_id: PeerId,
+        hashes: Vec<Hash>,
+    ) -> NetworkResult<Vec<Vec<u8>>> {
+        let request = SyncRequest::receipts(hashes);
+        let response = self.send_request(peer_id, request).await?;
+
+        match response {
+            SyncResponse::Receipts { receipts } => Ok(receipts),
+            SyncResponse::Error { message } => Err(NetworkError::Sync(format!(
+                "Receipts request failed: {}",
+                message
+            ))),
+            _ => Err(NetworkError::Sync(
+                "Unexpected response type for receipts request".to_string(),
+            )),
+        }
+    }
+
+    /// Request a page of state entries under `prefix` from `start` from a peer
+    ///
+    /// Returns the entries and the key to request the next page from, if
+    /// entries remain.
+    pub async fn request_state_snapshot(
+        &self,
+        peer_id: PeerId,
+        root: Hash,
+        prefix: Vec<u8>,
+        start: Vec<u8>,
+        limit: u32,
+    ) -> NetworkResult<(Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>)> {
+        let request = SyncRequest::state_snapshot(root, prefix, start, limit);
+        let response = self.send_request(peer_id, request).await?;
+
+        match response {
+            SyncResponse::StateSnapshot { entries, next } => Ok((entries, next)),
+            SyncResponse::Error { message } => Err(NetworkError::Sync(format!(
+                "State snapshot request failed: {}",
+                message
+            ))),
+            _ => Err(NetworkError::Sync(
+                "Unexpected response type for state snapshot request".to_string(),
+            )),
+        }
+    }
+
+    /// Request a range of state entries with a Merkle proof from a peer
+    ///
+    /// The proof is returned unchecked; callers verify it against `root`.
+    pub async fn request_state_range(
+        &self,
+        peer_id: PeerId,
+        root: Hash,
+        start: Vec<u8>,
+        limit: u32,
+    ) -> NetworkResult<(Vec<StateEntry>, RangeProof)> {
+        let request = SyncRequest::state_range(root, start, limit);
+        let response = self.send_request(peer_id, request).await?;
+
+        match response {
+            SyncResponse::StateRange { entries, proof } => Ok((entries, proof)),
+            SyncResponse::Error { message } => Err(NetworkError::Sync(format!(
+                "State range request failed: {}",
+                message
+            ))),
+            _ => Err(NetworkError::Sync(
+                "Unexpected response type for state range request".to_string(),
+            )),
+        }
+    }
+
+    /// Request the transactions at the given positions of a block
+    ///
+    /// Used to complete a [`PartialBlock`](crate::compact::PartialBlock).
+    pub async fn request_block_transactions(
+        &self,
+        peer_id: PeerId,
+        block_hash: Hash,
+        indices: Vec<u32>,
+    ) -> NetworkResult<Vec<Transaction>> {
+        let expected = indices.len();
+        let request = SyncRequest::block_transactions(block_hash, indices);
+        let response = self.send_request(peer_id, request).await?;
+
+        match response {
+            SyncResponse::BlockTransactions { transactions } if transactions.len() == expected => {
+                Ok(transactions)
+            }
+            SyncResponse::BlockTransactions { transactions } => Err(NetworkError::Sync(format!(
+                "Expected {} block transactions, got {}",
+                expected,
+                transactions.len()
+            ))),
+            SyncResponse::Error { message } => Err(NetworkError::Sync(format!(
+                "Block transactions request failed: {}",
+                message
+            ))),
+            _ => Err(NetworkError::Sync(
+                "Unexpected response type for block transactions request".to_string(),
+            )),
+        }
+    }
+
+    /// Request pending transactions by hash from a peer
+    ///
+    /// The peer returns the ones it knows; callers check the hashes.
+    pub async fn request_transactions(
+        &self,
+        peer_id: PeerId,
+        hashes: Vec<Hash>,
+    ) -> NetworkResult<Vec<Transaction>> {
+        let request = SyncRequest::transactions(hashes);
+        let response = self.send_request(peer_id, request).await?;
+
+        match response {
+            SyncResponse::Transactions { transactions } => Ok(transactions),
+            SyncResponse::Error { message } => Err(NetworkError::Sync(format!(
+                "Transactions request failed: {}",
+                message
+            ))),
+            _ => Err(NetworkError::Sync(
+                "Unexpected response type for transactions request".to_string(),
+            )),
+        }
+    }
+
+    /// Send a sync request to a peer and wait for its response
+    ///
+    /// Fails with [`NetworkError::Timeout`] if the peer does not answer
+    /// within the configured sync request timeout.
+    pub async fn send_request(
         &self,
         peer_id: PeerId,
         request: SyncRequest,
@@ -129,83 +264,328 @@ impl Default for SyncManager {
     }
 }
 
-/// Sync request handler
+/// Bytes reserved in every response for framing around the served items
+const RESPONSE_OVERHEAD: usize = 64;
+
+/// Sync request handler serving data from the local chain
 pub struct SyncHandler {
-    // Placeholder for blockchain access
-    // In a real implementation, this would have access to the blockchain storage
+    /// Chain the responses are read from
+    chain: Arc<dyn ChainReader>,
+    /// Pool pending transactions are served from
+    pool: Option<Arc<dyn GossipPool>>,
+    /// Maximum encoded response size in bytes
+    max_response_size: usize,
 }
 
 impl SyncHandler {
-    /// Create a new sync handler
-    pub fn new() -> Self {
-        Self {}
+    /// Create a sync handler serving from a chain
+    pub fn new(chain: Arc<dyn ChainReader>) -> Self {
+        Self {
+            chain,
+            pool: None,
+            max_response_size: limits::MAX_SYNC_RESPONSE_SIZE,
+        }
+    }
+
+    /// Serve pending transactions from a pool
+    pub fn with_transaction_pool(mut self, pool: Arc<dyn GossipPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Limit the encoded size of responses
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// Get the maximum encoded response size in bytes
+    pub fn max_response_size(&self) -> usize {
+        self.max_response_size
     }
 
     /// Handle an incoming sync request
-    pub async fn handle_request(&self, request: SyncRequest, _peer_id: PeerId) -> SyncResponse {
-        match request {
+    pub async fn handle_request(&self, request: SyncRequest, peer_id: PeerId) -> SyncResponse {
+        self.serve(request, peer_id)
+    }
+
+    /// Answer a sync request, reading from the chain on the calling thread
+    ///
+    /// Chain reads may block, so callers on an async runtime should run this
+    /// on a blocking task.
+    pub fn serve(&self, request: SyncRequest, peer_id: PeerId) -> SyncResponse {
+        let result = match request {
             SyncRequest::GetHeaders {
                 start,
                 amount,
                 skip,
                 reverse,
-            } => self.handle_get_headers(start, amount, skip, reverse).await,
-            SyncRequest::GetBodies { hashes } => self.handle_get_bodies(hashes).await,
-            SyncRequest::GetReceipts { hashes } => self.handle_get_receipts(hashes).await,
+            } => self.handle_get_headers(start, amount, skip, reverse),
+            SyncRequest::GetBodies { hashes } => self.handle_get_bodies(hashes),
+            SyncRequest::GetReceipts { hashes } => self.handle_get_receipts(hashes),
             SyncRequest::GetStateSnapshot {
                 root,
                 prefix,
+                start,
                 limit,
-            } => self.handle_get_state_snapshot(root, prefix, limit).await,
-        }
+            } => self.handle_get_state_snapshot(root, prefix, start, limit),
+            SyncRequest::GetStateRange { root, start, limit } => {
+                self.handle_get_state_range(root, start, limit)
+            }
+            SyncRequest::GetBlockTransactions {
+                block_hash,
+                indices,
+            } => self.handle_get_block_transactions(block_hash, indices),
+            SyncRequest::GetTransactions { hashes } => self.handle_get_transactions(hashes),
+        };
+
+        result.unwrap_or_else(|e| {
+            tracing::warn!("Failed to serve sync request from {}: {}", peer_id, e);
+            SyncResponse::error(e.to_string())
+        })
     }
 
     /// Handle get headers request
-    async fn handle_get_headers(
+    ///
+    /// Serves `start` followed by canonical headers every `skip + 1` blocks,
+    /// stopping at the first missing header. A `start` off the canonical
+    /// chain gets an empty response, as the canonical headers would not
+    /// connect to it.
+    fn handle_get_headers(
         &self,
-        _start: Hash,
-        _amount: u32,
-        _skip: u32,
-        _reverse: bool,
-    ) -> SyncResponse {
-        // TODO: Implement actual header retrieval from blockchain storage
-        tracing::info!("Handling get headers request");
-        SyncResponse::headers(vec![])
+        start: Hash,
+        amount: u32,
+        skip: u32,
+        reverse: bool,
+    ) -> NetworkResult<SyncResponse> {
+        let mut headers = Vec::new();
+        let Some(first) = self.chain.header(&start)? else {
+            return Ok(SyncResponse::headers(headers));
+        };
+        if self.chain.hash_at(first.number)? != Some(start) {
+            return Ok(SyncResponse::headers(headers));
+        }
+
+        let amount = amount.min(limits::MAX_HEADERS_PER_REQUEST);
+        let step = u64::from(skip) + 1;
+        let mut budget = ResponseBudget::new(self.max_response_size);
+        let mut number = first.number;
+        let mut next = Some(first);
+
+        while let Some(header) = next.take() {
+            if headers.len() >= amount as usize || !budget.fits(&header) {
+                break;
+            }
+            headers.push(header);
+
+            let following = if reverse {
+                number.checked_sub(step)
+            } else {
+                number.checked_add(step)
+            };
+            if let Some(following) = following {
+                number = following;
+                next = self.chain.header_at(number)?;
+            }
+        }
+
+        Ok(SyncResponse::headers(headers))
     }
 
     /// Handle get bodies request
-    async fn handle_get_bodies(&self, _hashes: Vec<Hash>) -> SyncResponse {
-        // TODO: Implement actual body retrieval from blockchain storage
-        tracing::info!("Handling get bodies request");
-        SyncResponse::bodies(vec![])
+    ///
+    /// Serves bodies for a prefix of the requested hashes.
+    fn handle_get_bodies(&self, hashes: Vec<Hash>) -> NetworkResult<SyncResponse> {
+        let mut bodies = Vec::new();
+        let mut budget = ResponseBudget::new(self.max_response_size);
+
+        for hash in hashes.iter().take(limits::MAX_BODIES_PER_REQUEST) {
+            match self.chain.body(hash)? {
+                Some(body) if budget.fits(&body) => bodies.push(body),
+                _ => break,
+            }
+        }
+
+        Ok(SyncResponse::bodies(bodies))
     }
 
     /// Handle get receipts request
-    async fn handle_get_receipts(&self, _hashes: Vec<Hash>) -> SyncResponse {
-        // TODO: Implement actual receipt retrieval from blockchain storage
-        tracing::info!("Handling get receipts request");
-        SyncResponse::Receipts { receipts: vec![] }
+    ///
+    /// Serves receipts for a prefix of the requested hashes.
+    fn handle_get_receipts(&self, hashes: Vec<Hash>) -> NetworkResult<SyncResponse> {
+        let mut receipts = Vec::new();
+        let mut budget = ResponseBudget::new(self.max_response_size);
+
+        for hash in hashes.iter().take(limits::MAX_BODIES_PER_REQUEST) {
+            match self.chain.receipts(hash)? {
+                Some(block_receipts) if budget.fits(&block_receipts) => {
+                    receipts.push(block_receipts)
+                }
+                _ => break,
+            }
+        }
+
+        Ok(SyncResponse::Receipts { receipts })
     }
 
     /// Handle get state snapshot request
-    async fn handle_get_state_snapshot(
+    ///
+    /// Serves entries under `prefix` in key order from `start`, at most
+    /// [`limits::MAX_STATE_ENTRIES_PER_REQUEST`] at a time. When the limit
+    /// or the response size cut the page short, `next` holds the first key
+    /// left out, for the requester to continue from.
+    fn handle_get_state_snapshot(
         &self,
-        _root: Hash,
-        _prefix: Vec<u8>,
-        _limit: u32,
-    ) -> SyncResponse {
-        // TODO: Implement actual state snapshot retrieval
-        tracing::info!("Handling get state snapshot request");
-        SyncResponse::StateSnapshot {
-            entries: vec![],
-            complete: true,
+        root: Hash,
+        prefix: Vec<u8>,
+        start: Vec<u8>,
+        limit: u32,
+    ) -> NetworkResult<SyncResponse> {
+        let limit = limit.min(limits::MAX_STATE_ENTRIES_PER_REQUEST) as usize;
+        let Some(found) =
+            self.chain
+                .state_entries(&root, &prefix, &start, limit.saturating_add(1))?
+        else {
+            return Ok(SyncResponse::error(format!(
+                "State at root {} is not available",
+                root
+            )));
+        };
+
+        let mut entries = Vec::with_capacity(found.len().min(limit));
+        let mut next = None;
+        let mut budget = ResponseBudget::new(self.max_response_size);
+        for entry in found {
+            if entries.len() >= limit || !budget.fits(&entry) {
+                next = Some(entry.0);
+                break;
+            }
+            entries.push(entry);
+        }
+
+        Ok(SyncResponse::StateSnapshot { entries, next })
+    }
+
+    /// Handle get state range request
+    ///
+    /// Halves the range until the response fits the size limit.
+    fn handle_get_state_range(
+        &self,
+        root: Hash,
+        start: Vec<u8>,
+        limit: u32,
+    ) -> NetworkResult<SyncResponse> {
+        let mut limit = limit.min(limits::MAX_STATE_ENTRIES_PER_REQUEST) as usize;
+        loop {
+            let Some((entries, proof)) = self.chain.state_range(&root, &start, limit)? else {
+                return Ok(SyncResponse::error(format!(
+                    "State at root {} is not available",
+                    root
+                )));
+            };
+
+            let response = SyncResponse::state_range(entries, proof);
+            let fits = encoded_len(&response).is_some_and(|len| len <= self.max_response_size);
+            if fits {
+                return Ok(response);
+            }
+            if limit <= 1 {
+                return Ok(SyncResponse::error(
+                    "State entry exceeds the response size limit".to_string(),
+                ));
+            }
+            limit /= 2;
         }
     }
+
+    /// Handle get block transactions request
+    ///
+    /// Serves every requested transaction or none, since a compact block
+    /// cannot be completed from a partial answer.
+    fn handle_get_block_transactions(
+        &self,
+        block_hash: Hash,
+        indices: Vec<u32>,
+    ) -> NetworkResult<SyncResponse> {
+        let Some(body) = self.chain.body(&block_hash)? else {
+            return Ok(SyncResponse::error(format!(
+                "Block {} is not available",
+                block_hash
+            )));
+        };
+
+        let mut transactions = Vec::with_capacity(indices.len());
+        let mut budget = ResponseBudget::new(self.max_response_size);
+        for index in indices {
+            let Some(tx) = body.get(index as usize) else {
+                return Ok(SyncResponse::error(format!(
+                    "Block {} has no transaction at index {}",
+                    block_hash, index
+                )));
+            };
+            if !budget.fits(tx) {
+                return Ok(SyncResponse::error(
+                    "Requested transactions exceed the response size limit".to_string(),
+                ));
+            }
+            transactions.push(tx.clone());
+        }
+
+        Ok(SyncResponse::block_transactions(transactions))
+    }
+
+    /// Handle get transactions request
+    ///
+    /// Serves the requested transactions that are in the pool.
+    fn handle_get_transactions(&self, hashes: Vec<Hash>) -> NetworkResult<SyncResponse> {
+        let Some(pool) = &self.pool else {
+            return Ok(SyncResponse::error(
+                "Pending transactions are not served".to_string(),
+            ));
+        };
+
+        let mut transactions = Vec::new();
+        let mut budget = ResponseBudget::new(self.max_response_size);
+        for hash in hashes.iter().take(limits::MAX_TRANSACTIONS_PER_REQUEST) {
+            if let Some(tx) = pool.get(hash) {
+                if !budget.fits(&tx) {
+                    break;
+                }
+                transactions.push(tx);
+            }
+        }
+
+        Ok(SyncResponse::transactions(transactions))
+    }
 }
 
 impl Default for SyncHandler {
     fn default() -> Self {
-        Self::new()
+        Self::new(Arc::new(MemoryChain::new()))
+    }
+}
+
+/// Tracks the encoded size of a response being assembled
+struct ResponseBudget {
+    remaining: usize,
+}
+
+impl ResponseBudget {
+    fn new(max_response_size: usize) -> Self {
+        Self {
+            remaining: max_response_size.saturating_sub(RESPONSE_OVERHEAD),
+        }
+    }
+
+    /// Reserve space for an item, returning false if it does not fit
+    fn fits<T: serde::Serialize>(&mut self, item: &T) -> bool {
+        match encoded_len(item) {
+            Some(len) if len <= self.remaining => {
+                self.remaining -= len;
+                true
+            }
+            _ => false,
+        }
     }
 }
 
@@ -222,6 +602,14 @@ pub struct SyncStats {
     pub headers_synced: u64,
     /// Number of bodies synced
     pub bodies_synced: u64,
+    /// Number of state entries synced
+    pub state_entries_synced: u64,
+    /// Number of sync requests that failed or timed out
+    pub requests_failed: u64,
+    /// Highest block imported by sync
+    pub best_block: u64,
+    /// Highest block announced by peers
+    pub target_block: u64,
 }
 
 impl SyncStats {
@@ -245,11 +633,21 @@ impl SyncStats {
         self.requests_handled += 1;
     }
 
+    /// Record a failed request
+    pub fn record_request_failed(&mut self) {
+        self.requests_failed += 1;
+    }
+
     /// Record synced headers
     pub fn record_headers_synced(&mut self, count: u64) {
         self.headers_synced += count;
     }
 
+    /// Record synced state entries
+    pub fn record_state_entries_synced(&mut self, count: u64) {
+        self.state_entries_synced += count;
+    }
+
     /// Record synced bodies
     pub fn record_bodies_synced(&mut self, count: u64) {
         self.bodies_synced += count;
@@ -298,7 +696,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_sync_handler() {
-        let handler = SyncHandler::new();
+        let handler = SyncHandler::default();
         let peer_id = PeerId::random();
 
         // Test header request
@@ -313,6 +711,207 @@ mod tests {
         }
     }
 
+    fn test_chain(length: u64) -> (Arc<MemoryChain>, Vec<Hash>) {
+        let chain = Arc::new(MemoryChain::new());
+        let mut hashes = Vec::new();
+        let mut parent = Hash::zero();
+        for number in 0..length {
+            let mut header = BlockHeader::genesis();
+            header.parent_hash = parent;
+            header.number = number;
+            parent = chain
+                .insert_block(chain_core::Block::new(header, vec![]))
+                .unwrap();
+            chain.insert_receipts(parent, vec![number as u8]);
+            hashes.push(parent);
+        }
+        (chain, hashes)
+    }
+
+    fn numbers(response: SyncResponse) -> Vec<u64> {
+        match response {
+            SyncResponse::Headers { headers } => headers.iter().map(|h| h.number).collect(),
+            _ => panic!("Wrong response type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_handler_headers() {
+        let (chain, hashes) = test_chain(20);
+        let handler = SyncHandler::new(chain.clone());
+        let peer_id = PeerId::random();
+
+        let request = SyncRequest::headers(hashes[5], 4);
+        let response = handler.handle_request(request, peer_id).await;
+        assert_eq!(numbers(response), vec![5, 6, 7, 8]);
+
+        let request = SyncRequest::GetHeaders {
+            start: hashes[2],
+            amount: 10,
+            skip: 4,
+            reverse: false,
+        };
+        let response = handler.handle_request(request, peer_id).await;
+        assert_eq!(numbers(response), vec![2, 7, 12, 17]);
+
+        let request = SyncRequest::GetHeaders {
+            start: hashes[10],
+            amount: 10,
+            skip: 2,
+            reverse: true,
+        };
+        let response = handler.handle_request(request, peer_id).await;
+        assert_eq!(numbers(response), vec![10, 7, 4, 1]);
+
+        // Headers off the canonical chain are not served
+        let canonical = chain.header(&hashes[5]).unwrap().unwrap();
+        let mut side = canonical.clone();
+        side.timestamp += 1;
+        let side = chain.insert_header(side).unwrap();
+        chain.insert_header(canonical).unwrap();
+        let response = handler
+            .handle_request(SyncRequest::headers(side, 4), peer_id)
+            .await;
+        assert!(numbers(response).is_empty());
+
+        // Responses are cut to the size limit
+        let handler = handler.with_max_response_size(RESPONSE_OVERHEAD + 300);
+        let response = handler
+            .handle_request(SyncRequest::headers(hashes[0], 20), peer_id)
+            .await;
+        let served = numbers(response).len();
+        assert!(served > 0 && served < 20);
+    }
+
+    #[tokio::test]
+    async fn test_sync_handler_bodies_and_receipts() {
+        let (chain, mut hashes) = test_chain(3);
+        let handler = SyncHandler::new(chain);
+        let peer_id = PeerId::random();
+
+        // Unknown hashes end the served prefix
+        hashes.insert(2, Hash::new([7; 32]));
+
+        let response = handler
+            .handle_request(SyncRequest::bodies(hashes.clone()), peer_id)
+            .await;
+        match response {
+            SyncResponse::Bodies { bodies } => assert_eq!(bodies.len(), 2),
+            _ => panic!("Wrong response type"),
+        }
+
+        let response = handler
+            .handle_request(SyncRequest::receipts(hashes), peer_id)
+            .await;
+        match response {
+            SyncResponse::Receipts { receipts } => assert_eq!(receipts, vec![vec![0], vec![1]]),
+            _ => panic!("Wrong response type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_handler_state_pages() {
+        let chain = Arc::new(MemoryChain::new());
+        let root = Hash::new([1; 32]);
+        chain.set_state(root, (0u8..10).map(|i| (vec![1, i], vec![i; 8])));
+        let handler = SyncHandler::new(chain.clone());
+        let peer_id = PeerId::random();
+
+        let page = |start: Vec<u8>, limit| {
+            let handler = &handler;
+            async move {
+                let request = SyncRequest::state_snapshot(root, vec![1], start, limit);
+                match handler.handle_request(request, peer_id).await {
+                    SyncResponse::StateSnapshot { entries, next } => (entries, next),
+                    _ => panic!("Wrong response type"),
+                }
+            }
+        };
+
+        // A short page points at the first key left out
+        let (entries, next) = page(vec![], 4).await;
+        assert_eq!(entries.len(), 4);
+        assert_eq!(next, Some(vec![1, 4]));
+
+        let (entries, next) = page(next.unwrap(), 10).await;
+        assert_eq!(entries.first().unwrap().0, vec![1, 4]);
+        assert_eq!(entries.len(), 6);
+        assert_eq!(next, None);
+
+        let (entries, next) = page(vec![], 10).await;
+        assert_eq!(entries.len(), 10);
+        assert_eq!(next, None);
+
+        let response = handler
+            .handle_request(
+                SyncRequest::state_snapshot(Hash::zero(), vec![], vec![], 10),
+                peer_id,
+            )
+            .await;
+        assert!(matches!(response, SyncResponse::Error { .. }));
+
+        // Requested limits are capped
+        let count = limits::MAX_STATE_ENTRIES_PER_REQUEST as u16 + 10;
+        chain.set_state(
+            root,
+            (0..count).map(|i| ([&[1], &i.to_be_bytes()[..]].concat(), vec![])),
+        );
+        let (entries, next) = page(vec![], u32::MAX).await;
+        assert_eq!(
+            entries.len(),
+            limits::MAX_STATE_ENTRIES_PER_REQUEST as usize
+        );
+        assert!(next.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_sync_handler_state_range() {
+        let chain = Arc::new(MemoryChain::new());
+        let entries: Vec<StateEntry> = (0u8..200).map(|i| (vec![i], vec![i; 64])).collect();
+        let root = chain_core::entries_root(&entries);
+        chain.set_state(root, entries.clone());
+        let handler = SyncHandler::new(chain).with_max_response_size(4 * 1024);
+        let peer_id = PeerId::random();
+
+        let request = SyncRequest::state_range(root, vec![50], 100);
+        match handler.handle_request(request, peer_id).await {
+            SyncResponse::StateRange { entries: served, proof } => {
+                // Halved until it fits the response limit
+                assert!(!served.is_empty() && served.len() < 100);
+                assert_eq!(proof.first_index, 50);
+                assert!(proof.verify(&root, &served).is_ok());
+            }
+            _ => panic!("Wrong response type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_handler_block_transactions() {
+        let chain = Arc::new(MemoryChain::new());
+        let transactions: Vec<Transaction> = (0..5)
+            .map(|nonce| Transaction::new(nonce, 1, 21_000, None, 0, vec![]))
+            .collect();
+        let block = chain_core::Block::new(BlockHeader::genesis(), transactions.clone());
+        let hash = chain.insert_block(block).unwrap();
+        let handler = SyncHandler::new(chain);
+        let peer_id = PeerId::random();
+
+        let request = SyncRequest::block_transactions(hash, vec![4, 1]);
+        match handler.handle_request(request, peer_id).await {
+            SyncResponse::BlockTransactions { transactions: served } => {
+                assert_eq!(served, vec![transactions[4].clone(), transactions[1].clone()]);
+            }
+            _ => panic!("Wrong response type"),
+        }
+
+        // Out of range positions are not served partially
+        let request = SyncRequest::block_transactions(hash, vec![0, 5]);
+        assert!(matches!(
+            handler.handle_request(request, peer_id).await,
+            SyncResponse::Error { .. }
+        ));
+    }
+
     #[test]
     fn test_sync_stats() {
         let mut stats = SyncStats::new();
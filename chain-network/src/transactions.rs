@@ -0,0 +1,879 @@
+//! Transaction gossip
+//!
+//! Inbound transactions are checked for a valid signature and basic limits,
+//! deduplicated by transaction hash and handed to the node's
+//! [`GossipPool`]. Only transactions the pool accepts are gossiped on; a
+//! message holding nothing else is forwarded as received.
+//! Peers that send invalid transactions are reported to the
+//! [`ReputationManager`].
+//!
+//! Transactions can also be announced by hash: receivers fetch the bodies they
+//! do not know yet with [`SyncRequest::GetTransactions`](crate::SyncRequest).
+//! Gossiped announcements are fetched off the gossip handler, with a few
+//! fetches in flight per peer, so a slow peer does not hold up other gossip.
+
+use crate::gossip::{GossipManager, GossipStep, GossipValidation};
+use crate::message::{limits, TransactionAnnounce, TransactionPropagate};
+use crate::reputation::{Misbehaviour, ReputationManager};
+use crate::seen_cache::{SeenCache, SeenCacheStats, DEFAULT_SEEN_TTL};
+use crate::sync::SyncManager;
+use crate::{NetworkError, NetworkResult};
+use chain_core::{Gas, Hash, Transaction};
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Minimum gas limit of any transaction
+pub const MIN_TRANSACTION_GAS: Gas = 21_000;
+
+/// Default number of invalid transaction hashes remembered
+pub const DEFAULT_MAX_KNOWN_TRANSACTIONS: usize = 10_000;
+
+/// Default number of announced transaction fetches in flight per peer
+pub const DEFAULT_MAX_FETCHES_PER_PEER: usize = 4;
+
+/// Outcome of adding a transaction to the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolImport {
+    /// Newly added, so worth gossiping on
+    Accepted,
+    /// Already in the pool
+    Known,
+    /// Valid but not kept, e.g. because the pool is full
+    Dropped,
+}
+
+/// Pool of pending transactions fed by gossip
+pub trait GossipPool: Send + Sync {
+    /// Check whether a transaction is in the pool
+    fn contains(&self, hash: &Hash) -> bool;
+
+    /// Get a transaction by hash
+    fn get(&self, hash: &Hash) -> Option<Transaction>;
+
+    /// Get every pooled transaction, e.g. to rebuild compact blocks
+    fn pending(&self) -> Vec<Transaction>;
+
+    /// Add a transaction that passed [`validate_transaction`]
+    ///
+    /// Fails with [`NetworkError::InvalidTransaction`] if the transaction is
+    /// invalid against the current state, e.g. because of a stale nonce.
+    fn import(&self, transaction: Transaction) -> NetworkResult<PoolImport>;
+}
+
+/// In-memory transaction pool, useful for tests and light nodes
+#[derive(Debug)]
+pub struct MemoryPool {
+    transactions: RwLock<HashMap<Hash, Transaction>>,
+    capacity: usize,
+}
+
+impl MemoryPool {
+    /// Create a pool holding up to `capacity` transactions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            transactions: RwLock::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// Get the number of pooled transactions
+    pub fn len(&self) -> usize {
+        self.transactions.read().unwrap().len()
+    }
+
+    /// Check whether the pool is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove transactions, e.g. once they are included in a block
+    pub fn remove(&self, hashes: &[Hash]) {
+        let mut transactions = self.transactions.write().unwrap();
+        for hash in hashes {
+            transactions.remove(hash);
+        }
+    }
+}
+
+impl GossipPool for MemoryPool {
+    fn contains(&self, hash: &Hash) -> bool {
+        self.transactions.read().unwrap().contains_key(hash)
+    }
+
+    fn get(&self, hash: &Hash) -> Option<Transaction> {
+        self.transactions.read().unwrap().get(hash).cloned()
+    }
+
+    fn pending(&self) -> Vec<Transaction> {
+        self.transactions
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    fn import(&self, transaction: Transaction) -> NetworkResult<PoolImport> {
+        let hash = transaction_hash(&transaction)?;
+        let mut transactions = self.transactions.write().unwrap();
+        if transactions.contains_key(&hash) {
+            return Ok(PoolImport::Known);
+        }
+        if transactions.len() >= self.capacity {
+            return Ok(PoolImport::Dropped);
+        }
+        transactions.insert(hash, transaction);
+        Ok(PoolImport::Accepted)
+    }
+}
+
+/// Check a transaction without looking at chain state
+///
+/// Returns the transaction hash on success.
+pub fn validate_transaction(transaction: &Transaction) -> NetworkResult<Hash> {
+    if transaction.gas_limit < MIN_TRANSACTION_GAS {
+        return Err(NetworkError::InvalidTransaction(format!(
+            "Gas limit {} is below the minimum of {}",
+            transaction.gas_limit, MIN_TRANSACTION_GAS
+        )));
+    }
+    if transaction.data.len() > limits::MAX_TRANSACTION_DATA_SIZE {
+        return Err(NetworkError::InvalidTransaction(format!(
+            "Data of {} bytes exceeds the limit of {}",
+            transaction.data.len(),
+            limits::MAX_TRANSACTION_DATA_SIZE
+        )));
+    }
+    if transaction.signature.is_none() {
+        return Err(NetworkError::InvalidTransaction(
+            "Transaction is not signed".to_string(),
+        ));
+    }
+    match transaction.verify_signature() {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(NetworkError::InvalidTransaction(
+                "Signature does not verify".to_string(),
+            ))
+        }
+        Err(e) => return Err(NetworkError::InvalidTransaction(e.to_string())),
+    }
+    transaction_hash(transaction)
+}
+
+/// Transactions a peer sent, after checking
+#[derive(Debug, Default)]
+struct Imported {
+    /// Transactions the pool accepted
+    accepted: Vec<(Hash, Transaction)>,
+    /// Number of invalid, unrequested or missing transactions
+    invalid: u64,
+}
+
+/// Announced transactions to fetch from a peer
+///
+/// Run it without holding the [`TransactionGossip`] and hand the result to
+/// [`TransactionGossip::finish_announcement`].
+pub struct TransactionFetch {
+    /// Sync requests to the peer
+    sync: SyncManager,
+    /// Peer that announced the transactions
+    peer_id: PeerId,
+    /// Hashes in the announcement
+    announced: Vec<Hash>,
+    /// Hashes we do not know yet, in the request
+    wanted: Vec<Hash>,
+}
+
+impl TransactionFetch {
+    /// Request the wanted transactions from the announcing peer
+    pub async fn run(self) -> FetchedTransactions {
+        let result = self
+            .sync
+            .request_transactions(self.peer_id, self.wanted.clone())
+            .await;
+        FetchedTransactions {
+            peer_id: self.peer_id,
+            announced: self.announced,
+            wanted: self.wanted,
+            result,
+        }
+    }
+}
+
+/// Response to a [`TransactionFetch`]
+pub struct FetchedTransactions {
+    /// Peer that announced the transactions
+    peer_id: PeerId,
+    /// Hashes in the announcement
+    announced: Vec<Hash>,
+    /// Hashes in the request
+    wanted: Vec<Hash>,
+    /// Transactions the peer served
+    result: NetworkResult<Vec<Transaction>>,
+}
+
+impl Imported {
+    fn hashes(&self) -> Vec<Hash> {
+        self.accepted.iter().map(|(hash, _)| *hash).collect()
+    }
+}
+
+/// Transaction gossip statistics
+#[derive(Debug, Default, Clone)]
+pub struct TransactionGossipStats {
+    /// Transactions received over gossip or fetched after an announcement
+    pub received: u64,
+    /// Transactions accepted into the pool
+    pub accepted: u64,
+    /// Transactions skipped because they were already known
+    pub duplicates: u64,
+    /// Transactions that failed validation
+    pub invalid: u64,
+    /// Transactions gossiped on to other peers
+    pub propagated: u64,
+}
+
+/// Validation and re-propagation of gossiped transactions
+pub struct TransactionGossip {
+    /// Pool accepted transactions go to
+    pool: Arc<dyn GossipPool>,
+    /// Gossip used to pass transactions on
+    gossip: GossipManager,
+    /// Sync requests used to fetch announced transactions
+    sync: SyncManager,
+    /// Reputation of sending peers
+    reputation: Arc<ReputationManager>,
+    /// Hashes of transactions found invalid
+    ///
+    /// Pooled transactions are deduplicated through the pool itself, and
+    /// dropped ones are not remembered so they can be retried later.
+    known: SeenCache<Hash>,
+    /// Pass transactions on as hash announcements instead of in full
+    announce_hashes: bool,
+    /// Announcement fetches in flight per peer
+    in_flight: HashMap<PeerId, usize>,
+    /// Announcements from a peer with this many fetches in flight are ignored
+    max_fetches_per_peer: usize,
+    /// Statistics
+    stats: TransactionGossipStats,
+}
+
+impl TransactionGossip {
+    /// Create a transaction gossip pipeline feeding a pool
+    pub fn new(
+        pool: Arc<dyn GossipPool>,
+        gossip: GossipManager,
+        sync: SyncManager,
+        reputation: Arc<ReputationManager>,
+    ) -> Self {
+        Self {
+            pool,
+            gossip,
+            sync,
+            reputation,
+            known: SeenCache::new(DEFAULT_MAX_KNOWN_TRANSACTIONS, DEFAULT_SEEN_TTL),
+            announce_hashes: false,
+            in_flight: HashMap::new(),
+            max_fetches_per_peer: DEFAULT_MAX_FETCHES_PER_PEER,
+            stats: TransactionGossipStats::default(),
+        }
+    }
+
+    /// Set the number of invalid transaction hashes remembered
+    pub fn with_max_known(mut self, max_known: usize) -> Self {
+        self.known = SeenCache::new(max_known, DEFAULT_SEEN_TTL);
+        self
+    }
+
+    /// Pass accepted transactions on as hash announcements
+    pub fn with_hash_announcements(mut self, announce_hashes: bool) -> Self {
+        self.announce_hashes = announce_hashes;
+        self
+    }
+
+    /// Set the number of announcement fetches in flight per peer
+    pub fn with_max_fetches_per_peer(mut self, max_fetches: usize) -> Self {
+        self.max_fetches_per_peer = max_fetches;
+        self
+    }
+
+    /// Get transaction gossip statistics
+    pub fn stats(&self) -> &TransactionGossipStats {
+        &self.stats
+    }
+
+    /// Get hit and eviction counts of the known-transaction cache
+    pub fn seen_stats(&self) -> &SeenCacheStats {
+        self.known.stats()
+    }
+
+    /// Check whether a transaction is pooled or was found invalid
+    pub fn is_known(&self, hash: &Hash) -> bool {
+        self.known.contains(hash) || self.pool.contains(hash)
+    }
+
+    /// Validate transactions from a peer and pass on the accepted ones
+    ///
+    /// Returns the hashes of the transactions the pool accepted.
+    pub async fn handle_transactions(
+        &mut self,
+        transactions: Vec<Transaction>,
+        peer_id: PeerId,
+    ) -> NetworkResult<Vec<Hash>> {
+        let imported = self.import(transactions, &peer_id)?;
+        let hashes = imported.hashes();
+        self.propagate(imported.accepted).await?;
+        Ok(hashes)
+    }
+
+    /// Fetch the announced transactions we do not know yet and handle them
+    ///
+    /// Returns the hashes of the transactions the pool accepted.
+    pub async fn handle_announcement(
+        &mut self,
+        announce: TransactionAnnounce,
+        peer_id: PeerId,
+    ) -> NetworkResult<Vec<Hash>> {
+        let imported = self.fetch_announced(announce.hashes, &peer_id).await?;
+        let hashes = imported.hashes();
+        self.propagate(imported.accepted).await?;
+        Ok(hashes)
+    }
+
+    /// Handle gossiped transactions and decide whether gossip forwards them
+    ///
+    /// See [`validate_announcement`](Self::validate_announcement) for the verdicts.
+    pub async fn validate_transactions(
+        &mut self,
+        propagate: TransactionPropagate,
+        peer_id: PeerId,
+    ) -> NetworkResult<GossipValidation> {
+        let hashes = propagate
+            .transactions
+            .iter()
+            .map(transaction_hash)
+            .collect::<NetworkResult<Vec<_>>>()?;
+        let imported = self.import(propagate.transactions, &peer_id)?;
+        let unchanged = !self.announce_hashes && imported.hashes() == hashes;
+        self.pass_on(imported, unchanged).await
+    }
+
+    /// Handle gossiped transaction hashes and decide whether gossip forwards them
+    ///
+    /// A message carrying exactly what we would pass on is accepted, so gossip
+    /// forwards it as is; message ids are content hashes, so our own copy
+    /// would be dropped as a duplicate. Otherwise the accepted transactions
+    /// are passed on in a new message and the original is ignored, or
+    /// rejected if the peer sent anything invalid.
+    pub async fn validate_announcement(
+        &mut self,
+        announce: TransactionAnnounce,
+        peer_id: PeerId,
+    ) -> NetworkResult<GossipValidation> {
+        match self.start_announcement(announce, peer_id) {
+            GossipStep::Done(validation) => Ok(validation),
+            GossipStep::Fetch(fetch) => self.finish_announcement(fetch.run().await).await,
+        }
+    }
+
+    /// Start handling gossiped transaction hashes without waiting on the peer
+    ///
+    /// Returns the fetch to run if there are unknown transactions. The
+    /// announcement is ignored if there are none, or if the peer already has
+    /// the maximum number of fetches in flight.
+    pub fn start_announcement(
+        &mut self,
+        announce: TransactionAnnounce,
+        peer_id: PeerId,
+    ) -> GossipStep {
+        let in_flight = self.in_flight.get(&peer_id).copied().unwrap_or(0);
+        if in_flight >= self.max_fetches_per_peer {
+            tracing::debug!(
+                "Ignoring transaction announcement from busy peer {}",
+                peer_id
+            );
+            return GossipStep::Done(GossipValidation::Ignore);
+        }
+        match self.start_fetch(announce.hashes, peer_id) {
+            Some(fetch) => GossipStep::Fetch(fetch),
+            None => GossipStep::Done(GossipValidation::Ignore),
+        }
+    }
+
+    /// Handle fetched announced transactions and decide whether gossip
+    /// forwards the announcement
+    ///
+    /// See [`validate_announcement`](Self::validate_announcement) for the verdicts.
+    pub async fn finish_announcement(
+        &mut self,
+        fetched: FetchedTransactions,
+    ) -> NetworkResult<GossipValidation> {
+        let hashes = fetched.announced.clone();
+        let imported = self.import_fetched(fetched)?;
+        let unchanged = self.announce_hashes && imported.hashes() == hashes;
+        self.pass_on(imported, unchanged).await
+    }
+
+    /// Check transactions and add them to the pool
+    fn import(
+        &mut self,
+        transactions: Vec<Transaction>,
+        peer_id: &PeerId,
+    ) -> NetworkResult<Imported> {
+        let mut imported = Imported::default();
+
+        for transaction in transactions {
+            self.stats.received += 1;
+            let hash = transaction_hash(&transaction)?;
+            if self.is_known(&hash) {
+                self.stats.duplicates += 1;
+                continue;
+            }
+
+            let result = validate_transaction(&transaction)
+                .and_then(|_| self.pool.import(transaction.clone()));
+            match result {
+                Ok(PoolImport::Accepted) => imported.accepted.push((hash, transaction)),
+                Ok(PoolImport::Known) => self.stats.duplicates += 1,
+                Ok(PoolImport::Dropped) => {}
+                Err(NetworkError::InvalidTransaction(reason)) => {
+                    tracing::debug!("Invalid transaction {} from {}: {}", hash, peer_id, reason);
+                    self.known.insert(hash);
+                    imported.invalid += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.stats.invalid += imported.invalid;
+        for _ in 0..imported.invalid {
+            self.report(peer_id, Misbehaviour::InvalidTransaction);
+        }
+        self.stats.accepted += imported.accepted.len() as u64;
+        Ok(imported)
+    }
+
+    /// Fetch the announced transactions we do not know yet and add them to the pool
+    async fn fetch_announced(
+        &mut self,
+        hashes: Vec<Hash>,
+        peer_id: &PeerId,
+    ) -> NetworkResult<Imported> {
+        match self.start_fetch(hashes, *peer_id) {
+            Some(fetch) => self.import_fetched(fetch.run().await),
+            None => Ok(Imported::default()),
+        }
+    }
+
+    /// Count a fetch of the announced transactions we do not know yet as in
+    /// flight, if there are any
+    fn start_fetch(&mut self, hashes: Vec<Hash>, peer_id: PeerId) -> Option<TransactionFetch> {
+        let mut unique = HashSet::new();
+        let mut wanted: Vec<Hash> = hashes
+            .iter()
+            .filter(|hash| !self.is_known(hash) && unique.insert(**hash))
+            .copied()
+            .collect();
+        wanted.truncate(limits::MAX_TRANSACTIONS_PER_REQUEST);
+        if wanted.is_empty() {
+            return None;
+        }
+
+        *self.in_flight.entry(peer_id).or_default() += 1;
+        Some(TransactionFetch {
+            sync: self.sync.clone(),
+            peer_id,
+            announced: hashes,
+            wanted,
+        })
+    }
+
+    /// Check fetched transactions and add them to the pool
+    fn import_fetched(&mut self, fetched: FetchedTransactions) -> NetworkResult<Imported> {
+        let peer_id = &fetched.peer_id;
+        if let Some(in_flight) = self.in_flight.get_mut(peer_id) {
+            *in_flight -= 1;
+            if *in_flight == 0 {
+                self.in_flight.remove(peer_id);
+            }
+        }
+        let transactions = fetched.result?;
+
+        // Anything we did not ask for is dropped and counts against the peer
+        let mut wanted: HashSet<Hash> = fetched.wanted.into_iter().collect();
+        let mut requested = Vec::with_capacity(transactions.len());
+        let mut unrequested = 0;
+        for transaction in transactions {
+            if wanted.remove(&transaction_hash(&transaction)?) {
+                requested.push(transaction);
+            } else {
+                unrequested += 1;
+            }
+        }
+        if unrequested > 0 {
+            self.stats.invalid += unrequested;
+            for _ in 0..unrequested {
+                self.report(peer_id, Misbehaviour::Spam);
+            }
+        }
+        // So does announcing transactions the peer then does not serve
+        let missing = wanted.len() as u64;
+        if missing > 0 {
+            tracing::debug!(
+                "{} announced transactions not served by {}",
+                missing,
+                peer_id
+            );
+            self.report(peer_id, Misbehaviour::Spam);
+        }
+
+        let mut imported = self.import(requested, peer_id)?;
+        imported.invalid += unrequested + missing;
+        Ok(imported)
+    }
+
+    /// Pass accepted transactions on and give the verdict on their message
+    async fn pass_on(
+        &mut self,
+        imported: Imported,
+        unchanged: bool,
+    ) -> NetworkResult<GossipValidation> {
+        if imported.invalid == 0 && unchanged && !imported.accepted.is_empty() {
+            self.stats.propagated += imported.accepted.len() as u64;
+            return Ok(GossipValidation::Accept);
+        }
+
+        let invalid = imported.invalid > 0;
+        self.propagate(imported.accepted).await?;
+        Ok(if invalid {
+            GossipValidation::Reject
+        } else {
+            GossipValidation::Ignore
+        })
+    }
+
+    /// Gossip accepted transactions to other peers
+    async fn propagate(&mut self, accepted: Vec<(Hash, Transaction)>) -> NetworkResult<()> {
+        if accepted.is_empty() {
+            return Ok(());
+        }
+
+        self.stats.propagated += accepted.len() as u64;
+        if self.announce_hashes {
+            let hashes = accepted.into_iter().map(|(hash, _)| hash).collect();
+            self.gossip
+                .announce_transactions(TransactionAnnounce::new(hashes))
+                .await
+        } else {
+            let transactions = accepted.into_iter().map(|(_, tx)| tx).collect();
+            self.gossip
+                .propagate_transactions(TransactionPropagate::new(transactions))
+                .await
+        }
+    }
+
+    fn report(&self, peer_id: &PeerId, misbehaviour: Misbehaviour) {
+        if let Err(e) = self.reputation.report(peer_id, misbehaviour) {
+            tracing::warn!("Failed to report {}: {}", peer_id, e);
+        }
+    }
+}
+
+fn transaction_hash(transaction: &Transaction) -> NetworkResult<Hash> {
+    transaction
+        .hash()
+        .map_err(|e| NetworkError::Encoding(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gossip::GossipCommand;
+    use crate::message::{GossipMessage, SyncResponse};
+    use crate::reputation::{MemoryReputationStore, ReputationConfig};
+    use crate::sync::{SyncCommand, SyncHandler};
+    use tokio::sync::mpsc;
+
+    fn signed(nonce: u64) -> Transaction {
+        let mut tx = Transaction::new(nonce, 1, 21_000, None, 0, vec![]);
+        tx.sign(&[7u8; 32]).unwrap();
+        tx
+    }
+
+    async fn setup(
+        pool: Arc<MemoryPool>,
+    ) -> (
+        TransactionGossip,
+        mpsc::UnboundedReceiver<GossipCommand>,
+        mpsc::UnboundedReceiver<SyncCommand>,
+        Arc<ReputationManager>,
+        PeerId,
+    ) {
+        let (gossip, gossip_rx) = GossipManager::new();
+        let (sync, sync_rx) = SyncManager::new();
+        let (reputation, _bans) = ReputationManager::new(
+            ReputationConfig::default(),
+            Arc::new(MemoryReputationStore::default()),
+        )
+        .unwrap();
+        let reputation = Arc::new(reputation);
+
+        let handler = TransactionGossip::new(pool, gossip, sync, reputation.clone());
+        (handler, gossip_rx, sync_rx, reputation, PeerId::random())
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        assert!(validate_transaction(&signed(0)).is_ok());
+
+        let unsigned = Transaction::new(0, 1, 21_000, None, 0, vec![]);
+        assert!(validate_transaction(&unsigned).is_err());
+
+        let mut low_gas = Transaction::new(0, 1, 1_000, None, 0, vec![]);
+        low_gas.sign(&[7u8; 32]).unwrap();
+        assert!(validate_transaction(&low_gas).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_only_accepted_transactions_propagate() {
+        let pool = Arc::new(MemoryPool::new(100));
+        let (mut handler, mut gossip_rx, _sync_rx, reputation, peer_id) = setup(pool.clone()).await;
+
+        let valid = signed(0);
+        let unsigned = Transaction::new(1, 1, 21_000, None, 0, vec![]);
+        let accepted = handler
+            .handle_transactions(vec![valid.clone(), unsigned, valid.clone()], peer_id)
+            .await
+            .unwrap();
+        assert_eq!(accepted, vec![valid.hash().unwrap()]);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(handler.stats().duplicates, 1);
+        assert_eq!(handler.stats().invalid, 1);
+        assert!(reputation.score(&peer_id) < 0.0);
+
+        match gossip_rx.try_recv().unwrap() {
+            GossipCommand::Publish { message, .. } => match *message {
+                GossipMessage::TransactionPropagate(propagate) => {
+                    assert_eq!(propagate.transactions, vec![valid.clone()]);
+                }
+                _ => panic!("Wrong message type"),
+            },
+            _ => panic!("Wrong command type"),
+        }
+
+        // Known transactions are not passed on again
+        handler
+            .handle_transactions(vec![valid], peer_id)
+            .await
+            .unwrap();
+        assert!(gossip_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gossip_verdicts() {
+        let pool = Arc::new(MemoryPool::new(100));
+        let (mut handler, mut gossip_rx, _sync_rx, reputation, peer_id) = setup(pool.clone()).await;
+        let message = |transactions: Vec<Transaction>| TransactionPropagate::new(transactions);
+
+        // A message of new, valid transactions is forwarded as received
+        let validation = handler
+            .validate_transactions(message(vec![signed(0)]), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Accept);
+        assert!(gossip_rx.try_recv().is_err());
+
+        // With an invalid transaction in it, only the valid ones are passed on
+        let unsigned = Transaction::new(2, 1, 21_000, None, 0, vec![]);
+        let validation = handler
+            .validate_transactions(message(vec![signed(1), unsigned]), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Reject);
+        match gossip_rx.try_recv().unwrap() {
+            GossipCommand::Publish { message, .. } => match *message {
+                GossipMessage::TransactionPropagate(propagate) => {
+                    assert_eq!(propagate.transactions, vec![signed(1)]);
+                }
+                _ => panic!("Wrong message type"),
+            },
+            _ => panic!("Wrong command type"),
+        }
+        assert!(reputation.score(&peer_id) < 0.0);
+
+        // Known transactions are not forwarded again
+        let validation = handler
+            .validate_transactions(message(vec![signed(0)]), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Ignore);
+        assert!(gossip_rx.try_recv().is_err());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_transactions_can_be_retried() {
+        let pool = Arc::new(MemoryPool::new(1));
+        let (mut handler, _gossip_rx, _sync_rx, reputation, peer_id) = setup(pool.clone()).await;
+
+        // The pool is full, so the second transaction is dropped
+        let (first, second) = (signed(0), signed(1));
+        let accepted = handler
+            .handle_transactions(vec![first.clone(), second.clone()], peer_id)
+            .await
+            .unwrap();
+        assert_eq!(accepted, vec![first.hash().unwrap()]);
+        assert!(!handler.is_known(&second.hash().unwrap()));
+
+        // Once there is room, the dropped transaction is taken
+        pool.remove(&[first.hash().unwrap()]);
+        let accepted = handler
+            .handle_transactions(vec![second.clone()], peer_id)
+            .await
+            .unwrap();
+        assert_eq!(accepted, vec![second.hash().unwrap()]);
+
+        // Invalid transactions are remembered and not checked again
+        let unsigned = Transaction::new(2, 1, 21_000, None, 0, vec![]);
+        for _ in 0..2 {
+            handler
+                .handle_transactions(vec![unsigned.clone()], peer_id)
+                .await
+                .unwrap();
+        }
+        assert!(handler.is_known(&unsigned.hash().unwrap()));
+        assert_eq!(handler.stats().invalid, 1);
+        assert_eq!(handler.stats().duplicates, 1);
+        let expected = Misbehaviour::InvalidTransaction.cost();
+        assert!((reputation.score(&peer_id) - expected).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_announced_transactions_are_fetched() {
+        let pool = Arc::new(MemoryPool::new(100));
+        let (handler, mut gossip_rx, mut sync_rx, _reputation, peer_id) = setup(pool.clone()).await;
+        let mut handler = handler.with_hash_announcements(true);
+
+        // The announcing peer serves transactions from its own pool
+        let remote = Arc::new(MemoryPool::new(100));
+        let transactions: Vec<Transaction> = (0..3).map(signed).collect();
+        for tx in &transactions {
+            remote.import(tx.clone()).unwrap();
+        }
+        pool.import(transactions[0].clone()).unwrap();
+        let server = SyncHandler::default().with_transaction_pool(remote);
+        tokio::spawn(async move {
+            while let Some(SyncCommand::SendRequest {
+                peer_id,
+                request,
+                response_sender,
+            }) = sync_rx.recv().await
+            {
+                let response = server.handle_request(request, peer_id).await;
+                assert!(
+                    matches!(response, SyncResponse::Transactions { ref transactions } if transactions.len() == 2)
+                );
+                let _ = response_sender.send(Ok(response));
+            }
+        });
+
+        let hashes: Vec<Hash> = transactions.iter().map(|tx| tx.hash().unwrap()).collect();
+        let accepted = handler
+            .handle_announcement(TransactionAnnounce::new(hashes.clone()), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(pool.len(), 3);
+
+        match gossip_rx.try_recv().unwrap() {
+            GossipCommand::Publish { message, .. } => match *message {
+                GossipMessage::TransactionAnnounce(announce) => {
+                    assert_eq!(announce.hashes.len(), 2);
+                }
+                _ => panic!("Wrong message type"),
+            },
+            _ => panic!("Wrong command type"),
+        }
+    }
+
+    /// Serve sync requests from a pool, as the announcing peer would
+    fn serve_from(remote: Arc<MemoryPool>, mut sync_rx: mpsc::UnboundedReceiver<SyncCommand>) {
+        let server = SyncHandler::default().with_transaction_pool(remote);
+        tokio::spawn(async move {
+            while let Some(SyncCommand::SendRequest {
+                peer_id,
+                request,
+                response_sender,
+            }) = sync_rx.recv().await
+            {
+                let response = server.handle_request(request, peer_id).await;
+                let _ = response_sender.send(Ok(response));
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_announcement_fetches_capped_per_peer() {
+        let pool = Arc::new(MemoryPool::new(100));
+        let (handler, _gossip_rx, sync_rx, _reputation, peer_id) = setup(pool.clone()).await;
+        let mut handler = handler.with_max_fetches_per_peer(1);
+        let remote = Arc::new(MemoryPool::new(100));
+        for nonce in 0..3 {
+            remote.import(signed(nonce)).unwrap();
+        }
+        serve_from(remote, sync_rx);
+        let announce = |nonce: u64| TransactionAnnounce::new(vec![signed(nonce).hash().unwrap()]);
+
+        // Fetches are handed out without waiting on the peer
+        let GossipStep::Fetch(first) = handler.start_announcement(announce(0), peer_id) else {
+            panic!("Expected a fetch");
+        };
+        // A peer with a fetch in flight is not sent another request
+        assert!(matches!(
+            handler.start_announcement(announce(1), peer_id),
+            GossipStep::Done(GossipValidation::Ignore)
+        ));
+        // Other peers are not held up by it
+        assert!(matches!(
+            handler.start_announcement(announce(1), PeerId::random()),
+            GossipStep::Fetch(_)
+        ));
+
+        let validation = handler
+            .finish_announcement(first.run().await)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Ignore);
+        assert!(pool.contains(&signed(0).hash().unwrap()));
+        assert!(matches!(
+            handler.start_announcement(announce(2), peer_id),
+            GossipStep::Fetch(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unserved_announcements_count_as_spam() {
+        let pool = Arc::new(MemoryPool::new(100));
+        let (handler, _gossip_rx, sync_rx, reputation, peer_id) = setup(pool.clone()).await;
+        let mut handler = handler.with_hash_announcements(true);
+
+        // The peer announces three transactions but only has two
+        let remote = Arc::new(MemoryPool::new(100));
+        for nonce in 0..2 {
+            remote.import(signed(nonce)).unwrap();
+        }
+        serve_from(remote, sync_rx);
+        let hashes = (0..3).map(|nonce| signed(nonce).hash().unwrap()).collect();
+
+        let validation = handler
+            .validate_announcement(TransactionAnnounce::new(hashes), peer_id)
+            .await
+            .unwrap();
+        assert_eq!(validation, GossipValidation::Reject);
+        assert_eq!(pool.len(), 2);
+        let expected = Misbehaviour::Spam.cost();
+        assert!((reputation.score(&peer_id) - expected).abs() < 1.0);
+    }
+}
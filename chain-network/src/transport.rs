@@ -1,28 +1,66 @@
 //! Network transport layer configuration
+//!
+//! Connections run over TCP, secured with Noise and multiplexed with Yamux.
+//! When a listen address uses `/quic-v1`, QUIC is added next to TCP; it brings
+//! its own encryption and multiplexing and needs fewer round trips to connect.
 
 use crate::{NetworkConfig, NetworkResult};
+use futures::future::Either;
+use futures::{AsyncRead, AsyncWrite};
 use libp2p::{
-    core::Transport,
+    core::{muxing::StreamMuxerBox, transport::Boxed, Transport},
     dns,
+    multiaddr::Protocol,
     noise::Config as NoiseConfig,
+    quic, relay,
     tcp::{self, Config as TcpConfig},
     yamux::Config as YamuxConfig,
     Multiaddr, PeerId,
 };
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+/// Authenticated and multiplexed transport produced by [`build_transport`]
+pub type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
+
 /// Build the libp2p transport stack
 pub fn build_transport(
     keypair: &libp2p::identity::Keypair,
     config: &NetworkConfig,
-) -> NetworkResult<libp2p::core::transport::Boxed<(PeerId, libp2p::core::muxing::StreamMuxerBox)>> {
-    // TCP transport with custom configuration
-    let tcp_config = TcpConfig::new().nodelay(true);
+) -> NetworkResult<BoxedTransport> {
+    let transport = upgrade(tcp_transport(config), keypair, config)?;
+    finish(transport, keypair, config)
+}
 
-    let tcp_transport = tcp::tokio::Transport::new(tcp_config);
-    // DNS resolution for domain names in multiaddrs
-    let dns_transport = dns::tokio::Transport::system(tcp_transport)
-        .map_err(|e| crate::NetworkError::Config(format!("DNS transport error: {}", e)))?;
+/// Build the libp2p transport stack with support for `/p2p-circuit`
+/// addresses through the relay client transport
+pub fn build_relay_transport(
+    keypair: &libp2p::identity::Keypair,
+    config: &NetworkConfig,
+    relay: relay::client::Transport,
+) -> NetworkResult<BoxedTransport> {
+    let transport = upgrade(relay.or_transport(tcp_transport(config)), keypair, config)?;
+    finish(transport, keypair, config)
+}
+
+/// TCP transport with custom configuration
+fn tcp_transport(config: &NetworkConfig) -> tcp::tokio::Transport {
+    tcp::tokio::Transport::new(TcpConfig::new().nodelay(config.transport.tcp_nodelay))
+}
+
+/// Secure and multiplex raw connections
+fn upgrade<T>(
+    transport: T,
+    keypair: &libp2p::identity::Keypair,
+    config: &NetworkConfig,
+) -> NetworkResult<BoxedTransport>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Error: Send + Sync + 'static,
+{
     // Noise encryption for secure communication
     let noise_config = NoiseConfig::new(keypair)
         .map_err(|e| crate::NetworkError::Config(format!("Noise config error: {}", e)))?;
@@ -31,7 +69,7 @@ pub fn build_transport(
     let yamux_config = YamuxConfig::default();
 
     // Combine all layers
-    let transport = dns_transport
+    let transport = transport
         .upgrade(libp2p::core::upgrade::Version::V1Lazy)
         .authenticate(noise_config)
         .multiplex(yamux_config)
@@ -41,8 +79,41 @@ pub fn build_transport(
     Ok(transport)
 }
 
+/// Add QUIC if a listen address uses it, and resolve DNS addresses
+///
+/// DNS wraps the combined transport, as it accepts every address and would
+/// keep QUIC from being tried if it wrapped TCP alone.
+fn finish(
+    transport: BoxedTransport,
+    keypair: &libp2p::identity::Keypair,
+    config: &NetworkConfig,
+) -> NetworkResult<BoxedTransport> {
+    let transport = if config
+        .listen_addresses
+        .iter()
+        .any(AddressFilter::is_quic_address)
+    {
+        let quic = quic::tokio::Transport::new(config.transport.quic_config(keypair));
+        transport
+            .or_transport(quic)
+            .map(|output, _| match output {
+                Either::Left(output) => output,
+                Either::Right((peer_id, connection)) => (peer_id, StreamMuxerBox::new(connection)),
+            })
+            .boxed()
+    } else {
+        transport
+    };
+
+    // DNS resolution for domain names in multiaddrs
+    let transport = dns::tokio::Transport::system(transport)
+        .map_err(|e| crate::NetworkError::Config(format!("DNS transport error: {}", e)))?;
+    Ok(transport.boxed())
+}
+
 /// Transport configuration options
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TransportConfig {
     /// Enable TCP nodelay
     pub tcp_nodelay: bool,
@@ -50,10 +121,18 @@ pub struct TransportConfig {
     pub tcp_port_reuse: bool,
     /// Connection timeout
     pub connection_timeout: Duration,
-    /// Maximum number of concurrent connections
+    /// Maximum number of concurrent connections, counting every connection
+    /// of a peer
     pub max_connections: u32,
-    /// Connection upgrade timeout
+    /// Connection upgrade timeout, also bounding the QUIC handshake
     pub upgrade_timeout: Duration,
+    /// Inactivity after which QUIC sends a keep-alive packet
+    pub quic_keep_alive_interval: Duration,
+    /// Inactivity after which a QUIC connection is closed
+    pub quic_idle_timeout: Duration,
+    /// Maximum number of concurrent streams a peer may open on a QUIC
+    /// connection
+    pub quic_max_streams: u32,
 }
 
 impl Default for TransportConfig {
@@ -64,6 +143,9 @@ impl Default for TransportConfig {
             connection_timeout: Duration::from_secs(10),
             max_connections: 100,
             upgrade_timeout: Duration::from_secs(5),
+            quic_keep_alive_interval: Duration::from_secs(5),
+            quic_idle_timeout: Duration::from_secs(10),
+            quic_max_streams: 256,
         }
     }
 }
@@ -98,6 +180,34 @@ impl TransportConfig {
         self
     }
 
+    /// Set the QUIC keep-alive interval
+    pub fn with_quic_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.quic_keep_alive_interval = interval;
+        self
+    }
+
+    /// Set the QUIC idle timeout
+    pub fn with_quic_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.quic_idle_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum number of concurrent QUIC streams per peer
+    pub fn with_quic_max_streams(mut self, max: u32) -> Self {
+        self.quic_max_streams = max;
+        self
+    }
+
+    /// Build the QUIC transport configuration
+    pub fn quic_config(&self, keypair: &libp2p::identity::Keypair) -> quic::Config {
+        let mut config = quic::Config::new(keypair);
+        config.handshake_timeout = self.upgrade_timeout;
+        config.keep_alive_interval = self.quic_keep_alive_interval;
+        config.max_idle_timeout = self.quic_idle_timeout.as_millis().min(u32::MAX as u128) as u32;
+        config.max_concurrent_stream_limit = self.quic_max_streams;
+        config
+    }
+
     /// Validate configuration
     pub fn validate(&self) -> Result<(), String> {
         if self.connection_timeout.is_zero() {
@@ -108,6 +218,14 @@ impl TransportConfig {
             return Err("Maximum connections must be greater than zero".to_string());
         }
 
+        if self.quic_keep_alive_interval >= self.quic_idle_timeout {
+            return Err("QUIC keep-alive interval must be below the idle timeout".to_string());
+        }
+
+        if self.quic_max_streams == 0 {
+            return Err("Maximum QUIC streams must be greater than zero".to_string());
+        }
+
         Ok(())
     }
 }
@@ -120,7 +238,7 @@ impl AddressFilter {
     pub fn is_valid_address(addr: &Multiaddr) -> bool {
         // Basic validation - ensure it has required components
         let mut has_ip = false;
-        let mut has_tcp = false;
+        let mut has_transport = false;
 
         for protocol in addr.iter() {
             match protocol {
@@ -131,14 +249,19 @@ impl AddressFilter {
                 | libp2p::multiaddr::Protocol::Dns6(_) => {
                     has_ip = true;
                 }
-                libp2p::multiaddr::Protocol::Tcp(_) => {
-                    has_tcp = true;
+                libp2p::multiaddr::Protocol::Tcp(_) | libp2p::multiaddr::Protocol::QuicV1 => {
+                    has_transport = true;
                 }
                 _ => {}
             }
         }
 
-        has_ip && has_tcp
+        has_ip && has_transport
+    }
+
+    /// Check if an address uses QUIC
+    pub fn is_quic_address(addr: &Multiaddr) -> bool {
+        addr.iter().any(|protocol| protocol == Protocol::QuicV1)
     }
 
     /// Filter out invalid or unwanted addresses
@@ -205,6 +328,17 @@ mod tests {
         config.connection_timeout = Duration::from_secs(10);
         config.max_connections = 0;
         assert!(config.validate().is_err());
+
+        // Keep-alives must be sent before the connection idles out
+        let config = TransportConfig::new()
+            .with_quic_keep_alive_interval(Duration::from_secs(30))
+            .with_quic_idle_timeout(Duration::from_secs(30));
+        assert!(config.validate().is_err());
+        assert!(config
+            .with_quic_idle_timeout(Duration::from_secs(60))
+            .with_quic_max_streams(0)
+            .validate()
+            .is_err());
     }
 
     #[test]
@@ -217,6 +351,11 @@ mod tests {
 
         let dns_addr: Multiaddr = "/dns4/example.com/tcp/30333".parse().unwrap();
         assert!(AddressFilter::is_valid_address(&dns_addr));
+
+        let quic_addr: Multiaddr = "/ip4/127.0.0.1/udp/30333/quic-v1".parse().unwrap();
+        assert!(AddressFilter::is_valid_address(&quic_addr));
+        assert!(AddressFilter::is_quic_address(&quic_addr));
+        assert!(!AddressFilter::is_quic_address(&valid_addr));
     }
 
     #[test]
@@ -238,5 +377,12 @@ mod tests {
 
         let result = build_transport(&keypair, &config);
         assert!(result.is_ok());
+
+        let (relay, _) = relay::client::new(keypair.public().to_peer_id());
+        assert!(build_relay_transport(&keypair, &config, relay).is_ok());
+
+        let quic_addr: Multiaddr = "/ip4/0.0.0.0/udp/30333/quic-v1".parse().unwrap();
+        let config = config.with_listen_addresses(vec![quic_addr]);
+        assert!(build_transport(&keypair, &config).is_ok());
     }
 }
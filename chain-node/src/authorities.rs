@@ -0,0 +1,11 @@
+//! Authority set hand-off to the validator network
+
+use chain_consensus::traits::AuthoritySet;
+use chain_network::AuthorityDiscovery;
+
+/// Keep the validators of an authority set connected
+///
+/// Call whenever the consensus engine moves to a new set.
+pub fn update_authorities(discovery: &AuthorityDiscovery, set: &AuthoritySet) {
+    discovery.set_authorities(set.validators.iter().map(|validator| validator.address));
+}
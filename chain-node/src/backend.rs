@@ -0,0 +1,871 @@
+//! Block execution and storage for the consensus worker
+//!
+//! Transactions pay their fees to [`fee_collector`]. Once the last
+//! transaction of a block has run, [`Issuance::apply_block`] mints the block
+//! reward and inflation for the author and splits the collected fees, both
+//! when authoring and when importing, so every node reaches the same state
+//! root. Imported blocks are written to the `headers` and `blocks` column
+//! families read by [`DbChainReader`](crate::DbChainReader), and the
+//! `indices` column family maps heights to the blocks of the best chain.
+//! The `state` column family holds the entries of the best block's state,
+//! which commit to its state root.
+//!
+//! Blocks may extend any recent block, not only the best one. The entries
+//! each of the last [`MAX_REORG_DEPTH`] blocks wrote are kept along with the
+//! state below them, so the state after any recent block can be rebuilt to
+//! execute side branches, and the best chain switches to a branch once it
+//! carries more total difficulty.
+
+use chain_consensus::{ChainBackend, ConsensusError, ConsensusResult};
+use chain_core::{
+    Address, Block, BlockHeader, BlockNumber, ForkSchedule, Hash, StateEntry, Transaction,
+};
+use chain_db::column_families::ColumnFamily;
+use chain_db::traits::TransactionBuilder;
+use chain_db::KeyValueDB;
+use chain_vm::executor::ExecutionContext;
+use chain_vm::{
+    fee_collector, BlockRewards, GasSchedule, Issuance, SharedStateDB, Staking, TransactionExecutor,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tracing::{debug, info};
+
+/// Number of blocks below the best block whose state is kept for side branches
+pub const MAX_REORG_DEPTH: u64 = 64;
+
+/// Resolves the account credited with a block's rewards
+///
+/// Called with the unsealed header when authoring and with the sealed header
+/// when importing, so both must resolve to the same account.
+pub type AuthorResolver = Arc<dyn Fn(&BlockHeader) -> ConsensusResult<Address> + Send + Sync>;
+
+/// Executes block transactions followed by the end-of-block issuance
+pub struct BlockExecutor {
+    /// Transaction execution
+    executor: TransactionExecutor,
+    /// End-of-block rewards and fee distribution
+    issuance: Issuance,
+}
+
+impl BlockExecutor {
+    /// Create a block executor
+    pub fn new(gas_schedule: GasSchedule, issuance: Issuance) -> Self {
+        Self {
+            executor: TransactionExecutor::new(gas_schedule),
+            issuance,
+        }
+    }
+
+    /// Apply the gas overrides of a fork schedule to each block's transactions
+    pub fn with_forks(mut self, forks: ForkSchedule) -> Self {
+        self.executor = self.executor.with_forks(forks);
+        self
+    }
+
+    /// Execute staking calls, dating unbonded funds by `epoch_length` blocks per epoch
+    pub fn with_staking(mut self, staking: Staking, epoch_length: u64) -> Self {
+        self.executor = self.executor.with_staking(staking, epoch_length);
+        self
+    }
+
+    /// Execute one transaction of a block, paying its fee to the fee collector
+    ///
+    /// Returns the gas used.
+    pub fn execute_transaction(
+        &self,
+        state: &SharedStateDB,
+        header: &BlockHeader,
+        transaction: &Transaction,
+    ) -> ConsensusResult<u64> {
+        let context = ExecutionContext {
+            block_number: header.number,
+            timestamp: header.timestamp,
+            gas_limit: header.gas_limit,
+            coinbase: fee_collector(),
+        };
+
+        self.executor
+            .execute(transaction, state, &context)
+            .map(|result| result.gas_used)
+            .map_err(|e| ConsensusError::InvalidBlock(format!("Transaction failed: {}", e)))
+    }
+
+    /// Close a block by paying its rewards and distributing the collected fees
+    pub fn finalize_block(
+        &self,
+        state: &SharedStateDB,
+        header: &BlockHeader,
+        author: Address,
+    ) -> ConsensusResult<BlockRewards> {
+        self.issuance
+            .apply_block(state, header.number, author)
+            .map_err(|e| ConsensusError::Other(format!("Failed to apply block rewards: {}", e)))
+    }
+
+    /// Execute all transactions of a block and close it
+    ///
+    /// Returns the total gas used.
+    pub fn execute_block(
+        &self,
+        state: &SharedStateDB,
+        block: &Block,
+        author: Address,
+    ) -> ConsensusResult<u64> {
+        let mut gas_used = 0u64;
+        for transaction in &block.transactions {
+            gas_used += self.execute_transaction(state, &block.header, transaction)?;
+        }
+        self.finalize_block(state, &block.header, author)?;
+        Ok(gas_used)
+    }
+}
+
+/// A recent block with the state entries it wrote
+struct ImportedBlock {
+    /// The block header
+    header: BlockHeader,
+    /// Entries written by executing the block, removed ones left empty
+    changes: Vec<StateEntry>,
+    /// Sum of difficulties from the backend's first block up to this one
+    total_difficulty: u128,
+}
+
+/// Recent blocks on the best chain and side branches
+struct ChainTree {
+    /// Imported blocks by hash, pruned below the reorg depth
+    blocks: HashMap<Hash, ImportedBlock>,
+    /// Hash of the best block
+    best: Hash,
+    /// State after the best block
+    best_state: SharedStateDB,
+    /// Hash of the lowest recent block, which all others descend from
+    base: Hash,
+    /// State after the base block, never handed out
+    base_state: SharedStateDB,
+}
+
+impl ChainTree {
+    /// Start a tree at a block and the state after it
+    fn new(hash: Hash, header: BlockHeader, state: SharedStateDB) -> Self {
+        let imported = ImportedBlock {
+            total_difficulty: header.difficulty as u128,
+            header,
+            changes: Vec::new(),
+        };
+        Self {
+            blocks: HashMap::from([(hash, imported)]),
+            best: hash,
+            base_state: state.fork(),
+            best_state: state,
+            base: hash,
+        }
+    }
+
+    fn best(&self) -> &ImportedBlock {
+        &self.blocks[&self.best]
+    }
+
+    /// Rebuild the state after a recent block on any branch
+    ///
+    /// The result is a copy the caller may execute on.
+    fn state_at(&self, hash: &Hash) -> ConsensusResult<Option<SharedStateDB>> {
+        if *hash == self.best {
+            return Ok(Some(self.best_state.fork()));
+        }
+
+        let mut route = Vec::new();
+        let mut next = *hash;
+        while next != self.base {
+            let Some(imported) = self.blocks.get(&next) else {
+                return Ok(None);
+            };
+            route.push(&imported.changes);
+            next = imported.header.parent_hash;
+        }
+        let state = self.base_state.fork();
+        for changes in route.into_iter().rev() {
+            apply_changes(&state, changes)?;
+        }
+        Ok(Some(state))
+    }
+
+    /// Drop blocks too far below the best block to be forked from, moving
+    /// the base up the best chain
+    fn prune(&mut self) -> ConsensusResult<()> {
+        let lowest = self.best().header.number.saturating_sub(MAX_REORG_DEPTH);
+        if self.blocks[&self.base].header.number >= lowest {
+            return Ok(());
+        }
+
+        let mut base = self.best;
+        while self.blocks[&base].header.number > lowest {
+            base = self.blocks[&base].header.parent_hash;
+        }
+        let mut route = Vec::new();
+        let mut next = base;
+        while next != self.base {
+            let imported = &self.blocks[&next];
+            route.push(&imported.changes);
+            next = imported.header.parent_hash;
+        }
+        for changes in route.into_iter().rev() {
+            apply_changes(&self.base_state, changes)?;
+        }
+        self.base = base;
+
+        // Keep the blocks still descending from the base
+        let mut above: Vec<_> = self
+            .blocks
+            .iter()
+            .filter(|(_, imported)| imported.header.number > lowest)
+            .map(|(hash, imported)| (imported.header.number, *hash))
+            .collect();
+        above.sort_unstable_by_key(|(number, _)| *number);
+        let mut kept = HashSet::from([base]);
+        for (_, hash) in above {
+            if kept.contains(&self.blocks[&hash].header.parent_hash) {
+                kept.insert(hash);
+            }
+        }
+        self.blocks.retain(|hash, _| kept.contains(hash));
+        Ok(())
+    }
+}
+
+/// Write a block's state entries on top of its parent's state
+fn apply_changes(state: &SharedStateDB, changes: &[StateEntry]) -> ConsensusResult<()> {
+    state
+        .apply_entries(changes)
+        .map_err(|e| ConsensusError::Other(format!("Failed to rebuild state: {}", e)))
+}
+
+/// [`ChainBackend`] executing blocks on the node state and storing them in the database
+pub struct NodeBackend {
+    /// Node database
+    db: Arc<dyn KeyValueDB>,
+    /// Recent blocks and their states
+    chain: RwLock<ChainTree>,
+    /// Block execution
+    executor: BlockExecutor,
+    /// Account credited with each block's rewards
+    author: AuthorResolver,
+}
+
+impl NodeBackend {
+    /// Create a backend on top of a block and the state it commits to
+    ///
+    /// The block's header and state are stored as the best block.
+    pub fn new(
+        db: Arc<dyn KeyValueDB>,
+        state: SharedStateDB,
+        best: BlockHeader,
+        executor: BlockExecutor,
+        author: AuthorResolver,
+    ) -> ConsensusResult<Self> {
+        let hash = hash_header(&best)?;
+        let mut batch = TransactionBuilder::new();
+        batch
+            .put(
+                ColumnFamily::Headers.name(),
+                hash.as_bytes(),
+                &serde_json::to_vec(&best)?,
+            )
+            .put(
+                ColumnFamily::Indices.name(),
+                &best.number.to_be_bytes(),
+                hash.as_bytes(),
+            );
+        write_state(db.as_ref(), &mut batch, &state.entries())?;
+        batch
+            .execute(db.as_ref())
+            .map_err(|e| ConsensusError::Other(format!("Failed to store state: {}", e)))?;
+
+        Ok(Self {
+            db,
+            chain: RwLock::new(ChainTree::new(hash, best, state)),
+            executor,
+            author,
+        })
+    }
+
+    /// Store verified headers of the best chain without their bodies
+    ///
+    /// Used by warp sync, which downloads the headers up to its pivot before
+    /// the state; the headers are indexed but cannot be built on.
+    pub fn import_headers(&self, headers: &[BlockHeader]) -> ConsensusResult<()> {
+        let mut batch = TransactionBuilder::new();
+        for header in headers {
+            let hash = hash_header(header)?;
+            batch
+                .put(
+                    ColumnFamily::Headers.name(),
+                    hash.as_bytes(),
+                    &serde_json::to_vec(header)?,
+                )
+                .put(
+                    ColumnFamily::Indices.name(),
+                    &header.number.to_be_bytes(),
+                    hash.as_bytes(),
+                );
+        }
+        batch
+            .execute(self.db.as_ref())
+            .map_err(|e| ConsensusError::Other(format!("Failed to store headers: {}", e)))
+    }
+
+    /// Make a block the best block on top of the state it commits to,
+    /// dropping all other recent blocks
+    ///
+    /// Used by warp sync to install the state downloaded at its pivot.
+    pub fn reset_to(&self, best: BlockHeader, state: SharedStateDB) -> ConsensusResult<()> {
+        let state_root = state.state_root();
+        if state_root != best.state_root {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Block #{} state root mismatch: expected {}, got {}",
+                best.number, best.state_root, state_root
+            )));
+        }
+        let hash = hash_header(&best)?;
+
+        let mut chain = self.chain.write().unwrap();
+        let mut batch = TransactionBuilder::new();
+        batch
+            .put(
+                ColumnFamily::Headers.name(),
+                hash.as_bytes(),
+                &serde_json::to_vec(&best)?,
+            )
+            .put(
+                ColumnFamily::Indices.name(),
+                &best.number.to_be_bytes(),
+                hash.as_bytes(),
+            );
+        for number in best.number + 1..=chain.best().header.number {
+            batch.delete(ColumnFamily::Indices.name(), &number.to_be_bytes());
+        }
+        write_state(self.db.as_ref(), &mut batch, &state.entries())?;
+        batch
+            .execute(self.db.as_ref())
+            .map_err(|e| ConsensusError::Other(format!("Failed to store state: {}", e)))?;
+
+        info!("Reset to block #{} at {}", best.number, state_root);
+        *chain = ChainTree::new(hash, best, state);
+        Ok(())
+    }
+
+    /// Get the state at the best block
+    pub fn state(&self) -> SharedStateDB {
+        self.chain.read().unwrap().best_state.clone()
+    }
+
+    /// Get a copy of the state after a recent block on any branch
+    pub fn state_at(&self, hash: &Hash) -> Option<SharedStateDB> {
+        self.chain.read().unwrap().state_at(hash).ok().flatten()
+    }
+
+    /// Get a copy of the state of a recent block on any branch by its root
+    pub fn state_with_root(&self, root: &Hash) -> Option<SharedStateDB> {
+        let chain = self.chain.read().unwrap();
+        let (hash, _) = chain
+            .blocks
+            .iter()
+            .find(|(_, imported)| imported.header.state_root == *root)?;
+        chain.state_at(hash).ok().flatten()
+    }
+
+    /// Blocks from `tip` back to the first one already on the best chain,
+    /// highest first
+    fn canonical_route(
+        &self,
+        chain: &ChainTree,
+        tip: Hash,
+    ) -> ConsensusResult<Vec<(BlockNumber, Hash)>> {
+        let mut route = Vec::new();
+        let mut next = Some(tip);
+        while let Some(imported) = next.and_then(|hash| chain.blocks.get(&hash)) {
+            let hash = next.unwrap();
+            let number = imported.header.number;
+            let indexed = self
+                .db
+                .get(ColumnFamily::Indices.name(), &number.to_be_bytes())
+                .map_err(|e| ConsensusError::Other(format!("Failed to read index: {}", e)))?;
+            if indexed.as_deref() == Some(hash.as_bytes()) {
+                break;
+            }
+            route.push((number, hash));
+            next = Some(imported.header.parent_hash);
+        }
+        Ok(route)
+    }
+
+    /// Write an imported block along with the state writes in `batch`, and
+    /// index the blocks in `route` as the best chain up to `best_number`
+    fn store_block(
+        &self,
+        mut batch: TransactionBuilder,
+        block: &Block,
+        hash: &Hash,
+        route: &[(BlockNumber, Hash)],
+        best_number: BlockNumber,
+        previous_best: BlockNumber,
+    ) -> ConsensusResult<()> {
+        batch
+            .put(
+                ColumnFamily::Headers.name(),
+                hash.as_bytes(),
+                &serde_json::to_vec(&block.header)?,
+            )
+            .put(
+                ColumnFamily::Blocks.name(),
+                hash.as_bytes(),
+                &serde_json::to_vec(block)?,
+            );
+        for (number, hash) in route {
+            batch.put(
+                ColumnFamily::Indices.name(),
+                &number.to_be_bytes(),
+                hash.as_bytes(),
+            );
+        }
+        // A shorter branch taking over leaves no canonical blocks above it
+        for number in best_number + 1..=previous_best {
+            batch.delete(ColumnFamily::Indices.name(), &number.to_be_bytes());
+        }
+        batch
+            .execute(self.db.as_ref())
+            .map_err(|e| ConsensusError::Other(format!("Failed to store block: {}", e)))
+    }
+}
+
+/// Add the writes turning the `state` column family into `entries` to a batch
+///
+/// Both sides are sorted by key, so only changed entries are written.
+fn write_state(
+    db: &dyn KeyValueDB,
+    batch: &mut TransactionBuilder,
+    entries: &[StateEntry],
+) -> ConsensusResult<()> {
+    let cf = ColumnFamily::State.name();
+    let read_error = |e| ConsensusError::Other(format!("Failed to read state: {}", e));
+    let mut entries = entries.iter().peekable();
+    for stored in db.iter(cf).map_err(read_error)? {
+        let (key, value) = stored.map_err(read_error)?;
+        while let Some((new_key, new_value)) = entries.next_if(|(new_key, _)| *new_key < key) {
+            batch.put(cf, new_key, new_value);
+        }
+        match entries.next_if(|(new_key, _)| *new_key == key) {
+            Some((_, new_value)) if *new_value == value => {}
+            Some((_, new_value)) => {
+                batch.put(cf, &key, new_value);
+            }
+            None => {
+                batch.delete(cf, &key);
+            }
+        }
+    }
+    for (key, value) in entries {
+        batch.put(cf, key, value);
+    }
+    Ok(())
+}
+
+/// Hash a header, mapping failures to a consensus error
+fn hash_header(header: &BlockHeader) -> ConsensusResult<Hash> {
+    header
+        .hash()
+        .map_err(|e| ConsensusError::Other(format!("Failed to hash header: {}", e)))
+}
+
+impl ChainBackend for NodeBackend {
+    fn best_header(&self) -> ConsensusResult<BlockHeader> {
+        Ok(self.chain.read().unwrap().best().header.clone())
+    }
+
+    fn build_block(
+        &self,
+        mut header: BlockHeader,
+        transactions: Vec<Transaction>,
+    ) -> ConsensusResult<Block> {
+        let author = (self.author)(&header)?;
+        let state = self
+            .chain
+            .read()
+            .unwrap()
+            .state_at(&header.parent_hash)?
+            .ok_or_else(|| {
+                ConsensusError::InvalidBlock(format!(
+                    "Unknown parent {} of block #{}",
+                    header.parent_hash, header.number
+                ))
+            })?;
+
+        let mut included = Vec::new();
+        let mut gas_used = 0u64;
+        for transaction in transactions {
+            match self
+                .executor
+                .execute_transaction(&state, &header, &transaction)
+            {
+                Ok(gas) => {
+                    gas_used += gas;
+                    included.push(transaction);
+                }
+                Err(e) => debug!("Leaving out transaction: {}", e),
+            }
+        }
+
+        let rewards = self.executor.finalize_block(&state, &header, author)?;
+        debug!(
+            "Built block #{} paying {} to {}",
+            header.number,
+            rewards.proposer_total(),
+            author
+        );
+
+        header.gas_used = gas_used;
+        header.state_root = state.state_root();
+        let mut block = Block::new(header, included);
+        block.header.transactions_root = block.calculate_transactions_root().map_err(|e| {
+            ConsensusError::Other(format!("Failed to compute transactions root: {}", e))
+        })?;
+        Ok(block)
+    }
+
+    fn import_block(&self, block: Block) -> ConsensusResult<()> {
+        let hash = hash_header(&block.header)?;
+        let (state, parent_difficulty) = {
+            let chain = self.chain.read().unwrap();
+            if chain.blocks.contains_key(&hash) {
+                return Ok(());
+            }
+            let parent = chain
+                .blocks
+                .get(&block.header.parent_hash)
+                .filter(|parent| parent.header.number + 1 == block.header.number)
+                .ok_or_else(|| {
+                    ConsensusError::InvalidBlock(format!(
+                        "Block #{} does not extend a recent block",
+                        block.header.number
+                    ))
+                })?;
+            let state = chain.state_at(&block.header.parent_hash)?.ok_or_else(|| {
+                ConsensusError::InvalidBlock(format!(
+                    "Block #{} does not extend a recent block",
+                    block.header.number
+                ))
+            })?;
+            (state, parent.total_difficulty)
+        };
+
+        let transactions_root = block.calculate_transactions_root().map_err(|e| {
+            ConsensusError::Other(format!("Failed to compute transactions root: {}", e))
+        })?;
+        if transactions_root != block.header.transactions_root {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Block #{} transactions root mismatch: expected {}, got {}",
+                block.header.number, block.header.transactions_root, transactions_root
+            )));
+        }
+
+        let author = (self.author)(&block.header)?;
+        let gas_used = self.executor.execute_block(&state, &block, author)?;
+
+        if gas_used != block.header.gas_used {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Block #{} gas used mismatch: expected {}, got {}",
+                block.header.number, block.header.gas_used, gas_used
+            )));
+        }
+        let state_root = state.state_root();
+        if state_root != block.header.state_root {
+            return Err(ConsensusError::InvalidBlock(format!(
+                "Block #{} state root mismatch: expected {}, got {}",
+                block.header.number, block.header.state_root, state_root
+            )));
+        }
+
+        let mut chain = self.chain.write().unwrap();
+        let total_difficulty = parent_difficulty + block.header.difficulty as u128;
+        let previous_best = chain.best().header.number;
+        let is_best = total_difficulty > chain.best().total_difficulty;
+        let changes = state.changes();
+        let mut batch = TransactionBuilder::new();
+        if is_best && block.header.parent_hash == chain.best {
+            // Extending the best block only touches the entries it wrote
+            let cf = ColumnFamily::State.name();
+            for (key, value) in &changes {
+                if value.is_empty() {
+                    batch.delete(cf, key);
+                } else {
+                    batch.put(cf, key, value);
+                }
+            }
+        } else if is_best {
+            write_state(self.db.as_ref(), &mut batch, &state.entries())?;
+        }
+        chain.blocks.insert(
+            hash,
+            ImportedBlock {
+                header: block.header.clone(),
+                changes,
+                total_difficulty,
+            },
+        );
+
+        if !is_best {
+            self.store_block(batch, &block, &hash, &[], previous_best, previous_best)?;
+            debug!("Stored side-branch block #{}", block.header.number);
+            return Ok(());
+        }
+
+        let route = self.canonical_route(&chain, hash)?;
+        self.store_block(
+            batch,
+            &block,
+            &hash,
+            &route,
+            block.header.number,
+            previous_best,
+        )?;
+        if block.header.parent_hash != chain.best {
+            info!(
+                "Switched to a heavier branch at block #{}",
+                block.header.number
+            );
+        }
+        info!("Imported block #{} at {}", block.header.number, state_root);
+        chain.best = hash;
+        chain.best_state = state;
+        chain.prune()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TestBackend, TestNode};
+    use chain_core::RewardConfig;
+
+    const SENDER_KEY: [u8; 32] = [7u8; 32];
+
+    fn address(byte: u8) -> Address {
+        Address::new([byte; 20])
+    }
+
+    fn transfer(nonce: u64) -> Transaction {
+        let mut tx = Transaction::transfer(nonce, address(2), 100, 1, 100_000);
+        tx.sign(&SENDER_KEY).unwrap();
+        tx
+    }
+
+    fn test_backend(author: Address) -> TestBackend {
+        let rewards = RewardConfig {
+            block_reward: 1_000,
+            fee_burn_bps: 5_000,
+            ..Default::default()
+        };
+        TestBackend::new()
+            .with_rewards(rewards)
+            .with_balance(transfer(0).sender().unwrap(), 1_000_000)
+            .with_author(author)
+    }
+
+    fn child_of(parent: &BlockHeader, delay: u64) -> BlockHeader {
+        BlockHeader {
+            parent_hash: parent.hash().unwrap(),
+            number: parent.number + 1,
+            timestamp: parent.timestamp + delay,
+            extra_data: vec![],
+            ..parent.clone()
+        }
+    }
+
+    fn next_header(backend: &NodeBackend) -> BlockHeader {
+        child_of(&backend.best_header().unwrap(), 1_000)
+    }
+
+    #[test]
+    fn test_blocks_pay_rewards_and_fees() {
+        let author = address(9);
+        let fixture = test_backend(author);
+        let issuance = fixture.issuance();
+        let TestNode { db, backend, .. } = &fixture.build();
+
+        // Unsigned transactions are left out
+        let unsigned = Transaction::transfer(1, address(2), 100, 1, 100_000);
+        let block = backend
+            .build_block(next_header(backend), vec![transfer(0), unsigned])
+            .unwrap();
+        assert_eq!(block.transactions, vec![transfer(0)]);
+        let fees = block.header.gas_used;
+        assert!(fees > 0);
+        backend.import_block(block.clone()).unwrap();
+
+        let state = backend.state();
+        let balance = |who: &Address| state.get_account(who).unwrap().unwrap_or_default().balance;
+        assert_eq!(balance(&author), 1_000 + fees / 2);
+        assert_eq!(balance(&fee_collector()), 0);
+        assert_eq!(balance(&address(2)), 100);
+        assert_eq!(
+            issuance.total_issuance(&state).unwrap(),
+            1_000_000 + 1_000 - (fees - fees / 2) as u128
+        );
+
+        // The block is stored and is the new best block
+        assert_eq!(backend.best_header().unwrap(), block.header);
+        let hash = block.hash().unwrap();
+        assert_eq!(
+            db.get(ColumnFamily::Indices.name(), &1u64.to_be_bytes())
+                .unwrap(),
+            Some(hash.as_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_rejects_block_with_wrong_rewards() {
+        let author_node = test_backend(address(9)).build();
+        let author_backend = &author_node.backend;
+        let importer_node = test_backend(address(8)).build();
+        let importer = &importer_node.backend;
+
+        // The importer credits a different author, so the state roots differ
+        let block = author_backend
+            .build_block(next_header(author_backend), vec![transfer(0)])
+            .unwrap();
+        assert!(matches!(
+            importer.import_block(block),
+            Err(ConsensusError::InvalidBlock(_))
+        ));
+        assert_eq!(importer.best_header().unwrap().number, 0);
+    }
+
+    #[test]
+    fn test_rejects_block_with_wrong_transactions_root() {
+        let node = test_backend(address(9)).build();
+        let backend = &node.backend;
+
+        // A body swapped under a header with an empty transfer list
+        let empty = backend.build_block(next_header(backend), vec![]).unwrap();
+        let swapped = Block::new(empty.header.clone(), vec![transfer(0)]);
+        assert!(matches!(
+            backend.import_block(swapped),
+            Err(ConsensusError::InvalidBlock(_))
+        ));
+        assert_eq!(backend.best_header().unwrap().number, 0);
+
+        backend.import_block(empty).unwrap();
+        assert_eq!(backend.best_header().unwrap().number, 1);
+    }
+
+    #[test]
+    fn test_switches_to_heavier_branch() {
+        let TestNode { db, backend, .. } = &test_backend(address(9)).build();
+        let genesis = backend.best_header().unwrap();
+        let index = |number: u64| {
+            db.get(ColumnFamily::Indices.name(), &number.to_be_bytes())
+                .unwrap()
+        };
+
+        let a1 = backend
+            .build_block(child_of(&genesis, 1_000), vec![transfer(0)])
+            .unwrap();
+        backend.import_block(a1.clone()).unwrap();
+
+        // A sibling with the same total difficulty is kept on a side branch
+        let b1 = backend
+            .build_block(child_of(&genesis, 2_000), vec![])
+            .unwrap();
+        backend.import_block(b1.clone()).unwrap();
+        assert_eq!(backend.best_header().unwrap(), a1.header);
+        assert_eq!(index(1), Some(a1.hash().unwrap().as_bytes().to_vec()));
+        assert!(db
+            .get(ColumnFamily::Blocks.name(), b1.hash().unwrap().as_bytes())
+            .unwrap()
+            .is_some());
+
+        // Extending the side branch makes it heavier
+        let b2 = backend
+            .build_block(child_of(&b1.header, 1_000), vec![])
+            .unwrap();
+        backend.import_block(b2.clone()).unwrap();
+        assert_eq!(backend.best_header().unwrap(), b2.header);
+        assert_eq!(index(1), Some(b1.hash().unwrap().as_bytes().to_vec()));
+        assert_eq!(index(2), Some(b2.hash().unwrap().as_bytes().to_vec()));
+
+        // The transfer only happened on the old branch
+        let balance = |state: SharedStateDB| {
+            state
+                .get_account(&address(2))
+                .unwrap()
+                .unwrap_or_default()
+                .balance
+        };
+        assert_eq!(balance(backend.state()), 0);
+        assert_eq!(balance(backend.state_at(&a1.hash().unwrap()).unwrap()), 100);
+
+        // The old branch can still be extended without taking over
+        let a2 = backend
+            .build_block(child_of(&a1.header, 1_000), vec![])
+            .unwrap();
+        backend.import_block(a2).unwrap();
+        assert_eq!(backend.best_header().unwrap(), b2.header);
+        assert_eq!(index(2), Some(b2.hash().unwrap().as_bytes().to_vec()));
+
+        // Blocks on unknown parents are rejected
+        let orphan = child_of(&child_of(&b2.header, 1_000), 1_000);
+        assert!(matches!(
+            backend.build_block(orphan, vec![]),
+            Err(ConsensusError::InvalidBlock(_))
+        ));
+    }
+
+    #[test]
+    fn test_rebuilds_states_down_to_reorg_depth() {
+        let TestNode { db, backend, .. } = &test_backend(address(9)).build();
+        let genesis = backend.best_header().unwrap();
+        let side = backend
+            .build_block(child_of(&genesis, 2_000), vec![])
+            .unwrap();
+        backend.import_block(side.clone()).unwrap();
+
+        let mut blocks = Vec::new();
+        for number in 1..=MAX_REORG_DEPTH + 2 {
+            let transactions = if number == 1 {
+                vec![transfer(0)]
+            } else {
+                vec![]
+            };
+            let block = backend
+                .build_block(next_header(backend), transactions)
+                .unwrap();
+            backend.import_block(block.clone()).unwrap();
+            blocks.push(block);
+        }
+
+        // States are rebuilt from the entries each block wrote
+        for block in &blocks[1..] {
+            let state = backend.state_at(&block.hash().unwrap()).unwrap();
+            assert_eq!(state.state_root(), block.header.state_root);
+            assert_eq!(
+                state.get_account(&address(2)).unwrap().unwrap().balance,
+                100
+            );
+        }
+
+        // Blocks below the reorg depth, and branches off them, are dropped
+        assert!(backend.state_at(&blocks[0].hash().unwrap()).is_none());
+        assert!(backend.state_at(&side.hash().unwrap()).is_none());
+        assert!(backend
+            .build_block(child_of(&side.header, 1_000), vec![])
+            .is_err());
+
+        // The stored state follows the entries each block wrote
+        let stored = db
+            .iter(ColumnFamily::State.name())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(stored, backend.state().entries());
+    }
+}
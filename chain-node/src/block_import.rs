@@ -0,0 +1,145 @@
+//! Import of blocks announced over gossip
+//!
+//! The network's gossip handler checks announced headers with an
+//! [`EngineHeaderVerifier`] before fetching their bodies and forwarding the
+//! announcements. That check is cheap and incomplete, so the blocks it lets
+//! through are handed to the consensus worker, which verifies them against
+//! their parent and executes them before they are stored.
+
+use crate::verifier::EngineHeaderVerifier;
+use chain_consensus::{SharedEngine, WorkerHandle};
+use chain_network::{BlockAnnounceHandler, NetworkHandle, QueuedBlock};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Build the handler of block announcements for a network
+///
+/// Headers are checked with the consensus worker's
+/// [`engine`](chain_consensus::ConsensusWorker::engine), which follows the
+/// authority sets of the blocks it imports. Pass the handler to the network's
+/// [`GossipHandler`](chain_network::GossipHandler) and the queue to
+/// [`import_announced_blocks`].
+pub fn block_announce_handler(
+    network: &NetworkHandle,
+    engine: SharedEngine,
+) -> (BlockAnnounceHandler, mpsc::Receiver<QueuedBlock>) {
+    BlockAnnounceHandler::new(
+        Arc::new(EngineHeaderVerifier::new(engine)),
+        network.sync.clone(),
+        network.reputation.clone(),
+    )
+}
+
+/// Hand queued blocks to the consensus worker until either side stops
+pub async fn import_announced_blocks(mut queue: mpsc::Receiver<QueuedBlock>, worker: WorkerHandle) {
+    while let Some(QueuedBlock { peer_id, block }) = queue.recv().await {
+        debug!(
+            "Importing block #{} announced by {}",
+            block.header.number, peer_id
+        );
+        if let Err(e) = worker.import_block(block) {
+            warn!("Stopped importing announced blocks: {}", e);
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestBackend;
+    use chain_consensus::{
+        ChainBackend, ConsensusWorker, Engine, InstantSealConfig, InstantSealEngine,
+        TransactionPool,
+    };
+    use chain_core::{Block, BlockHeader, Hash, Transaction};
+    use chain_network::block_announce::AnnounceOutcome;
+    use chain_network::message::BlockAnnounce;
+    use chain_network::{NetworkConfig, NetworkService, PeerId, PeerIdentity};
+    use std::time::Duration;
+
+    /// Pool that never has anything to author
+    struct EmptyPool;
+
+    impl TransactionPool for EmptyPool {
+        fn pending_count(&self) -> usize {
+            0
+        }
+
+        fn ready_transactions(&self, _gas_limit: u64) -> Vec<Transaction> {
+            vec![]
+        }
+
+        fn remove_included(&self, _transactions: &[Transaction]) {}
+    }
+
+    fn instant_seal() -> Box<dyn Engine> {
+        let config = InstantSealConfig {
+            poll_interval: 10,
+            seal_empty_blocks: false,
+        };
+        Box::new(InstantSealEngine::new(config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_announced_blocks_fully_checked_on_import() {
+        let (_service, network) =
+            NetworkService::new(NetworkConfig::new(), &PeerIdentity::generate()).unwrap();
+        let node = TestBackend::new().build();
+        let backend = node.backend.clone();
+        let (worker, worker_handle) =
+            ConsensusWorker::new(instant_seal(), backend.clone(), Arc::new(EmptyPool));
+        let (mut announces, queue) = block_announce_handler(&network, worker.engine());
+        tokio::spawn(worker.run());
+        tokio::spawn(import_announced_blocks(queue, worker_handle.clone()));
+
+        let genesis = backend.best_header().unwrap();
+        let header = BlockHeader {
+            parent_hash: genesis.hash().unwrap(),
+            number: 1,
+            timestamp: genesis.timestamp + 1_000,
+            ..genesis
+        };
+        let valid = backend.build_block(header, vec![]).unwrap();
+        let mut wrong_state = valid.clone();
+        wrong_state.header.state_root = Hash::new([1u8; 32]);
+        let mut future = valid.clone();
+        future.header.timestamp = u64::MAX / 2;
+
+        let peer_id = PeerId::random();
+        let announce =
+            |block: &Block| BlockAnnounce::new(block.header.clone()).with_block(block.clone());
+        // Headers the engine rejects are never fetched or queued
+        assert_eq!(
+            announces
+                .handle_announce(announce(&future), peer_id)
+                .await
+                .unwrap(),
+            AnnounceOutcome::Invalid
+        );
+        // The header check does not execute the block, so this one is only
+        // caught on import
+        for block in [&wrong_state, &valid] {
+            assert_eq!(
+                announces
+                    .handle_announce(announce(block), peer_id)
+                    .await
+                    .unwrap(),
+                AnnounceOutcome::Queued
+            );
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while worker_handle.stats().blocks_imported == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let stats = worker_handle.stats();
+        assert_eq!(stats.import_failures, 1);
+        assert_eq!(backend.best_header().unwrap(), valid.header);
+        worker_handle.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+//! Chain reader over the node database
+//!
+//! Serves sync requests from the column families written by block import:
+//! `indices` maps big-endian block numbers to block hashes, `headers` and
+//! `blocks` hold JSON-encoded headers and blocks by hash, `receipts` holds
+//! encoded receipts by hash and `state` holds the current state entries.
+//! The reader commits to the stored state once per state root, on the first
+//! range request for it, so blocks are imported without rehashing the state
+//! and range proofs are served without rehashing it again. States of recent blocks
+//! below the best one, such as a warp sync pivot, can be served from memory
+//! through a [`RecentStateResolver`].
+
+use chain_core::{Block, BlockHeader, BlockNumber, Hash, MerkleTree, RangeProof, Transaction};
+use chain_db::column_families::ColumnFamily;
+use chain_db::{DbError, KeyValueDB};
+use chain_network::chain::StateEntry;
+use chain_network::{ChainReader, NetworkError, NetworkResult};
+use serde::de::DeserializeOwned;
+use std::sync::{Arc, RwLock};
+
+/// Commitment to the entries of the `state` column family
+struct StateCommitment {
+    /// Root of the tree
+    root: Hash,
+    /// Stored keys in order, giving the leaf position of each entry
+    keys: Vec<Vec<u8>>,
+    /// Tree over the stored entries
+    tree: MerkleTree,
+}
+
+/// Resolves the entries of a recent state by its root
+pub type RecentStateResolver = Arc<dyn Fn(&Hash) -> Option<Vec<StateEntry>> + Send + Sync>;
+
+/// Entries of a recent state held in memory
+struct RecentState {
+    /// Root of the tree
+    root: Hash,
+    /// Entries sorted by key
+    entries: Vec<StateEntry>,
+    /// Tree over the entries
+    tree: MerkleTree,
+}
+
+/// [`ChainReader`] backed by a [`KeyValueDB`]
+pub struct DbChainReader {
+    /// Node database
+    db: Arc<dyn KeyValueDB>,
+    /// Root of the state held in the `state` column family
+    state_root: RwLock<Option<Hash>>,
+    /// Commitment to that state, built when a range of it is first requested
+    state: RwLock<Option<Arc<StateCommitment>>>,
+    /// Source of states not in the `state` column family
+    recent_states: Option<RecentStateResolver>,
+    /// Last recent state served, kept while peers download it
+    recent: RwLock<Option<Arc<RecentState>>>,
+}
+
+impl DbChainReader {
+    /// Create a reader over a database
+    pub fn new(db: Arc<dyn KeyValueDB>) -> Self {
+        Self {
+            db,
+            state_root: RwLock::new(None),
+            state: RwLock::new(None),
+            recent_states: None,
+            recent: RwLock::new(None),
+        }
+    }
+
+    /// Also serve the recent states found by a resolver
+    pub fn with_recent_states(mut self, resolver: RecentStateResolver) -> Self {
+        self.recent_states = Some(resolver);
+        self
+    }
+
+    /// Serve state for the given root
+    pub fn with_state_root(self, root: Hash) -> Self {
+        self.set_state_root(root);
+        self
+    }
+
+    /// Serve the stored state after importing a block
+    ///
+    /// Range requests fail if the entries in the `state` column family do
+    /// not commit to `root`.
+    pub fn set_state_root(&self, root: Hash) {
+        *self.state_root.write().unwrap() = Some(root);
+    }
+
+    fn has_state(&self, root: &Hash) -> bool {
+        *self.state_root.read().unwrap() == Some(*root)
+    }
+
+    /// Get the commitment to the stored state, building it on first use
+    fn commitment(&self, root: &Hash) -> NetworkResult<Arc<StateCommitment>> {
+        if let Some(state) = &*self.state.read().unwrap() {
+            if state.root == *root {
+                return Ok(state.clone());
+            }
+        }
+
+        let entries = self
+            .db
+            .iter(ColumnFamily::State.name())
+            .map_err(storage_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(storage_error)?;
+        let tree = MerkleTree::new(&entries);
+        if tree.root() != *root {
+            return Err(NetworkError::Sync(format!(
+                "Stored state commits to {}, not {}",
+                tree.root(),
+                root
+            )));
+        }
+
+        let keys = entries.into_iter().map(|(key, _)| key).collect();
+        let state = Arc::new(StateCommitment {
+            root: *root,
+            keys,
+            tree,
+        });
+        *self.state.write().unwrap() = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Get a recent state, committing to it on first use
+    fn recent_state(&self, root: &Hash) -> Option<Arc<RecentState>> {
+        if let Some(recent) = &*self.recent.read().unwrap() {
+            if recent.root == *root {
+                return Some(recent.clone());
+            }
+        }
+
+        let entries = (self.recent_states.as_ref()?)(root)?;
+        let tree = MerkleTree::new(&entries);
+        if tree.root() != *root {
+            return None;
+        }
+        let recent = Arc::new(RecentState {
+            root: *root,
+            entries,
+            tree,
+        });
+        *self.recent.write().unwrap() = Some(recent.clone());
+        Some(recent)
+    }
+
+    /// Read up to `limit` state entries from the first key not below `start`
+    fn read_state(
+        &self,
+        start: &[u8],
+        limit: usize,
+        prefix: &[u8],
+    ) -> NetworkResult<Vec<StateEntry>> {
+        self.db
+            .iter_from(ColumnFamily::State.name(), start)
+            .map_err(storage_error)?
+            .take_while(|entry| !matches!(entry, Ok((key, _)) if !key.starts_with(prefix)))
+            .take(limit)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(storage_error)
+    }
+
+    fn get(&self, cf: ColumnFamily, key: &[u8]) -> NetworkResult<Option<Vec<u8>>> {
+        self.db.get(cf.name(), key).map_err(storage_error)
+    }
+
+    fn get_json<T: DeserializeOwned>(
+        &self,
+        cf: ColumnFamily,
+        key: &[u8],
+    ) -> NetworkResult<Option<T>> {
+        match self.get(cf, key)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl ChainReader for DbChainReader {
+    fn header(&self, hash: &Hash) -> NetworkResult<Option<BlockHeader>> {
+        self.get_json(ColumnFamily::Headers, hash.as_bytes())
+    }
+
+    fn hash_at(&self, number: BlockNumber) -> NetworkResult<Option<Hash>> {
+        match self.get(ColumnFamily::Indices, &number.to_be_bytes())? {
+            Some(hash) if hash.len() == 32 => Ok(Some(Hash::from_slice(&hash))),
+            Some(hash) => Err(NetworkError::Sync(format!(
+                "Corrupt index entry for block {}: {} bytes",
+                number,
+                hash.len()
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    fn body(&self, hash: &Hash) -> NetworkResult<Option<Vec<Transaction>>> {
+        let block: Option<Block> = self.get_json(ColumnFamily::Blocks, hash.as_bytes())?;
+        Ok(block.map(|block| block.transactions))
+    }
+
+    fn receipts(&self, hash: &Hash) -> NetworkResult<Option<Vec<u8>>> {
+        self.get(ColumnFamily::Receipts, hash.as_bytes())
+    }
+
+    fn state_entries(
+        &self,
+        root: &Hash,
+        prefix: &[u8],
+        start: &[u8],
+        limit: usize,
+    ) -> NetworkResult<Option<Vec<StateEntry>>> {
+        if self.has_state(root) {
+            return Ok(Some(self.read_state(start.max(prefix), limit, prefix)?));
+        }
+
+        let Some(recent) = self.recent_state(root) else {
+            return Ok(None);
+        };
+        let start = start.max(prefix);
+        let first = recent
+            .entries
+            .partition_point(|(key, _)| key.as_slice() < start);
+        Ok(Some(
+            recent.entries[first..]
+                .iter()
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .take(limit)
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    fn state_range(
+        &self,
+        root: &Hash,
+        start: &[u8],
+        limit: usize,
+    ) -> NetworkResult<Option<(Vec<StateEntry>, RangeProof)>> {
+        if !self.has_state(root) {
+            return Ok(self.recent_state(root).map(|recent| {
+                let first = recent
+                    .entries
+                    .partition_point(|(key, _)| key.as_slice() < start);
+                let end = first.saturating_add(limit).min(recent.entries.len());
+                (
+                    recent.entries[first..end].to_vec(),
+                    recent.tree.prove(first, end),
+                )
+            }));
+        }
+
+        let state = self.commitment(root)?;
+        let first = state.keys.partition_point(|key| key.as_slice() < start);
+        let end = first.saturating_add(limit).min(state.keys.len());
+        let entries = self.read_state(start, end - first, &[])?;
+        if entries.len() != end - first {
+            return Err(NetworkError::Sync(format!(
+                "State at root {} changed while being served",
+                root
+            )));
+        }
+        Ok(Some((entries, state.tree.prove(first, end))))
+    }
+}
+
+pub(crate) fn storage_error(err: DbError) -> NetworkError {
+    NetworkError::Sync(format!("Storage error: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_core::Address;
+    use chain_db::traits::TransactionBuilder;
+    use chain_db::Database;
+
+    fn test_block(number: BlockNumber) -> Block {
+        let header = BlockHeader {
+            number,
+            ..BlockHeader::genesis()
+        };
+        let transaction = Transaction::transfer(number, Address::new([2u8; 20]), 100, 1, 21_000);
+        Block::new(header, vec![transaction])
+    }
+
+    fn test_reader() -> (tempfile::TempDir, Arc<dyn KeyValueDB>, DbChainReader) {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Arc<dyn KeyValueDB> = Arc::new(Database::open(dir.path()).unwrap());
+        let reader = DbChainReader::new(db.clone());
+        (dir, db, reader)
+    }
+
+    /// Store a block the way block import does
+    fn store_block(db: &dyn KeyValueDB, block: &Block) -> Hash {
+        let hash = block.hash().unwrap();
+        let mut batch = TransactionBuilder::new();
+        batch
+            .put(
+                ColumnFamily::Indices.name(),
+                &block.header.number.to_be_bytes(),
+                hash.as_bytes(),
+            )
+            .put(
+                ColumnFamily::Headers.name(),
+                hash.as_bytes(),
+                &serde_json::to_vec(&block.header).unwrap(),
+            )
+            .put(
+                ColumnFamily::Blocks.name(),
+                hash.as_bytes(),
+                &serde_json::to_vec(block).unwrap(),
+            );
+        batch.execute(db).unwrap();
+        hash
+    }
+
+    #[test]
+    fn test_reads_stored_blocks() {
+        let (_dir, db, reader) = test_reader();
+        let block = test_block(3);
+        let hash = store_block(db.as_ref(), &block);
+        db.put(ColumnFamily::Receipts.name(), hash.as_bytes(), &[1, 2, 3])
+            .unwrap();
+
+        assert_eq!(reader.hash_at(3).unwrap(), Some(hash));
+        assert_eq!(reader.header(&hash).unwrap(), Some(block.header.clone()));
+        assert_eq!(reader.header_at(3).unwrap(), Some(block.header.clone()));
+        assert_eq!(reader.body(&hash).unwrap(), Some(block.transactions));
+        assert_eq!(reader.receipts(&hash).unwrap(), Some(vec![1, 2, 3]));
+
+        // Unknown blocks are missing rather than errors
+        let unknown = Hash::new([9u8; 32]);
+        assert_eq!(reader.hash_at(4).unwrap(), None);
+        assert_eq!(reader.header_at(4).unwrap(), None);
+        assert_eq!(reader.header(&unknown).unwrap(), None);
+        assert_eq!(reader.body(&unknown).unwrap(), None);
+        assert_eq!(reader.receipts(&unknown).unwrap(), None);
+
+        // A truncated index entry is reported
+        db.put(ColumnFamily::Indices.name(), &5u64.to_be_bytes(), &[1, 2])
+            .unwrap();
+        assert!(reader.hash_at(5).is_err());
+    }
+
+    #[test]
+    fn test_serves_state_at_its_root() {
+        let (_dir, db, reader) = test_reader();
+        let stored: Vec<StateEntry> = [(&b"a1"[..], 1u8), (b"b1", 2), (b"b2", 3), (b"b3", 4)]
+            .into_iter()
+            .map(|(key, value)| (key.to_vec(), vec![value]))
+            .collect();
+        for (key, value) in &stored {
+            db.put(ColumnFamily::State.name(), key, value).unwrap();
+        }
+
+        // Nothing is served until the state root is known
+        let root = chain_core::entries_root(&stored);
+        assert_eq!(reader.state_entries(&root, b"", b"", 10).unwrap(), None);
+        assert_eq!(reader.state_range(&root, b"", 10).unwrap(), None);
+
+        // Ranges are not served under roots the stored entries do not commit to
+        let wrong = Hash::new([1u8; 32]);
+        reader.set_state_root(wrong);
+        assert!(reader.state_range(&wrong, b"", 10).is_err());
+
+        let reader = reader.with_state_root(root);
+        let entries = reader.state_entries(&root, b"b", b"", 2).unwrap().unwrap();
+        assert_eq!(
+            entries,
+            vec![(b"b1".to_vec(), vec![2]), (b"b2".to_vec(), vec![3])]
+        );
+
+        // Continuing from a key within the prefix
+        let entries = reader
+            .state_entries(&root, b"b", b"b3", 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entries, vec![(b"b3".to_vec(), vec![4])]);
+
+        // Starting before the prefix
+        let entries = reader.state_entries(&root, b"b", b"a", 1).unwrap().unwrap();
+        assert_eq!(entries, vec![(b"b1".to_vec(), vec![2])]);
+
+        // Ranges are proven against the root
+        let (entries, proof) = reader.state_range(&root, b"a2", 2).unwrap().unwrap();
+        assert_eq!(entries, stored[1..3].to_vec());
+        assert!(proof.verify(&root, &entries).is_ok());
+        let (entries, proof) = reader.state_range(&root, b"b3", 10).unwrap().unwrap();
+        assert_eq!(entries, stored[3..].to_vec());
+        assert!(proof.verify(&root, &entries).is_ok());
+
+        // Once the next block's state is committed, the old root is not served
+        db.put(ColumnFamily::State.name(), b"c1", &[5]).unwrap();
+        let mut updated = stored;
+        updated.push((b"c1".to_vec(), vec![5]));
+        let new_root = chain_core::entries_root(&updated);
+        reader.set_state_root(new_root);
+        assert_eq!(reader.state_entries(&root, b"", b"", 10).unwrap(), None);
+        let (entries, proof) = reader.state_range(&new_root, b"b3", 10).unwrap().unwrap();
+        assert_eq!(entries, updated[3..].to_vec());
+        assert!(proof.verify(&new_root, &entries).is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+//! Validator elections from the staking state
+
+use chain_consensus::traits::Validator;
+use chain_consensus::{ConsensusError, ConsensusResult, ElectionProvider};
+use chain_core::Hash;
+use chain_vm::{SharedStateDB, Staking};
+use std::sync::Arc;
+
+/// Resolves the state after a block, such as [`NodeBackend::state_at`](crate::NodeBackend::state_at)
+pub type StateResolver = Arc<dyn Fn(&Hash) -> Option<SharedStateDB> + Send + Sync>;
+
+/// [`ElectionProvider`] electing the bonded validators in the chain state
+///
+/// Each winner of the staking election becomes an authority weighted by its
+/// backing stake. The staking records are read from the state after the
+/// election block, so every node importing it elects the same set.
+pub struct StakingElection {
+    /// Staking rules
+    staking: Staking,
+    /// State after each recent block
+    state_at: StateResolver,
+}
+
+impl StakingElection {
+    /// Create an election provider over the staking records in the states of `state_at`
+    pub fn new(staking: Staking, state_at: StateResolver) -> Self {
+        Self { staking, state_at }
+    }
+}
+
+impl ElectionProvider for StakingElection {
+    fn elect(&self, epoch: u64, block: &Hash) -> ConsensusResult<Vec<Validator>> {
+        let state = (self.state_at)(block).ok_or_else(|| {
+            ConsensusError::AuthoritySet(format!(
+                "Election for epoch {} needs the state of unknown block {}",
+                epoch, block
+            ))
+        })?;
+        let result = self.staking.elect(&state).map_err(|e| {
+            ConsensusError::AuthoritySet(format!("Election for epoch {} failed: {}", epoch, e))
+        })?;
+
+        Ok(result
+            .winners
+            .into_iter()
+            .map(|winner| Validator {
+                address: winner.address,
+                weight: winner.backing,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TestBackend, TestNode, GENESIS_TIME};
+    use chain_consensus::poa::config::{
+        keyed_test_authorities, test_authority_address, test_authority_key,
+    };
+    use chain_consensus::{
+        ChainBackend, Clock, Engine, MockClock, PoAConfig, PoAEngine, StepContext, StepResult,
+    };
+    use chain_core::{Address, Block, Transaction};
+    use chain_vm::{StakingCall, StakingConfig};
+
+    fn staking() -> Staking {
+        Staking::new(StakingConfig {
+            validator_count: 2,
+            min_validator_bond: 1_000,
+            min_nominator_bond: 10,
+            max_nominations: 4,
+            bonding_duration: 2,
+        })
+    }
+
+    /// Signed staking call from test staker `index`
+    fn call(index: u8, nonce: u64, call: StakingCall) -> Transaction {
+        let mut tx = call.transaction(nonce, 1, 100_000).unwrap();
+        tx.sign(&[index + 10; 32]).unwrap();
+        tx
+    }
+
+    fn staker(index: u8) -> Address {
+        call(index, 0, StakingCall::Chill).sender().unwrap()
+    }
+
+    fn test_backend() -> TestNode {
+        (1..=4)
+            .fold(TestBackend::new(), |fixture, index| {
+                fixture.with_balance(staker(index), 1_000_000)
+            })
+            .with_author(test_authority_address(0))
+            .with_genesis_timestamp(GENESIS_TIME)
+            .with_staking(staking(), 10)
+            .build()
+    }
+
+    #[test]
+    fn test_elects_validators_bonded_on_chain() {
+        let node = test_backend();
+        let backend = node.backend.clone();
+        let state_at: StateResolver = {
+            let backend = backend.clone();
+            Arc::new(move |hash| backend.state_at(hash))
+        };
+
+        let config = PoAConfig {
+            slot_duration: 3000,
+            authorities: keyed_test_authorities(1),
+            vrf_seed: [1u8; 32],
+            epoch_length: 10,
+            rewards: Default::default(),
+        };
+        let clock = MockClock::new(GENESIS_TIME);
+        let mut engine = PoAEngine::new(config, Some(test_authority_address(0)), GENESIS_TIME)
+            .unwrap()
+            .with_signing_key(&test_authority_key(0))
+            .with_clock(Arc::new(clock.clone()))
+            .with_election_provider(Arc::new(StakingElection::new(staking(), state_at)));
+
+        // Three candidates; the nominator lifts #2 above #3
+        let mut transactions = Vec::new();
+        for (index, bond) in [(1, 5_000), (2, 2_000), (3, 3_000)] {
+            transactions.push(call(index, 0, StakingCall::Bond { value: bond }));
+            transactions.push(call(index, 1, StakingCall::Validate));
+        }
+        transactions.push(call(4, 0, StakingCall::Bond { value: 2_000 }));
+        transactions.push(call(
+            4,
+            1,
+            StakingCall::Nominate {
+                targets: vec![staker(2)],
+            },
+        ));
+
+        // Author blocks #1 to #10 as the worker would, bonding in block #1
+        for slot in 1..=10 {
+            clock.set(engine.slot_timestamp(slot));
+            let parent = backend.best_header().unwrap();
+            let ctx = StepContext {
+                block_number: parent.number + 1,
+                parent_hash: parent.hash().unwrap(),
+                timestamp: clock.now(),
+                validator_index: None,
+                pending_transactions: 0,
+            };
+            let StepResult::Propose { header, .. } = engine.step(ctx.clone()).unwrap() else {
+                panic!("Expected to author slot {}", slot);
+            };
+            let block = backend
+                .build_block(header, std::mem::take(&mut transactions))
+                .unwrap();
+            let header = engine.seal(block.header).unwrap();
+            backend
+                .import_block(Block::new(header.clone(), block.transactions))
+                .unwrap();
+            engine.import_block(header).unwrap();
+            engine.step(ctx).unwrap();
+
+            // Only the block at the election height elects
+            assert_eq!(engine.next_authority_set().is_some(), slot == 10);
+        }
+
+        // Block #10 in epoch 1 elected the set for epoch 2 from its state
+        let expected = vec![
+            Validator {
+                address: staker(1),
+                weight: 5_000,
+            },
+            Validator {
+                address: staker(2),
+                weight: 4_000,
+            },
+        ];
+        let next = engine.next_authority_set().unwrap();
+        assert_eq!((next.epoch, next.set_id), (2, 1));
+        assert_eq!(next.validators, expected);
+
+        // The elected stakers author epoch 2, so we no longer propose
+        clock.set(engine.slot_timestamp(20));
+        let best = backend.best_header().unwrap();
+        let step = engine
+            .step(StepContext {
+                block_number: best.number + 1,
+                parent_hash: best.hash().unwrap(),
+                timestamp: clock.now(),
+                validator_index: None,
+                pending_transactions: 0,
+            })
+            .unwrap();
+        assert!(!matches!(step, StepResult::Propose { .. }));
+        assert!(engine.get_proposer_for_slot(20) < 2);
+    }
+
+    #[test]
+    fn test_election_needs_known_block() {
+        let election = StakingElection::new(staking(), Arc::new(|_| None));
+        assert!(matches!(
+            election.elect(1, &Hash::zero()),
+            Err(ConsensusError::AuthoritySet(_))
+        ));
+    }
+}
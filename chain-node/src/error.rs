@@ -0,0 +1,43 @@
+//! Node error types
+
+use chain_consensus::ConsensusError;
+use chain_core::CoreError;
+use chain_vm::VmError;
+use thiserror::Error;
+
+/// Node error type
+#[derive(Error, Debug)]
+pub enum NodeError {
+    /// Invalid chain specification
+    #[error("Invalid chain spec: {0}")]
+    Spec(String),
+
+    /// Consensus error
+    #[error("Consensus error: {0}")]
+    Consensus(#[from] ConsensusError),
+
+    /// State execution error
+    #[error("VM error: {0}")]
+    Vm(#[from] VmError),
+
+    /// Core data structure error
+    #[error("Core error: {0}")]
+    Core(#[from] CoreError),
+
+    /// Serialization error
+    #[error("Serialization error: {0}")]
+    Serialization(String),
+
+    /// IO error
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<serde_json::Error> for NodeError {
+    fn from(err: serde_json::Error) -> Self {
+        NodeError::Serialization(err.to_string())
+    }
+}
+
+/// Result type for node operations
+pub type NodeResult<T> = Result<T, NodeError>;
@@ -0,0 +1,28 @@
+//! Blockchain full node
+//!
+//! This crate wires the core, consensus, vm and network crates together,
+//! starting from a chain specification describing the network.
+
+pub mod authorities;
+pub mod backend;
+pub mod block_import;
+pub mod chain_reader;
+pub mod election;
+pub mod error;
+pub mod reputation_store;
+pub mod spec;
+pub mod sync_import;
+#[cfg(test)]
+mod test_utils;
+pub mod verifier;
+
+pub use authorities::update_authorities;
+pub use backend::{AuthorResolver, BlockExecutor, NodeBackend};
+pub use block_import::{block_announce_handler, import_announced_blocks};
+pub use chain_reader::{DbChainReader, RecentStateResolver};
+pub use election::{StakingElection, StateResolver};
+pub use error::{NodeError, NodeResult};
+pub use reputation_store::DbReputationStore;
+pub use spec::{ChainSpec, GenesisBuilder};
+pub use sync_import::SyncImport;
+pub use verifier::EngineHeaderVerifier;
@@ -0,0 +1,155 @@
+//! Peer reputation storage in the node database
+//!
+//! Records live in the `peers` column family as JSON. Peer reputations are
+//! keyed by `peer/` and the peer id bytes, address bans by `ip/` and the
+//! address text.
+
+use crate::chain_reader::storage_error;
+use chain_db::column_families::ColumnFamily;
+use chain_db::KeyValueDB;
+use chain_network::reputation::{Ban, Reputation};
+use chain_network::{NetworkError, NetworkResult, PeerId, ReputationStore};
+use serde::de::DeserializeOwned;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+const PEER_PREFIX: &[u8] = b"peer/";
+const IP_PREFIX: &[u8] = b"ip/";
+
+/// [`ReputationStore`] backed by a [`KeyValueDB`]
+pub struct DbReputationStore {
+    /// Node database
+    db: Arc<dyn KeyValueDB>,
+}
+
+impl DbReputationStore {
+    /// Create a store over a database
+    pub fn new(db: Arc<dyn KeyValueDB>) -> Self {
+        Self { db }
+    }
+
+    /// Decode every record under a key prefix
+    fn load<T: DeserializeOwned>(&self, prefix: &[u8]) -> NetworkResult<Vec<(Vec<u8>, T)>> {
+        self.db
+            .iter_prefix(ColumnFamily::Peers.name(), prefix)
+            .map_err(storage_error)?
+            .map(|entry| {
+                let (key, value) = entry.map_err(storage_error)?;
+                Ok((
+                    key[prefix.len()..].to_vec(),
+                    serde_json::from_slice(&value)?,
+                ))
+            })
+            .collect()
+    }
+
+    fn put_json<T: serde::Serialize>(&self, key: &[u8], value: &T) -> NetworkResult<()> {
+        let value = serde_json::to_vec(value)?;
+        self.db
+            .put(ColumnFamily::Peers.name(), key, &value)
+            .map_err(storage_error)
+    }
+}
+
+impl ReputationStore for DbReputationStore {
+    fn load_peers(&self) -> NetworkResult<Vec<(PeerId, Reputation)>> {
+        self.load(PEER_PREFIX)?
+            .into_iter()
+            .map(|(key, reputation)| {
+                let peer_id = PeerId::from_bytes(&key)
+                    .map_err(|e| NetworkError::InvalidPeerId(e.to_string()))?;
+                Ok((peer_id, reputation))
+            })
+            .collect()
+    }
+
+    fn save_peer(&self, peer_id: &PeerId, reputation: Option<&Reputation>) -> NetworkResult<()> {
+        let key = [PEER_PREFIX, &peer_id.to_bytes()].concat();
+        match reputation {
+            Some(reputation) => self.put_json(&key, reputation),
+            None => self
+                .db
+                .delete(ColumnFamily::Peers.name(), &key)
+                .map_err(storage_error),
+        }
+    }
+
+    fn load_ip_bans(&self) -> NetworkResult<Vec<(IpAddr, Ban)>> {
+        self.load(IP_PREFIX)?
+            .into_iter()
+            .map(|(key, ban)| {
+                let ip = String::from_utf8_lossy(&key)
+                    .parse()
+                    .map_err(|e| NetworkError::Encoding(format!("Corrupt address ban: {}", e)))?;
+                Ok((ip, ban))
+            })
+            .collect()
+    }
+
+    fn save_ip_ban(&self, ip: &IpAddr, ban: Option<&Ban>) -> NetworkResult<()> {
+        let key = [IP_PREFIX, ip.to_string().as_bytes()].concat();
+        match ban {
+            Some(ban) => self.put_json(&key, ban),
+            None => self
+                .db
+                .delete(ColumnFamily::Peers.name(), &key)
+                .map_err(storage_error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_db::Database;
+    use chain_network::reputation::ReputationConfig;
+    use chain_network::{Misbehaviour, ReputationManager};
+    use std::path::Path;
+
+    fn open(path: &Path) -> Arc<DbReputationStore> {
+        let db: Arc<dyn KeyValueDB> = Arc::new(Database::open(path).unwrap());
+        Arc::new(DbReputationStore::new(db))
+    }
+
+    #[test]
+    fn test_reputations_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (offender, banned) = (PeerId::random(), PeerId::random());
+        let (banned_ip, unbanned_ip): (IpAddr, IpAddr) = (
+            "203.0.113.1".parse().unwrap(),
+            "203.0.113.2".parse().unwrap(),
+        );
+
+        let (peers, ip_bans) = {
+            let store = open(dir.path());
+            let (manager, _bans) =
+                ReputationManager::new(ReputationConfig::default(), store.clone()).unwrap();
+            manager
+                .report(&offender, Misbehaviour::InvalidBlock)
+                .unwrap();
+            manager.ban_peer(&banned, None).unwrap();
+            manager.ban_ip(banned_ip, None).unwrap();
+            manager.ban_ip(unbanned_ip, None).unwrap();
+            manager.unban_ip(&unbanned_ip).unwrap();
+            (store.load_peers().unwrap(), store.load_ip_bans().unwrap())
+        };
+        assert_eq!(peers.len(), 2);
+        assert_eq!(ip_bans, vec![(banned_ip, Ban::permanent())]);
+
+        // The database is closed and opened again
+        let store = open(dir.path());
+        let mut reopened = store.load_peers().unwrap();
+        reopened.sort_by_key(|(peer_id, _)| *peer_id);
+        let mut saved = peers;
+        saved.sort_by_key(|(peer_id, _)| *peer_id);
+        assert_eq!(reopened, saved);
+        assert_eq!(store.load_ip_bans().unwrap(), ip_bans);
+
+        let (manager, _bans) = ReputationManager::new(ReputationConfig::default(), store).unwrap();
+        assert!(manager.score(&offender) < 0.0);
+        assert!(!manager.is_banned(&offender));
+        assert!(manager.is_banned(&banned));
+        assert!(manager.is_ip_banned(&banned_ip));
+        assert!(!manager.is_ip_banned(&unbanned_ip));
+    }
+}
@@ -0,0 +1,463 @@
+//! Chain specification and genesis construction
+//!
+//! A [`ChainSpec`] describes a network in a single JSON or TOML file: chain
+//! id, genesis balances, consensus engine (including the initial
+//! authorities), gas schedule, fork schedule, staking and bootnodes. [`GenesisBuilder`] executes it
+//! into the genesis block and its state.
+
+use crate::{BlockExecutor, NodeError, NodeResult};
+use chain_consensus::{EngineConfig, InstantSealConfig};
+use chain_core::{Address, Block, BlockHeader, ForkSchedule, RewardConfig};
+use chain_network::Multiaddr;
+use chain_vm::account::{Account, AccountChanges};
+use chain_vm::{GasSchedule, Issuance, SharedStateDB, Staking, StakingConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// Network description used to build the genesis block and configure a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainSpec {
+    /// Human readable network name
+    pub name: String,
+    /// Network identifier, distinguishing otherwise identical networks
+    pub chain_id: u64,
+    /// Genesis block parameters
+    pub genesis: GenesisConfig,
+    /// Consensus engine and its parameters, including initial authorities
+    pub consensus: EngineConfig,
+    /// Gas costs
+    #[serde(default)]
+    pub gas_schedule: GasSchedule,
+    /// Protocol upgrades by block number
+    #[serde(default)]
+    pub forks: ForkSchedule,
+    /// Staking parameters; staking calls are only executed if set
+    #[serde(default)]
+    pub staking: Option<StakingConfig>,
+    /// Bootstrap node multiaddresses
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
+}
+
+/// Genesis block parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    /// Genesis timestamp in milliseconds
+    pub timestamp: u64,
+    /// Block gas limit
+    #[serde(default = "default_gas_limit")]
+    pub gas_limit: u64,
+    /// Extra data, followed by the chain id; defaults to the network name
+    #[serde(default)]
+    pub extra_data: String,
+    /// Initial account balances
+    #[serde(default)]
+    pub balances: Vec<GenesisAccount>,
+}
+
+/// Initial balance of an account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    /// Account address as 0x-prefixed hex
+    pub address: String,
+    /// Initial balance
+    pub balance: u64,
+}
+
+fn default_gas_limit() -> u64 {
+    8_000_000
+}
+
+/// Well-known development account funded in the dev spec
+pub const DEV_ACCOUNT: &str = "0x1234567890123456789012345678901234567890";
+
+impl ChainSpec {
+    /// Single-node development network sealing blocks on demand
+    pub fn dev() -> Self {
+        Self {
+            name: "dev".to_string(),
+            chain_id: 1337,
+            genesis: GenesisConfig {
+                timestamp: 0,
+                gas_limit: default_gas_limit(),
+                extra_data: String::new(),
+                balances: vec![GenesisAccount {
+                    address: DEV_ACCOUNT.to_string(),
+                    balance: 1_000_000_000_000,
+                }],
+            },
+            consensus: EngineConfig::InstantSeal(InstantSealConfig::default()),
+            gas_schedule: GasSchedule::default(),
+            forks: ForkSchedule::default(),
+            staking: None,
+            bootnodes: Vec::new(),
+        }
+    }
+
+    /// Parse a JSON chain spec
+    pub fn from_json(json: &str) -> NodeResult<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Parse a TOML chain spec
+    pub fn from_toml(toml_str: &str) -> NodeResult<Self> {
+        toml::from_str(toml_str)
+            .map_err(|e| NodeError::Serialization(format!("Failed to parse chain spec: {}", e)))
+    }
+
+    /// Serialize to pretty JSON
+    pub fn to_json(&self) -> NodeResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Serialize to TOML
+    pub fn to_toml(&self) -> NodeResult<String> {
+        toml::to_string(self)
+            .map_err(|e| NodeError::Serialization(format!("Failed to serialize chain spec: {}", e)))
+    }
+
+    /// Load a chain spec, choosing the format from the file extension
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> NodeResult<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+
+        let spec = if is_toml(path) {
+            Self::from_toml(&content)?
+        } else {
+            Self::from_json(&content)?
+        };
+        spec.validate()?;
+
+        Ok(spec)
+    }
+
+    /// Save the chain spec, choosing the format from the file extension
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> NodeResult<()> {
+        let path = path.as_ref();
+        let content = if is_toml(path) {
+            self.to_toml()?
+        } else {
+            self.to_json()?
+        };
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Validate the chain spec
+    pub fn validate(&self) -> NodeResult<()> {
+        if self.name.is_empty() {
+            return Err(NodeError::Spec(
+                "Network name must not be empty".to_string(),
+            ));
+        }
+
+        if self.genesis.gas_limit == 0 {
+            return Err(NodeError::Spec(
+                "Genesis gas limit must be greater than 0".to_string(),
+            ));
+        }
+
+        self.consensus.validate()?;
+
+        if self.staking.is_some() && self.epoch_length().is_none() {
+            return Err(NodeError::Spec(format!(
+                "Staking needs a consensus engine with epochs, not {}",
+                self.consensus.name()
+            )));
+        }
+
+        // Every fork's gas overrides must name known gas costs
+        for fork in self.forks.forks() {
+            self.gas_schedule.at_block(&self.forks, fork.block)?;
+        }
+
+        let mut seen = HashSet::new();
+        for (address, _) in self.genesis_accounts()? {
+            if !seen.insert(address) {
+                return Err(NodeError::Spec(format!(
+                    "Duplicate genesis account 0x{}",
+                    address.to_hex()
+                )));
+            }
+        }
+
+        self.bootnode_addrs()?;
+        Ok(())
+    }
+
+    /// Parsed genesis balances
+    pub fn genesis_accounts(&self) -> NodeResult<Vec<(Address, u64)>> {
+        self.genesis
+            .balances
+            .iter()
+            .map(|account| Ok((parse_address(&account.address)?, account.balance)))
+            .collect()
+    }
+
+    /// Parsed bootnode addresses
+    pub fn bootnode_addrs(&self) -> NodeResult<Vec<Multiaddr>> {
+        self.bootnodes
+            .iter()
+            .map(|addr| {
+                addr.parse()
+                    .map_err(|e| NodeError::Spec(format!("Invalid bootnode {}: {}", addr, e)))
+            })
+            .collect()
+    }
+
+    /// Block executor applying the spec's gas schedule, forks, rewards and staking
+    pub fn block_executor(&self) -> NodeResult<BlockExecutor> {
+        let issuance = Issuance::new(self.rewards().cloned().unwrap_or_default())?;
+        let executor =
+            BlockExecutor::new(self.gas_schedule.clone(), issuance).with_forks(self.forks.clone());
+        match (&self.staking, self.epoch_length()) {
+            (Some(staking), Some(epoch_length)) => {
+                Ok(executor.with_staking(Staking::new(staking.clone()), epoch_length))
+            }
+            (Some(_), None) => Err(NodeError::Spec(format!(
+                "Staking needs a consensus engine with epochs, not {}",
+                self.consensus.name()
+            ))),
+            (None, _) => Ok(executor),
+        }
+    }
+
+    /// Blocks per epoch of the consensus engine, if it has epochs
+    pub fn epoch_length(&self) -> Option<u64> {
+        match &self.consensus {
+            EngineConfig::Poa(config) | EngineConfig::Aura(config) => Some(config.epoch_length),
+            EngineConfig::InstantSeal(_) | EngineConfig::Pow(_) => None,
+        }
+    }
+
+    /// Reward schedule of the consensus engine, if it has one
+    pub fn rewards(&self) -> Option<&RewardConfig> {
+        match &self.consensus {
+            EngineConfig::Poa(config) | EngineConfig::Aura(config) => Some(&config.rewards),
+            EngineConfig::InstantSeal(_) | EngineConfig::Pow(_) => None,
+        }
+    }
+}
+
+/// Executes a chain spec into the genesis block and state
+pub struct GenesisBuilder<'a> {
+    /// Chain spec to execute
+    spec: &'a ChainSpec,
+    /// State the genesis allocations are written to
+    state: SharedStateDB,
+}
+
+impl<'a> GenesisBuilder<'a> {
+    /// Create a builder writing genesis state to a fresh in-memory database
+    pub fn new(spec: &'a ChainSpec) -> Self {
+        Self {
+            spec,
+            state: SharedStateDB::memory(),
+        }
+    }
+
+    /// Write genesis state to the given database instead
+    pub fn with_state(mut self, state: SharedStateDB) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Apply the genesis allocations and build the genesis block
+    ///
+    /// Returns the block together with the state it commits to.
+    pub fn build(self) -> NodeResult<(Block, SharedStateDB)> {
+        self.spec.validate()?;
+
+        let accounts = self.spec.genesis_accounts()?;
+        let mut changes = AccountChanges::new();
+        for (address, balance) in &accounts {
+            changes.update_account(*address, Account::with_balance(*balance));
+        }
+        self.state.apply_changes(changes)?;
+
+        // Inflation is computed from the total issuance, which starts at the genesis supply
+        let total_issuance: u128 = accounts.iter().map(|(_, b)| *b as u128).sum();
+        Issuance::new(self.spec.rewards().cloned().unwrap_or_default())?
+            .set_total_issuance(&self.state, total_issuance)?;
+
+        let mut header = BlockHeader::genesis();
+        header.state_root = self.state.state_root();
+        header.timestamp = self.spec.genesis.timestamp;
+        header.gas_limit = self.spec.genesis.gas_limit;
+        header.extra_data = self.extra_data();
+        if let EngineConfig::Pow(config) = &self.spec.consensus {
+            header.difficulty = config.initial_difficulty;
+        }
+
+        let block = Block::new(header, Vec::new());
+        info!(
+            "Built genesis for {} (chain {}) with {} accounts, hash {:?}",
+            self.spec.name,
+            self.spec.chain_id,
+            accounts.len(),
+            block.hash()?
+        );
+
+        Ok((block, self.state))
+    }
+
+    /// Genesis extra data, always committing to the chain id
+    fn extra_data(&self) -> Vec<u8> {
+        let extra_data = if self.spec.genesis.extra_data.is_empty() {
+            format!("{} genesis", self.spec.name)
+        } else {
+            self.spec.genesis.extra_data.clone()
+        };
+        format!("{}, chain id {}", extra_data, self.spec.chain_id).into_bytes()
+    }
+}
+
+/// Parse a 0x-prefixed hex address
+fn parse_address(address: &str) -> NodeResult<Address> {
+    let hex = address.strip_prefix("0x").unwrap_or(address);
+    Address::from_hex(hex)
+        .map_err(|e| NodeError::Spec(format!("Invalid address {}: {}", address, e)))
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_consensus::poa::config::default_test_authorities;
+    use chain_consensus::PoAConfig;
+    use chain_core::Fork;
+    use chain_vm::StakingCall;
+    use tempfile::tempdir;
+
+    fn testnet_spec() -> ChainSpec {
+        ChainSpec {
+            name: "testnet".to_string(),
+            chain_id: 7,
+            consensus: EngineConfig::Poa(
+                PoAConfig::new(3000, default_test_authorities()).with_vrf_seed([1u8; 32]),
+            ),
+            forks: ForkSchedule::new(vec![
+                Fork::new("cheaper_transfers", 1000).with_param("gas.balance_transfer", 5000)
+            ])
+            .unwrap(),
+            bootnodes: vec!["/ip4/127.0.0.1/tcp/30333".to_string()],
+            ..ChainSpec::dev()
+        }
+    }
+
+    #[test]
+    fn test_dev_genesis() {
+        let spec = ChainSpec::dev();
+        let (block, state) = GenesisBuilder::new(&spec).build().unwrap();
+
+        assert!(block.is_genesis());
+        assert_ne!(block.header.state_root, chain_core::Hash::zero());
+        assert_eq!(block.header.state_root, state.state_root());
+
+        let dev = parse_address(DEV_ACCOUNT).unwrap();
+        assert_eq!(
+            state.get_account(&dev).unwrap().unwrap().balance,
+            1_000_000_000_000
+        );
+
+        // Building the same spec again is reproducible
+        let (again, _) = GenesisBuilder::new(&spec).build().unwrap();
+        assert_eq!(block.hash().unwrap(), again.hash().unwrap());
+
+        // A different chain id yields a different genesis
+        let other = ChainSpec {
+            chain_id: 1338,
+            ..spec
+        };
+        let (other_block, _) = GenesisBuilder::new(&other).build().unwrap();
+        assert_ne!(block.hash().unwrap(), other_block.hash().unwrap());
+
+        // Even when both set the same extra data
+        let mut spec = ChainSpec::dev();
+        spec.genesis.extra_data = "launch".to_string();
+        let mut other = spec.clone();
+        other.chain_id = 1338;
+        let (block, _) = GenesisBuilder::new(&spec).build().unwrap();
+        let (other_block, _) = GenesisBuilder::new(&other).build().unwrap();
+        assert_ne!(block.hash().unwrap(), other_block.hash().unwrap());
+    }
+
+    #[test]
+    fn test_spec_file_formats() {
+        let dir = tempdir().unwrap();
+        let spec = testnet_spec();
+        let (genesis, _) = GenesisBuilder::new(&spec).build().unwrap();
+
+        for file in ["testnet.json", "testnet.toml"] {
+            let path = dir.path().join(file);
+            spec.save_to_file(&path).unwrap();
+
+            let loaded = ChainSpec::load_from_file(&path).unwrap();
+            assert_eq!(loaded.chain_id, 7);
+            assert_eq!(loaded.bootnode_addrs().unwrap().len(), 1);
+            assert_eq!(loaded.forks, spec.forks);
+
+            let (loaded_genesis, _) = GenesisBuilder::new(&loaded).build().unwrap();
+            assert_eq!(genesis.hash().unwrap(), loaded_genesis.hash().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        let mut spec = ChainSpec::dev();
+        spec.genesis.balances.push(spec.genesis.balances[0].clone());
+        assert!(spec.validate().is_err());
+
+        let mut spec = ChainSpec::dev();
+        spec.bootnodes.push("not a multiaddr".to_string());
+        assert!(spec.validate().is_err());
+
+        let mut spec = ChainSpec::dev();
+        spec.forks =
+            ForkSchedule::new(vec![Fork::new("typo", 10).with_param("gas.unknown", 1)]).unwrap();
+        assert!(spec.validate().is_err());
+
+        let mut spec = ChainSpec::dev();
+        spec.genesis.balances[0].address = "0x1234".to_string();
+        assert!(spec.validate().is_err());
+
+        // Instant seal has no epochs to date unbonding by
+        let mut spec = ChainSpec::dev();
+        spec.staking = Some(StakingConfig::default());
+        assert!(spec.validate().is_err());
+        assert!(spec.block_executor().is_err());
+    }
+
+    #[test]
+    fn test_executes_staking_calls() {
+        let mut bond = StakingCall::Bond { value: 1_000 }
+            .transaction(0, 1, 100_000)
+            .unwrap();
+        bond.sign(&[7u8; 32]).unwrap();
+        let sender = bond.sender().unwrap();
+
+        let mut spec = testnet_spec();
+        spec.genesis.balances.push(GenesisAccount {
+            address: format!("0x{}", sender.to_hex()),
+            balance: 1_000_000,
+        });
+        spec.staking = Some(StakingConfig::default());
+        let (genesis, state) = GenesisBuilder::new(&spec).build().unwrap();
+
+        spec.block_executor()
+            .unwrap()
+            .execute_transaction(&state, &genesis.header, &bond)
+            .unwrap();
+        let staker = Staking::new(StakingConfig::default())
+            .staker(&state, &sender)
+            .unwrap()
+            .unwrap();
+        assert_eq!(staker.ledger.active, 1_000);
+    }
+}
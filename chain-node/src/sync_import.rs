@@ -0,0 +1,331 @@
+//! Import of blocks and warp-synced state downloaded by chain sync
+//!
+//! Downloaded headers are checked by a consensus engine and blocks are
+//! executed by the [`NodeBackend`], so synced blocks pass the same checks as
+//! blocks from the consensus worker. Warp sync stores the headers up to its
+//! pivot and installs the downloaded state, rebuilt into the VM state, as the
+//! best state; the engine then takes over from the pivot, e.g. rebuilding the
+//! authority sets in force from the election on that state. After every import the [`DbChainReader`] commits to the new
+//! best state, so the node serves it to peers warping in turn.
+
+use crate::backend::NodeBackend;
+use crate::chain_reader::DbChainReader;
+use chain_consensus::{ChainBackend, ConsensusError, Engine};
+use chain_core::{Block, BlockHeader, StateEntry};
+use chain_network::chain_sync::WarpImport;
+use chain_network::{BlockImport, NetworkError, NetworkResult};
+use chain_vm::SharedStateDB;
+use std::sync::{Arc, RwLock};
+
+/// [`BlockImport`] and [`WarpImport`] on top of the node backend
+///
+/// The engine is used for sync only; start the consensus worker, with its
+/// own engine, once the node has caught up.
+pub struct SyncImport {
+    /// Block execution and storage
+    backend: Arc<NodeBackend>,
+    /// Reader serving the stored chain to peers
+    reader: Arc<DbChainReader>,
+    /// Engine verifying headers and following imported blocks
+    engine: RwLock<Box<dyn Engine>>,
+}
+
+impl SyncImport {
+    /// Create an importer and serve the backend's best state
+    pub fn new(
+        backend: Arc<NodeBackend>,
+        reader: Arc<DbChainReader>,
+        engine: Box<dyn Engine>,
+    ) -> NetworkResult<Self> {
+        let import = Self {
+            backend,
+            reader,
+            engine: RwLock::new(engine),
+        };
+        import.commit_state();
+        Ok(import)
+    }
+
+    /// Serve the state of the best block
+    fn commit_state(&self) {
+        self.reader.set_state_root(self.best_header().state_root);
+    }
+}
+
+impl BlockImport for SyncImport {
+    fn best_header(&self) -> BlockHeader {
+        self.backend
+            .best_header()
+            .expect("node backend always has a best block")
+    }
+
+    fn verify_header(&self, _parent: &BlockHeader, header: &BlockHeader) -> NetworkResult<()> {
+        self.engine
+            .read()
+            .unwrap()
+            .verify_block(header)
+            .map_err(invalid_block)
+    }
+
+    fn import_block(&self, block: Block) -> NetworkResult<()> {
+        let header = block.header.clone();
+        self.backend.import_block(block).map_err(invalid_block)?;
+        self.engine
+            .write()
+            .unwrap()
+            .import_block(header)
+            .map_err(invalid_block)?;
+        self.commit_state();
+        Ok(())
+    }
+}
+
+impl WarpImport for SyncImport {
+    fn import_headers(&self, headers: Vec<BlockHeader>) -> NetworkResult<()> {
+        self.backend
+            .import_headers(&headers)
+            .map_err(|e| NetworkError::Sync(e.to_string()))
+    }
+
+    fn import_state(&self, pivot: &BlockHeader, entries: Vec<StateEntry>) -> NetworkResult<()> {
+        let state = SharedStateDB::from_entries(&entries)
+            .map_err(|e| NetworkError::Sync(format!("Invalid warp state: {}", e)))?;
+        self.backend
+            .reset_to(pivot.clone(), state)
+            .map_err(|e| NetworkError::Sync(e.to_string()))?;
+        self.commit_state();
+        self.engine
+            .write()
+            .unwrap()
+            .import_pivot(pivot.clone())
+            .map_err(invalid_block)
+    }
+}
+
+fn invalid_block(e: ConsensusError) -> NetworkError {
+    NetworkError::InvalidBlock(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{author_poa_blocks, poa_engine, TestBackend, TestNode, GENESIS_TIME};
+    use chain_consensus::{InstantSealConfig, InstantSealEngine, MockClock};
+    use chain_core::{Address, RewardConfig, Transaction};
+    use chain_network::reputation::{MemoryReputationStore, ReputationConfig};
+    use chain_network::sync::SyncCommand;
+    use chain_network::{
+        ChainReader, ChainSync, PeerId, ReputationManager, SyncHandler, SyncManager,
+    };
+    use std::collections::HashMap;
+
+    const SENDER_KEY: [u8; 32] = [7u8; 32];
+
+    fn transfer(nonce: u64, to: u8) -> Transaction {
+        let mut tx = Transaction::transfer(nonce, Address::new([to; 20]), 100, 1, 100_000);
+        tx.sign(&SENDER_KEY).unwrap();
+        tx
+    }
+
+    /// Node on a fresh database, at a genesis funding the sender
+    ///
+    /// Its reader serves recent states, so peers warp to a pivot below their
+    /// best block.
+    fn test_node() -> (TestNode, Arc<DbChainReader>) {
+        let rewards = RewardConfig {
+            block_reward: 1_000,
+            ..Default::default()
+        };
+        let node = TestBackend::new()
+            .with_rewards(rewards)
+            .with_balance(transfer(0, 0).sender().unwrap(), 100_000_000)
+            .build();
+        let reader = node.reader();
+        (node, reader)
+    }
+
+    fn instant_seal() -> Box<dyn Engine> {
+        let engine = InstantSealEngine::new(InstantSealConfig::default())
+            .unwrap()
+            .with_clock(Arc::new(MockClock::new(u64::MAX / 2)));
+        Box::new(engine)
+    }
+
+    fn test_reputation() -> Arc<ReputationManager> {
+        let (reputation, _bans) = ReputationManager::new(
+            ReputationConfig::default(),
+            Arc::new(MemoryReputationStore::default()),
+        )
+        .unwrap();
+        Arc::new(reputation)
+    }
+
+    /// Answer sync requests from each peer's handler
+    fn spawn_peers(handlers: HashMap<PeerId, SyncHandler>) -> SyncManager {
+        let (manager, mut rx) = SyncManager::new();
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                if let SyncCommand::SendRequest {
+                    peer_id,
+                    request,
+                    response_sender,
+                } = command
+                {
+                    let result = match handlers.get(&peer_id) {
+                        Some(handler) => Ok(handler.handle_request(request, peer_id).await),
+                        None => Err(NetworkError::Timeout),
+                    };
+                    let _ = response_sender.send(result);
+                }
+            }
+        });
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_warps_to_state_executed_by_vm() {
+        // The source executes blocks with transfers through the VM
+        let (source_node, source_reader) = test_node();
+        let source = &source_node.backend;
+        let source_import =
+            SyncImport::new(source.clone(), source_reader.clone(), instant_seal()).unwrap();
+        for number in 1..=30u64 {
+            let parent = source.best_header().unwrap();
+            let header = BlockHeader {
+                parent_hash: parent.hash().unwrap(),
+                number,
+                timestamp: parent.timestamp + 1_000,
+                ..parent
+            };
+            let transactions = vec![transfer(number - 1, number as u8)];
+            let block = source.build_block(header, transactions).unwrap();
+            source_import.import_block(block).unwrap();
+        }
+        let source_state = source.state();
+        assert_eq!(
+            source_state.state_root(),
+            chain_core::entries_root(&source_state.entries())
+        );
+
+        let peers: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+        let handlers = peers
+            .iter()
+            .map(|peer_id| (*peer_id, SyncHandler::new(source_reader.clone())))
+            .collect();
+
+        let (node, reader) = test_node();
+        let destination = &node.backend;
+        let import =
+            Arc::new(SyncImport::new(destination.clone(), reader.clone(), instant_seal()).unwrap());
+        let mut sync = ChainSync::new(
+            spawn_peers(handlers),
+            test_reputation(),
+            reader.clone(),
+            import.clone(),
+        )
+        .with_warp_import(import.clone())
+        .with_pivot_distance(10)
+        .with_pivot_peers(2);
+        for peer_id in &peers {
+            sync.update_peer_head(*peer_id, 30);
+        }
+
+        // The state at the pivot is downloaded rather than executed
+        assert_eq!(sync.warp_sync().await.unwrap(), Some(20));
+        let pivot = source_reader.header_at(20).unwrap().unwrap();
+        assert_eq!(destination.best_header().unwrap(), pivot);
+        assert_eq!(destination.state().state_root(), pivot.state_root);
+        let balance = |state: SharedStateDB, to: u8| {
+            state
+                .get_account(&Address::new([to; 20]))
+                .unwrap()
+                .unwrap_or_default()
+                .balance
+        };
+        assert_eq!(balance(destination.state(), 20), 100);
+        assert_eq!(balance(destination.state(), 21), 0);
+
+        // The downloaded state is stored and served at the pivot's root
+        assert!(reader
+            .state_entries(&pivot.state_root, b"", b"", 1)
+            .unwrap()
+            .is_some());
+
+        // Regular import executes the blocks after the pivot
+        assert_eq!(sync.sync_to_best().await.unwrap(), 10);
+        assert_eq!(
+            destination.best_header().unwrap(),
+            source.best_header().unwrap()
+        );
+        assert_eq!(destination.state().state_root(), source_state.state_root());
+        assert_eq!(balance(destination.state(), 30), 100);
+    }
+
+    #[tokio::test]
+    async fn test_syncs_poa_chain_across_election() {
+        let (source_node, source_reader) = test_node();
+        let source = &source_node.backend;
+        let clock = MockClock::new(GENESIS_TIME);
+        author_poa_blocks(source, &clock, 12);
+
+        let peer = PeerId::random();
+        let handlers = HashMap::from([(peer, SyncHandler::new(source_reader))]);
+        let reputation = test_reputation();
+        let (node, reader) = test_node();
+        let destination = &node.backend;
+        let engine = Box::new(poa_engine(&clock, None));
+        let import =
+            Arc::new(SyncImport::new(destination.clone(), reader.clone(), engine).unwrap());
+        let mut sync = ChainSync::new(spawn_peers(handlers), reputation.clone(), reader, import);
+        sync.update_peer_head(peer, 12);
+
+        // Headers after block #4 announce the set it elected, and those from
+        // block #8 are sealed by that set, all within one batch
+        assert_eq!(sync.sync_to_best().await.unwrap(), 12);
+        assert_eq!(
+            destination.best_header().unwrap(),
+            source.best_header().unwrap()
+        );
+        assert!(reputation.score(&peer) > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_warps_poa_chain_past_elections() {
+        let (source_node, source_reader) = test_node();
+        let source = &source_node.backend;
+        let clock = MockClock::new(GENESIS_TIME);
+        author_poa_blocks(source, &clock, 12);
+
+        let peers: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+        let handlers = peers
+            .iter()
+            .map(|peer_id| (*peer_id, SyncHandler::new(source_reader.clone())))
+            .collect();
+        let (node, reader) = test_node();
+        let destination = &node.backend;
+        let engine = Box::new(poa_engine(&clock, None));
+        let import =
+            Arc::new(SyncImport::new(destination.clone(), reader.clone(), engine).unwrap());
+        let mut sync = ChainSync::new(
+            spawn_peers(handlers),
+            test_reputation(),
+            reader,
+            import.clone(),
+        )
+        .with_warp_import(import.clone())
+        .with_pivot_distance(3)
+        .with_pivot_peers(2);
+        for peer_id in &peers {
+            sync.update_peer_head(*peer_id, 12);
+        }
+
+        // Pivot #9 is sealed by the set elected at block #4 and announces the
+        // one elected at block #8, both rebuilt from its state
+        assert_eq!(sync.warp_sync().await.unwrap(), Some(9));
+        assert_eq!(sync.sync_to_best().await.unwrap(), 3);
+        assert_eq!(
+            destination.best_header().unwrap(),
+            source.best_header().unwrap()
+        );
+    }
+}
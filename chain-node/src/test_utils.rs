@@ -0,0 +1,210 @@
+//! Fixtures shared by the node's unit tests
+
+use crate::backend::{BlockExecutor, NodeBackend};
+use crate::chain_reader::DbChainReader;
+use chain_consensus::poa::config::{
+    keyed_test_authorities, test_authority_address, test_authority_key,
+};
+use chain_consensus::traits::Validator;
+use chain_consensus::{
+    ChainBackend, Clock, Engine, MockClock, PoAConfig, PoAEngine, StaticElection, StepContext,
+    StepResult,
+};
+use chain_core::{Address, Block, BlockHeader, RewardConfig};
+use chain_db::{Database, KeyValueDB};
+use chain_vm::account::AccountChanges;
+use chain_vm::{Account, GasSchedule, Issuance, SharedStateDB, Staking};
+use std::sync::Arc;
+
+/// Genesis timestamp of the PoA fixtures
+pub(crate) const GENESIS_TIME: u64 = 1_000_000;
+
+/// Node backend on a fresh database
+pub(crate) struct TestNode {
+    /// Keeps the database directory alive
+    pub(crate) _dir: tempfile::TempDir,
+    /// Node database
+    pub(crate) db: Arc<dyn KeyValueDB>,
+    /// Backend at the genesis block
+    pub(crate) backend: Arc<NodeBackend>,
+}
+
+impl TestNode {
+    /// Reader over the node database, also serving recent states as warp pivots
+    pub(crate) fn reader(&self) -> Arc<DbChainReader> {
+        let backend = self.backend.clone();
+        let reader =
+            DbChainReader::new(self.db.clone()).with_recent_states(Arc::new(move |root| {
+                backend.state_with_root(root).map(|state| state.entries())
+            }));
+        Arc::new(reader)
+    }
+}
+
+/// Builds a [`TestNode`] whose genesis funds the given accounts
+pub(crate) struct TestBackend {
+    /// Block rewards and fee split
+    rewards: RewardConfig,
+    /// Genesis balances
+    balances: Vec<(Address, u64)>,
+    /// Account credited with every block's rewards
+    author: Address,
+    /// Genesis header timestamp
+    genesis_timestamp: u64,
+    /// Staking rules and epoch length, if staking calls are executed
+    staking: Option<(Staking, u64)>,
+}
+
+impl TestBackend {
+    pub(crate) fn new() -> Self {
+        Self {
+            rewards: RewardConfig::default(),
+            balances: Vec::new(),
+            author: Address::new([9u8; 20]),
+            genesis_timestamp: BlockHeader::genesis().timestamp,
+            staking: None,
+        }
+    }
+
+    pub(crate) fn with_rewards(mut self, rewards: RewardConfig) -> Self {
+        self.rewards = rewards;
+        self
+    }
+
+    pub(crate) fn with_balance(mut self, address: Address, balance: u64) -> Self {
+        self.balances.push((address, balance));
+        self
+    }
+
+    pub(crate) fn with_author(mut self, author: Address) -> Self {
+        self.author = author;
+        self
+    }
+
+    pub(crate) fn with_genesis_timestamp(mut self, timestamp: u64) -> Self {
+        self.genesis_timestamp = timestamp;
+        self
+    }
+
+    pub(crate) fn with_staking(mut self, staking: Staking, epoch_length: u64) -> Self {
+        self.staking = Some((staking, epoch_length));
+        self
+    }
+
+    /// Issuance under the configured rewards
+    pub(crate) fn issuance(&self) -> Issuance {
+        Issuance::new(self.rewards.clone()).unwrap()
+    }
+
+    /// Open the database and start the backend at genesis
+    ///
+    /// The funded balances make up the total issuance.
+    pub(crate) fn build(self) -> TestNode {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Arc<dyn KeyValueDB> = Arc::new(Database::open(dir.path()).unwrap());
+
+        let state = SharedStateDB::memory();
+        let mut changes = AccountChanges::new();
+        for (address, balance) in &self.balances {
+            changes.update_account(*address, Account::with_balance(*balance));
+        }
+        state.apply_changes(changes).unwrap();
+        let issuance = self.issuance();
+        let total = self
+            .balances
+            .iter()
+            .map(|(_, balance)| *balance as u128)
+            .sum();
+        issuance.set_total_issuance(&state, total).unwrap();
+
+        let genesis = BlockHeader {
+            timestamp: self.genesis_timestamp,
+            state_root: state.state_root(),
+            ..BlockHeader::genesis()
+        };
+        let mut executor = BlockExecutor::new(GasSchedule::default(), issuance);
+        if let Some((staking, epoch_length)) = self.staking {
+            executor = executor.with_staking(staking, epoch_length);
+        }
+        let author = self.author;
+        let backend = NodeBackend::new(
+            db.clone(),
+            state,
+            genesis,
+            executor,
+            Arc::new(move |_| Ok(author)),
+        )
+        .unwrap();
+
+        TestNode {
+            _dir: dir,
+            db,
+            backend: Arc::new(backend),
+        }
+    }
+}
+
+/// PoA engine whose single authority hands over to test authority 1 at
+/// every election, authoring as `authority` if given
+pub(crate) fn poa_engine(clock: &MockClock, authority: Option<usize>) -> PoAEngine {
+    let config = PoAConfig {
+        slot_duration: 3000,
+        authorities: keyed_test_authorities(1),
+        vrf_seed: [1u8; 32],
+        epoch_length: 4,
+        rewards: Default::default(),
+    };
+    let elected = vec![Validator {
+        address: test_authority_address(1),
+        weight: 1,
+    }];
+    let engine = PoAEngine::new(config, authority.map(test_authority_address), GENESIS_TIME)
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()))
+        .with_election_provider(Arc::new(StaticElection::new(elected)));
+    match authority {
+        Some(index) => engine.with_signing_key(&test_authority_key(index)),
+        None => engine,
+    }
+}
+
+/// Author one PoA block per slot up to `count` on the backend, as the
+/// workers of authorities 0 and 1 would
+///
+/// Block #4 elects authority 1, which takes over with block #8 in epoch 2.
+pub(crate) fn author_poa_blocks(
+    backend: &NodeBackend,
+    clock: &MockClock,
+    count: u64,
+) -> Vec<Block> {
+    let mut authors = [poa_engine(clock, Some(0)), poa_engine(clock, Some(1))];
+    let mut blocks = Vec::new();
+    for slot in 1..=count {
+        clock.set(authors[0].slot_timestamp(slot));
+        let parent = backend.best_header().unwrap();
+        let ctx = StepContext {
+            block_number: parent.number + 1,
+            parent_hash: parent.hash().unwrap(),
+            timestamp: clock.now(),
+            validator_index: None,
+            pending_transactions: 0,
+        };
+
+        let mut sealed = None;
+        for engine in &mut authors {
+            if let StepResult::Propose { header, .. } = engine.step(ctx.clone()).unwrap() {
+                let block = backend.build_block(header, vec![]).unwrap();
+                let header = engine.seal(block.header).unwrap();
+                sealed = Some(Block::new(header, block.transactions));
+            }
+        }
+        let block = sealed.unwrap_or_else(|| panic!("No authority proposed in slot {}", slot));
+        backend.import_block(block.clone()).unwrap();
+        for engine in &mut authors {
+            engine.import_block(block.header.clone()).unwrap();
+            engine.step(ctx.clone()).unwrap();
+        }
+        blocks.push(block);
+    }
+    blocks
+}
@@ -0,0 +1,131 @@
+//! Header pre-check for announced blocks
+//!
+//! The check runs before a block's body is fetched and its announcement is
+//! forwarded. It only sees the header, so it is no substitute for import:
+//! the parent, transactions, gas and state root are checked when the
+//! consensus worker imports the block.
+
+use chain_consensus::SharedEngine;
+use chain_core::BlockHeader;
+use chain_network::{HeaderVerifier, NetworkError, NetworkResult};
+
+/// [`HeaderVerifier`] running a consensus engine's header checks, such as the
+/// seal and timestamp, without the parent or state
+pub struct EngineHeaderVerifier {
+    /// Engine of the consensus worker, so headers are checked against the
+    /// authority set the imported chain has reached
+    engine: SharedEngine,
+}
+
+impl EngineHeaderVerifier {
+    /// Create a verifier over the consensus worker's engine
+    pub fn new(engine: SharedEngine) -> Self {
+        Self { engine }
+    }
+}
+
+impl HeaderVerifier for EngineHeaderVerifier {
+    fn verify_header(&self, header: &BlockHeader) -> NetworkResult<()> {
+        self.engine
+            .read()
+            .unwrap()
+            .verify_block(header)
+            .map_err(|e| NetworkError::InvalidBlock(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{author_poa_blocks, poa_engine, TestBackend, GENESIS_TIME};
+    use chain_consensus::poa::config::{keyed_test_authorities, test_authority_key};
+    use chain_consensus::{slots, MockClock, PoAConfig, PoAEngine};
+    use std::sync::{Arc, RwLock};
+
+    fn shared(engine: PoAEngine) -> SharedEngine {
+        Arc::new(RwLock::new(Box::new(engine)))
+    }
+
+    #[test]
+    fn test_checks_headers_against_engine() {
+        let config = PoAConfig {
+            slot_duration: 3000,
+            authorities: keyed_test_authorities(1),
+            vrf_seed: [1u8; 32],
+            epoch_length: 10,
+            rewards: Default::default(),
+        };
+        let clock = MockClock::new(GENESIS_TIME);
+        let engine = PoAEngine::new(config, None, GENESIS_TIME)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        let commitment = engine.authority_set().commitment().as_bytes().to_vec();
+        let header_at = |timestamp| {
+            let header = BlockHeader {
+                number: 1,
+                timestamp,
+                extra_data: commitment.clone(),
+                ..BlockHeader::genesis()
+            };
+            slots::sign_header(header, &test_authority_key(0)).unwrap()
+        };
+        let in_slot = header_at(engine.slot_timestamp(2));
+        let future = header_at(engine.slot_timestamp(20));
+        let verifier = EngineHeaderVerifier::new(shared(engine));
+
+        clock.set(GENESIS_TIME + 2 * 3000);
+        assert!(verifier.verify_header(&in_slot).is_ok());
+
+        // Headers from slots that have not started are rejected
+        assert!(matches!(
+            verifier.verify_header(&future),
+            Err(NetworkError::InvalidBlock(_))
+        ));
+        // Headers before genesis are rejected
+        assert!(matches!(
+            verifier.verify_header(&header_at(GENESIS_TIME - 3000)),
+            Err(NetworkError::InvalidBlock(_))
+        ));
+        // Headers sealed by a key outside the authority set are rejected
+        let forged = slots::sign_header(
+            BlockHeader {
+                number: 1,
+                timestamp: GENESIS_TIME + 2 * 3000,
+                ..BlockHeader::genesis()
+            },
+            &test_authority_key(1),
+        )
+        .unwrap();
+        assert!(matches!(
+            verifier.verify_header(&forged),
+            Err(NetworkError::InvalidBlock(_))
+        ));
+    }
+
+    #[test]
+    fn test_follows_authority_sets_imported_by_worker() {
+        let node = TestBackend::new().build();
+        let clock = MockClock::new(GENESIS_TIME);
+        let blocks = author_poa_blocks(&node.backend, &clock, 10);
+
+        // Block #4 elects authority 1, which seals the blocks of epoch 2
+        let engine = shared(poa_engine(&clock, None));
+        let verifier = EngineHeaderVerifier::new(engine.clone());
+        for block in &blocks {
+            assert!(verifier.verify_header(&block.header).is_ok());
+            engine
+                .write()
+                .unwrap()
+                .import_block(block.header.clone())
+                .unwrap();
+        }
+
+        // An engine that never imports stays on the genesis set
+        let stale = EngineHeaderVerifier::new(shared(poa_engine(&clock, None)));
+        assert!(stale.verify_header(&blocks[0].header).is_ok());
+        assert!(matches!(
+            stale.verify_header(&blocks[8].header),
+            Err(NetworkError::InvalidBlock(_))
+        ));
+    }
+}
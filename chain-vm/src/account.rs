@@ -132,7 +132,7 @@ impl AccountState {
 
     /// Set contract code
     pub fn set_code(&mut self, code: Vec<u8>) {
-        let code_hash = Hash::from_data(&code);
+        let code_hash = Hash::new(*blake3::hash(&code).as_bytes());
         self.account.set_code_hash(code_hash);
         self.code = Some(code);
     }
@@ -184,7 +184,7 @@ impl AccountChanges {
     pub fn update_storage(&mut self, address: Address, key: Hash, value: Vec<u8>) {
         self.storage_changes
             .entry(address)
-            .or_insert_with(HashMap::new)
+            .or_default()
             .insert(key, value);
     }
 
@@ -213,7 +213,7 @@ impl AccountChanges {
 
         // Merge storage changes
         for (addr, storage) in other.storage_changes {
-            let entry = self.storage_changes.entry(addr).or_insert_with(HashMap::new);
+            let entry = self.storage_changes.entry(addr).or_default();
             for (key, value) in storage {
                 entry.insert(key, value);
             }
@@ -282,7 +282,7 @@ mod tests {
         let mut account = Account::new();
         assert!(!account.is_contract());
 
-        let code_hash = Hash::from_data(b"some code");
+        let code_hash = Hash::new(*blake3::hash(b"some code").as_bytes());
         account.set_code_hash(code_hash);
         assert!(account.is_contract());
     }
@@ -293,7 +293,7 @@ mod tests {
         let mut state = AccountState::new(account);
 
         // Test storage
-        let key = Hash::from_data(b"storage_key");
+        let key = Hash::new(*blake3::hash(b"storage_key").as_bytes());
         let value = b"storage_value".to_vec();
         
         state.set_storage(key, value.clone());
@@ -311,13 +311,13 @@ mod tests {
         let mut changes = AccountChanges::new();
         assert!(changes.is_empty());
 
-        let address = Address([1u8; 20]);
+        let address = Address::new([1u8; 20]);
         let account = Account::with_balance(1000);
 
         changes.update_account(address, account);
         assert!(!changes.is_empty());
 
-        let key = Hash::from_data(b"key");
+        let key = Hash::new(*blake3::hash(b"key").as_bytes());
         let value = b"value".to_vec();
         changes.update_storage(address, key, value);
 
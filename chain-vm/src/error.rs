@@ -48,6 +48,12 @@ impl From<bincode::Error> for VmError {
     }
 }
 
+impl From<chain_core::CoreError> for VmError {
+    fn from(err: chain_core::CoreError) -> Self {
+        VmError::InvalidTransaction(err.to_string())
+    }
+}
+
 impl From<anyhow::Error> for VmError {
     fn from(err: anyhow::Error) -> Self {
         VmError::Other(err.to_string())
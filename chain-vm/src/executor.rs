@@ -1,13 +1,13 @@
 //! Transaction execution engine
 
-use crate::account::{Account, AccountChanges, AccountState};
+use crate::account::AccountChanges;
 use crate::gas::{GasMeter, GasSchedule, StorageOp};
-use crate::state::{SharedStateDB, StateDB};
+use crate::staking::{is_staking_call, Staking, StakingCall};
+use crate::state::SharedStateDB;
 use crate::{VmError, VmResult};
-use chain_core::{Address, Hash, Transaction};
+use chain_core::{Address, BlockNumber, ForkSchedule, Gas, Hash, Transaction, Wei};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use tracing::{debug, warn, info};
+use tracing::{debug, info, warn};
 
 /// State change record
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,14 +112,25 @@ pub struct ExecutionContext {
 
 /// Native balance transfer engine (Engine 0)
 pub struct BalanceTransferEngine {
-    /// Gas schedule
+    /// Gas schedule before any fork
     gas_schedule: GasSchedule,
+    /// Forks overriding gas costs from their activation block
+    forks: ForkSchedule,
 }
 
 impl BalanceTransferEngine {
     /// Create new balance transfer engine
     pub fn new(gas_schedule: GasSchedule) -> Self {
-        Self { gas_schedule }
+        Self {
+            gas_schedule,
+            forks: ForkSchedule::default(),
+        }
+    }
+
+    /// Apply the gas overrides of a fork schedule
+    pub fn with_forks(mut self, forks: ForkSchedule) -> Self {
+        self.forks = forks;
+        self
     }
 
     /// Apply a balance transfer transaction
@@ -129,9 +140,20 @@ impl BalanceTransferEngine {
         state: &SharedStateDB,
         context: &ExecutionContext,
     ) -> VmResult<ExecutionResult> {
-        info!("Executing balance transfer from {:?} to {:?}", tx.from()?, tx.to);
+        let sender = tx.sender()?;
+        let recipient = match tx.to {
+            Some(recipient) => recipient,
+            None => {
+                return Err(VmError::InvalidTransaction(
+                    "Balance transfer without recipient".to_string(),
+                ))
+            }
+        };
+        let value = to_balance(tx.value)?;
+        info!("Executing balance transfer from {:?} to {:?}", sender, recipient);
 
-        let mut gas_meter = GasMeter::new(tx.gas_limit, self.gas_schedule.clone());
+        let gas_schedule = self.gas_schedule.at_block(&self.forks, context.block_number)?;
+        let mut gas_meter = GasMeter::new(tx.gas_limit, gas_schedule);
         let mut state_changes = Vec::new();
         let mut account_changes = AccountChanges::new();
 
@@ -139,7 +161,6 @@ impl BalanceTransferEngine {
         gas_meter.consume_tx_base(tx.data.len())?;
 
         // 2. Get sender account
-        let sender = tx.from()?;
         let mut sender_account = state.get_account(&sender)?.unwrap_or_default();
 
         // 3. Verify nonce
@@ -151,7 +172,9 @@ impl BalanceTransferEngine {
         }
 
         // 4. Check balance for value + gas
-        let total_cost = tx.value + (tx.gas_limit * tx.gas_price);
+        let total_cost = value
+            .checked_add(gas_cost(tx.gas_limit, tx.gas_price)?)
+            .ok_or_else(|| VmError::InvalidTransaction("Transaction cost overflow".to_string()))?;
         if sender_account.balance < total_cost {
             return Ok(ExecutionResult::failure(
                 gas_meter.consumed(),
@@ -163,23 +186,22 @@ impl BalanceTransferEngine {
         gas_meter.consume_transfer()?;
 
         // 6. Get or create recipient account
-        let recipient = tx.to;
         let recipient_exists = state.get_account(&recipient)?.is_some();
         let mut recipient_account = state.get_account(&recipient)?.unwrap_or_default();
 
         // 7. Charge for account creation if needed
-        if !recipient_exists && tx.value > 0 {
+        if !recipient_exists && value > 0 {
             gas_meter.consume_account_creation()?;
             state_changes.push(StateChange::AccountCreated { address: recipient });
         }
 
         // 8. Perform the transfer
-        if tx.value > 0 {
+        if value > 0 {
             let old_sender_balance = sender_account.balance;
             let old_recipient_balance = recipient_account.balance;
 
-            sender_account.sub_balance(tx.value)?;
-            recipient_account.add_balance(tx.value)?;
+            sender_account.sub_balance(value)?;
+            recipient_account.add_balance(value)?;
 
             state_changes.push(StateChange::BalanceChange {
                 address: sender,
@@ -204,11 +226,11 @@ impl BalanceTransferEngine {
         });
 
         // 10. Pay gas fees to coinbase
-        let gas_cost = gas_meter.consumed() * tx.gas_price;
-        sender_account.sub_balance(gas_cost)?;
+        let fee = gas_cost(gas_meter.consumed(), tx.gas_price)?;
+        sender_account.sub_balance(fee)?;
 
         let mut coinbase_account = state.get_account(&context.coinbase)?.unwrap_or_default();
-        coinbase_account.add_balance(gas_cost)?;
+        coinbase_account.add_balance(fee)?;
 
         // 11. Apply changes to state
         account_changes.update_account(sender, sender_account);
@@ -223,12 +245,149 @@ impl BalanceTransferEngine {
     }
 }
 
+/// Staking call engine (Engine 1)
+///
+/// Executes [`StakingCall`]s sent to the staking account. The sender pays
+/// for gas even when the call itself fails.
+pub struct StakingEngine {
+    /// Staking rules
+    staking: Staking,
+    /// Blocks per staking epoch, used to date unbonded funds
+    epoch_length: u64,
+    /// Gas schedule before any fork
+    gas_schedule: GasSchedule,
+    /// Forks overriding gas costs from their activation block
+    forks: ForkSchedule,
+}
+
+impl StakingEngine {
+    /// Create new staking engine
+    pub fn new(staking: Staking, epoch_length: u64, gas_schedule: GasSchedule) -> Self {
+        Self {
+            staking,
+            epoch_length: epoch_length.max(1),
+            gas_schedule,
+            forks: ForkSchedule::default(),
+        }
+    }
+
+    /// Apply the gas overrides of a fork schedule
+    pub fn with_forks(mut self, forks: ForkSchedule) -> Self {
+        self.forks = forks;
+        self
+    }
+
+    /// Apply a staking call transaction
+    pub fn apply(
+        &self,
+        tx: &Transaction,
+        state: &SharedStateDB,
+        context: &ExecutionContext,
+    ) -> VmResult<ExecutionResult> {
+        let sender = tx.sender()?;
+        let gas_schedule = self
+            .gas_schedule
+            .at_block(&self.forks, context.block_number)?;
+        let mut gas_meter = GasMeter::new(tx.gas_limit, gas_schedule);
+
+        // 1. Charge base transaction cost and the staking record update
+        gas_meter.consume_tx_base(tx.data.len())?;
+        gas_meter.consume_storage(StorageOp::Write)?;
+
+        // 2. Verify nonce and that the sender can pay for gas
+        let mut sender_account = state.get_account(&sender)?.unwrap_or_default();
+        if sender_account.nonce != tx.nonce {
+            return Ok(ExecutionResult::failure(
+                gas_meter.consumed(),
+                format!(
+                    "Invalid nonce: expected {}, got {}",
+                    sender_account.nonce, tx.nonce
+                ),
+            ));
+        }
+        if tx.value != 0 {
+            return Ok(ExecutionResult::failure(
+                gas_meter.consumed(),
+                "Staking calls cannot transfer value".to_string(),
+            ));
+        }
+        let max_fee = gas_cost(tx.gas_limit, tx.gas_price)?;
+        if sender_account.balance < max_fee {
+            return Ok(ExecutionResult::failure(
+                gas_meter.consumed(),
+                format!(
+                    "Insufficient balance: required {}, available {}",
+                    max_fee, sender_account.balance
+                ),
+            ));
+        }
+
+        // 3. Update nonce and pay gas fees to coinbase before the call runs
+        let old_nonce = sender_account.nonce;
+        sender_account.increment_nonce();
+        let fee = gas_cost(gas_meter.consumed(), tx.gas_price)?;
+        sender_account.sub_balance(fee)?;
+
+        let mut coinbase_account = state.get_account(&context.coinbase)?.unwrap_or_default();
+        coinbase_account.add_balance(fee)?;
+
+        let mut account_changes = AccountChanges::new();
+        account_changes.update_account(sender, sender_account);
+        account_changes.update_account(context.coinbase, coinbase_account);
+        state.apply_changes(account_changes)?;
+
+        let state_changes = vec![StateChange::NonceChange {
+            address: sender,
+            old_nonce,
+            new_nonce: old_nonce + 1,
+        }];
+
+        // 4. Run the call; a failed call leaves the staking records untouched
+        let epoch = context.block_number / self.epoch_length;
+        let outcome = StakingCall::decode(&tx.data).and_then(|call| {
+            info!("Executing staking call {:?} from {:?}", call, sender);
+            match call {
+                StakingCall::Bond { value } => self.staking.bond(state, sender, value),
+                StakingCall::Unbond { value } => self.staking.unbond(state, sender, value, epoch),
+                StakingCall::WithdrawUnbonded => self
+                    .staking
+                    .withdraw_unbonded(state, sender, epoch)
+                    .map(|_| ()),
+                StakingCall::Validate => self.staking.validate(state, sender),
+                StakingCall::Nominate { targets } => self.staking.nominate(state, sender, targets),
+                StakingCall::Chill => self.staking.chill(state, sender),
+            }
+        });
+
+        match outcome {
+            Ok(()) => {
+                debug!(
+                    "Staking call completed successfully, gas used: {}",
+                    gas_meter.consumed()
+                );
+                Ok(ExecutionResult::success(
+                    gas_meter.consumed(),
+                    state_changes,
+                ))
+            }
+            Err(e) => Ok(ExecutionResult::failure(
+                gas_meter.consumed(),
+                e.to_string(),
+            )),
+        }
+    }
+}
+
 /// Main transaction executor
 pub struct TransactionExecutor {
     /// Balance transfer engine
     balance_engine: BalanceTransferEngine,
-    /// Gas schedule
+    /// Staking call engine, if staking calls are enabled
+    staking_engine: Option<StakingEngine>,
+    /// Gas schedule before any fork
     gas_schedule: GasSchedule,
+    /// Forks overriding gas costs from their activation block
+    forks: ForkSchedule,
 }
 
 impl TransactionExecutor {
@@ -236,10 +395,38 @@ impl TransactionExecutor {
     pub fn new(gas_schedule: GasSchedule) -> Self {
         Self {
             balance_engine: BalanceTransferEngine::new(gas_schedule.clone()),
+            staking_engine: None,
             gas_schedule,
+            forks: ForkSchedule::default(),
         }
     }
 
+    /// Apply the gas overrides of a fork schedule
+    pub fn with_forks(mut self, forks: ForkSchedule) -> Self {
+        self.balance_engine = self.balance_engine.with_forks(forks.clone());
+        self.staking_engine = self
+            .staking_engine
+            .map(|engine| engine.with_forks(forks.clone()));
+        self.forks = forks;
+        self
+    }
+
+    /// Execute staking calls sent to the staking account
+    ///
+    /// `epoch_length` is the number of blocks per staking epoch.
+    pub fn with_staking(mut self, staking: Staking, epoch_length: u64) -> Self {
+        self.staking_engine = Some(
+            StakingEngine::new(staking, epoch_length, self.gas_schedule.clone())
+                .with_forks(self.forks.clone()),
+        );
+        self
+    }
+
+    /// Gas schedule in effect at a block
+    pub fn gas_schedule_at(&self, block_number: BlockNumber) -> VmResult<GasSchedule> {
+        self.gas_schedule.at_block(&self.forks, block_number)
+    }
+
     /// Execute a transaction
     pub fn execute(
         &self,
@@ -259,7 +446,10 @@ impl TransactionExecutor {
         }
 
         // Determine execution engine based on transaction type
-        if tx.data.is_empty() {
+        if let Some(staking_engine) = self.staking_engine.as_ref().filter(|_| is_staking_call(tx)) {
+            // Staking call (Engine 1)
+            staking_engine.apply(tx, state, context)
+        } else if tx.data.is_empty() {
             // Simple balance transfer (Engine 0)
             self.balance_engine.apply(tx, state, context)
         } else {
@@ -273,9 +463,9 @@ impl TransactionExecutor {
         &self,
         tx: &Transaction,
         _state: &SharedStateDB,
-        _context: &ExecutionContext,
+        context: &ExecutionContext,
     ) -> VmResult<ExecutionResult> {
-        let mut gas_meter = GasMeter::new(tx.gas_limit, self.gas_schedule.clone());
+        let mut gas_meter = GasMeter::new(tx.gas_limit, self.gas_schedule_at(context.block_number)?);
         gas_meter.consume_tx_base(tx.data.len())?;
 
         warn!("Contract execution not yet implemented");
@@ -328,7 +518,7 @@ impl TransactionExecutor {
         }
 
         // Check sender account
-        let sender = tx.from()?;
+        let sender = tx.sender()?;
         let sender_account = state.get_account(&sender)?.unwrap_or_default();
 
         // Check nonce
@@ -340,7 +530,9 @@ impl TransactionExecutor {
         }
 
         // Check balance
-        let total_cost = tx.value + (tx.gas_limit * tx.gas_price);
+        let total_cost = to_balance(tx.value)?
+            .checked_add(gas_cost(tx.gas_limit, tx.gas_price)?)
+            .ok_or_else(|| VmError::InvalidTransaction("Transaction cost overflow".to_string()))?;
         if sender_account.balance < total_cost {
             return Err(VmError::InsufficientBalance {
                 required: total_cost,
@@ -352,27 +544,28 @@ impl TransactionExecutor {
     }
 }
 
+/// Convert a transaction amount to an account balance
+fn to_balance(amount: Wei) -> VmResult<u64> {
+    u64::try_from(amount)
+        .map_err(|_| VmError::InvalidTransaction(format!("Amount {} out of range", amount)))
+}
+
+/// Get the price of an amount of gas
+fn gas_cost(gas: Gas, gas_price: Wei) -> VmResult<u64> {
+    to_balance(gas_price)?
+        .checked_mul(gas)
+        .ok_or_else(|| VmError::InvalidTransaction("Gas cost overflow".to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chain_core::{PrivateKey};
+    use crate::account::Account;
 
     fn create_test_transaction() -> VmResult<Transaction> {
-        let private_key = PrivateKey::generate();
-        let sender = private_key.public_key().address();
-        let recipient = Address([2u8; 20]);
-
-        let mut tx = Transaction {
-            nonce: 0,
-            gas_price: 1000,
-            gas_limit: 21000,
-            to: recipient,
-            value: 100,
-            data: vec![],
-            signature: None,
-        };
-
-        tx.sign(&private_key)?;
+        let recipient = Address::new([2u8; 20]);
+        let mut tx = Transaction::transfer(0, recipient, 100, 1, 100_000);
+        tx.sign(&[7u8; 32])?;
         Ok(tx)
     }
 
@@ -383,7 +576,7 @@ mod tests {
         let state = SharedStateDB::memory();
 
         let tx = create_test_transaction().unwrap();
-        let sender = tx.from().unwrap();
+        let sender = tx.sender().unwrap();
 
         // Fund sender account
         let mut changes = AccountChanges::new();
@@ -394,7 +587,7 @@ mod tests {
             block_number: 1,
             timestamp: 1000000,
             gas_limit: 1000000,
-            coinbase: Address([3u8; 20]),
+            coinbase: Address::new([3u8; 20]),
         };
 
         let result = engine.apply(&tx, &state, &context).unwrap();
@@ -410,7 +603,7 @@ mod tests {
         let state = SharedStateDB::memory();
 
         let tx = create_test_transaction().unwrap();
-        let sender = tx.from().unwrap();
+        let sender = tx.sender().unwrap();
 
         // Fund sender account
         let mut changes = AccountChanges::new();
@@ -421,18 +614,18 @@ mod tests {
             block_number: 1,
             timestamp: 1000000,
             gas_limit: 1000000,
-            coinbase: Address([3u8; 20]),
+            coinbase: Address::new([3u8; 20]),
         };
 
         // Test validation
         assert!(executor.validate_transaction(&tx, &state, &context).is_ok());
 
+        // Test gas estimation, before the nonce is used up
+        let estimated_gas = executor.estimate_gas(&tx, &state, &context).unwrap();
+
         // Test execution
         let result = executor.execute(&tx, &state, &context).unwrap();
         assert!(result.success);
-
-        // Test gas estimation
-        let estimated_gas = executor.estimate_gas(&tx, &state, &context).unwrap();
         assert!(estimated_gas >= result.gas_used);
     }
 
@@ -443,7 +636,7 @@ mod tests {
         let state = SharedStateDB::memory();
 
         let tx = create_test_transaction().unwrap();
-        let sender = tx.from().unwrap();
+        let sender = tx.sender().unwrap();
 
         // Fund sender account with insufficient balance
         let mut changes = AccountChanges::new();
@@ -454,7 +647,7 @@ mod tests {
             block_number: 1,
             timestamp: 1000000,
             gas_limit: 1000000,
-            coinbase: Address([3u8; 20]),
+            coinbase: Address::new([3u8; 20]),
         };
 
         let result = engine.apply(&tx, &state, &context).unwrap();
@@ -471,7 +664,7 @@ mod tests {
         let mut tx = create_test_transaction().unwrap();
         tx.nonce = 5; // Wrong nonce
 
-        let sender = tx.from().unwrap();
+        let sender = tx.sender().unwrap();
 
         // Fund sender account
         let mut changes = AccountChanges::new();
@@ -482,10 +675,114 @@ mod tests {
             block_number: 1,
             timestamp: 1000000,
             gas_limit: 1000000,
-            coinbase: Address([3u8; 20]),
+            coinbase: Address::new([3u8; 20]),
         };
 
         // Should fail validation
         assert!(executor.validate_transaction(&tx, &state, &context).is_err());
     }
+
+    #[test]
+    fn test_gas_costs_follow_forks() {
+        use chain_core::Fork;
+
+        let forks = ForkSchedule::new(vec![
+            Fork::new("cheaper_transfers", 10).with_param("gas.balance_transfer", 5000)
+        ])
+        .unwrap();
+        let executor = TransactionExecutor::new(GasSchedule::default()).with_forks(forks);
+
+        let gas_used_at = |block_number| {
+            let state = SharedStateDB::memory();
+            let tx = create_test_transaction().unwrap();
+            let mut changes = AccountChanges::new();
+            changes.update_account(tx.sender().unwrap(), Account::with_balance(1000000));
+            state.apply_changes(changes).unwrap();
+
+            let context = ExecutionContext {
+                block_number,
+                timestamp: 1000000,
+                gas_limit: 1000000,
+                coinbase: Address::new([3u8; 20]),
+            };
+            executor.execute(&tx, &state, &context).unwrap().gas_used
+        };
+
+        // The same transfer costs less once the fork is active
+        assert_eq!(gas_used_at(9) - gas_used_at(10), 9000 - 5000);
+        assert_eq!(executor.gas_schedule_at(10).unwrap().balance_transfer, 5000);
+    }
+
+    #[test]
+    fn test_staking_calls() {
+        use crate::staking::{staking_account, StakingConfig};
+
+        let config = StakingConfig {
+            min_validator_bond: 1_000,
+            ..StakingConfig::default()
+        };
+        let executor = TransactionExecutor::new(GasSchedule::default())
+            .with_staking(Staking::new(config.clone()), 10);
+        let state = SharedStateDB::memory();
+        let key = [7u8; 32];
+
+        let call = |nonce, call: StakingCall| {
+            let mut tx = call.transaction(nonce, 1, 100_000).unwrap();
+            tx.sign(&key).unwrap();
+            tx
+        };
+        let sender = call(0, StakingCall::Chill).sender().unwrap();
+        let mut changes = AccountChanges::new();
+        changes.update_account(sender, Account::with_balance(1000000));
+        state.apply_changes(changes).unwrap();
+
+        let context = ExecutionContext {
+            block_number: 1,
+            timestamp: 1000000,
+            gas_limit: 1000000,
+            coinbase: Address::new([3u8; 20]),
+        };
+
+        // Validating needs a bond first; the failed call still pays for gas
+        let result = executor
+            .execute(&call(0, StakingCall::Validate), &state, &context)
+            .unwrap();
+        assert!(!result.success);
+        let after_failure = state.get_account(&sender).unwrap().unwrap();
+        assert_eq!(after_failure.nonce, 1);
+        assert_eq!(after_failure.balance, 1000000 - result.gas_used);
+
+        let bond = executor
+            .execute(
+                &call(1, StakingCall::Bond { value: 5_000 }),
+                &state,
+                &context,
+            )
+            .unwrap();
+        assert!(bond.success);
+        let validate = executor
+            .execute(&call(2, StakingCall::Validate), &state, &context)
+            .unwrap();
+        assert!(validate.success);
+
+        let account = state.get_account(&sender).unwrap().unwrap();
+        assert_eq!(account.nonce, 3);
+        assert_eq!(
+            account.balance,
+            after_failure.balance - 5_000 - bond.gas_used - validate.gas_used
+        );
+        assert_eq!(
+            state
+                .get_account(&staking_account())
+                .unwrap()
+                .unwrap()
+                .balance,
+            5_000
+        );
+
+        let elected = Staking::new(config).elect(&state).unwrap();
+        assert_eq!(elected.winners.len(), 1);
+        assert_eq!(elected.winners[0].address, sender);
+        assert_eq!(elected.winners[0].backing, 5_000);
+    }
 }
@@ -1,9 +1,13 @@
 //! Gas metering and scheduling
 
 use crate::{VmError, VmResult};
+use chain_core::{BlockNumber, ForkSchedule};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Prefix of fork parameters overriding a gas cost, e.g. `gas.tx_base`
+pub const GAS_PARAM_PREFIX: &str = "gas.";
+
 /// Gas costs for different operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GasSchedule {
@@ -56,6 +60,42 @@ impl GasSchedule {
             .map_err(|e| VmError::Other(format!("Failed to serialize gas schedule: {}", e)))
     }
 
+    /// Gas schedule in effect at a block, with fork overrides applied
+    ///
+    /// Fails if a fork sets a `gas.` parameter that is not a known gas cost.
+    pub fn at_block(&self, forks: &ForkSchedule, block: BlockNumber) -> VmResult<Self> {
+        let mut schedule = self.clone();
+        for (key, value) in forks.params(block) {
+            if let Some(name) = key.strip_prefix(GAS_PARAM_PREFIX) {
+                schedule.set_cost(name, value)?;
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// Set a gas cost by field name
+    fn set_cost(&mut self, name: &str, value: u64) -> VmResult<()> {
+        let cost = match name {
+            "tx_base" => &mut self.tx_base,
+            "tx_data_per_byte" => &mut self.tx_data_per_byte,
+            "account_creation" => &mut self.account_creation,
+            "storage_write" => &mut self.storage_write,
+            "storage_read" => &mut self.storage_read,
+            "balance_transfer" => &mut self.balance_transfer,
+            "contract_call" => &mut self.contract_call,
+            "memory_per_byte" => &mut self.memory_per_byte,
+            "cpu_instruction" => &mut self.cpu_instruction,
+            _ => {
+                return Err(VmError::Other(format!(
+                    "Unknown gas parameter: {}{}",
+                    GAS_PARAM_PREFIX, name
+                )))
+            }
+        };
+        *cost = value;
+        Ok(())
+    }
+
     /// Calculate transaction base cost
     pub fn transaction_cost(&self, data_size: usize) -> u64 {
         self.tx_base + (data_size as u64 * self.tx_data_per_byte)
@@ -249,6 +289,27 @@ mod tests {
         assert_eq!(schedule.tx_data_per_byte, 68);
     }
 
+    #[test]
+    fn test_gas_schedule_at_block() {
+        use chain_core::Fork;
+
+        let forks = ForkSchedule::new(vec![
+            Fork::new("cheaper_transfers", 100).with_param("gas.balance_transfer", 5000),
+            Fork::new("faster_slots", 200).with_param("slot_duration", 2000),
+        ])
+        .unwrap();
+        let schedule = GasSchedule::default();
+
+        assert_eq!(schedule.at_block(&forks, 99).unwrap().balance_transfer, 9000);
+        let upgraded = schedule.at_block(&forks, 200).unwrap();
+        assert_eq!(upgraded.balance_transfer, 5000);
+        assert_eq!(upgraded.tx_base, schedule.tx_base);
+
+        let unknown =
+            ForkSchedule::new(vec![Fork::new("typo", 1).with_param("gas.tx_bsae", 1)]).unwrap();
+        assert!(schedule.at_block(&unknown, 1).is_err());
+    }
+
     #[test]
     fn test_gas_meter_basic() {
         let schedule = GasSchedule::default();
@@ -297,7 +358,7 @@ mod tests {
         meter.consume_storage(StorageOp::Write).unwrap();
 
         assert!(meter.consumed() > 0);
-        assert!(meter.breakdown().len() > 0);
+        assert!(!meter.breakdown().is_empty());
     }
 
     #[test]
@@ -0,0 +1,221 @@
+//! End-of-block issuance and fee distribution
+//!
+//! Transactions pay their fees to [`fee_collector`] (used as
+//! `ExecutionContext::coinbase`). After the last transaction of a block,
+//! [`Issuance::apply_block`] mints the block reward and inflation for the
+//! proposer and splits the collected fees according to the [`RewardConfig`].
+
+use crate::account::AccountChanges;
+use crate::state::SharedStateDB;
+use crate::{VmError, VmResult};
+use chain_core::{Address, Hash, RewardConfig};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Reserved account collecting the fees of the current block
+pub fn fee_collector() -> Address {
+    let mut bytes = [0u8; 20];
+    bytes[..13].copy_from_slice(b"fee_collector");
+    Address::new(bytes)
+}
+
+/// Storage key of the total issuance, kept in the fee collector's storage
+fn total_issuance_key() -> Hash {
+    Hash::new(*blake3::hash(b"issuance:total").as_bytes())
+}
+
+/// Balance movements of one end-of-block transition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockRewards {
+    /// Newly minted reward and inflation paid to the proposer
+    pub minted: u64,
+    /// Fees paid to the proposer
+    pub proposer_fees: u64,
+    /// Fees paid to the treasury
+    pub treasury_fees: u64,
+    /// Fees removed from circulation
+    pub burned: u64,
+}
+
+impl BlockRewards {
+    /// Total amount credited to the proposer
+    pub fn proposer_total(&self) -> u64 {
+        self.minted + self.proposer_fees
+    }
+}
+
+/// Issuance module applying a reward schedule to state
+pub struct Issuance {
+    /// Reward schedule
+    config: RewardConfig,
+}
+
+impl Issuance {
+    /// Create a new issuance module
+    pub fn new(config: RewardConfig) -> VmResult<Self> {
+        config
+            .validate()
+            .map_err(|e| VmError::Other(e.to_string()))?;
+        Ok(Self { config })
+    }
+
+    /// Get the reward schedule
+    pub fn config(&self) -> &RewardConfig {
+        &self.config
+    }
+
+    /// Get the total issuance tracked in state
+    pub fn total_issuance(&self, state: &SharedStateDB) -> VmResult<u128> {
+        match state.get_storage(&fee_collector(), &total_issuance_key())? {
+            Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Set the total issuance, typically to the sum of genesis balances
+    pub fn set_total_issuance(&self, state: &SharedStateDB, total: u128) -> VmResult<()> {
+        let mut changes = AccountChanges::new();
+        changes.update_storage(
+            fee_collector(),
+            total_issuance_key(),
+            bincode::serialize(&total)?,
+        );
+        state.apply_changes(changes)
+    }
+
+    /// Apply the end-of-block reward and fee distribution
+    pub fn apply_block(
+        &self,
+        state: &SharedStateDB,
+        block_number: u64,
+        proposer: Address,
+    ) -> VmResult<BlockRewards> {
+        let total_issuance = self.total_issuance(state)?;
+        let minted = self.config.issuance_for_block(total_issuance);
+
+        let mut collector = state.get_account(&fee_collector())?.unwrap_or_default();
+        let fees = collector.balance;
+        collector.sub_balance(fees)?;
+
+        let split = self.config.split_fees(fees);
+        let rewards = BlockRewards {
+            minted,
+            proposer_fees: split.proposer,
+            treasury_fees: split.treasury,
+            burned: split.burned,
+        };
+
+        // Credits are accumulated per account so a treasury that is also the proposer is paid once
+        let mut credits: HashMap<Address, u64> = HashMap::new();
+        *credits.entry(proposer).or_insert(0) += rewards.proposer_total();
+        if let Some(treasury) = self.config.treasury {
+            *credits.entry(treasury).or_insert(0) += rewards.treasury_fees;
+        }
+
+        let mut changes = AccountChanges::new();
+        changes.update_account(fee_collector(), collector);
+        for (address, amount) in credits {
+            if amount == 0 {
+                continue;
+            }
+            let mut account = state.get_account(&address)?.unwrap_or_default();
+            account.add_balance(amount)?;
+            changes.update_account(address, account);
+        }
+
+        let new_total = (total_issuance + minted as u128).saturating_sub(split.burned as u128);
+        changes.update_storage(
+            fee_collector(),
+            total_issuance_key(),
+            bincode::serialize(&new_total)?,
+        );
+        state.apply_changes(changes)?;
+
+        debug!(
+            "Block #{} rewards: minted {}, fees {} (proposer {}, treasury {}, burned {})",
+            block_number, minted, fees, split.proposer, split.treasury, split.burned
+        );
+
+        Ok(rewards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Account;
+
+    fn address(byte: u8) -> Address {
+        Address::new([byte; 20])
+    }
+
+    fn state_with_fees(fees: u64) -> SharedStateDB {
+        let state = SharedStateDB::memory();
+        let mut changes = AccountChanges::new();
+        changes.update_account(fee_collector(), Account::with_balance(fees));
+        state.apply_changes(changes).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_block_reward_and_fee_split() {
+        let treasury = address(7);
+        let issuance = Issuance::new(RewardConfig {
+            block_reward: 100,
+            fee_burn_bps: 5_000,
+            fee_treasury_bps: 2_000,
+            treasury: Some(treasury),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let state = state_with_fees(1_000);
+        issuance.set_total_issuance(&state, 1_000).unwrap();
+
+        let rewards = issuance.apply_block(&state, 1, address(1)).unwrap();
+        assert_eq!(rewards.minted, 100);
+        assert_eq!(rewards.burned, 500);
+        assert_eq!(rewards.treasury_fees, 200);
+        assert_eq!(rewards.proposer_fees, 300);
+
+        let balance = |who: &Address| state.get_account(who).unwrap().unwrap_or_default().balance;
+        assert_eq!(balance(&address(1)), 400);
+        assert_eq!(balance(&treasury), 200);
+        assert_eq!(balance(&fee_collector()), 0);
+
+        // Minted amount added, burned fees removed
+        assert_eq!(issuance.total_issuance(&state).unwrap(), 1_000 + 100 - 500);
+    }
+
+    #[test]
+    fn test_inflation_compounds_on_total_issuance() {
+        let issuance = Issuance::new(RewardConfig {
+            inflation_bps: 1_000, // 10% per year
+            blocks_per_year: 10,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let state = SharedStateDB::memory();
+        issuance.set_total_issuance(&state, 10_000).unwrap();
+
+        assert_eq!(
+            issuance.apply_block(&state, 1, address(1)).unwrap().minted,
+            100
+        );
+        assert_eq!(
+            issuance.apply_block(&state, 2, address(1)).unwrap().minted,
+            101
+        );
+        assert_eq!(issuance.total_issuance(&state).unwrap(), 10_201);
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        let config = RewardConfig {
+            fee_treasury_bps: 100,
+            ..Default::default()
+        };
+        assert!(Issuance::new(config).is_err());
+    }
+}
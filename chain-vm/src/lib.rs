@@ -7,13 +7,17 @@ pub mod account;
 pub mod error;
 pub mod executor;
 pub mod gas;
+pub mod issuance;
+pub mod staking;
 pub mod state;
 
 pub use account::{Account, AccountState};
 pub use error::{VmError, VmResult};
 pub use executor::{ExecutionResult, StateChange, TransactionExecutor};
-pub use gas::{GasMeter, GasSchedule};
-pub use state::{StateDB, StateSnapshot};
+pub use gas::{GasMeter, GasSchedule, GAS_PARAM_PREFIX};
+pub use issuance::{fee_collector, BlockRewards, Issuance};
+pub use staking::{ElectionResult, Staking, StakingCall, StakingConfig};
+pub use state::{SharedStateDB, StateDB, StateSnapshot};
 
 #[cfg(test)]
 mod tests {
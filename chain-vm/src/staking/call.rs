@@ -0,0 +1,86 @@
+//! Staking calls carried by transactions
+//!
+//! A transaction to [`staking_account`] with no value carries an encoded
+//! [`StakingCall`] in its data and is executed by the staking engine instead
+//! of the balance transfer engine.
+
+use crate::staking::ledger::staking_account;
+use crate::{VmError, VmResult};
+use chain_core::{Address, Gas, Nonce, Transaction, Wei};
+use serde::{Deserialize, Serialize};
+
+/// Staking operation requested by a transaction's sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StakingCall {
+    /// Bond funds from the sender's balance
+    Bond { value: u64 },
+    /// Schedule bonded funds for withdrawal
+    Unbond { value: u64 },
+    /// Return unlocked funds to the sender's balance
+    WithdrawUnbonded,
+    /// Declare intent to validate
+    Validate,
+    /// Nominate validator candidates
+    Nominate { targets: Vec<Address> },
+    /// Stop validating or nominating
+    Chill,
+}
+
+impl StakingCall {
+    /// Encode the call as transaction data
+    pub fn encode(&self) -> VmResult<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Decode a call from transaction data
+    pub fn decode(data: &[u8]) -> VmResult<Self> {
+        bincode::deserialize(data)
+            .map_err(|e| VmError::InvalidTransaction(format!("Invalid staking call: {}", e)))
+    }
+
+    /// Build an unsigned transaction making this call
+    pub fn transaction(
+        &self,
+        nonce: Nonce,
+        gas_price: Wei,
+        gas_limit: Gas,
+    ) -> VmResult<Transaction> {
+        Ok(Transaction::new(
+            nonce,
+            gas_price,
+            gas_limit,
+            Some(staking_account()),
+            0,
+            self.encode()?,
+        ))
+    }
+}
+
+/// Whether a transaction is a staking call
+pub fn is_staking_call(tx: &Transaction) -> bool {
+    tx.to == Some(staking_account()) && !tx.data.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_roundtrip() {
+        let call = StakingCall::Nominate {
+            targets: vec![Address::new([1u8; 20]), Address::new([2u8; 20])],
+        };
+        let tx = call.transaction(3, 1, 50_000).unwrap();
+
+        assert!(is_staking_call(&tx));
+        assert_eq!(StakingCall::decode(&tx.data).unwrap(), call);
+        assert!(StakingCall::decode(&[0xff; 3]).is_err());
+        assert!(!is_staking_call(&Transaction::transfer(
+            0,
+            staking_account(),
+            10,
+            1,
+            21_000
+        )));
+    }
+}
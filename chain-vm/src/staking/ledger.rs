@@ -0,0 +1,545 @@
+//! Bonded stake, roles and nominations kept in state storage
+//!
+//! Bonded funds are moved from the staker's balance to the reserved
+//! [`staking_account`], whose storage also holds every staker record and
+//! the list of bonded accounts.
+
+use crate::account::AccountChanges;
+use crate::staking::phragmen::{seq_phragmen, ElectionResult, Voter};
+use crate::state::SharedStateDB;
+use crate::{VmError, VmResult};
+use chain_core::{Address, Hash};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+/// Reserved account holding bonded funds and staking records
+pub fn staking_account() -> Address {
+    let mut bytes = [0u8; 20];
+    bytes[..7].copy_from_slice(b"staking");
+    Address::new(bytes)
+}
+
+/// Storage key of the list of bonded accounts
+fn stakers_key() -> Hash {
+    Hash::new(*blake3::hash(b"staking:stakers").as_bytes())
+}
+
+/// Storage key of a staker record
+fn staker_key(who: &Address) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"staking:staker:");
+    hasher.update(who.as_bytes());
+    Hash::new(*hasher.finalize().as_bytes())
+}
+
+/// Staking parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StakingConfig {
+    /// Number of validators to elect each epoch
+    pub validator_count: usize,
+    /// Minimum active bond to declare intent to validate
+    pub min_validator_bond: u64,
+    /// Minimum active bond to nominate
+    pub min_nominator_bond: u64,
+    /// Maximum number of nomination targets per nominator
+    pub max_nominations: usize,
+    /// Epochs unbonded funds stay locked before they can be withdrawn
+    pub bonding_duration: u64,
+}
+
+impl Default for StakingConfig {
+    fn default() -> Self {
+        Self {
+            validator_count: 21,
+            min_validator_bond: 10_000,
+            min_nominator_bond: 100,
+            max_nominations: 16,
+            bonding_duration: 28,
+        }
+    }
+}
+
+/// A chunk of stake waiting to be withdrawn
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnlockChunk {
+    /// Amount being unbonded
+    pub value: u64,
+    /// Epoch from which the amount can be withdrawn
+    pub epoch: u64,
+}
+
+/// Bonded funds of a staker
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StakingLedger {
+    /// Stake counted in elections
+    pub active: u64,
+    /// Stake being unbonded
+    pub unlocking: Vec<UnlockChunk>,
+}
+
+impl StakingLedger {
+    /// Total bonded funds, active and unlocking
+    pub fn total(&self) -> u64 {
+        self.active + self.unlocking.iter().map(|c| c.value).sum::<u64>()
+    }
+}
+
+/// Role a staker takes in elections
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StakingRole {
+    /// Bonded but not participating
+    #[default]
+    Idle,
+    /// Candidate for the validator set
+    Validator,
+    /// Backing the given validator candidates
+    Nominator { targets: Vec<Address> },
+}
+
+/// Staking record of an account
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Staker {
+    /// Bonded funds
+    pub ledger: StakingLedger,
+    /// Election role
+    pub role: StakingRole,
+}
+
+/// Staking operations over a state database
+pub struct Staking {
+    /// Staking parameters
+    config: StakingConfig,
+}
+
+impl Staking {
+    /// Create a new staking module
+    pub fn new(config: StakingConfig) -> Self {
+        Self { config }
+    }
+
+    /// Get the staking parameters
+    pub fn config(&self) -> &StakingConfig {
+        &self.config
+    }
+
+    /// Get the staking record of an account
+    pub fn staker(&self, state: &SharedStateDB, who: &Address) -> VmResult<Option<Staker>> {
+        match state.get_storage(&staking_account(), &staker_key(who))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get all bonded accounts
+    pub fn stakers(&self, state: &SharedStateDB) -> VmResult<Vec<Address>> {
+        match state.get_storage(&staking_account(), &stakers_key())? {
+            Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Bond funds from an account's balance
+    pub fn bond(&self, state: &SharedStateDB, who: Address, value: u64) -> VmResult<()> {
+        if value == 0 {
+            return Err(VmError::InvalidTransaction(
+                "Bond value must be greater than 0".to_string(),
+            ));
+        }
+
+        let mut account = state.get_account(&who)?.unwrap_or_default();
+        account.sub_balance(value)?;
+
+        let mut pot = state.get_account(&staking_account())?.unwrap_or_default();
+        pot.add_balance(value)?;
+
+        let mut staker = self.staker(state, &who)?.unwrap_or_default();
+        staker.ledger.active += value;
+
+        let mut changes = AccountChanges::new();
+        changes.update_account(who, account);
+        changes.update_account(staking_account(), pot);
+        self.write_staker(state, &mut changes, who, Some(&staker))?;
+        state.apply_changes(changes)?;
+
+        debug!(
+            "Bonded {} from {:?}, active {}",
+            value, who, staker.ledger.active
+        );
+        Ok(())
+    }
+
+    /// Schedule bonded funds for withdrawal after the bonding duration
+    pub fn unbond(
+        &self,
+        state: &SharedStateDB,
+        who: Address,
+        value: u64,
+        current_epoch: u64,
+    ) -> VmResult<()> {
+        let mut staker = self.bonded_staker(state, &who)?;
+        if value > staker.ledger.active {
+            return Err(VmError::InsufficientBalance {
+                required: value,
+                available: staker.ledger.active,
+            });
+        }
+
+        staker.ledger.active -= value;
+        if staker.ledger.active == 0 {
+            staker.role = StakingRole::Idle;
+        } else if staker.ledger.active < self.min_bond(&staker.role) {
+            return Err(VmError::InvalidTransaction(
+                "Remaining bond is below the minimum for the current role".to_string(),
+            ));
+        }
+
+        staker.ledger.unlocking.push(UnlockChunk {
+            value,
+            epoch: current_epoch + self.config.bonding_duration,
+        });
+
+        let mut changes = AccountChanges::new();
+        self.write_staker(state, &mut changes, who, Some(&staker))?;
+        state.apply_changes(changes)
+    }
+
+    /// Return unlocked funds to the account balance, returning the amount withdrawn
+    pub fn withdraw_unbonded(
+        &self,
+        state: &SharedStateDB,
+        who: Address,
+        current_epoch: u64,
+    ) -> VmResult<u64> {
+        let mut staker = self.bonded_staker(state, &who)?;
+
+        let (unlocked, locked): (Vec<_>, Vec<_>) = staker
+            .ledger
+            .unlocking
+            .drain(..)
+            .partition(|chunk| chunk.epoch <= current_epoch);
+        staker.ledger.unlocking = locked;
+
+        let value: u64 = unlocked.iter().map(|c| c.value).sum();
+        if value == 0 {
+            return Ok(0);
+        }
+
+        let mut pot = state.get_account(&staking_account())?.unwrap_or_default();
+        pot.sub_balance(value)?;
+
+        let mut account = state.get_account(&who)?.unwrap_or_default();
+        account.add_balance(value)?;
+
+        let mut changes = AccountChanges::new();
+        changes.update_account(staking_account(), pot);
+        changes.update_account(who, account);
+
+        let fully_unbonded = staker.ledger.total() == 0;
+        self.write_staker(
+            state,
+            &mut changes,
+            who,
+            (!fully_unbonded).then_some(&staker),
+        )?;
+        state.apply_changes(changes)?;
+
+        Ok(value)
+    }
+
+    /// Declare intent to validate
+    pub fn validate(&self, state: &SharedStateDB, who: Address) -> VmResult<()> {
+        self.set_role(state, who, StakingRole::Validator)
+    }
+
+    /// Nominate validator candidates
+    pub fn nominate(
+        &self,
+        state: &SharedStateDB,
+        who: Address,
+        targets: Vec<Address>,
+    ) -> VmResult<()> {
+        let mut unique: Vec<Address> = Vec::with_capacity(targets.len());
+        for target in targets {
+            if !unique.contains(&target) {
+                unique.push(target);
+            }
+        }
+
+        if unique.is_empty() {
+            return Err(VmError::InvalidTransaction(
+                "At least one nomination target is required".to_string(),
+            ));
+        }
+        if unique.len() > self.config.max_nominations {
+            return Err(VmError::InvalidTransaction(format!(
+                "Too many nomination targets: {} > {}",
+                unique.len(),
+                self.config.max_nominations
+            )));
+        }
+
+        self.set_role(state, who, StakingRole::Nominator { targets: unique })
+    }
+
+    /// Stop validating or nominating
+    pub fn chill(&self, state: &SharedStateDB, who: Address) -> VmResult<()> {
+        self.set_role(state, who, StakingRole::Idle)
+    }
+
+    /// Elect the next validator set with sequential Phragmén
+    ///
+    /// Validators back themselves with their own active bond; nominations
+    /// of accounts that are not validator candidates are ignored.
+    pub fn elect(&self, state: &SharedStateDB) -> VmResult<ElectionResult> {
+        let mut candidates = Vec::new();
+        let mut voters = Vec::new();
+
+        for who in self.stakers(state)? {
+            let Some(staker) = self.staker(state, &who)? else {
+                continue;
+            };
+
+            match staker.role {
+                StakingRole::Validator
+                    if staker.ledger.active >= self.config.min_validator_bond =>
+                {
+                    candidates.push(who);
+                    voters.push(Voter {
+                        who,
+                        stake: staker.ledger.active,
+                        targets: vec![who],
+                    });
+                }
+                StakingRole::Nominator { targets }
+                    if staker.ledger.active >= self.config.min_nominator_bond =>
+                {
+                    voters.push(Voter {
+                        who,
+                        stake: staker.ledger.active,
+                        targets,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let result = seq_phragmen(self.config.validator_count, &candidates, &voters);
+        info!(
+            "Elected {} of {} validator candidates with {} voters",
+            result.winners.len(),
+            candidates.len(),
+            voters.len()
+        );
+
+        Ok(result)
+    }
+
+    /// Minimum active bond required for a role
+    fn min_bond(&self, role: &StakingRole) -> u64 {
+        match role {
+            StakingRole::Idle => 0,
+            StakingRole::Validator => self.config.min_validator_bond,
+            StakingRole::Nominator { .. } => self.config.min_nominator_bond,
+        }
+    }
+
+    /// Get the staking record of an account that must be bonded
+    fn bonded_staker(&self, state: &SharedStateDB, who: &Address) -> VmResult<Staker> {
+        self.staker(state, who)?
+            .ok_or_else(|| VmError::InvalidTransaction(format!("Account {:?} is not bonded", who)))
+    }
+
+    /// Change the election role of a bonded account
+    fn set_role(&self, state: &SharedStateDB, who: Address, role: StakingRole) -> VmResult<()> {
+        let mut staker = self.bonded_staker(state, &who)?;
+
+        let min_bond = self.min_bond(&role);
+        if staker.ledger.active < min_bond {
+            return Err(VmError::InsufficientBalance {
+                required: min_bond,
+                available: staker.ledger.active,
+            });
+        }
+
+        staker.role = role;
+
+        let mut changes = AccountChanges::new();
+        self.write_staker(state, &mut changes, who, Some(&staker))?;
+        state.apply_changes(changes)
+    }
+
+    /// Record a staker update (or removal) and keep the staker list in sync
+    fn write_staker(
+        &self,
+        state: &SharedStateDB,
+        changes: &mut AccountChanges,
+        who: Address,
+        staker: Option<&Staker>,
+    ) -> VmResult<()> {
+        let mut stakers = self.stakers(state)?;
+        let listed = stakers.contains(&who);
+
+        match staker {
+            Some(staker) => {
+                changes.update_storage(
+                    staking_account(),
+                    staker_key(&who),
+                    bincode::serialize(staker)?,
+                );
+                if !listed {
+                    stakers.push(who);
+                    changes.update_storage(
+                        staking_account(),
+                        stakers_key(),
+                        bincode::serialize(&stakers)?,
+                    );
+                }
+            }
+            None => {
+                // An empty value removes the storage entry
+                changes.update_storage(staking_account(), staker_key(&who), Vec::new());
+                if listed {
+                    stakers.retain(|s| s != &who);
+                    changes.update_storage(
+                        staking_account(),
+                        stakers_key(),
+                        bincode::serialize(&stakers)?,
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Account;
+
+    fn address(byte: u8) -> Address {
+        Address::new([byte; 20])
+    }
+
+    fn funded_state(accounts: &[(u8, u64)]) -> SharedStateDB {
+        let state = SharedStateDB::memory();
+        let mut changes = AccountChanges::new();
+        for (byte, balance) in accounts {
+            changes.update_account(address(*byte), Account::with_balance(*balance));
+        }
+        state.apply_changes(changes).unwrap();
+        state
+    }
+
+    fn test_config() -> StakingConfig {
+        StakingConfig {
+            validator_count: 2,
+            min_validator_bond: 1_000,
+            min_nominator_bond: 10,
+            max_nominations: 4,
+            bonding_duration: 2,
+        }
+    }
+
+    #[test]
+    fn test_bond_moves_balance() {
+        let state = funded_state(&[(1, 5_000)]);
+        let staking = Staking::new(test_config());
+
+        staking.bond(&state, address(1), 2_000).unwrap();
+
+        assert_eq!(
+            state.get_account(&address(1)).unwrap().unwrap().balance,
+            3_000
+        );
+        assert_eq!(
+            state
+                .get_account(&staking_account())
+                .unwrap()
+                .unwrap()
+                .balance,
+            2_000
+        );
+        assert_eq!(
+            staking
+                .staker(&state, &address(1))
+                .unwrap()
+                .unwrap()
+                .ledger
+                .active,
+            2_000
+        );
+        assert_eq!(staking.stakers(&state).unwrap(), vec![address(1)]);
+
+        // Cannot bond more than the balance
+        assert!(staking.bond(&state, address(1), 10_000).is_err());
+    }
+
+    #[test]
+    fn test_unbond_and_withdraw() {
+        let state = funded_state(&[(1, 5_000)]);
+        let staking = Staking::new(test_config());
+
+        staking.bond(&state, address(1), 2_000).unwrap();
+        staking.unbond(&state, address(1), 2_000, 10).unwrap();
+
+        // Still locked during the bonding duration
+        assert_eq!(
+            staking.withdraw_unbonded(&state, address(1), 11).unwrap(),
+            0
+        );
+        assert_eq!(
+            staking.withdraw_unbonded(&state, address(1), 12).unwrap(),
+            2_000
+        );
+
+        assert_eq!(
+            state.get_account(&address(1)).unwrap().unwrap().balance,
+            5_000
+        );
+        assert!(staking.staker(&state, &address(1)).unwrap().is_none());
+        assert!(staking.stakers(&state).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_roles_require_minimum_bond() {
+        let state = funded_state(&[(1, 5_000)]);
+        let staking = Staking::new(test_config());
+
+        // Not bonded yet
+        assert!(staking.validate(&state, address(1)).is_err());
+
+        staking.bond(&state, address(1), 500).unwrap();
+        assert!(staking.validate(&state, address(1)).is_err());
+        assert!(staking
+            .nominate(&state, address(1), vec![address(2)])
+            .is_ok());
+        assert!(staking.nominate(&state, address(1), vec![]).is_err());
+    }
+
+    #[test]
+    fn test_election_from_bonded_stake() {
+        let state = funded_state(&[(1, 5_000), (2, 5_000), (3, 5_000), (10, 5_000)]);
+        let staking = Staking::new(test_config());
+
+        for (validator, bond) in [(1, 1_000), (2, 1_500), (3, 1_200)] {
+            staking.bond(&state, address(validator), bond).unwrap();
+            staking.validate(&state, address(validator)).unwrap();
+        }
+
+        // A nominator lifts validator 1 above validator 3
+        staking.bond(&state, address(10), 1_000).unwrap();
+        staking
+            .nominate(&state, address(10), vec![address(1)])
+            .unwrap();
+
+        let result = staking.elect(&state).unwrap();
+        let winners: Vec<_> = result
+            .winners
+            .iter()
+            .map(|w| (w.address, w.backing))
+            .collect();
+        assert_eq!(winners, vec![(address(1), 2_000), (address(2), 1_500)]);
+    }
+}
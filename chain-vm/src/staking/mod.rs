@@ -0,0 +1,11 @@
+//! Nominated proof-of-stake: bonding, nominations and validator elections
+
+pub mod call;
+pub mod ledger;
+pub mod phragmen;
+
+pub use call::{is_staking_call, StakingCall};
+pub use ledger::{
+    staking_account, Staker, Staking, StakingConfig, StakingLedger, StakingRole, UnlockChunk,
+};
+pub use phragmen::{seq_phragmen, ElectionResult, Voter, Winner};
@@ -0,0 +1,322 @@
+//! Sequential Phragmén validator election
+//!
+//! All arithmetic is integer fixed-point so every node computes the same
+//! result. Loads and scores are expressed in units of `1 / LOAD_PRECISION`.
+
+use chain_core::Address;
+use std::collections::BTreeMap;
+
+/// Fixed-point denominator for loads and scores
+const LOAD_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+/// A staker voting for one or more candidates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Voter {
+    /// Voter account
+    pub who: Address,
+    /// Stake backing the votes
+    pub stake: u64,
+    /// Candidates the voter approves of
+    pub targets: Vec<Address>,
+}
+
+/// An elected candidate and its total backing stake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Winner {
+    /// Candidate account
+    pub address: Address,
+    /// Stake assigned to the candidate by its voters
+    pub backing: u64,
+}
+
+/// Result of an election, winners in order of election
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElectionResult {
+    /// Elected candidates
+    pub winners: Vec<Winner>,
+}
+
+impl ElectionResult {
+    /// Total stake backing the elected set
+    pub fn total_backing(&self) -> u128 {
+        self.winners.iter().map(|w| w.backing as u128).sum()
+    }
+}
+
+#[derive(Debug)]
+struct CandidateState {
+    approval_stake: u128,
+    score: u128,
+    elected: bool,
+}
+
+#[derive(Debug)]
+struct Edge {
+    candidate: Address,
+    load: u128,
+}
+
+#[derive(Debug)]
+struct VoterState {
+    stake: u128,
+    load: u128,
+    edges: Vec<Edge>,
+}
+
+/// Elect up to `to_elect` candidates with sequential Phragmén
+///
+/// Votes for addresses that are not candidates are ignored. Candidates
+/// without any approval stake are never elected, so fewer than `to_elect`
+/// winners may be returned. Ties are broken by the lower address.
+pub fn seq_phragmen(to_elect: usize, candidates: &[Address], voters: &[Voter]) -> ElectionResult {
+    // Keyed by raw address bytes for a deterministic iteration order
+    let mut candidate_states: BTreeMap<[u8; 20], CandidateState> = candidates
+        .iter()
+        .map(|c| {
+            (
+                *c.as_bytes(),
+                CandidateState {
+                    approval_stake: 0,
+                    score: 0,
+                    elected: false,
+                },
+            )
+        })
+        .collect();
+
+    let mut voter_states: Vec<VoterState> = Vec::with_capacity(voters.len());
+    for voter in voters {
+        let mut edges: Vec<Edge> = Vec::new();
+        for target in &voter.targets {
+            if edges.iter().any(|e| e.candidate == *target) {
+                continue;
+            }
+            if let Some(candidate) = candidate_states.get_mut(target.as_bytes()) {
+                candidate.approval_stake += voter.stake as u128;
+                edges.push(Edge {
+                    candidate: *target,
+                    load: 0,
+                });
+            }
+        }
+
+        if !edges.is_empty() && voter.stake > 0 {
+            voter_states.push(VoterState {
+                stake: voter.stake as u128,
+                load: 0,
+                edges,
+            });
+        }
+    }
+
+    let mut elected: Vec<Address> = Vec::new();
+    for _round in 0..to_elect {
+        // Base score: the load a voter takes on by backing the candidate alone
+        for candidate in candidate_states.values_mut() {
+            if !candidate.elected && candidate.approval_stake > 0 {
+                candidate.score = LOAD_PRECISION / candidate.approval_stake;
+            }
+        }
+
+        // Add the load already carried by each voter of the candidate
+        for voter in &voter_states {
+            for edge in &voter.edges {
+                let candidate = candidate_states.get_mut(edge.candidate.as_bytes()).unwrap();
+                if !candidate.elected && candidate.approval_stake > 0 {
+                    candidate.score = candidate.score.saturating_add(mul_div(
+                        voter.stake,
+                        voter.load,
+                        candidate.approval_stake,
+                    ));
+                }
+            }
+        }
+
+        let winner = candidate_states
+            .iter()
+            .filter(|(_, c)| !c.elected && c.approval_stake > 0)
+            .min_by_key(|(address, c)| (c.score, **address))
+            .map(|(address, c)| (Address::new(*address), c.score));
+
+        let Some((winner, winner_score)) = winner else {
+            break;
+        };
+
+        candidate_states.get_mut(winner.as_bytes()).unwrap().elected = true;
+        elected.push(winner);
+
+        for voter in &mut voter_states {
+            for edge in &mut voter.edges {
+                if edge.candidate == winner {
+                    edge.load = winner_score.saturating_sub(voter.load);
+                    voter.load = winner_score;
+                }
+            }
+        }
+    }
+
+    // Distribute each voter's stake over its elected targets in proportion to edge load
+    let mut backing: BTreeMap<[u8; 20], u128> = BTreeMap::new();
+    for voter in &voter_states {
+        if voter.load == 0 {
+            continue;
+        }
+
+        let elected_edges: Vec<&Edge> = voter
+            .edges
+            .iter()
+            .filter(|e| candidate_states[e.candidate.as_bytes()].elected && e.load > 0)
+            .collect();
+
+        let mut remaining = voter.stake;
+        for (i, edge) in elected_edges.iter().enumerate() {
+            let share = if i + 1 == elected_edges.len() {
+                remaining
+            } else {
+                mul_div(voter.stake, edge.load, voter.load).min(remaining)
+            };
+            remaining -= share;
+            *backing.entry(*edge.candidate.as_bytes()).or_insert(0) += share;
+        }
+    }
+
+    ElectionResult {
+        winners: elected
+            .into_iter()
+            .map(|address| Winner {
+                address,
+                backing: backing
+                    .get(address.as_bytes())
+                    .copied()
+                    .unwrap_or(0)
+                    .min(u64::MAX as u128) as u64,
+            })
+            .collect(),
+    }
+}
+
+/// Compute `a * b / c` without overflowing the intermediate product
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    if c == 0 {
+        return 0;
+    }
+    if let Some(product) = a.checked_mul(b) {
+        return product / c;
+    }
+
+    // 256-bit product as (high, low) followed by binary long division
+    let (high, low) = widening_mul(a, b);
+    let mut quotient: u128 = 0;
+    let mut remainder: u128 = 0;
+    for i in (0..256).rev() {
+        let bit = if i >= 128 {
+            (high >> (i - 128)) & 1
+        } else {
+            (low >> i) & 1
+        };
+        let overflow = remainder >> 127;
+        remainder = (remainder << 1) | bit;
+        if overflow == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            if i < 128 {
+                quotient |= 1 << i;
+            } else {
+                // Quotient does not fit in 128 bits
+                return u128::MAX;
+            }
+        }
+    }
+    quotient
+}
+
+/// Full 256-bit product of two u128 values as (high, low)
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+
+    let cross = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+    let low = (cross << 64) | (lo_lo & MASK);
+    let high = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (cross >> 64);
+
+    (high, low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(byte: u8) -> Address {
+        Address::new([byte; 20])
+    }
+
+    fn voter(who: u8, stake: u64, targets: &[u8]) -> Voter {
+        Voter {
+            who: address(who),
+            stake,
+            targets: targets.iter().map(|t| address(*t)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_elects_by_approval_and_load() {
+        let candidates = [address(1), address(2), address(3)];
+        let voters = [
+            voter(10, 10, &[1]),
+            voter(11, 20, &[2]),
+            voter(12, 5, &[1, 3]),
+        ];
+
+        let result = seq_phragmen(2, &candidates, &voters);
+
+        assert_eq!(
+            result.winners,
+            vec![
+                Winner {
+                    address: address(2),
+                    backing: 20
+                },
+                Winner {
+                    address: address(1),
+                    backing: 15
+                },
+            ]
+        );
+        assert_eq!(result.total_backing(), 35);
+    }
+
+    #[test]
+    fn test_stake_split_between_winners() {
+        let candidates = [address(1), address(2)];
+        let voters = [voter(10, 30, &[1, 2]), voter(11, 10, &[1])];
+
+        let result = seq_phragmen(2, &candidates, &voters);
+
+        assert_eq!(result.winners.len(), 2);
+        assert_eq!(result.winners[0].address, address(1));
+        // All stake is assigned exactly once
+        assert_eq!(result.total_backing(), 40);
+    }
+
+    #[test]
+    fn test_ignores_unbacked_and_unknown() {
+        let candidates = [address(1), address(2)];
+        let voters = [voter(10, 10, &[1, 9])];
+
+        let result = seq_phragmen(5, &candidates, &voters);
+        assert_eq!(result.winners.len(), 1);
+        assert_eq!(result.winners[0].backing, 10);
+    }
+
+    #[test]
+    fn test_mul_div_wide() {
+        assert_eq!(mul_div(u128::MAX, 4, 8), u128::MAX / 2);
+        assert_eq!(mul_div(1 << 100, 1 << 100, 1 << 90), 1 << 110);
+        assert_eq!(mul_div(6, 7, 3), 14);
+    }
+}
@@ -1,11 +1,16 @@
 //! State database and snapshots
+//!
+//! State is committed to by the [`entries_root`] of its [`StateEntry`]s,
+//! the same commitment warp sync proves ranges against. Accounts are stored
+//! under `a ++ address`, contract code under `c ++ address` and storage
+//! values under `s ++ address ++ key`.
 
 use crate::account::{Account, AccountChanges, AccountState};
 use crate::{VmError, VmResult};
-use chain_core::{Address, Hash};
+use chain_core::{entries_root, Address, Hash, StateEntry};
 use parking_lot::RwLock;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, OnceLock};
 
 /// State database trait
 pub trait StateDB: Send + Sync {
@@ -35,9 +40,22 @@ pub trait StateDB: Send + Sync {
     
     /// Get state root hash
     fn state_root(&self) -> Hash;
+
+    /// Get every state entry, sorted by key
+    fn entries(&self) -> Vec<StateEntry>;
+
+    /// Get the entries written since the state was created or forked,
+    /// sorted by key, with removed entries left empty
+    fn changes(&self) -> Vec<StateEntry>;
+
+    /// Write state entries, removing those left empty
+    fn apply_entries(&mut self, entries: &[StateEntry]) -> VmResult<()>;
     
     /// Create a snapshot
     fn snapshot(&self) -> Box<dyn StateSnapshot>;
+
+    /// Downcast support for snapshot restores
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any;
 }
 
 /// State snapshot for rollback/forking
@@ -49,6 +67,54 @@ pub trait StateSnapshot: Send + Sync {
     fn fork(&self) -> Box<dyn StateDB>;
 }
 
+/// Key prefix of account entries
+const ACCOUNT_PREFIX: u8 = b'a';
+/// Key prefix of contract code entries
+const CODE_PREFIX: u8 = b'c';
+/// Key prefix of storage entries
+const STORAGE_PREFIX: u8 = b's';
+/// Length of an encoded account: nonce, balance, code hash and storage root
+const ACCOUNT_LENGTH: usize = 80;
+
+fn entry_key(prefix: u8, address: &Address, suffix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(21 + suffix.len());
+    key.push(prefix);
+    key.extend_from_slice(address.as_bytes());
+    key.extend_from_slice(suffix);
+    key
+}
+
+fn encode_account(account: &Account) -> Vec<u8> {
+    let mut value = Vec::with_capacity(ACCOUNT_LENGTH);
+    value.extend_from_slice(&account.nonce.to_be_bytes());
+    value.extend_from_slice(&account.balance.to_be_bytes());
+    value.extend_from_slice(account.code_hash.as_bytes());
+    value.extend_from_slice(account.storage_root.as_bytes());
+    value
+}
+
+fn invalid_entry(key: &[u8]) -> VmError {
+    VmError::State(format!("Invalid state entry with a {}-byte key", key.len()))
+}
+
+fn decode_account(value: &[u8]) -> VmResult<Account> {
+    if value.len() != ACCOUNT_LENGTH {
+        return Err(VmError::State(format!(
+            "Invalid account entry of {} bytes",
+            value.len()
+        )));
+    }
+    let (nonce, rest) = value.split_at(8);
+    let (balance, rest) = rest.split_at(8);
+    let (code_hash, storage_root) = rest.split_at(32);
+    Ok(Account {
+        nonce: u64::from_be_bytes(nonce.try_into().unwrap()),
+        balance: u64::from_be_bytes(balance.try_into().unwrap()),
+        code_hash: Hash::from_slice(code_hash),
+        storage_root: Hash::from_slice(storage_root),
+    })
+}
+
 /// In-memory state database implementation
 #[derive(Debug, Clone)]
 pub struct MemoryStateDB {
@@ -58,8 +124,10 @@ pub struct MemoryStateDB {
     storage: HashMap<Address, HashMap<Hash, Vec<u8>>>,
     /// Contract code
     code: HashMap<Address, Vec<u8>>,
-    /// State root cache
-    state_root: Hash,
+    /// State root, computed on first use after a change
+    state_root: OnceLock<Hash>,
+    /// Keys of the entries written since the state was created or forked
+    changed: BTreeSet<Vec<u8>>,
 }
 
 impl MemoryStateDB {
@@ -69,7 +137,8 @@ impl MemoryStateDB {
             accounts: HashMap::new(),
             storage: HashMap::new(),
             code: HashMap::new(),
-            state_root: Hash::zero(),
+            state_root: OnceLock::new(),
+            changed: BTreeSet::new(),
         }
     }
 
@@ -77,29 +146,120 @@ impl MemoryStateDB {
     pub fn with_accounts(accounts: HashMap<Address, Account>) -> Self {
         let mut db = Self::new();
         db.accounts = accounts;
-        db.update_state_root();
         db
     }
 
-    /// Update state root hash
+    /// Rebuild a state from its entries
+    pub fn from_entries(entries: &[StateEntry]) -> VmResult<Self> {
+        let mut db = Self::new();
+        for (key, value) in entries {
+            if value.is_empty() {
+                return Err(invalid_entry(key));
+            }
+            db.insert_entry(key, value)?;
+        }
+        db.changed.clear();
+        Ok(db)
+    }
+
+    /// Write a state entry, removing it if left empty
+    fn insert_entry(&mut self, key: &[u8], value: &[u8]) -> VmResult<()> {
+        match key.split_first() {
+            Some((&ACCOUNT_PREFIX, address)) if address.len() == 20 => {
+                let account = if value.is_empty() {
+                    Account::default()
+                } else {
+                    decode_account(value)?
+                };
+                self.insert_account(Address::from_slice(address), account);
+            }
+            Some((&CODE_PREFIX, address)) if address.len() == 20 => {
+                self.insert_code(Address::from_slice(address), value.to_vec());
+            }
+            Some((&STORAGE_PREFIX, rest)) if rest.len() == 52 => {
+                let (address, key) = rest.split_at(20);
+                self.insert_storage(
+                    Address::from_slice(address),
+                    Hash::from_slice(key),
+                    value.to_vec(),
+                );
+            }
+            _ => return Err(invalid_entry(key)),
+        }
+        Ok(())
+    }
+
+    /// Get the value of a state entry, empty if it is not set
+    fn entry(&self, key: &[u8]) -> Vec<u8> {
+        let value = match key.split_first() {
+            Some((&ACCOUNT_PREFIX, address)) => self
+                .accounts
+                .get(&Address::from_slice(address))
+                .map(encode_account),
+            Some((&CODE_PREFIX, address)) => self.code.get(&Address::from_slice(address)).cloned(),
+            Some((&STORAGE_PREFIX, rest)) => {
+                let (address, key) = rest.split_at(20);
+                self.storage
+                    .get(&Address::from_slice(address))
+                    .and_then(|storage| storage.get(&Hash::from_slice(key)))
+                    .cloned()
+            }
+            _ => None,
+        };
+        value.unwrap_or_default()
+    }
+
+    /// Drop the state root, so it is computed again when next asked for
+    ///
+    /// Hashing every entry is the costly part of a change, so a block's
+    /// changes are committed to once, when its root is read.
     fn update_state_root(&mut self) {
-        // Simple hash of all account data - in production this would use a Merkle tree
-        let mut hasher = blake3::Hasher::new();
-        
-        // Sort accounts for deterministic hashing
-        let mut sorted_accounts: Vec<_> = self.accounts.iter().collect();
-        sorted_accounts.sort_by_key(|(addr, _)| *addr);
-        
-        for (address, account) in sorted_accounts {
-            hasher.update(&address.0);
-            hasher.update(&account.nonce.to_le_bytes());
-            hasher.update(&account.balance.to_le_bytes());
-            hasher.update(&account.code_hash.0);
-            hasher.update(&account.storage_root.0);
+        self.state_root = OnceLock::new();
+    }
+
+    fn insert_account(&mut self, address: Address, account: Account) {
+        self.changed
+            .insert(entry_key(ACCOUNT_PREFIX, &address, &[]));
+        if account.is_empty() {
+            self.accounts.remove(&address);
+        } else {
+            self.accounts.insert(address, account);
+        }
+    }
+
+    fn insert_storage(&mut self, address: Address, key: Hash, value: Vec<u8>) {
+        self.changed
+            .insert(entry_key(STORAGE_PREFIX, &address, key.as_bytes()));
+        let storage = self.storage.entry(address).or_default();
+
+        if value.is_empty() {
+            storage.remove(&key);
+            if storage.is_empty() {
+                self.storage.remove(&address);
+            }
+        } else {
+            storage.insert(key, value);
+        }
+    }
+
+    fn remove_account(&mut self, address: &Address) {
+        self.insert_account(*address, Account::default());
+        self.insert_code(*address, Vec::new());
+        if let Some(storage) = self.storage.remove(address) {
+            for key in storage.keys() {
+                self.changed
+                    .insert(entry_key(STORAGE_PREFIX, address, key.as_bytes()));
+            }
+        }
+    }
+
+    fn insert_code(&mut self, address: Address, code: Vec<u8>) {
+        self.changed.insert(entry_key(CODE_PREFIX, &address, &[]));
+        if code.is_empty() {
+            self.code.remove(&address);
+        } else {
+            self.code.insert(address, code);
         }
-        
-        let hash = hasher.finalize();
-        self.state_root = Hash(hash.as_bytes()[..32].try_into().unwrap());
     }
 
     /// Get account state for modification
@@ -150,19 +310,13 @@ impl StateDB for MemoryStateDB {
     }
 
     fn set_account(&mut self, address: Address, account: Account) -> VmResult<()> {
-        if account.is_empty() {
-            self.accounts.remove(&address);
-        } else {
-            self.accounts.insert(address, account);
-        }
+        self.insert_account(address, account);
         self.update_state_root();
         Ok(())
     }
 
     fn delete_account(&mut self, address: &Address) -> VmResult<()> {
-        self.accounts.remove(address);
-        self.storage.remove(address);
-        self.code.remove(address);
+        self.remove_account(address);
         self.update_state_root();
         Ok(())
     }
@@ -174,17 +328,7 @@ impl StateDB for MemoryStateDB {
     }
 
     fn set_storage(&mut self, address: Address, key: Hash, value: Vec<u8>) -> VmResult<()> {
-        let storage = self.storage.entry(address).or_insert_with(HashMap::new);
-        
-        if value.is_empty() {
-            storage.remove(&key);
-            if storage.is_empty() {
-                self.storage.remove(&address);
-            }
-        } else {
-            storage.insert(key, value);
-        }
-        
+        self.insert_storage(address, key, value);
         self.update_state_root();
         Ok(())
     }
@@ -194,11 +338,7 @@ impl StateDB for MemoryStateDB {
     }
 
     fn set_code(&mut self, address: Address, code: Vec<u8>) -> VmResult<()> {
-        if code.is_empty() {
-            self.code.remove(&address);
-        } else {
-            self.code.insert(address, code);
-        }
+        self.insert_code(address, code);
         self.update_state_root();
         Ok(())
     }
@@ -206,31 +346,78 @@ impl StateDB for MemoryStateDB {
     fn apply_changes(&mut self, changes: AccountChanges) -> VmResult<()> {
         // Apply account updates
         for (address, account) in changes.accounts {
-            self.set_account(address, account)?;
+            self.insert_account(address, account);
         }
 
         // Apply deletions
         for address in changes.deleted {
-            self.delete_account(&address)?;
+            self.remove_account(&address);
         }
 
         // Apply storage changes
         for (address, storage_changes) in changes.storage_changes {
             for (key, value) in storage_changes {
-                self.set_storage(address, key, value)?;
+                self.insert_storage(address, key, value);
             }
         }
 
         // Apply code changes
         for (address, code) in changes.code_changes {
-            self.set_code(address, code)?;
+            self.insert_code(address, code);
         }
 
+        // Recommit once for the whole batch
+        self.update_state_root();
+        Ok(())
+    }
+
+    fn changes(&self) -> Vec<StateEntry> {
+        self.changed
+            .iter()
+            .map(|key| (key.clone(), self.entry(key)))
+            .collect()
+    }
+
+    fn apply_entries(&mut self, entries: &[StateEntry]) -> VmResult<()> {
+        for (key, value) in entries {
+            self.insert_entry(key, value)?;
+        }
+        self.update_state_root();
         Ok(())
     }
 
     fn state_root(&self) -> Hash {
-        self.state_root
+        *self
+            .state_root
+            .get_or_init(|| entries_root(&self.entries()))
+    }
+
+    fn entries(&self) -> Vec<StateEntry> {
+        let mut entries: Vec<StateEntry> = self
+            .accounts
+            .iter()
+            .map(|(address, account)| {
+                (
+                    entry_key(ACCOUNT_PREFIX, address, &[]),
+                    encode_account(account),
+                )
+            })
+            .chain(
+                self.code
+                    .iter()
+                    .map(|(address, code)| (entry_key(CODE_PREFIX, address, &[]), code.clone())),
+            )
+            .chain(self.storage.iter().flat_map(|(address, storage)| {
+                storage.iter().map(move |(key, value)| {
+                    (
+                        entry_key(STORAGE_PREFIX, address, key.as_bytes()),
+                        value.clone(),
+                    )
+                })
+            }))
+            .collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
     }
 
     fn snapshot(&self) -> Box<dyn StateSnapshot> {
@@ -238,9 +425,13 @@ impl StateDB for MemoryStateDB {
             accounts: self.accounts.clone(),
             storage: self.storage.clone(),
             code: self.code.clone(),
-            state_root: self.state_root,
+            state_root: self.state_root.clone(),
         })
     }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
 }
 
 /// Memory state snapshot
@@ -249,7 +440,7 @@ pub struct MemoryStateSnapshot {
     accounts: HashMap<Address, Account>,
     storage: HashMap<Address, HashMap<Hash, Vec<u8>>>,
     code: HashMap<Address, Vec<u8>>,
-    state_root: Hash,
+    state_root: OnceLock<Hash>,
 }
 
 impl StateSnapshot for MemoryStateSnapshot {
@@ -259,7 +450,7 @@ impl StateSnapshot for MemoryStateSnapshot {
             memory_state.accounts = self.accounts.clone();
             memory_state.storage = self.storage.clone();
             memory_state.code = self.code.clone();
-            memory_state.state_root = self.state_root;
+            memory_state.state_root = self.state_root.clone();
             Ok(())
         } else {
             Err(VmError::State("Incompatible state DB type".to_string()))
@@ -271,18 +462,12 @@ impl StateSnapshot for MemoryStateSnapshot {
             accounts: self.accounts.clone(),
             storage: self.storage.clone(),
             code: self.code.clone(),
-            state_root: self.state_root,
+            state_root: self.state_root.clone(),
+            changed: BTreeSet::new(),
         })
     }
 }
 
-// Add downcast support for MemoryStateDB
-impl MemoryStateDB {
-    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
-        self
-    }
-}
-
 /// Thread-safe state DB wrapper
 pub struct SharedStateDB {
     inner: Arc<RwLock<Box<dyn StateDB>>>,
@@ -306,6 +491,11 @@ impl SharedStateDB {
         self.inner.read().get_account(address)
     }
 
+    /// Get storage value (read-only)
+    pub fn get_storage(&self, address: &Address, key: &Hash) -> VmResult<Option<Vec<u8>>> {
+        self.inner.read().get_storage(address, key)
+    }
+
     /// Apply changes atomically
     pub fn apply_changes(&self, changes: AccountChanges) -> VmResult<()> {
         self.inner.write().apply_changes(changes)
@@ -322,10 +512,31 @@ impl SharedStateDB {
         Self::new(snapshot.fork())
     }
 
+    /// Rebuild a memory state from its entries
+    pub fn from_entries(entries: &[StateEntry]) -> VmResult<Self> {
+        Ok(Self::new(Box::new(MemoryStateDB::from_entries(entries)?)))
+    }
+
     /// Get state root
     pub fn state_root(&self) -> Hash {
         self.inner.read().state_root()
     }
+
+    /// Get every state entry, sorted by key
+    pub fn entries(&self) -> Vec<StateEntry> {
+        self.inner.read().entries()
+    }
+
+    /// Get the entries written since the state was created or forked,
+    /// sorted by key, with removed entries left empty
+    pub fn changes(&self) -> Vec<StateEntry> {
+        self.inner.read().changes()
+    }
+
+    /// Write state entries atomically, removing those left empty
+    pub fn apply_entries(&self, entries: &[StateEntry]) -> VmResult<()> {
+        self.inner.write().apply_entries(entries)
+    }
 }
 
 impl Clone for SharedStateDB {
@@ -343,7 +554,7 @@ mod tests {
     #[test]
     fn test_memory_state_db() {
         let mut state = MemoryStateDB::new();
-        let address = Address([1u8; 20]);
+        let address = Address::new([1u8; 20]);
 
         // Test account operations
         assert!(state.get_account(&address).unwrap().is_none());
@@ -355,7 +566,7 @@ mod tests {
         assert_eq!(retrieved.balance, 1000);
 
         // Test storage operations
-        let key = Hash::from_data(b"test_key");
+        let key = Hash::new(*blake3::hash(b"test_key").as_bytes());
         let value = b"test_value".to_vec();
 
         state.set_storage(address, key, value.clone()).unwrap();
@@ -372,12 +583,12 @@ mod tests {
     #[test]
     fn test_account_changes() {
         let mut state = MemoryStateDB::new();
-        let address = Address([1u8; 20]);
+        let address = Address::new([1u8; 20]);
 
         let mut changes = AccountChanges::new();
         changes.update_account(address, Account::with_balance(1000));
 
-        let key = Hash::from_data(b"key");
+        let key = Hash::new(*blake3::hash(b"key").as_bytes());
         changes.update_storage(address, key, b"value".to_vec());
 
         changes.update_code(address, b"code".to_vec());
@@ -392,7 +603,7 @@ mod tests {
     #[test]
     fn test_state_snapshot() {
         let mut state = MemoryStateDB::new();
-        let address = Address([1u8; 20]);
+        let address = Address::new([1u8; 20]);
 
         // Initial state
         state.set_account(address, Account::with_balance(1000)).unwrap();
@@ -410,7 +621,7 @@ mod tests {
     #[test]
     fn test_shared_state_db() {
         let shared = SharedStateDB::memory();
-        let address = Address([1u8; 20]);
+        let address = Address::new([1u8; 20]);
 
         let mut changes = AccountChanges::new();
         changes.update_account(address, Account::with_balance(1000));
@@ -422,4 +633,77 @@ mod tests {
         let forked = shared.fork();
         assert_eq!(forked.get_account(&address).unwrap().unwrap().balance, 1000);
     }
+
+    #[test]
+    fn test_state_committed_as_entries() {
+        let address = Address::new([1u8; 20]);
+        let key = Hash::new(*blake3::hash(b"key").as_bytes());
+        let mut changes = AccountChanges::new();
+        changes.update_account(address, Account::with_balance(1000));
+        changes.update_storage(address, key, b"value".to_vec());
+        changes.update_code(address, b"code".to_vec());
+
+        let shared = SharedStateDB::memory();
+        assert_eq!(shared.state_root(), Hash::zero());
+        shared.apply_changes(changes).unwrap();
+
+        // Storage and code are committed to along with accounts
+        let entries = shared.entries();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(shared.state_root(), entries_root(&entries));
+
+        let rebuilt = SharedStateDB::from_entries(&entries).unwrap();
+        assert_eq!(rebuilt.state_root(), shared.state_root());
+        assert_eq!(
+            rebuilt.get_storage(&address, &key).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(
+            rebuilt.get_account(&address).unwrap().unwrap().balance,
+            1000
+        );
+
+        let mut changes = AccountChanges::new();
+        changes.update_storage(address, key, b"other".to_vec());
+        shared.apply_changes(changes).unwrap();
+        assert_ne!(shared.state_root(), rebuilt.state_root());
+
+        // Entries that do not decode are refused
+        assert!(SharedStateDB::from_entries(&[(b"x".to_vec(), vec![1])]).is_err());
+        assert!(SharedStateDB::from_entries(&[(entries[0].0.clone(), vec![1])]).is_err());
+    }
+
+    #[test]
+    fn test_fork_changes_replay_on_parent() {
+        let address = Address::new([1u8; 20]);
+        let other = Address::new([2u8; 20]);
+        let key = Hash::new(*blake3::hash(b"key").as_bytes());
+        let mut changes = AccountChanges::new();
+        changes.update_account(address, Account::with_balance(1000));
+        changes.update_storage(address, key, b"value".to_vec());
+        changes.update_code(address, b"code".to_vec());
+        let parent = SharedStateDB::memory();
+        parent.apply_changes(changes).unwrap();
+
+        let child = parent.fork();
+        assert!(child.changes().is_empty());
+        let mut changes = AccountChanges::new();
+        changes.delete_account(address);
+        changes.update_account(other, Account::with_balance(5));
+        child.apply_changes(changes).unwrap();
+
+        // Removed entries are left empty
+        let written = child.changes();
+        assert_eq!(written.len(), 4);
+        assert_eq!(
+            written.iter().filter(|(_, value)| value.is_empty()).count(),
+            3
+        );
+
+        parent.apply_entries(&written).unwrap();
+        assert_eq!(parent.entries(), child.entries());
+        assert_eq!(parent.state_root(), child.state_root());
+        assert!(parent.get_storage(&address, &key).unwrap().is_none());
+    }
 }